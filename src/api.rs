@@ -0,0 +1,617 @@
+//! The Docker Engine API backend, selected with `runtime = "api"`
+//!
+//! Builds, runs and pushes described by the same [`BuildSpec`] and [`RunSpec`] as the docker
+//! CLI path, so both backends resolve their arguments identically. Only compiled in with the
+//! `bollard` feature; without it, selecting the backend fails with an explanation.
+
+use crate::docker::{BuildOutput, BuildSpec, Endpoint, RunSpec};
+use crate::error::Result;
+use std::path::Path;
+
+#[cfg(feature = "bollard")]
+pub use engine::{build, push, run};
+
+#[cfg(not(feature = "bollard"))]
+pub use unavailable::{build, push, run};
+
+#[cfg(not(feature = "bollard"))]
+mod unavailable {
+    use super::*;
+    use crate::error::PixiDockerError;
+
+    fn unavailable() -> PixiDockerError {
+        PixiDockerError::msg(
+            "runtime = \"api\" needs pixi-docker built with the `bollard` feature \
+             (`cargo install pixi-docker --features bollard`)",
+        )
+    }
+
+    pub fn build(
+        _endpoint: &Endpoint,
+        _spec: &BuildSpec,
+        _context_dir: &Path,
+        _dockerfile: &str,
+        _content: &str,
+        _output: BuildOutput,
+        _tail: usize,
+    ) -> Result<String> {
+        Err(unavailable())
+    }
+
+    pub fn push(_endpoint: &Endpoint, _tags: &[String], _output: BuildOutput) -> Result<()> {
+        Err(unavailable())
+    }
+
+    pub fn run(_endpoint: &Endpoint, _spec: &RunSpec) -> Result<()> {
+        Err(unavailable())
+    }
+}
+
+#[cfg(feature = "bollard")]
+mod engine {
+    use super::*;
+    use crate::config::byte_size;
+    use crate::docker;
+    use crate::dockerignore::DockerIgnore;
+    use crate::error::{bail, Context, PixiDockerError};
+    use crate::reference::split_reference;
+    use bollard::auth::DockerCredentials;
+    use bollard::container::{
+        AttachContainerOptions, AttachContainerResults, Config as ContainerConfig,
+        CreateContainerOptions, LogOutput, WaitContainerOptions,
+    };
+    use bollard::errors::Error as ApiError;
+    use bollard::image::{BuildImageOptions, PushImageOptions, TagImageOptions};
+    use bollard::models::{
+        HostConfig, Mount as ApiMount, MountTypeEnum, PortBinding, RestartPolicy,
+        RestartPolicyNameEnum,
+    };
+    use bollard::{Docker, API_DEFAULT_VERSION};
+    use futures_util::StreamExt;
+    use std::collections::{HashMap, VecDeque};
+    use std::fs;
+    use std::future::Future;
+    use std::io::{self, Write};
+
+    /// Context of a failure to show the daemon's progress output
+    const ECHO_FAILED: &str = "Failed to write the progress output";
+
+    /// Context of a failure to pass on the container's output
+    const OUTPUT_FAILED: &str = "Failed to write the container's output";
+
+    /// Seconds a request may take, as in bollard's own defaults
+    const TIMEOUT: u64 = 120;
+
+    /// Registry key of Docker Hub in the docker CLI config
+    const DOCKER_HUB: &str = "https://index.docker.io/v1/";
+
+    /// Drive a request to completion on a runtime of its own
+    fn block_on<F: Future>(future: F) -> Result<F::Output> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("Failed to start the async runtime")?;
+        let output = runtime.block_on(future);
+        // Don't wait for a stdin reader still blocked on the terminal
+        runtime.shutdown_background();
+        Ok(output)
+    }
+
+    /// Connect to the daemon the docker CLI would use; TLS and `ssh://` daemons aren't supported
+    fn connect(endpoint: &Endpoint) -> Result<Docker> {
+        let docker = match endpoint.host.as_deref() {
+            None => Docker::connect_with_local_defaults(),
+            Some(host) if host.starts_with("unix://") || host.starts_with("npipe://") => {
+                Docker::connect_with_socket(host, TIMEOUT, API_DEFAULT_VERSION)
+            }
+            Some(host) if host.starts_with("tcp://") || host.starts_with("http://") => {
+                Docker::connect_with_http(host, TIMEOUT, API_DEFAULT_VERSION)
+            }
+            Some(_) => bail!(
+                "The Docker Engine API backend can't connect to {}; use the docker CLI for it",
+                endpoint
+            ),
+        };
+        docker
+            .with_context(|| format!("Failed to connect to the Docker Engine API at {}", endpoint))
+    }
+
+    /// Build an image from the build context and the rendered Dockerfile, returning its ID
+    ///
+    /// The context is sent as a tar archive without what `.dockerignore` excludes, and the
+    /// Dockerfile is added to it as `dockerfile`. Output is shown like the CLI's.
+    pub fn build(
+        endpoint: &Endpoint,
+        spec: &BuildSpec,
+        context_dir: &Path,
+        dockerfile: &str,
+        content: &str,
+        output: BuildOutput,
+        tail: usize,
+    ) -> Result<String> {
+        let archive = context_archive(context_dir, dockerfile, content)?;
+        let options = BuildImageOptions {
+            dockerfile: dockerfile.to_string(),
+            t: spec.tags[0].clone(),
+            target: spec.target.clone().unwrap_or_default(),
+            platform: spec.platform.clone().unwrap_or_default(),
+            buildargs: spec
+                .build_args
+                .iter()
+                .cloned()
+                .chain(
+                    spec.env_build_args
+                        .iter()
+                        .filter_map(|name| Some((name.clone(), std::env::var(name).ok()?))),
+                )
+                .collect(),
+            labels: spec.labels.iter().cloned().collect(),
+            rm: true,
+            ..Default::default()
+        };
+
+        block_on(async {
+            let docker = connect(endpoint)?;
+            let mut lines = VecDeque::with_capacity(tail);
+            let mut pending = String::new();
+            let mut image_id = None;
+            let mut stream = docker.build_image(options, None, Some(archive.into()));
+            while let Some(info) = stream.next().await {
+                let error = match info {
+                    Ok(info) => {
+                        if let Some(text) = info.stream {
+                            pending.push_str(&text);
+                            while let Some(end) = pending.find('\n') {
+                                let line: String = pending.drain(..=end).collect();
+                                docker::echo(&output, line.as_bytes(), false)
+                                    .context(ECHO_FAILED)?;
+                                docker::keep_tail(&mut lines, tail, line.as_bytes());
+                            }
+                        }
+                        if let Some(id) = info.aux.and_then(|aux| aux.id) {
+                            image_id = Some(id);
+                        }
+                        info.error
+                    }
+                    Err(err) => Some(err.to_string()),
+                };
+                if let Some(error) = error {
+                    docker::keep_tail(&mut lines, tail, pending.as_bytes());
+                    docker::keep_tail(&mut lines, tail, error.as_bytes());
+                    return Err(PixiDockerError::RuntimeExit {
+                        what: "Docker build".to_string(),
+                        code: None,
+                        note: Some("Docker Engine API".to_string()),
+                        output_tail: lines.into(),
+                    });
+                }
+            }
+            if !pending.is_empty() {
+                docker::echo(&output, pending.as_bytes(), false).context(ECHO_FAILED)?;
+            }
+            let image_id = image_id
+                .context("The Docker Engine API didn't report the ID of the built image")?;
+
+            // The build endpoint takes a single tag
+            for reference in &spec.tags[1..] {
+                let (repo, tag) = repository_and_tag(reference);
+                docker
+                    .tag_image(&image_id, Some(TagImageOptions { repo, tag }))
+                    .await
+                    .with_context(|| format!("Failed to tag {} as {}", image_id, reference))?;
+            }
+            Ok(image_id)
+        })?
+    }
+
+    /// Push images, authenticating with the credentials stored in the docker CLI config
+    pub fn push(endpoint: &Endpoint, tags: &[String], output: BuildOutput) -> Result<()> {
+        block_on(async {
+            let docker = connect(endpoint)?;
+            for reference in tags {
+                let (repo, tag) = repository_and_tag(reference);
+                let credentials = registry_credentials(split_reference(reference).0);
+                let mut stream =
+                    docker.push_image(&repo, Some(PushImageOptions { tag }), credentials);
+                while let Some(info) = stream.next().await {
+                    let info = info.with_context(|| format!("Failed to push {}", reference))?;
+                    if let Some(error) = info.error {
+                        bail!("Failed to push {}: {}", reference, error);
+                    }
+                    // Progress bars only make sense on a terminal that redraws them
+                    if let Some(status) = info.status.filter(|_| info.progress.is_none()) {
+                        docker::echo(&output, format!("{}\n", status).as_bytes(), false)
+                            .context(ECHO_FAILED)?;
+                    }
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    /// Create a container, attach to it, start it and wait for it to exit
+    pub fn run(endpoint: &Endpoint, spec: &RunSpec) -> Result<()> {
+        let config = container_config(spec)?;
+        block_on(async {
+            let docker = connect(endpoint)?;
+            let options = spec.name.clone().map(|name| CreateContainerOptions {
+                name,
+                platform: None,
+            });
+            let container = docker
+                .create_container(options, config)
+                .await
+                .with_context(|| format!("Failed to create a container from {}", spec.image))?;
+            if spec.detach {
+                docker
+                    .start_container::<String>(&container.id, None)
+                    .await
+                    .context("Failed to start the container")?;
+                println!("{}", container.id);
+                return Ok(());
+            }
+
+            // Attach and wait before starting, so neither output nor the exit is missed
+            let AttachContainerResults { mut output, input } = docker
+                .attach_container(
+                    &container.id,
+                    Some(AttachContainerOptions::<String> {
+                        stdin: Some(spec.interactive),
+                        stdout: Some(true),
+                        stderr: Some(true),
+                        stream: Some(true),
+                        ..Default::default()
+                    }),
+                )
+                .await
+                .context("Failed to attach to the container")?;
+            let condition = if spec.rm { "removed" } else { "next-exit" };
+            let wait = tokio::spawn({
+                let docker = docker.clone();
+                let id = container.id.clone();
+                async move {
+                    let options = WaitContainerOptions { condition };
+                    docker.wait_container(&id, Some(options)).next().await
+                }
+            });
+            tokio::task::yield_now().await;
+            docker
+                .start_container::<String>(&container.id, None)
+                .await
+                .context("Failed to start the container")?;
+
+            if spec.interactive {
+                let mut input = input;
+                tokio::spawn(async move {
+                    let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut input).await;
+                });
+            }
+            while let Some(chunk) = output.next().await {
+                match chunk.context("Lost the connection to the container")? {
+                    LogOutput::StdErr { message } => {
+                        io::stderr().write_all(&message).context(OUTPUT_FAILED)?
+                    }
+                    LogOutput::StdOut { message } | LogOutput::Console { message } => {
+                        let mut stdout = io::stdout().lock();
+                        stdout.write_all(&message).context(OUTPUT_FAILED)?;
+                        stdout.flush().context(OUTPUT_FAILED)?;
+                    }
+                    LogOutput::StdIn { .. } => {}
+                }
+            }
+
+            let code = match wait.await.context("Failed to wait for the container")? {
+                Some(Ok(response)) => response.status_code,
+                Some(Err(ApiError::DockerContainerWaitError { code, .. })) => code,
+                Some(Err(err)) => return Err(err).context("Failed to wait for the container"),
+                None => 0,
+            };
+            if code != 0 {
+                return Err(PixiDockerError::RuntimeExit {
+                    what: "Docker run".to_string(),
+                    code: Some(code as i32),
+                    note: None,
+                    output_tail: Vec::new(),
+                });
+            }
+            Ok(())
+        })?
+    }
+
+    /// The container settings the `docker run` flags of `spec` stand for
+    fn container_config(spec: &RunSpec) -> Result<ContainerConfig<String>> {
+        let mut host_config = HostConfig {
+            auto_remove: Some(spec.rm),
+            network_mode: spec.network.clone(),
+            restart_policy: spec.restart.as_deref().map(restart_policy).transpose()?,
+            ..Default::default()
+        };
+        for (flag, value) in &spec.limits {
+            match *flag {
+                "--memory" => host_config.memory = byte_size(value).map(|bytes| bytes as i64),
+                "--shm-size" => host_config.shm_size = byte_size(value).map(|bytes| bytes as i64),
+                "--cpus" => {
+                    host_config.nano_cpus =
+                        value.parse::<f64>().ok().map(|cpus| (cpus * 1e9) as i64)
+                }
+                _ => {}
+            }
+        }
+
+        let mut exposed_ports = HashMap::new();
+        let mut port_bindings = HashMap::new();
+        for port in &spec.ports {
+            let (host, container) = port.rsplit_once(':').unwrap_or((port, port));
+            let container = match container.contains('/') {
+                true => container.to_string(),
+                false => format!("{}/tcp", container),
+            };
+            let (host_ip, host_port) = match host.rsplit_once(':') {
+                Some((ip, port)) => (Some(ip.to_string()), port),
+                None => (None, host),
+            };
+            exposed_ports.insert(container.clone(), HashMap::new());
+            port_bindings.insert(
+                container,
+                Some(vec![PortBinding {
+                    host_ip,
+                    host_port: Some(host_port.to_string()),
+                }]),
+            );
+        }
+        if !port_bindings.is_empty() {
+            host_config.port_bindings = Some(port_bindings);
+        }
+        if spec.publish_all {
+            host_config.publish_all_ports = Some(true);
+        }
+        if spec.init {
+            host_config.init = Some(true);
+        }
+        host_config.userns_mode = spec.userns.clone();
+        if !spec.mounts.is_empty() {
+            let mounts = spec.mounts.iter().map(|mount| match mount {
+                docker::Mount::Bind { source, target } => ApiMount {
+                    typ: Some(MountTypeEnum::BIND),
+                    source: Some(source.clone()),
+                    target: Some(target.clone()),
+                    ..Default::default()
+                },
+                docker::Mount::Volume { target } => ApiMount {
+                    typ: Some(MountTypeEnum::VOLUME),
+                    target: Some(target.clone()),
+                    ..Default::default()
+                },
+            });
+            host_config.mounts = Some(mounts.collect());
+        }
+
+        Ok(ContainerConfig {
+            image: Some(spec.image.clone()),
+            cmd: (!spec.command.is_empty()).then(|| spec.command.clone()),
+            working_dir: spec.workdir.clone(),
+            user: spec.user.clone(),
+            tty: Some(spec.tty),
+            open_stdin: Some(spec.interactive),
+            stdin_once: Some(spec.interactive),
+            attach_stdin: Some(spec.interactive),
+            attach_stdout: Some(!spec.detach),
+            attach_stderr: Some(!spec.detach),
+            exposed_ports: (!exposed_ports.is_empty()).then_some(exposed_ports),
+            stop_timeout: spec.stop_timeout.map(|timeout| timeout as i64),
+            host_config: Some(host_config),
+            ..Default::default()
+        })
+    }
+
+    /// Parse a `--restart` value such as `always` or `on-failure:3`
+    fn restart_policy(value: &str) -> Result<RestartPolicy> {
+        let (name, retries) = match value.split_once(':') {
+            Some((name, retries)) => (name, Some(retries)),
+            None => (value, None),
+        };
+        let name = name
+            .parse::<RestartPolicyNameEnum>()
+            .map_err(|_| PixiDockerError::msg(format!("Invalid restart policy '{}'", value)))?;
+        let maximum_retry_count = retries
+            .map(|retries| retries.parse::<i64>())
+            .transpose()
+            .with_context(|| format!("Invalid restart policy '{}'", value))?;
+        Ok(RestartPolicy {
+            name: Some(name),
+            maximum_retry_count,
+        })
+    }
+
+    /// Split a reference into the repository including its registry and the tag
+    fn repository_and_tag(reference: &str) -> (String, String) {
+        let (registry, repository, tag) = split_reference(reference);
+        let repository = match registry {
+            Some(registry) => format!("{}/{}", registry, repository),
+            None => repository.to_string(),
+        };
+        (repository, tag.unwrap_or("latest").to_string())
+    }
+
+    /// Credentials for a registry from `auths` in the docker CLI config
+    ///
+    /// Credential helpers (`credsStore`, `credHelpers`) aren't consulted.
+    fn registry_credentials(registry: Option<&str>) -> Option<DockerCredentials> {
+        let config = docker::cli_config()?;
+        let host = match registry {
+            Some("docker.io") | None => "index.docker.io",
+            Some(registry) => registry,
+        };
+        let (server, entry) = config.get("auths")?.as_object()?.iter().find(|(key, _)| {
+            let key = key
+                .trim_start_matches("https://")
+                .trim_start_matches("http://");
+            key.split('/').next() == Some(host)
+        })?;
+        Some(DockerCredentials {
+            auth: Some(entry.get("auth")?.as_str()?.to_string()),
+            serveraddress: Some(match registry {
+                Some(_) => server.clone(),
+                None => DOCKER_HUB.to_string(),
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// Tar up the build context without what `.dockerignore` excludes, adding the rendered
+    /// Dockerfile as `dockerfile` in place of any file of that name
+    fn context_archive(context_dir: &Path, dockerfile: &str, content: &str) -> Result<Vec<u8>> {
+        let ignore = DockerIgnore::load(context_dir).with_context(|| {
+            format!(
+                "Failed to read {}",
+                context_dir.join(".dockerignore").display()
+            )
+        })?;
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.follow_symlinks(false);
+        add_context_dir(&mut builder, context_dir, "", &ignore, dockerfile).with_context(|| {
+            format!(
+                "Failed to archive the build context {}",
+                context_dir.display()
+            )
+        })?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, dockerfile, content.as_bytes())
+            .context("Failed to add the Dockerfile to the build context")?;
+        builder
+            .into_inner()
+            .context("Failed to archive the build context")
+    }
+
+    fn add_context_dir(
+        builder: &mut tar::Builder<Vec<u8>>,
+        dir: &Path,
+        prefix: &str,
+        ignore: &DockerIgnore,
+        dockerfile: &str,
+    ) -> io::Result<()> {
+        // Sorted, so that the same context gives the same archive
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if name == dockerfile {
+                continue;
+            }
+            // Like the docker CLI, always send `.dockerignore` itself
+            let excluded = ignore.excludes(&name) && name != ".dockerignore";
+            if entry.file_type()?.is_dir() {
+                if !excluded {
+                    builder.append_dir(&name, entry.path())?;
+                }
+                // Exceptions like `!build/keep` can include paths inside excluded directories
+                if !excluded || ignore.has_exceptions() {
+                    let prefix = format!("{}/", name);
+                    add_context_dir(builder, &entry.path(), &prefix, ignore, dockerfile)?;
+                }
+            } else if !excluded {
+                builder.append_path_with_name(entry.path(), &name)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_context_archive() {
+            let dir = tempfile::tempdir().unwrap();
+            let context = dir.path();
+            fs::write(
+                context.join(".dockerignore"),
+                ".pixi\n*.log\nbuild\n!build/keep\n",
+            )
+            .unwrap();
+            fs::write(context.join("pixi.toml"), "[workspace]\n").unwrap();
+            fs::write(context.join("Dockerfile.default"), "stale").unwrap();
+            fs::write(context.join("debug.log"), "").unwrap();
+            fs::create_dir_all(context.join(".pixi/envs")).unwrap();
+            fs::create_dir_all(context.join("src")).unwrap();
+            fs::write(context.join("src/app.py"), "print()").unwrap();
+            fs::create_dir_all(context.join("build")).unwrap();
+            fs::write(context.join("build/keep"), "").unwrap();
+            fs::write(context.join("build/drop"), "").unwrap();
+
+            let archive = context_archive(context, "Dockerfile.default", "FROM scratch\n").unwrap();
+            let mut archive = tar::Archive::new(archive.as_slice());
+            let mut entries = Vec::new();
+            for entry in archive.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let path = entry.path().unwrap().display().to_string();
+                let mut content = String::new();
+                io::Read::read_to_string(&mut entry, &mut content).unwrap();
+                entries.push((path, content));
+            }
+            let paths: Vec<&str> = entries.iter().map(|(path, _)| path.as_str()).collect();
+            assert_eq!(
+                paths,
+                [
+                    ".dockerignore",
+                    "build/keep",
+                    "pixi.toml",
+                    "src",
+                    "src/app.py",
+                    "Dockerfile.default"
+                ]
+            );
+            assert_eq!(entries.last().unwrap().1, "FROM scratch\n");
+        }
+
+        #[test]
+        fn test_container_config() {
+            let spec = RunSpec {
+                image: "my-app:latest".to_string(),
+                rm: true,
+                restart: None,
+                limits: vec![
+                    ("--memory", "512m".to_string()),
+                    ("--cpus", "1.5".to_string()),
+                ],
+                ports: vec!["8080:80".to_string(), "127.0.0.1:9000:9000/udp".to_string()],
+                interactive: true,
+                tty: true,
+                workdir: Some("/app".to_string()),
+                user: Some("1000:1000".to_string()),
+                mounts: vec![docker::Mount::Bind {
+                    source: "/home/me/app".to_string(),
+                    target: "/app".to_string(),
+                }],
+                command: vec!["pytest".to_string()],
+                ..Default::default()
+            };
+            let config = container_config(&spec).unwrap();
+            let host = config.host_config.unwrap();
+            assert_eq!(host.auto_remove, Some(true));
+            assert_eq!(host.memory, Some(512 * 1024 * 1024));
+            assert_eq!(host.nano_cpus, Some(1_500_000_000));
+            let bindings = host.port_bindings.unwrap();
+            let binding = bindings["80/tcp"].as_ref().unwrap();
+            assert_eq!(binding[0].host_port.as_deref(), Some("8080"));
+            let binding = bindings["9000/udp"].as_ref().unwrap();
+            assert_eq!(binding[0].host_ip.as_deref(), Some("127.0.0.1"));
+            let mount = &host.mounts.unwrap()[0];
+            assert_eq!(mount.typ, Some(MountTypeEnum::BIND));
+            assert_eq!(mount.source.as_deref(), Some("/home/me/app"));
+            assert_eq!(config.cmd, Some(vec!["pytest".to_string()]));
+            assert_eq!(config.user.as_deref(), Some("1000:1000"));
+            assert_eq!(config.tty, Some(true));
+
+            let policy = restart_policy("on-failure:3").unwrap();
+            assert_eq!(policy.name, Some(RestartPolicyNameEnum::ON_FAILURE));
+            assert_eq!(policy.maximum_retry_count, Some(3));
+            assert!(restart_policy("sometimes").is_err());
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::template::json_string;
+use std::collections::BTreeMap;
+
+/// A `docker buildx bake` target for one environment
+#[derive(Debug, Default)]
+pub struct Target {
+    pub name: String,
+    pub context: String,
+    pub dockerfile: String,
+    pub tags: Vec<String>,
+    pub platforms: Vec<String>,
+    pub args: BTreeMap<String, String>,
+    pub cache_from: Vec<String>,
+    pub cache_to: Vec<String>,
+    pub secret: Vec<String>,
+    pub ssh: Vec<String>,
+}
+
+impl Target {
+    /// Attributes in bake order; empty lists and maps are left out
+    fn attributes(&self) -> Vec<(&'static str, Value<'_>)> {
+        let mut attributes = vec![
+            ("context", Value::String(&self.context)),
+            ("dockerfile", Value::String(&self.dockerfile)),
+            ("tags", Value::List(&self.tags)),
+            ("platforms", Value::List(&self.platforms)),
+            ("args", Value::Map(&self.args)),
+            ("cache-from", Value::List(&self.cache_from)),
+            ("cache-to", Value::List(&self.cache_to)),
+            ("secret", Value::List(&self.secret)),
+            ("ssh", Value::List(&self.ssh)),
+        ];
+        attributes.retain(|(_, value)| !value.is_empty());
+        attributes
+    }
+}
+
+enum Value<'a> {
+    String(&'a str),
+    List(&'a [String]),
+    Map(&'a BTreeMap<String, String>),
+}
+
+impl Value<'_> {
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(items) => items.is_empty(),
+            Value::Map(map) => map.is_empty(),
+        }
+    }
+}
+
+/// Render a bake file in HCL, with a `default` group building every target
+pub fn to_hcl(targets: &[Target]) -> String {
+    let names: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    let mut out = String::from("# Generated by pixi-docker\n\n");
+    out.push_str(&format!(
+        "group \"default\" {{\n  targets = {}\n}}\n",
+        bake_list(&names)
+    ));
+
+    for target in targets {
+        out.push_str(&format!("\ntarget {} {{\n", bake_string(&target.name)));
+        for (key, value) in target.attributes() {
+            match value {
+                Value::String(value) => {
+                    out.push_str(&format!("  {} = {}\n", key, bake_string(value)))
+                }
+                Value::List(items) => {
+                    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+                    out.push_str(&format!("  {} = {}\n", key, bake_list(&items)));
+                }
+                Value::Map(map) => {
+                    out.push_str(&format!("  {} = {{\n", key));
+                    for (name, value) in map {
+                        out.push_str(&format!(
+                            "    {} = {}\n",
+                            bake_string(name),
+                            bake_string(value)
+                        ));
+                    }
+                    out.push_str("  }\n");
+                }
+            }
+        }
+        out.push_str("}\n");
+    }
+    out
+}
+
+/// Render a bake file in JSON, which bake accepts as well
+pub fn to_json(targets: &[Target]) -> String {
+    let names: Vec<&str> = targets.iter().map(|t| t.name.as_str()).collect();
+    let mut out = String::from("{\n");
+    out.push_str(&format!(
+        "  \"group\": {{\n    \"default\": {{\n      \"targets\": {}\n    }}\n  }},\n",
+        bake_list(&names)
+    ));
+    out.push_str("  \"target\": {");
+
+    for (i, target) in targets.iter().enumerate() {
+        out.push_str(if i == 0 { "\n" } else { ",\n" });
+        out.push_str(&format!("    {}: {{", bake_string(&target.name)));
+        for (j, (key, value)) in target.attributes().into_iter().enumerate() {
+            out.push_str(if j == 0 { "\n" } else { ",\n" });
+            let value = match value {
+                Value::String(value) => bake_string(value),
+                Value::List(items) => {
+                    let items: Vec<&str> = items.iter().map(String::as_str).collect();
+                    bake_list(&items)
+                }
+                Value::Map(map) => {
+                    let entries: Vec<String> = map
+                        .iter()
+                        .map(|(name, value)| {
+                            format!("{}: {}", bake_string(name), bake_string(value))
+                        })
+                        .collect();
+                    format!("{{{}}}", entries.join(", "))
+                }
+            };
+            out.push_str(&format!("      {}: {}", bake_string(key), value));
+        }
+        out.push_str("\n    }");
+    }
+    out.push_str("\n  }\n}\n");
+    out
+}
+
+/// Quote a string for a bake file, escaping the `${` and `%{` interpolation of HCL and JSON files
+fn bake_string(value: &str) -> String {
+    json_string(value).replace("${", "$${").replace("%{", "%%{")
+}
+
+fn bake_list(items: &[&str]) -> String {
+    let items: Vec<String> = items.iter().map(|item| bake_string(item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Vec<Target> {
+        vec![
+            Target {
+                name: "dev".to_string(),
+                context: ".".to_string(),
+                dockerfile: "Dockerfile.dev".to_string(),
+                tags: vec!["app:dev".to_string()],
+                ..Default::default()
+            },
+            Target {
+                name: "prod".to_string(),
+                context: ".".to_string(),
+                dockerfile: "Dockerfile.prod".to_string(),
+                tags: vec!["app:1.0".to_string(), "app:latest".to_string()],
+                platforms: vec!["linux/amd64".to_string()],
+                args: BTreeMap::from([("GREETING".to_string(), "hi ${USER}".to_string())]),
+                cache_from: vec!["type=registry,ref=app:cache".to_string()],
+                cache_to: vec!["type=inline".to_string()],
+                secret: vec!["id=pixi_auth,src=credentials.json".to_string()],
+                ssh: vec!["default".to_string()],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_to_hcl() {
+        assert_eq!(
+            to_hcl(&targets()),
+            r#"# Generated by pixi-docker
+
+group "default" {
+  targets = ["dev", "prod"]
+}
+
+target "dev" {
+  context = "."
+  dockerfile = "Dockerfile.dev"
+  tags = ["app:dev"]
+}
+
+target "prod" {
+  context = "."
+  dockerfile = "Dockerfile.prod"
+  tags = ["app:1.0", "app:latest"]
+  platforms = ["linux/amd64"]
+  args = {
+    "GREETING" = "hi $${USER}"
+  }
+  cache-from = ["type=registry,ref=app:cache"]
+  cache-to = ["type=inline"]
+  secret = ["id=pixi_auth,src=credentials.json"]
+  ssh = ["default"]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(
+            to_json(&targets()),
+            r#"{
+  "group": {
+    "default": {
+      "targets": ["dev", "prod"]
+    }
+  },
+  "target": {
+    "dev": {
+      "context": ".",
+      "dockerfile": "Dockerfile.dev",
+      "tags": ["app:dev"]
+    },
+    "prod": {
+      "context": ".",
+      "dockerfile": "Dockerfile.prod",
+      "tags": ["app:1.0", "app:latest"],
+      "platforms": ["linux/amd64"],
+      "args": {"GREETING": "hi $${USER}"},
+      "cache-from": ["type=registry,ref=app:cache"],
+      "cache-to": ["type=inline"],
+      "secret": ["id=pixi_auth,src=credentials.json"],
+      "ssh": ["default"]
+    }
+  }
+}
+"#
+        );
+    }
+}
@@ -1,59 +1,1793 @@
+use crate::error::{bail, Context, PixiDockerError, Result};
+use crate::interpolate;
+use crate::migrate;
+use crate::network::NetworkPolicy;
+use crate::pixi::{conda_platform, discover_manifest, PixiToml};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix for environment variables that override config values
+pub const ENV_PREFIX: &str = "PIXI_DOCKER_";
+
+/// Tag of the pixi base image when no `pixi_version` is configured
+pub const DEFAULT_PIXI_VERSION: &str = "latest";
+
+/// Base image of the final stage when no `base_image` is configured, as in the templates
+pub const DEFAULT_BASE_IMAGE: &str = "ubuntu:24.04";
+
+/// Config file name searched for when `--config` isn't given
+pub const CONFIG_FILE: &str = "pixi_docker.toml";
+
+/// `runtime` value selecting the Docker Engine API backend instead of a CLI executable
+pub const API_RUNTIME: &str = "api";
+
+/// Socket of the local docker daemon, used to detect the Engine API backend
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// BuildKit secret id under which `auth_file` is mounted for `pixi install`
+pub const AUTH_SECRET_ID: &str = "pixi_auth";
+
+/// Proxy variables forwarded from the host with `forward_proxy`, also in lower case
+pub const PROXY_VARS: [&str; 3] = ["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY"];
+
+/// Config values holding paths, resolved relative to the file that sets them
+const PATH_FIELDS: &[&str] = &[
+    "template_path",
+    "template_dir",
+    "manifest_path",
+    "auth_file",
+    "output_dir",
+    "output_file",
+];
+
+/// Lists of BuildKit specs whose `src` host paths are resolved like `PATH_FIELDS`
+const SPEC_PATH_FIELDS: &[&str] = &["secrets"];
+
+/// Environment lists that fall back to the `[docker]` list, combined as `list_merge` says
+const MERGED_LIST_FIELDS: &[&str] = &[
+    "ports",
+    "copy_files",
+    "runtime_copy",
+    "pre_build",
+    "post_build",
+];
+
+/// Find the config file by walking up from `cwd`, returning its path relative to `cwd`
+///
+/// Stops at the filesystem root or the root of the enclosing git repository.
+pub fn discover_config(cwd: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for dir in cwd.ancestors() {
+        if dir.join(CONFIG_FILE).is_file() {
+            return Some(relative.join(CONFIG_FILE));
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+        relative.push("..");
+    }
+    None
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     pub docker: DockerConfig,
     #[serde(default)]
-    pub environments: HashMap<String, EnvironmentConfig>,
+    pub environments: BTreeMap<String, EnvironmentConfig>,
+    /// Where each effective value came from, keyed by dotted path (e.g. `docker.ports`)
+    #[serde(skip)]
+    pub sources: BTreeMap<String, ValueSource>,
+    /// Values as written before `${VAR}` expansion, keyed like `sources`, for those it changed
+    #[serde(skip)]
+    pub raw_values: BTreeMap<String, toml::Value>,
+    /// The config file this was loaded from
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Whether network features may go online, set by `--offline`
+    #[serde(skip)]
+    pub network: NetworkPolicy,
+    /// Print diagnostic output, set by `--verbose`
+    #[serde(skip)]
+    pub verbose: bool,
+    /// Fail on inputs missing from the lock file instead of resolving and recording them, set
+    /// for commands that only read
+    #[serde(skip)]
+    pub lock_read_only: bool,
+}
+
+/// Origin of an effective configuration value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueSource {
+    File(PathBuf),
+    Env(String),
+    Cli(String),
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSource::File(path) => write!(f, "{}", path.display()),
+            ValueSource::Env(var) => write!(f, "env {}", var),
+            ValueSource::Cli(flag) => write!(f, "cli {}", flag),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct DockerConfig {
     pub environment: String,
+    /// Pixi environment installed in the image and used to look up tasks (default: the
+    /// docker environment's name)
+    pub pixi_environment: Option<String>,
     #[serde(default)]
     pub ports: Vec<u16>,
-    pub entrypoint: Option<String>,
+    pub entrypoint: Option<Entrypoint>,
     #[serde(default)]
     pub copy_files: Vec<String>,
+    /// Build-stage paths copied into the final image instead of the pixi environment prefix
+    #[serde(default)]
+    pub runtime_copy: Vec<String>,
+    /// Prefix shell entrypoints with `exec` so the command replaces bash and receives signals
+    #[serde(default = "default_true")]
+    pub exec_entrypoint: bool,
+    /// Signal sent to stop the container, rendered as `STOPSIGNAL`, e.g. `SIGINT`
+    pub stop_signal: Option<String>,
+    /// Time between the stop signal and SIGKILL for `pixi-docker run`, e.g. `30s` or `1m30s`
+    pub stop_grace_period: Option<String>,
     pub image_name: Option<String>,
+    /// Registry host prepended to the image name, e.g. `ghcr.io`
+    pub registry: Option<String>,
+    /// Namespace between the registry and the image name, e.g. `acme`
+    pub namespace: Option<String>,
     pub image_tag: Option<String>,
+    /// Additional tags applied by `build`, e.g. `latest`
+    #[serde(default)]
+    pub extra_tags: Vec<String>,
     pub pixi_version: Option<String>,
-    pub build_command: Option<String>,
+    pub build_command: Option<BuildCommand>,
+    /// Chain all build commands into a single RUN instruction
+    #[serde(default)]
+    pub single_layer: bool,
     #[serde(default = "default_multi_stage")]
     pub multi_stage: bool,
-    pub base_image: Option<String>,
+    /// Remove the package cache in the install layer (default: only for single-stage builds)
+    pub clean_cache: Option<bool>,
+    pub base_image: Option<BaseImage>,
+    /// Render base images pinned to the digests recorded in `pixi_docker.lock`
+    #[serde(default)]
+    pub pin_digests: bool,
+    /// Target platform of the image, e.g. `linux/arm64` (default: the host's)
+    pub platform: Option<String>,
+    /// Where buildx exports the build result instead of loading it, e.g. `type=local,dest=dist`
+    pub output: Option<String>,
     pub template_path: Option<String>,
+    /// Directory searched for `Dockerfile.<environment>.j2`, then `Dockerfile.j2`
+    pub template_dir: Option<String>,
+    /// Directory generated Dockerfiles are written to and read from (default: `.`, and the
+    /// build context for `build`)
+    pub output_dir: Option<String>,
+    /// Built-in template to render when no `template_path` is set
+    pub flavor: Option<String>,
+    pub test_command: Option<String>,
+    /// Run the test command in a `test` stage of the multi-stage build, so failing tests fail
+    /// the build
+    #[serde(default)]
+    pub test_in_build: bool,
+    /// Architectures stitched together by `pixi-docker manifest`
+    #[serde(default)]
+    pub manifest_archs: Vec<String>,
+    /// Pattern for the per-architecture tags, e.g. `{tag}-{arch}`
+    pub manifest_tag_pattern: Option<String>,
+    /// Container runtime executable (default: `docker`), or `api` to talk to the Docker Engine
+    /// API directly
+    pub runtime: Option<String>,
+    /// Docker context the runtime uses, passed as `--context`
+    pub context: Option<String>,
+    /// Attach an SBOM attestation to built images
+    #[serde(default)]
+    pub sbom: bool,
+    /// Provenance attestation mode passed to buildx, e.g. `mode=max`
+    pub provenance: Option<String>,
+    /// Emit `org.opencontainers.image.*` labels from pixi.toml metadata
+    #[serde(default = "default_true")]
+    pub oci_labels: bool,
+    /// Produce byte-identical output across runs (no timestamps)
+    #[serde(default)]
+    pub reproducible: bool,
+    /// Start generated Dockerfiles with a comment saying they are generated
+    #[serde(default = "default_true")]
+    pub header: bool,
+    /// Check the structure of rendered Dockerfiles before writing or building them
+    #[serde(default = "default_true")]
+    pub lint: bool,
+    /// Guess the entrypoint from conventional pixi task names when none is configured
+    #[serde(default = "default_true")]
+    pub infer_entrypoint: bool,
+    /// Fail instead of warning when a task reference doesn't match a pixi task
+    #[serde(default)]
+    pub strict: bool,
+    /// Explicit pixi manifest, bypassing discovery from the working directory
+    pub manifest_path: Option<String>,
+    /// Git repository `build` uses as the build context instead of the local project, e.g.
+    /// `https://github.com/org/repo.git#main:app`
+    pub git_context: Option<String>,
+    /// How `pixi install` and `pixi run` treat the lockfile inside the image
+    #[serde(default)]
+    pub install_mode: InstallMode,
+    /// Shell commands run on the host before building
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    /// Shell commands run on the host after a successful build
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    /// Shell started by `pixi-docker shell` (default: `/bin/bash`)
+    pub shell: Option<String>,
+    /// Pass the Dockerfile to `docker build` on stdin instead of writing `Dockerfile.<env>`
+    #[serde(default)]
+    pub ephemeral_dockerfile: bool,
+    /// Stamp built images with pixi-docker, config, git and timestamp labels
+    #[serde(default = "default_true")]
+    pub metadata_labels: bool,
+    /// Default `docker run` flags for `pixi-docker run`
+    #[serde(default)]
+    pub run: RunConfig,
+    /// `--build-arg` values passed to every build
+    #[serde(default)]
+    pub build_args: BTreeMap<String, String>,
+    /// Cache sources for builds and bake targets, e.g. `type=registry,ref=...`; `{name}` and
+    /// `{environment}` are expanded
+    #[serde(default)]
+    pub cache_from: Vec<String>,
+    /// Cache export for builds and bake targets, expanded like `cache_from`
+    pub cache_to: Option<String>,
+    /// Host credentials file (rattler/pixi `credentials.json`) mounted for `pixi install`
+    pub auth_file: Option<String>,
+    /// BuildKit secrets passed to `build` and bake targets, e.g. `id=token,env=TOKEN`
+    #[serde(default)]
+    pub secrets: Vec<String>,
+    /// Forward the host's SSH agent to `pixi install`, e.g. for git dependencies over SSH
+    #[serde(default)]
+    pub ssh_forward: bool,
+    /// Pass the host's proxy variables to the build stage; unset forwards them when set
+    pub forward_proxy: Option<bool>,
+    /// How the environment is carried into the final stage of a multi-stage build
+    #[serde(default)]
+    pub packaging: Packaging,
+    /// Init process run as PID 1: `true` adds tini to the image, `"docker"` has `run` pass
+    /// `--init`
+    #[serde(default)]
+    pub init: InitMode,
+    /// Have `run` bind-mount the project sources over the image's copies, as with `run --dev`
+    #[serde(default)]
+    pub dev_mode: bool,
+    /// What dev mode mounts: the whole project at `/app`, or only the `copy_files` paths
+    #[serde(default)]
+    pub dev_mount: DevMount,
+    /// Command run in dev mode instead of the image's, e.g. a reloading server task
+    pub dev_command: Option<String>,
+    /// Have `run` use the host user's uid and gid on Linux; defaults to following dev mode
+    pub match_host_user: Option<bool>,
+    /// How environment lists such as `copy_files` combine with the `[docker]` ones
+    #[serde(default)]
+    pub list_merge: ListMerge,
+}
+
+/// Lockfile handling of the pixi commands in the generated Dockerfile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    /// Require an up-to-date pixi.lock (`--locked`)
+    #[default]
+    Locked,
+    /// Install pixi.lock as-is without checking it against the manifest (`--frozen`)
+    Frozen,
+    /// Resolve the environment during the build
+    None,
+}
+
+impl InstallMode {
+    /// The flag passed to `pixi install` and `pixi run`
+    pub fn flag(self) -> Option<&'static str> {
+        match self {
+            InstallMode::Locked => Some("--locked"),
+            InstallMode::Frozen => Some("--frozen"),
+            InstallMode::None => None,
+        }
+    }
+}
+
+/// How containers get an init process as PID 1, which reaps zombies and forwards signals
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitMode {
+    /// The container command runs as PID 1
+    #[default]
+    None,
+    /// tini is added to the image and wraps the entrypoint, for runtimes that don't pass
+    /// `--init`, such as Kubernetes
+    Tini,
+    /// `pixi-docker run` passes `--init`, so docker runs its own init
+    Docker,
+}
+
+impl<'de> Deserialize<'de> for InitMode {
+    /// `true` or `"tini"`, `"docker"`, and `false` or `"none"`
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bool(bool),
+            Name(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bool(true) => Ok(InitMode::Tini),
+            Raw::Bool(false) => Ok(InitMode::None),
+            // Environment variable overrides arrive as strings
+            Raw::Name(name) => match name.as_str() {
+                "tini" | "true" => Ok(InitMode::Tini),
+                "docker" => Ok(InitMode::Docker),
+                "none" | "false" => Ok(InitMode::None),
+                other => Err(serde::de::Error::custom(format!(
+                    "invalid init '{}': expected true, false, \"tini\" or \"docker\"",
+                    other
+                ))),
+            },
+        }
+    }
+}
+
+/// How the final stage of a multi-stage build receives the environment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Packaging {
+    /// Copy `.pixi/envs/<env>` and activate it with `pixi shell-hook`
+    #[default]
+    Copy,
+    /// Pack the environment with pixi-pack and unpack only the prefix
+    PixiPack,
+}
+
+impl Packaging {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Packaging::Copy => "copy",
+            Packaging::PixiPack => "pixi-pack",
+        }
+    }
+}
+
+/// The host paths `run --dev` mounts into the container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DevMount {
+    /// The project directory over `/app`, with the image's environment kept in volumes
+    #[default]
+    Project,
+    /// Each `copy_files` path over its copy in `/app`
+    CopyFiles,
+}
+
+/// How an environment's list fields combine with the `[docker]` lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListMerge {
+    /// A non-empty environment list replaces the `[docker]` one
+    #[default]
+    Replace,
+    /// Environment entries are added after the `[docker]` ones, skipping duplicates
+    Append,
+}
+
+/// The container command: a shell command or pixi task, or an exec-form argv
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Entrypoint {
+    /// Run through `/bin/bash -c`, with pixi task names translated
+    Shell(String),
+    /// Run as-is, so the first element becomes the container's main process
+    Exec(Vec<String>),
+}
+
+impl Entrypoint {
+    /// The shell command, unless this is the exec form
+    pub fn as_shell(&self) -> Option<&str> {
+        match self {
+            Entrypoint::Shell(command) => Some(command),
+            Entrypoint::Exec(_) => None,
+        }
+    }
+}
+
+/// One build task or command, or several run in sequence
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BuildCommand {
+    Single(String),
+    List(Vec<String>),
+}
+
+impl BuildCommand {
+    pub fn commands(&self) -> &[String] {
+        match self {
+            BuildCommand::Single(command) => std::slice::from_ref(command),
+            BuildCommand::List(commands) => commands,
+        }
+    }
+}
+
+/// The base image of the final stage: one for every platform, or one per architecture
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BaseImage {
+    Single(String),
+    /// Keyed by docker's architecture names, e.g. `{ amd64 = "...", arm64 = "..." }`
+    PerArch(BTreeMap<String, String>),
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct EnvironmentConfig {
+    pub pixi_environment: Option<String>,
     #[serde(default)]
     pub ports: Vec<u16>,
-    pub entrypoint: Option<String>,
+    pub entrypoint: Option<Entrypoint>,
     #[serde(default)]
     pub copy_files: Vec<String>,
-    pub build_command: Option<String>,
+    #[serde(default)]
+    pub runtime_copy: Vec<String>,
+    pub exec_entrypoint: Option<bool>,
+    pub stop_signal: Option<String>,
+    pub stop_grace_period: Option<String>,
+    pub build_command: Option<BuildCommand>,
+    pub pixi_version: Option<String>,
     pub multi_stage: Option<bool>,
-    pub base_image: Option<String>,
+    pub base_image: Option<BaseImage>,
+    pub platform: Option<String>,
+    pub output: Option<String>,
+    /// The generated Dockerfile of this environment, replacing `output_dir/Dockerfile.<name>`
+    pub output_file: Option<String>,
+    /// The init process of this environment's containers, replacing `[docker].init`
+    pub init: Option<InitMode>,
+    pub dev_mode: Option<bool>,
+    pub dev_mount: Option<DevMount>,
+    pub dev_command: Option<String>,
+    pub match_host_user: Option<bool>,
+    pub template_path: Option<String>,
+    pub test_command: Option<String>,
+    pub test_in_build: Option<bool>,
+    pub registry: Option<String>,
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    #[serde(default)]
+    pub run: RunConfig,
+    #[serde(default)]
+    pub build_args: BTreeMap<String, String>,
+}
+
+/// Default flags for `pixi-docker run`, from `[docker.run]` or `[environments.<name>.run]`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RunConfig {
+    /// Remove the container when it exits (`--rm`)
+    pub rm: Option<bool>,
+    /// Container name; `{name}` and `{environment}` are expanded
+    pub name: Option<String>,
+    pub network: Option<String>,
+    /// Restart policy, only applied to detached containers
+    pub restart: Option<String>,
+    /// Run in the background (`-d`) instead of attaching a terminal
+    pub detach: Option<bool>,
+    /// Memory limit (`--memory`), e.g. `512m` or `2g`
+    pub memory: Option<String>,
+    /// CPU limit (`--cpus`), e.g. `2` or `1.5`
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub cpus: Option<String>,
+    /// Size of `/dev/shm` (`--shm-size`), e.g. `1g`
+    pub shm_size: Option<String>,
+    /// Absolute working directory (`-w`); `run_workdir` in the section before schema 2
+    pub workdir: Option<String>,
+}
+
+impl RunConfig {
+    /// The `--memory`, `--cpus` and `--shm-size` flags with their values, checked loosely
+    /// so docker's unit suffixes pass but typos fail before docker runs
+    pub fn resource_limits(&self) -> Result<Vec<(&'static str, &str)>> {
+        let mut limits = Vec::new();
+        for (key, flag, value) in [
+            ("memory", "--memory", &self.memory),
+            ("shm_size", "--shm-size", &self.shm_size),
+        ] {
+            if let Some(value) = value {
+                if byte_size(value).is_none() {
+                    bail!(
+                        "Invalid run {} '{}': expected a number of bytes with an optional unit, e.g. 512m or 2g",
+                        key,
+                        value
+                    );
+                }
+                limits.push((flag, value.as_str()));
+            }
+        }
+        if let Some(cpus) = &self.cpus {
+            if !cpus.parse::<f64>().is_ok_and(|n| n > 0.0) {
+                bail!(
+                    "Invalid run cpus '{}': expected a positive number, e.g. 2 or 1.5",
+                    cpus
+                );
+            }
+            limits.push(("--cpus", cpus.as_str()));
+        }
+        Ok(limits)
+    }
+}
+
+/// Parse a docker byte size like `512m` or `1.5g` into bytes, with binary units as docker uses
+pub fn byte_size(value: &str) -> Option<u64> {
+    let lower = value.to_ascii_lowercase();
+    let number = lower.strip_suffix('b').unwrap_or(&lower);
+    let (number, exponent) = match number.strip_suffix(['k', 'm', 'g', 't', 'p']) {
+        Some(stripped) => {
+            let unit = number.chars().last().unwrap_or_default();
+            (stripped, "kmgtp".find(unit).map_or(0, |i| i as i32 + 1))
+        }
+        None => (number, 0),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| *n > 0.0)
+        .map(|n| (n * 1024f64.powi(exponent)) as u64)
+}
+
+/// Whether `name` is an executable file in one of the `PATH` directories
+fn executable_on_path(name: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(name);
+        candidate.is_file() || candidate.with_extension("exe").is_file()
+    })
+}
+
+/// Accept `cpus = 2` as well as `cpus = "2"`
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match Option::<toml::Value>::deserialize(deserializer)? {
+        None => None,
+        Some(toml::Value::String(value)) => Some(value),
+        Some(toml::Value::Integer(value)) => Some(value.to_string()),
+        Some(toml::Value::Float(value)) => Some(value.to_string()),
+        Some(other) => {
+            return Err(serde::de::Error::custom(format!(
+                "expected a number or string, found {}",
+                other.type_str()
+            )))
+        }
+    })
 }
 
 fn default_multi_stage() -> bool {
     true
 }
 
+fn default_true() -> bool {
+    true
+}
+
+/// How an environment variable value is parsed into a config field
+#[derive(Debug, Clone, Copy)]
+enum FieldKind {
+    String,
+    Bool,
+    Ports,
+    List,
+}
+
+const DOCKER_FIELDS: &[(&str, FieldKind)] = &[
+    ("environment", FieldKind::String),
+    ("pixi_environment", FieldKind::String),
+    ("ports", FieldKind::Ports),
+    ("entrypoint", FieldKind::String),
+    ("copy_files", FieldKind::List),
+    ("runtime_copy", FieldKind::List),
+    ("exec_entrypoint", FieldKind::Bool),
+    ("stop_signal", FieldKind::String),
+    ("stop_grace_period", FieldKind::String),
+    ("image_name", FieldKind::String),
+    ("registry", FieldKind::String),
+    ("namespace", FieldKind::String),
+    ("image_tag", FieldKind::String),
+    ("extra_tags", FieldKind::List),
+    ("pixi_version", FieldKind::String),
+    ("build_command", FieldKind::String),
+    ("single_layer", FieldKind::Bool),
+    ("multi_stage", FieldKind::Bool),
+    ("clean_cache", FieldKind::Bool),
+    ("base_image", FieldKind::String),
+    ("pin_digests", FieldKind::Bool),
+    ("platform", FieldKind::String),
+    ("output", FieldKind::String),
+    ("template_path", FieldKind::String),
+    ("template_dir", FieldKind::String),
+    ("output_dir", FieldKind::String),
+    ("flavor", FieldKind::String),
+    ("test_command", FieldKind::String),
+    ("test_in_build", FieldKind::Bool),
+    ("manifest_archs", FieldKind::List),
+    ("manifest_tag_pattern", FieldKind::String),
+    ("runtime", FieldKind::String),
+    ("context", FieldKind::String),
+    ("sbom", FieldKind::Bool),
+    ("provenance", FieldKind::String),
+    ("oci_labels", FieldKind::Bool),
+    ("reproducible", FieldKind::Bool),
+    ("header", FieldKind::Bool),
+    ("lint", FieldKind::Bool),
+    ("infer_entrypoint", FieldKind::Bool),
+    ("strict", FieldKind::Bool),
+    ("manifest_path", FieldKind::String),
+    ("git_context", FieldKind::String),
+    ("install_mode", FieldKind::String),
+    ("pre_build", FieldKind::List),
+    ("post_build", FieldKind::List),
+    ("run.workdir", FieldKind::String),
+    ("shell", FieldKind::String),
+    ("ephemeral_dockerfile", FieldKind::Bool),
+    ("metadata_labels", FieldKind::Bool),
+    ("cache_from", FieldKind::List),
+    ("cache_to", FieldKind::String),
+    ("auth_file", FieldKind::String),
+    ("secrets", FieldKind::List),
+    ("ssh_forward", FieldKind::Bool),
+    ("forward_proxy", FieldKind::Bool),
+    ("packaging", FieldKind::String),
+    ("init", FieldKind::String),
+    ("dev_mode", FieldKind::Bool),
+    ("dev_mount", FieldKind::String),
+    ("dev_command", FieldKind::String),
+    ("match_host_user", FieldKind::Bool),
+    ("list_merge", FieldKind::String),
+];
+
+const ENVIRONMENT_FIELDS: &[(&str, FieldKind)] = &[
+    ("pixi_environment", FieldKind::String),
+    ("ports", FieldKind::Ports),
+    ("entrypoint", FieldKind::String),
+    ("copy_files", FieldKind::List),
+    ("runtime_copy", FieldKind::List),
+    ("exec_entrypoint", FieldKind::Bool),
+    ("stop_signal", FieldKind::String),
+    ("stop_grace_period", FieldKind::String),
+    ("build_command", FieldKind::String),
+    ("pixi_version", FieldKind::String),
+    ("multi_stage", FieldKind::Bool),
+    ("base_image", FieldKind::String),
+    ("platform", FieldKind::String),
+    ("output", FieldKind::String),
+    ("output_file", FieldKind::String),
+    ("init", FieldKind::String),
+    ("dev_mode", FieldKind::Bool),
+    ("dev_mount", FieldKind::String),
+    ("dev_command", FieldKind::String),
+    ("match_host_user", FieldKind::Bool),
+    ("template_path", FieldKind::String),
+    ("test_command", FieldKind::String),
+    ("test_in_build", FieldKind::Bool),
+    ("registry", FieldKind::String),
+    ("namespace", FieldKind::String),
+    ("pre_build", FieldKind::List),
+    ("post_build", FieldKind::List),
+    ("run.workdir", FieldKind::String),
+];
+
 impl Config {
-    pub fn from_file(path: &PathBuf) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+    pub fn from_file(path: &Path) -> Result<Self, PixiDockerError> {
+        Self::from_file_with_env(path, std::env::vars())
+    }
+
+    /// Load a config file and apply `PIXI_DOCKER_*` overrides from the given variables
+    pub fn from_file_with_env(
+        path: &Path,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, PixiDockerError> {
+        let vars: BTreeMap<String, String> = vars.into_iter().collect();
+        let mut sources = BTreeMap::new();
+        let mut raw_values = BTreeMap::new();
+        let mut table = load_table(path, &mut Vec::new(), &mut sources)?;
+        interpolate_table(&mut table, "", &vars, &sources, &mut raw_values)?;
+        resolve_relative_paths(&mut table, &sources);
+        apply_env_overrides(&mut table, vars, &mut sources)?;
+        merge_lists(&mut table, &mut sources)?;
+        // Values replaced by overrides no longer come from what was written
+        raw_values.retain(|key, _| matches!(sources.get(key), Some(ValueSource::File(_))));
+
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err| PixiDockerError::ConfigParse(crate::parse::schema_error(path, err)))?;
+        config.sources = sources;
+        config.raw_values = raw_values;
+        config.path = path.to_path_buf();
+        config.validate_ports()?;
         Ok(config)
     }
+
+    /// Reject port 0 and drop duplicate ports with a warning, per config section
+    fn validate_ports(&mut self) -> Result<()> {
+        let sections = std::iter::once(("docker".to_string(), &mut self.docker.ports)).chain(
+            self.environments
+                .iter_mut()
+                .map(|(name, env)| (format!("environments.{}", name), &mut env.ports)),
+        );
+        for (section, ports) in sections {
+            let origin = match self.sources.get(&format!("{}.ports", section)) {
+                Some(source) => format!("[{}] ports (from {})", section, source),
+                None => format!("[{}] ports", section),
+            };
+            if ports.contains(&0) {
+                bail!(
+                    "Invalid port 0 in {}: ports must be between 1 and 65535",
+                    origin
+                );
+            }
+            let mut seen = Vec::with_capacity(ports.len());
+            for port in ports.iter() {
+                if seen.contains(port) {
+                    eprintln!(
+                        "Warning: duplicate port {} in {}, exposing it once",
+                        port, origin
+                    );
+                } else {
+                    seen.push(*port);
+                }
+            }
+            *ports = seen;
+        }
+        Ok(())
+    }
+
+    /// All known environments: the default one plus every `[environments.*]` section, sorted
+    pub fn environment_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.environments.keys().cloned().collect();
+        names.push(self.docker.environment.clone());
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The container runtime executable
+    ///
+    /// With the Engine API backend this is still `docker`, for the commands the API doesn't cover.
+    pub fn runtime(&self) -> &str {
+        match self.docker.runtime.as_deref() {
+            Some(API_RUNTIME) | None => "docker",
+            Some(runtime) => runtime,
+        }
+    }
+
+    /// Whether builds and runs talk to the Docker Engine API instead of running the CLI
+    ///
+    /// Set with `runtime = "api"`, or detected when no runtime is configured, no `docker`
+    /// executable is on `PATH` and the docker socket exists (only with the `bollard` feature).
+    pub fn engine_api(&self) -> bool {
+        match self.docker.runtime.as_deref() {
+            Some(runtime) => runtime == API_RUNTIME,
+            None => {
+                cfg!(feature = "bollard")
+                    && !executable_on_path("docker")
+                    && Path::new(DOCKER_SOCKET).exists()
+            }
+        }
+    }
+
+    /// The pixi manifest: `manifest_path` if set, otherwise discovered from the working directory
+    pub fn manifest_path(&self) -> Option<PathBuf> {
+        match &self.docker.manifest_path {
+            Some(path) => Some(PathBuf::from(path)),
+            None => std::env::current_dir()
+                .ok()
+                .and_then(|cwd| discover_manifest(&cwd)),
+        }
+    }
+
+    /// The directory containing the pixi manifest, used as the docker build context
+    pub fn project_dir(&self) -> PathBuf {
+        self.manifest_path()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default()
+    }
+
+    /// The config file as shown to users: relative to the enclosing git repository, or just
+    /// its name outside one
+    pub fn display_path(&self) -> String {
+        let path = match self.path.as_os_str().is_empty() {
+            true => Path::new(CONFIG_FILE),
+            false => self.path.as_path(),
+        };
+        let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let relative = absolute
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.join(".git").exists())
+            .and_then(|root| absolute.strip_prefix(root).ok());
+        match relative {
+            Some(relative) => relative.display().to_string(),
+            None => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| CONFIG_FILE.to_string()),
+        }
+    }
+
+    /// The directory of the config file, which relative paths in it were resolved against
+    pub fn base_dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    /// The `pixi_docker.lock` file next to the config file
+    pub fn lock_path(&self) -> PathBuf {
+        self.base_dir().join(crate::lock::LOCK_FILE)
+    }
+
+    /// Fail early when the install mode needs a pixi.lock that the project doesn't have
+    pub fn check_lockfile(&self) -> Result<()> {
+        if self.docker.install_mode != InstallMode::Locked || self.manifest_path().is_none() {
+            return Ok(());
+        }
+
+        let lockfile = self.project_dir().join("pixi.lock");
+        if !lockfile.exists() {
+            bail!(
+                "{} not found, but the Dockerfile installs with --locked; run `pixi lock`, \
+                 set `install_mode = \"frozen\"` or `\"none\"`, or pass --no-lock-check",
+                lockfile.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Fail when the pixi environment isn't solved for the Linux platform the image targets,
+    /// which would otherwise surface as a solve error deep inside `pixi install`
+    pub fn check_platform(&self, environment: &str) -> Result<()> {
+        if self
+            .platform(environment)
+            .is_some_and(|p| !p.starts_with("linux/"))
+        {
+            return Ok(());
+        }
+        let Some(manifest) = self.manifest_path() else {
+            return Ok(());
+        };
+        let Some(pixi_toml) = PixiToml::load(&manifest) else {
+            return Ok(());
+        };
+        let platforms = pixi_toml.environment_platforms(Some(self.pixi_environment(environment)));
+        let required = conda_platform(&self.target_arch(environment));
+        if platforms.is_empty() || platforms.contains(&required.as_str()) {
+            return Ok(());
+        }
+        bail!(
+            "{} doesn't list the platform {} for environment '{}' (platforms: {}); add it with \
+             `pixi workspace platform add {}` and re-lock with `pixi lock`, or pass \
+             --skip-platform-check",
+            manifest.display(),
+            required,
+            environment,
+            platforms.join(", "),
+            required
+        );
+    }
+
+    /// Record that a value was set by a command line flag
+    pub fn record_cli_source(&mut self, key: &str, flag: &str) {
+        self.sources
+            .insert(key.to_string(), ValueSource::Cli(flag.to_string()));
+        self.raw_values.remove(key);
+    }
+
+    /// The configured entrypoint of an environment, falling back to the `[docker]` value
+    pub fn entrypoint(&self, environment: &str) -> Option<&Entrypoint> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.entrypoint.as_ref())
+            .or(self.docker.entrypoint.as_ref())
+    }
+
+    /// The build tasks of an environment, falling back to the `[docker]` value
+    pub fn build_commands(&self, environment: &str) -> &[String] {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.build_command.as_ref())
+            .or(self.docker.build_command.as_ref())
+            .map(BuildCommand::commands)
+            .unwrap_or_default()
+    }
+
+    /// The pixi environment behind a docker environment: the configured one, or the same name
+    pub fn pixi_environment<'a>(&'a self, environment: &'a str) -> &'a str {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.pixi_environment.as_deref())
+            .or(self.docker.pixi_environment.as_deref())
+            .unwrap_or(environment)
+    }
+
+    /// The files copied into an environment's final stage: its own `copy_files` if it sets
+    /// any, else the `[docker]` ones
+    pub fn copy_files(&self, environment: &str) -> &[String] {
+        match self.environments.get(environment) {
+            Some(env) if !env.copy_files.is_empty() => &env.copy_files,
+            _ => &self.docker.copy_files,
+        }
+    }
+
+    /// The explicit template of an environment, falling back to the `[docker]` value
+    pub fn template_path(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.template_path.as_deref())
+            .or(self.docker.template_path.as_deref())
+    }
+
+    /// The test task for an environment, falling back to the `[docker]` value
+    pub fn test_command(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.test_command.as_deref())
+            .or(self.docker.test_command.as_deref())
+    }
+
+    /// The tests of an environment: its test command, or else the manifest's `test` task
+    pub fn test_task(&self, environment: &str, pixi_toml: Option<&PixiToml>) -> Option<String> {
+        self.test_command(environment)
+            .map(str::to_string)
+            .or_else(|| {
+                pixi_toml
+                    .filter(|p| {
+                        p.task("test", Some(self.pixi_environment(environment)))
+                            .is_some()
+                    })
+                    .map(|_| "test".to_string())
+            })
+    }
+
+    /// Whether an environment's build runs its tests, falling back to the `[docker]` value
+    pub fn test_in_build(&self, environment: &str) -> bool {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.test_in_build)
+            .unwrap_or(self.docker.test_in_build)
+    }
+
+    /// The pixi image version of an environment, falling back to the `[docker]` value
+    pub fn pixi_version(&self, environment: &str) -> &str {
+        self.configured_pixi_version(environment)
+            .unwrap_or(DEFAULT_PIXI_VERSION)
+    }
+
+    /// The pixi version set for an environment or in `[docker]`, if any
+    pub fn configured_pixi_version(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.pixi_version.as_deref())
+            .or(self.docker.pixi_version.as_deref())
+    }
+
+    /// The target platform of an environment, falling back to the `[docker]` value
+    pub fn platform(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.platform.as_deref())
+            .or(self.docker.platform.as_deref())
+    }
+
+    /// The buildx output of an environment, falling back to the `[docker]` value
+    pub fn output(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.output.as_deref())
+            .or(self.docker.output.as_deref())
+    }
+
+    /// The architecture an environment is built for, in docker's naming, e.g. `arm64`
+    ///
+    /// Taken from the platform, e.g. `linux/arm64/v8`, or the host when none is set.
+    pub fn target_arch(&self, environment: &str) -> String {
+        match self.platform(environment) {
+            Some(platform) => platform.split('/').nth(1).unwrap_or(platform).to_string(),
+            None => match std::env::consts::ARCH {
+                "x86_64" => "amd64",
+                "aarch64" => "arm64",
+                "x86" => "386",
+                "powerpc64" => "ppc64le",
+                arch => arch,
+            }
+            .to_string(),
+        }
+    }
+
+    /// The configured base image of an environment, falling back to the `[docker]` value
+    pub fn configured_base_image(&self, environment: &str) -> Option<&BaseImage> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.base_image.as_ref())
+            .or(self.docker.base_image.as_ref())
+    }
+
+    /// The base image an environment is generated with, picked for its target architecture
+    /// when `base_image` is a table
+    pub fn base_image(&self, environment: &str) -> Result<Option<&str>> {
+        let images = match self.configured_base_image(environment) {
+            None => return Ok(None),
+            Some(BaseImage::Single(image)) => return Ok(Some(image)),
+            Some(BaseImage::PerArch(images)) => images,
+        };
+        let arch = self.target_arch(environment);
+        match images.get(&arch) {
+            Some(image) => Ok(Some(image)),
+            None => bail!(
+                "base_image of environment '{}' has no image for {} (configured: {}); add \
+                 `{} = \"...\"` to it or pick another architecture with --platform",
+                environment,
+                arch,
+                images.keys().cloned().collect::<Vec<_>>().join(", "),
+                arch
+            ),
+        }
+    }
+
+    /// The image registry of an environment, falling back to the `[docker]` value
+    pub fn registry(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.registry.as_deref())
+            .or(self.docker.registry.as_deref())
+    }
+
+    /// The image namespace of an environment, falling back to the `[docker]` value
+    pub fn namespace(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.namespace.as_deref())
+            .or(self.docker.namespace.as_deref())
+    }
+
+    /// The `runtime_copy` paths of an environment, falling back to the `[docker]` ones
+    pub fn runtime_copy(&self, environment: &str) -> &[String] {
+        match self.environments.get(environment) {
+            Some(env) if !env.runtime_copy.is_empty() => &env.runtime_copy,
+            _ => &self.docker.runtime_copy,
+        }
+    }
+
+    /// Whether the shell entrypoint of an environment is prefixed with `exec`
+    pub fn exec_entrypoint(&self, environment: &str) -> bool {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.exec_entrypoint)
+            .unwrap_or(self.docker.exec_entrypoint)
+    }
+
+    /// Whether `run` mounts the project sources in an environment, falling back to `[docker]`
+    pub fn dev_mode(&self, environment: &str) -> bool {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.dev_mode)
+            .unwrap_or(self.docker.dev_mode)
+    }
+
+    /// What dev mode mounts in an environment, falling back to the `[docker]` value
+    pub fn dev_mount(&self, environment: &str) -> DevMount {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.dev_mount)
+            .unwrap_or(self.docker.dev_mount)
+    }
+
+    /// The dev mode command of an environment, falling back to the `[docker]` value
+    pub fn dev_command(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.dev_command.as_deref())
+            .or(self.docker.dev_command.as_deref())
+    }
+
+    /// Whether `run` uses the host user's uid and gid in an environment, falling back to
+    /// `[docker]`; `None` follows dev mode
+    pub fn match_host_user(&self, environment: &str) -> Option<bool> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.match_host_user)
+            .or(self.docker.match_host_user)
+    }
+
+    /// Whether an environment builds in several stages, falling back to the `[docker]` value
+    pub fn multi_stage(&self, environment: &str) -> bool {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.multi_stage)
+            .unwrap_or(self.docker.multi_stage)
+    }
+
+    /// The init process of an environment's containers, falling back to the `[docker]` value
+    pub fn init(&self, environment: &str) -> InitMode {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.init)
+            .unwrap_or(self.docker.init)
+    }
+
+    /// The stop signal of an environment, falling back to the `[docker]` value
+    pub fn stop_signal(&self, environment: &str) -> Result<Option<&str>> {
+        let signal = self
+            .environments
+            .get(environment)
+            .and_then(|e| e.stop_signal.as_deref())
+            .or(self.docker.stop_signal.as_deref());
+        // Rendered verbatim into the Dockerfile, so only accept names and numbers
+        if let Some(signal) = signal
+            .filter(|s| s.is_empty() || !s.chars().all(|c| c.is_ascii_alphanumeric() || c == '+'))
+        {
+            bail!(
+                "Invalid stop_signal '{}' for environment '{}': expected a signal name or number, e.g. SIGTERM or 15",
+                signal,
+                environment
+            );
+        }
+        Ok(signal)
+    }
+
+    /// The stop grace period of an environment in whole seconds, for `docker run --stop-timeout`
+    pub fn stop_timeout(&self, environment: &str) -> Result<Option<u64>> {
+        let Some(period) = self
+            .environments
+            .get(environment)
+            .and_then(|e| e.stop_grace_period.as_deref())
+            .or(self.docker.stop_grace_period.as_deref())
+        else {
+            return Ok(None);
+        };
+        parse_duration(period).map(Some).ok_or_else(|| {
+            PixiDockerError::msg(format!(
+                "Invalid stop_grace_period '{}' for environment '{}': expected seconds or a duration like 30s, 2m or 1m30s",
+                period,
+                environment
+            ))
+        })
+    }
+
+    /// Build arguments of an environment, merged over the `[docker]` ones
+    pub fn build_args(&self, environment: &str) -> BTreeMap<String, String> {
+        let mut args = self.docker.build_args.clone();
+        if let Some(env) = self.environments.get(environment) {
+            args.extend(env.build_args.clone());
+        }
+        args
+    }
+
+    /// The `--secret` values of a build: the credentials file followed by the extra secrets
+    pub fn build_secrets(&self) -> Vec<String> {
+        let auth_file = self
+            .docker
+            .auth_file
+            .as_ref()
+            .map(|path| format!("id={},src={}", AUTH_SECRET_ID, path));
+        auth_file
+            .into_iter()
+            .chain(self.docker.secrets.iter().cloned())
+            .collect()
+    }
+
+    /// The `--ssh` values of a build: the default agent when `ssh_forward` is set
+    pub fn build_ssh(&self) -> Vec<String> {
+        match self.docker.ssh_forward {
+            true => vec!["default".to_string()],
+            false => Vec::new(),
+        }
+    }
+
+    /// The proxy variables declared as build stage ARGs, unless `forward_proxy = false`
+    pub fn proxy_args(&self) -> &'static [&'static str] {
+        match self.docker.forward_proxy {
+            Some(false) => &[],
+            _ => &PROXY_VARS,
+        }
+    }
+
+    /// The host proxy variables passed to a build as bare `--build-arg NAME`, so the values
+    /// come from the environment and never show up in printed commands
+    pub fn build_proxy(&self) -> Vec<String> {
+        if self.docker.forward_proxy == Some(false) {
+            return Vec::new();
+        }
+        PROXY_VARS
+            .iter()
+            .flat_map(|name| [name.to_string(), name.to_lowercase()])
+            .filter(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+            .collect()
+    }
+
+    /// Fail when `ssh_forward` is set but there is no SSH agent to forward
+    pub fn check_ssh_agent(&self) -> Result<()> {
+        let agent = std::env::var_os("SSH_AUTH_SOCK").filter(|socket| !socket.is_empty());
+        if self.docker.ssh_forward && agent.is_none() {
+            bail!(
+                "`ssh_forward` is set, but SSH_AUTH_SOCK is not, so there is no SSH agent to \
+                 forward to the build; start one with `eval $(ssh-agent)` and add your key with \
+                 `ssh-add`"
+            );
+        }
+        Ok(())
+    }
+
+    /// The `run` defaults of an environment, each field falling back to `[docker.run]`
+    pub fn run_defaults(&self, environment: &str) -> RunConfig {
+        let base = &self.docker.run;
+        let Some(env) = self.environments.get(environment).map(|e| &e.run) else {
+            return base.clone();
+        };
+        RunConfig {
+            rm: env.rm.or(base.rm),
+            name: env.name.clone().or_else(|| base.name.clone()),
+            network: env.network.clone().or_else(|| base.network.clone()),
+            restart: env.restart.clone().or_else(|| base.restart.clone()),
+            detach: env.detach.or(base.detach),
+            memory: env.memory.clone().or_else(|| base.memory.clone()),
+            cpus: env.cpus.clone().or_else(|| base.cpus.clone()),
+            shm_size: env.shm_size.clone().or_else(|| base.shm_size.clone()),
+            workdir: env.workdir.clone().or_else(|| base.workdir.clone()),
+        }
+    }
+
+    /// The container working directory for `run`, falling back to the `[docker]` value
+    pub fn run_workdir(&self, environment: &str) -> Option<&str> {
+        self.environments
+            .get(environment)
+            .and_then(|e| e.run.workdir.as_deref())
+            .or(self.docker.run.workdir.as_deref())
+    }
+
+    /// Hook commands run before building an environment, falling back to the `[docker]` value
+    pub fn pre_build(&self, environment: &str) -> &[String] {
+        self.environments
+            .get(environment)
+            .filter(|e| !e.pre_build.is_empty())
+            .map(|e| e.pre_build.as_slice())
+            .unwrap_or(&self.docker.pre_build)
+    }
+
+    /// Hook commands run after building an environment, falling back to the `[docker]` value
+    pub fn post_build(&self, environment: &str) -> &[String] {
+        self.environments
+            .get(environment)
+            .filter(|e| !e.post_build.is_empty())
+            .map(|e| e.post_build.as_slice())
+            .unwrap_or(&self.docker.post_build)
+    }
+
+    /// SHA-256 of the effective configuration, independent of key order and formatting
+    pub fn digest(&self) -> Result<String> {
+        let value = toml::Value::try_from(self).context("Failed to serialize the config")?;
+        Ok(format!("{:x}", Sha256::digest(value.to_string())))
+    }
+
+    /// Flatten the effective configuration into dotted keys with their sources
+    pub fn effective_values(&self) -> Result<Vec<(String, toml::Value, Option<&ValueSource>)>> {
+        let value = toml::Value::try_from(self).context("Failed to serialize the config")?;
+        let mut flat = Vec::new();
+        flatten_value(&value, "", &mut flat);
+        flat.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(flat
+            .into_iter()
+            .map(|(key, value)| {
+                let source = self.sources.get(&key);
+                (key, value, source)
+            })
+            .collect())
+    }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn record_sources(
+    table: &toml::Table,
+    prefix: &str,
+    source: &ValueSource,
+    sources: &mut BTreeMap<String, ValueSource>,
+) {
+    for (key, value) in table {
+        let key = join_key(prefix, key);
+        match value {
+            toml::Value::Table(inner) => record_sources(inner, &key, source, sources),
+            _ => {
+                sources.insert(key, source.clone());
+            }
+        }
+    }
+}
+
+/// How deeply `include`d config files may nest
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Load a config file with its `include`s merged underneath, recording where each value came from
+///
+/// `stack` holds the canonical paths of the files currently being loaded, to detect cycles.
+fn load_table(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    sources: &mut BTreeMap<String, ValueSource>,
+) -> Result<toml::Table> {
+    let read_error = |source| PixiDockerError::ConfigIo {
+        path: path.to_path_buf(),
+        source,
+    };
+    let canonical = std::fs::canonicalize(path).map_err(read_error)?;
+    if stack.contains(&canonical) {
+        let cycle: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        bail!("Config include cycle: {}", cycle.join(" -> "));
+    }
+    if stack.len() >= MAX_INCLUDE_DEPTH {
+        bail!(
+            "Config includes nested more than {} levels deep at {}",
+            MAX_INCLUDE_DEPTH,
+            path.display()
+        );
+    }
+
+    let content = std::fs::read_to_string(path).map_err(read_error)?;
+    let mut table: toml::Table =
+        crate::parse::toml(path, &content).map_err(PixiDockerError::ConfigParse)?;
+
+    // Older files are read through the same rewrite `pixi-docker migrate` would apply
+    let version = migrate::schema_version(path, &table)?;
+    if version < migrate::SCHEMA_VERSION {
+        let migrated = migrate::migrate(&content, version)?;
+        table = crate::parse::toml(path, &migrated).map_err(PixiDockerError::ConfigParse)?;
+    }
+    table.remove("schema_version");
+
+    let includes = match table.remove("include") {
+        None => Vec::new(),
+        Some(toml::Value::Array(items)) => items
+            .into_iter()
+            .map(|item| match item {
+                toml::Value::String(include) => Ok(include),
+                other => bail!(
+                    "Invalid include in {}: expected a path, got {}",
+                    path.display(),
+                    other
+                ),
+            })
+            .collect::<Result<_>>()?,
+        Some(other) => bail!(
+            "Invalid include in {}: expected an array of paths, got {}",
+            path.display(),
+            other
+        ),
+    };
+
+    // Included files are relative to the including file and merged in order beneath it
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut merged = toml::Table::new();
+    stack.push(canonical);
+    for include in includes {
+        let included = load_table(&dir.join(include), stack, sources)?;
+        merge_tables(&mut merged, included);
+    }
+    stack.pop();
+
+    record_sources(&table, "", &ValueSource::File(path.to_path_buf()), sources);
+    merge_tables(&mut merged, table);
+    Ok(merged)
+}
+
+/// Expand `${VAR}` references in the values of a raw table, keeping the values they changed
+fn interpolate_table(
+    table: &mut toml::Table,
+    prefix: &str,
+    vars: &BTreeMap<String, String>,
+    sources: &BTreeMap<String, ValueSource>,
+    raw_values: &mut BTreeMap<String, toml::Value>,
+) -> Result<()> {
+    for (key, value) in table.iter_mut() {
+        let key = join_key(prefix, key);
+        if let toml::Value::Table(inner) = value {
+            interpolate_table(inner, &key, vars, sources, raw_values)?;
+            continue;
+        }
+        let raw = value.clone();
+        interpolate_value(value, &|name| vars.get(name).cloned()).map_err(|err| {
+            let origin = match sources.get(&key) {
+                Some(source) => format!("`{}` (from {})", key, source),
+                None => format!("`{}`", key),
+            };
+            match err {
+                interpolate::Error::Undefined(name) => PixiDockerError::msg(format!(
+                    "Environment variable {} used in {} is not set; set it or give a default \
+                     with ${{{}:-...}}",
+                    name, origin, name
+                )),
+                interpolate::Error::Invalid(reference) => PixiDockerError::msg(format!(
+                    "Invalid variable reference {} in {}; write ${{NAME}} or ${{NAME:-default}}, \
+                     or $${{ for a literal ${{",
+                    reference, origin
+                )),
+            }
+        })?;
+        if *value != raw {
+            raw_values.insert(key, raw);
+        }
+    }
+    Ok(())
+}
+
+fn interpolate_value(
+    value: &mut toml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), interpolate::Error> {
+    match value {
+        toml::Value::String(string) => *string = interpolate::expand(string, lookup)?,
+        toml::Value::Array(items) => {
+            for item in items {
+                interpolate_value(item, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                interpolate_value(item, lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Make relative paths set in config files relative to the directory of that file
+fn resolve_relative_paths(table: &mut toml::Table, sources: &BTreeMap<String, ValueSource>) {
+    if let Some(toml::Value::Table(docker)) = table.get_mut("docker") {
+        resolve_section_paths(docker, "docker", sources);
+    }
+    if let Some(toml::Value::Table(environments)) = table.get_mut("environments") {
+        for (name, environment) in environments.iter_mut() {
+            if let toml::Value::Table(environment) = environment {
+                resolve_section_paths(environment, &format!("environments.{}", name), sources);
+            }
+        }
+    }
+}
+
+fn resolve_section_paths(
+    section: &mut toml::Table,
+    prefix: &str,
+    sources: &BTreeMap<String, ValueSource>,
+) {
+    for field in PATH_FIELDS {
+        let Some(toml::Value::String(value)) = section.get_mut(*field) else {
+            continue;
+        };
+        let Some(ValueSource::File(file)) = sources.get(&format!("{}.{}", prefix, field)) else {
+            continue;
+        };
+        let dir = file.parent().unwrap_or(Path::new(""));
+        if Path::new(value.as_str()).is_relative() {
+            *value = dir.join(value.as_str()).display().to_string();
+        }
+    }
+    for field in SPEC_PATH_FIELDS {
+        let Some(toml::Value::Array(specs)) = section.get_mut(*field) else {
+            continue;
+        };
+        let Some(ValueSource::File(file)) = sources.get(&format!("{}.{}", prefix, field)) else {
+            continue;
+        };
+        let dir = file.parent().unwrap_or(Path::new(""));
+        for spec in specs {
+            if let toml::Value::String(spec) = spec {
+                *spec = resolve_spec_source(spec, dir);
+            }
+        }
+    }
+}
+
+/// Resolve the relative `src` (or `source`) host path of a spec like `id=token,src=token.txt`
+fn resolve_spec_source(spec: &str, dir: &Path) -> String {
+    spec.split(',')
+        .map(|pair| match pair.split_once('=') {
+            Some((key @ ("src" | "source"), path)) if Path::new(path).is_relative() => {
+                format!("{}={}", key, dir.join(path).display())
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Deep-merge `overlay` into `base`: tables are merged key by key, other values replaced
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_inner)), toml::Value::Table(inner)) => {
+                merge_tables(base_inner, inner)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn flatten_value(value: &toml::Value, prefix: &str, out: &mut Vec<(String, toml::Value)>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, inner) in table {
+                flatten_value(inner, &join_key(prefix, key), out);
+            }
+        }
+        other => out.push((prefix.to_string(), other.clone())),
+    }
+}
+
+/// Normalize a name for matching against an environment variable segment
+fn env_segment(name: &str) -> String {
+    name.to_uppercase().replace(['-', '.'], "_")
+}
+
+/// Set a field of a raw section, creating the tables of a dotted field like `run.workdir`
+fn insert_field(
+    section: &mut toml::Table,
+    prefix: &str,
+    field: &str,
+    value: toml::Value,
+) -> Result<()> {
+    match field.split_once('.') {
+        None => {
+            section.insert(field.to_string(), value);
+        }
+        Some((table, rest)) => {
+            let inner = section
+                .entry(table)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .with_context(|| format!("`{}.{}` must be a table", prefix, table))?;
+            insert_field(inner, &format!("{}.{}", prefix, table), rest, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Combine the environment lists with the `[docker]` ones on the raw table, so every command
+/// sees the same merged lists
+///
+/// A list follows `docker.list_merge`; `{ append = [...] }` or `{ replace = [...] }` picks the
+/// strategy for one field.
+fn merge_lists(table: &mut toml::Table, sources: &mut BTreeMap<String, ValueSource>) -> Result<()> {
+    let docker = table
+        .get("docker")
+        .and_then(toml::Value::as_table)
+        .cloned()
+        .unwrap_or_default();
+    let default = match docker.get("list_merge") {
+        Some(value) => value.clone().try_into().map_err(|_| {
+            PixiDockerError::msg(format!(
+                "`docker.list_merge` must be \"replace\" or \"append\", got {}",
+                value
+            ))
+        })?,
+        None => ListMerge::Replace,
+    };
+    let Some(toml::Value::Table(environments)) = table.get_mut("environments") else {
+        return Ok(());
+    };
+
+    for (name, environment) in environments.iter_mut() {
+        let toml::Value::Table(environment) = environment else {
+            continue;
+        };
+        for field in MERGED_LIST_FIELDS {
+            let key = format!("environments.{}.{}", name, field);
+            let (merge, entries) = match environment.get(*field) {
+                Some(toml::Value::Array(entries)) => (default, entries.clone()),
+                Some(toml::Value::Table(strategy)) => {
+                    let (merge, entries) = match strategy.iter().collect::<Vec<_>>()[..] {
+                        [(merge, toml::Value::Array(entries))] if merge == "append" => {
+                            (ListMerge::Append, entries.clone())
+                        }
+                        [(merge, toml::Value::Array(entries))] if merge == "replace" => {
+                            (ListMerge::Replace, entries.clone())
+                        }
+                        _ => bail!(
+                            "`{}` must be a list, or a table with only `append` or `replace` \
+                             set to a list, e.g. {} = {{ append = [...] }}",
+                            key,
+                            field
+                        ),
+                    };
+                    // The list came from wherever its `append` or `replace` key did
+                    for nested in ["append", "replace"] {
+                        if let Some(source) = sources.remove(&format!("{}.{}", key, nested)) {
+                            sources.insert(key.clone(), source);
+                        }
+                    }
+                    (merge, entries)
+                }
+                // Anything else is left for deserializing to reject
+                _ => continue,
+            };
+            let merged = match merge {
+                ListMerge::Replace => entries,
+                ListMerge::Append => {
+                    let mut merged = match docker.get(*field) {
+                        Some(toml::Value::Array(base)) => base.clone(),
+                        _ => Vec::new(),
+                    };
+                    for entry in entries {
+                        if !merged.contains(&entry) {
+                            merged.push(entry);
+                        }
+                    }
+                    merged
+                }
+            };
+            environment.insert(field.to_string(), toml::Value::Array(merged));
+        }
+    }
+    Ok(())
+}
+
+/// Map `PIXI_DOCKER_<FIELD>` and `PIXI_DOCKER_ENVIRONMENTS_<NAME>_<FIELD>` onto the raw table
+fn apply_env_overrides(
+    table: &mut toml::Table,
+    vars: impl IntoIterator<Item = (String, String)>,
+    sources: &mut BTreeMap<String, ValueSource>,
+) -> Result<()> {
+    // Sort so that overrides are applied in a stable order
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+
+    for (var, raw) in vars {
+        let rest = &var[ENV_PREFIX.len()..];
+
+        if let Some(env_rest) = rest.strip_prefix("ENVIRONMENTS_") {
+            let Some((env_part, field, kind)) =
+                ENVIRONMENT_FIELDS.iter().find_map(|(field, kind)| {
+                    env_rest
+                        .strip_suffix(&env_segment(field))
+                        .and_then(|env| env.strip_suffix('_'))
+                        .filter(|env| !env.is_empty())
+                        .map(|env| (env, *field, *kind))
+                })
+            else {
+                continue;
+            };
+
+            let environments = table
+                .entry("environments")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .context("`environments` must be a table")?;
+            let name = environments
+                .keys()
+                .find(|name| env_segment(name) == env_part)
+                .cloned()
+                .unwrap_or_else(|| env_part.to_lowercase());
+            let section = environments
+                .entry(name.clone())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .with_context(|| format!("`environments.{}` must be a table", name))?;
+
+            let prefix = format!("environments.{}", name);
+            insert_field(section, &prefix, field, parse_env_value(&var, &raw, kind)?)?;
+            sources.insert(
+                format!("environments.{}.{}", name, field),
+                ValueSource::Env(var.clone()),
+            );
+        } else if let Some((field, kind)) = DOCKER_FIELDS
+            .iter()
+            .find(|(field, _)| env_segment(field) == rest)
+        {
+            let docker = table
+                .entry("docker")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .context("`docker` must be a table")?;
+            insert_field(docker, "docker", field, parse_env_value(&var, &raw, *kind)?)?;
+            sources.insert(format!("docker.{}", field), ValueSource::Env(var.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Seconds in a duration such as `30`, `30s`, `2m` or `1m30s`
+fn parse_duration(duration: &str) -> Option<u64> {
+    if let Ok(seconds) = duration.parse() {
+        return Some(seconds);
+    }
+    let mut total = 0u64;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let value: u64 = rest[..digits].parse().ok()?;
+        let unit = match rest[digits..].chars().next()? {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        rest = &rest[digits + 1..];
+    }
+    (!duration.is_empty()).then_some(total)
+}
+
+fn parse_env_value(var: &str, raw: &str, kind: FieldKind) -> Result<toml::Value> {
+    let items = || {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+    };
+
+    let value = match kind {
+        FieldKind::String => toml::Value::String(raw.to_string()),
+        FieldKind::Bool => match raw.trim().to_lowercase().as_str() {
+            "true" | "1" => toml::Value::Boolean(true),
+            "false" | "0" => toml::Value::Boolean(false),
+            _ => bail!(
+                "Invalid value for {}: expected true or false, got '{}'",
+                var,
+                raw
+            ),
+        },
+        FieldKind::Ports => {
+            let ports = items()
+                .map(|item| {
+                    item.parse::<u16>()
+                        .map(|port| toml::Value::Integer(port.into()))
+                        .map_err(|_| {
+                            PixiDockerError::msg(format!(
+                                "Invalid value for {}: '{}' is not a valid port number",
+                                var, item
+                            ))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            toml::Value::Array(ports)
+        }
+        FieldKind::List => toml::Value::Array(
+            items()
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        ),
+    };
+
+    Ok(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use std::path::PathBuf;
 
     #[test]
@@ -63,12 +1797,21 @@ mod tests {
 
         assert_eq!(config.docker.environment, "prod");
         assert_eq!(config.docker.ports, vec![8080]);
-        assert_eq!(config.docker.entrypoint, Some("serve".to_string()));
+        assert_eq!(
+            config.docker.entrypoint,
+            Some(Entrypoint::Shell("serve".to_string()))
+        );
         assert_eq!(config.docker.copy_files, vec!["src/"]);
         assert_eq!(config.docker.pixi_version, Some("0.40.0".to_string()));
-        assert_eq!(config.docker.build_command, Some("build".to_string()));
-        assert_eq!(config.docker.multi_stage, true);
-        assert_eq!(config.docker.base_image, Some("ubuntu:24.04".to_string()));
+        assert_eq!(
+            config.docker.build_command,
+            Some(BuildCommand::Single("build".to_string()))
+        );
+        assert!(config.docker.multi_stage);
+        assert_eq!(
+            config.docker.base_image,
+            Some(BaseImage::Single("ubuntu:24.04".to_string()))
+        );
     }
 
     #[test]
@@ -82,20 +1825,97 @@ mod tests {
         // Check dev environment
         let dev_env = config.environments.get("dev").unwrap();
         assert_eq!(dev_env.ports, vec![3000, 3001]);
-        assert_eq!(dev_env.entrypoint, Some("dev".to_string()));
+        assert_eq!(
+            dev_env.entrypoint,
+            Some(Entrypoint::Shell("dev".to_string()))
+        );
         assert_eq!(dev_env.copy_files, vec!["app/", "tests/"]);
         assert_eq!(dev_env.multi_stage, Some(false));
 
         // Check test environment
         let test_env = config.environments.get("test").unwrap();
-        assert_eq!(test_env.ports, vec![]);
-        assert_eq!(test_env.entrypoint, Some("test".to_string()));
-        assert_eq!(test_env.build_command, Some("test-build".to_string()));
+        assert!(test_env.ports.is_empty());
+        assert_eq!(
+            test_env.entrypoint,
+            Some(Entrypoint::Shell("test".to_string()))
+        );
+        assert_eq!(
+            test_env.build_command,
+            Some(BuildCommand::Single("test-build".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_environment_names() {
+        let path = PathBuf::from("tests/fixtures/multi_env_config.toml");
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.environment_names(), vec!["dev", "prod", "test"]);
+    }
+
+    #[test]
+    fn test_test_command_fallback() {
+        let toml_str = r#"
+            [docker]
+            environment = "prod"
+            test_command = "test"
+
+            [environments.dev]
+            test_command = "test-dev"
+
+            [environments.ci]
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.test_command("dev"), Some("test-dev"));
+        assert_eq!(config.test_command("ci"), Some("test"));
+        assert_eq!(config.test_command("prod"), Some("test"));
+    }
+
+    #[test]
+    fn test_build_hooks_fallback() {
+        let toml_str = r#"
+            [docker]
+            environment = "prod"
+            pre_build = ["npm run bundle"]
+            post_build = ["./record-tag.sh"]
+
+            [environments.dev]
+            pre_build = ["npm run bundle:dev"]
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.pre_build("prod"), ["npm run bundle"]);
+        assert_eq!(config.pre_build("dev"), ["npm run bundle:dev"]);
+        assert_eq!(config.post_build("dev"), ["./record-tag.sh"]);
+    }
+
+    #[test]
+    fn test_pixi_version_fallback() {
+        let toml_str = r#"
+            [docker]
+            environment = "prod"
+            pixi_version = "0.40.0"
+
+            [environments.dev]
+            pixi_version = "0.45.0"
+
+            [environments.test]
+            ports = [8000]
+        "#;
+
+        let mut config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.pixi_version("prod"), "0.40.0");
+        assert_eq!(config.pixi_version("dev"), "0.45.0");
+        assert_eq!(config.pixi_version("test"), "0.40.0");
+
+        config.docker.pixi_version = None;
+        assert_eq!(config.pixi_version("dev"), "0.45.0");
+        assert_eq!(config.pixi_version("prod"), DEFAULT_PIXI_VERSION);
     }
 
     #[test]
     fn test_default_multi_stage() {
-        assert_eq!(default_multi_stage(), true);
+        assert!(default_multi_stage());
     }
 
     #[test]
@@ -110,8 +1930,32 @@ mod tests {
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.docker.environment, "production");
         assert_eq!(config.docker.ports, vec![80, 443]);
-        assert_eq!(config.docker.entrypoint, Some("app".to_string()));
-        assert_eq!(config.docker.multi_stage, true); // default value
+        assert_eq!(
+            config.docker.entrypoint,
+            Some(Entrypoint::Shell("app".to_string()))
+        );
+        assert!(config.docker.multi_stage); // default value
+    }
+
+    #[test]
+    fn test_build_command_list() {
+        let toml_str = r#"
+            [docker]
+            environment = "prod"
+            build_command = ["codegen", "compile-assets", "build"]
+
+            [environments.dev]
+            build_command = "dev-build"
+        "#;
+
+        let config: Config = toml::from_str(toml_str).unwrap();
+        let build_command = config.docker.build_command.unwrap();
+        assert_eq!(
+            build_command.commands(),
+            ["codegen", "compile-assets", "build"]
+        );
+        let dev_build = config.environments["dev"].build_command.as_ref().unwrap();
+        assert_eq!(dev_build.commands(), ["dev-build"]);
     }
 
     #[test]
@@ -136,4 +1980,580 @@ mod tests {
             Some("custom/template.j2".to_string())
         );
     }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let path = PathBuf::from("tests/fixtures/multi_env_config.toml");
+        let config = Config::from_file_with_env(
+            &path,
+            vars(&[
+                ("PIXI_DOCKER_IMAGE_NAME", "ci-image"),
+                ("PIXI_DOCKER_PORTS", "80, 443"),
+                ("PIXI_DOCKER_MULTI_STAGE", "false"),
+                ("PIXI_DOCKER_ENVIRONMENTS_DEV_COPY_FILES", "src/,docs/"),
+                ("PIXI_DOCKER_UNRELATED", "ignored"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(config.docker.image_name, Some("ci-image".to_string()));
+        assert_eq!(config.docker.ports, vec![80, 443]);
+        assert!(!config.docker.multi_stage);
+        assert_eq!(
+            config.environments.get("dev").unwrap().copy_files,
+            vec!["src/", "docs/"]
+        );
+
+        assert_eq!(
+            config.sources.get("docker.ports"),
+            Some(&ValueSource::Env("PIXI_DOCKER_PORTS".to_string()))
+        );
+        assert_eq!(
+            config.sources.get("docker.entrypoint"),
+            Some(&ValueSource::File(path.clone()))
+        );
+    }
+
+    #[test]
+    fn test_list_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pixi_docker.toml");
+        let write = |list_merge: &str| {
+            fs::write(
+                &path,
+                format!(
+                    r#"
+                    [docker]
+                    environment = "prod"
+                    {}
+                    ports = [8000]
+                    copy_files = ["src/", "pyproject.toml"]
+                    pre_build = ["fetch"]
+
+                    [environments.dev]
+                    ports = [8000, 5678]
+                    copy_files = {{ append = ["tests/", "src/", "tests/"] }}
+
+                    [environments.ci]
+                    copy_files = {{ replace = ["ci/"] }}
+                    pre_build = ["lint"]
+                    "#,
+                    list_merge
+                ),
+            )
+            .unwrap();
+        };
+
+        // Plain lists keep replacing, `append` tables add to the [docker] list without duplicates
+        write("");
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        assert_eq!(
+            config.copy_files("dev"),
+            ["src/", "pyproject.toml", "tests/"]
+        );
+        assert_eq!(config.environments["dev"].ports, [8000, 5678]);
+        assert_eq!(config.copy_files("ci"), ["ci/"]);
+        assert_eq!(config.pre_build("ci"), ["lint"]);
+        assert_eq!(
+            config.sources.get("environments.dev.copy_files"),
+            Some(&ValueSource::File(path.clone()))
+        );
+        assert!(!config
+            .sources
+            .contains_key("environments.dev.copy_files.append"));
+
+        // `list_merge = "append"` makes plain lists append; `replace` tables still replace
+        write("list_merge = \"append\"");
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        assert_eq!(config.environments["dev"].ports, [8000, 5678]);
+        assert_eq!(config.copy_files("ci"), ["ci/"]);
+        assert_eq!(config.pre_build("ci"), ["fetch", "lint"]);
+        let config = Config::from_file_with_env(
+            &path,
+            vars(&[("PIXI_DOCKER_ENVIRONMENTS_CI_RUNTIME_COPY", ".venv")]),
+        )
+        .unwrap();
+        assert_eq!(config.runtime_copy("ci"), [".venv"]);
+
+        write("list_merge = \"merge\"");
+        let err = Config::from_file_with_env(&path, vars(&[])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("must be \"replace\" or \"append\""));
+
+        fs::write(
+            &path,
+            "[docker]\nenvironment = \"prod\"\n\n[environments.dev]\ncopy_files = { add = [\"a\"] }\n",
+        )
+        .unwrap();
+        let err = Config::from_file_with_env(&path, vars(&[])).unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "`environments.dev.copy_files` must be a list, or a table with only `append`"
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_env_override_parse_errors() {
+        let path = PathBuf::from("tests/fixtures/basic_config.toml");
+
+        let err = Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_PORTS", "80,abc")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("PIXI_DOCKER_PORTS"));
+        assert!(err.to_string().contains("'abc'"));
+
+        let err = Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_MULTI_STAGE", "yes")]))
+            .unwrap_err();
+        assert!(err.to_string().contains("expected true or false"));
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pixi_docker.toml");
+        fs::write(
+            &path,
+            r#"
+            [docker]
+            environment = "prod"
+            image_name = "${REGISTRY}/myapp"
+            auth_file = "${HOME}/.rattler/credentials.json"
+            image_tag = "${TAG:-latest}"
+            pre_build = ["echo $${literal} $HOME"]
+
+            [docker.build_args]
+            TOKEN = "${TOKEN:-none}"
+            "#,
+        )
+        .unwrap();
+        let env = vars(&[("REGISTRY", "ghcr.io/acme"), ("HOME", "/home/ci")]);
+        let config = Config::from_file_with_env(&path, env.clone()).unwrap();
+        assert_eq!(
+            config.docker.image_name.as_deref(),
+            Some("ghcr.io/acme/myapp")
+        );
+        assert_eq!(
+            config.docker.auth_file.as_deref(),
+            Some("/home/ci/.rattler/credentials.json")
+        );
+        assert_eq!(config.docker.image_tag.as_deref(), Some("latest"));
+        assert_eq!(config.docker.pre_build, vec!["echo ${literal} $HOME"]);
+        assert_eq!(config.docker.build_args["TOKEN"], "none");
+        assert_eq!(
+            config.raw_values.get("docker.image_name"),
+            Some(&toml::Value::String("${REGISTRY}/myapp".to_string()))
+        );
+        assert!(config.raw_values.contains_key("docker.build_args.TOKEN"));
+        assert!(!config.raw_values.contains_key("docker.environment"));
+
+        // Overridden values drop their raw value
+        let mut overridden = env.clone();
+        overridden.push(("PIXI_DOCKER_IMAGE_NAME".to_string(), "other".to_string()));
+        let config = Config::from_file_with_env(&path, overridden).unwrap();
+        assert!(!config.raw_values.contains_key("docker.image_name"));
+
+        let err = Config::from_file_with_env(&path, vars(&[("HOME", "/home/ci")]))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("Environment variable REGISTRY used in `docker.image_name` (from "),
+            "{}",
+            err
+        );
+        assert!(err.contains("${REGISTRY:-...}"), "{}", err);
+    }
+
+    #[test]
+    fn test_port_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pixi_docker.toml");
+        fs::write(
+            &path,
+            r#"
+            [docker]
+            environment = "prod"
+            ports = [8080, 443, 8080]
+
+            [environments.dev]
+            ports = [3000, 0]
+            "#,
+        )
+        .unwrap();
+        let err = Config::from_file_with_env(&path, vars(&[])).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("Invalid port 0 in [environments.dev] ports"));
+        assert!(message.contains(&path.display().to_string()));
+
+        let err =
+            Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_PORTS", "0")])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("[docker] ports (from env PIXI_DOCKER_PORTS)"));
+
+        fs::write(
+            &path,
+            "[docker]\nenvironment = \"prod\"\nports = [8080, 443, 8080]\n",
+        )
+        .unwrap();
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        assert_eq!(config.docker.ports, vec![8080, 443]);
+    }
+
+    #[test]
+    fn test_effective_values() {
+        let path = PathBuf::from("tests/fixtures/basic_config.toml");
+        let config =
+            Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_BASE_IMAGE", "debian:12")]))
+                .unwrap();
+
+        let values = config.effective_values().unwrap();
+        let (_, value, source) = values
+            .iter()
+            .find(|(key, _, _)| key == "docker.base_image")
+            .unwrap();
+        assert_eq!(value.as_str(), Some("debian:12"));
+        assert_eq!(
+            *source,
+            Some(&ValueSource::Env("PIXI_DOCKER_BASE_IMAGE".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_include_layering() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("project")).unwrap();
+        fs::write(
+            dir.path().join("base.toml"),
+            r#"
+            [docker]
+            environment = "prod"
+            image_name = "registry.example.com/base"
+            ports = [80]
+
+            [environments.dev]
+            ports = [3000]
+            entrypoint = "dev"
+            "#,
+        )
+        .unwrap();
+        let path = dir.path().join("project/pixi_docker.toml");
+        fs::write(
+            &path,
+            r#"
+            include = ["../base.toml"]
+
+            [docker]
+            environment = "prod"
+            ports = [8080]
+
+            [environments.dev]
+            entrypoint = "serve"
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        assert_eq!(config.docker.ports, vec![8080]);
+        assert_eq!(
+            config.docker.image_name.as_deref(),
+            Some("registry.example.com/base")
+        );
+        let dev = &config.environments["dev"];
+        assert_eq!(dev.ports, vec![3000]);
+        assert_eq!(
+            dev.entrypoint.as_ref().and_then(Entrypoint::as_shell),
+            Some("serve")
+        );
+
+        let base = path.parent().unwrap().join("../base.toml");
+        assert_eq!(
+            config.sources.get("docker.image_name"),
+            Some(&ValueSource::File(base))
+        );
+        assert_eq!(
+            config.sources.get("docker.ports"),
+            Some(&ValueSource::File(path.clone()))
+        );
+    }
+
+    #[test]
+    fn test_schema_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("base.toml"),
+            "[environments.dev]\nrun_workdir = \"/app/dev\"\n",
+        )
+        .unwrap();
+        let path = dir.path().join("pixi_docker.toml");
+        fs::write(
+            &path,
+            r#"
+            include = ["base.toml"]
+
+            [docker]
+            environment = "prod"
+            run_workdir = "/srv"
+            "#,
+        )
+        .unwrap();
+
+        // Files without a schema_version still load in the old shape, includes included
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        assert_eq!(config.run_workdir("prod"), Some("/srv"));
+        assert_eq!(config.run_workdir("dev"), Some("/app/dev"));
+        assert_eq!(
+            config.sources.get("environments.dev.run.workdir"),
+            Some(&ValueSource::File(dir.path().join("base.toml")))
+        );
+
+        let config = Config::from_file_with_env(
+            &path,
+            vars(&[("PIXI_DOCKER_ENVIRONMENTS_DEV_RUN_WORKDIR", "/work")]),
+        )
+        .unwrap();
+        assert_eq!(config.run_workdir("dev"), Some("/work"));
+
+        let newer = migrate::SCHEMA_VERSION + 1;
+        fs::write(
+            &path,
+            format!(
+                "schema_version = {}\n[docker]\nenvironment = \"prod\"\n",
+                newer
+            ),
+        )
+        .unwrap();
+        let err = Config::from_file_with_env(&path, vars(&[])).unwrap_err();
+        assert!(err.to_string().contains("upgrade pixi-docker"), "{}", err);
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.toml");
+        fs::write(
+            &a,
+            "include = [\"b.toml\"]\n[docker]\nenvironment = \"prod\"\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let err = Config::from_file_with_env(&a, vars(&[])).unwrap_err();
+        assert!(err.to_string().contains("Config include cycle"));
+
+        // A file including itself is a cycle too
+        fs::write(&a, "include = [\"./a.toml\"]\n").unwrap();
+        let err = Config::from_file_with_env(&a, vars(&[])).unwrap_err();
+        assert!(err.to_string().contains("Config include cycle"));
+    }
+
+    #[test]
+    fn test_include_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        for level in 0..=MAX_INCLUDE_DEPTH {
+            fs::write(
+                dir.path().join(format!("{}.toml", level)),
+                format!("include = [\"{}.toml\"]\n", level + 1),
+            )
+            .unwrap();
+        }
+
+        let err = Config::from_file_with_env(&dir.path().join("0.toml"), vars(&[])).unwrap_err();
+        assert!(err.to_string().contains("nested more than"));
+    }
+
+    #[test]
+    fn test_discover_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("repo/src/app");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(dir.path().join("repo/.git")).unwrap();
+        assert_eq!(discover_config(&nested), None);
+
+        fs::write(dir.path().join("repo").join(CONFIG_FILE), "").unwrap();
+        assert_eq!(
+            discover_config(&nested),
+            Some(PathBuf::from("../..").join(CONFIG_FILE))
+        );
+        assert_eq!(
+            discover_config(&dir.path().join("repo")),
+            Some(PathBuf::from(CONFIG_FILE))
+        );
+    }
+
+    #[test]
+    fn test_paths_relative_to_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(
+            &path,
+            "[docker]\nenvironment = \"prod\"\ntemplate_path = \"docker/custom.j2\"\nmanifest_path = \"/abs/pixi.toml\"\n\n[environments.dev]\ntemplate_path = \"docker/dev.j2\"\n",
+        )
+        .unwrap();
+
+        let config =
+            Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_BASE_IMAGE", "debian:12")]))
+                .unwrap();
+        assert_eq!(
+            config.docker.template_path,
+            Some(dir.path().join("docker/custom.j2").display().to_string())
+        );
+        assert_eq!(
+            config.docker.manifest_path.as_deref(),
+            Some("/abs/pixi.toml")
+        );
+        assert_eq!(
+            config.template_path("dev"),
+            Some(
+                dir.path()
+                    .join("docker/dev.j2")
+                    .display()
+                    .to_string()
+                    .as_str()
+            )
+        );
+
+        // Environment variables stay relative to the working directory
+        let config =
+            Config::from_file_with_env(&path, vars(&[("PIXI_DOCKER_TEMPLATE_PATH", "other.j2")]))
+                .unwrap();
+        assert_eq!(config.docker.template_path.as_deref(), Some("other.j2"));
+        assert_eq!(config.base_dir(), dir.path());
+        assert_eq!(config.lock_path(), dir.path().join("pixi_docker.lock"));
+    }
+
+    #[test]
+    fn test_host_paths_relative_to_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(
+            &path,
+            r#"
+            [docker]
+            environment = "prod"
+            auth_file = "../secrets/credentials.json"
+            secrets = ["id=token,src=token.txt", "id=key,env=KEY", "id=abs,source=/run/abs"]
+            copy_files = ["src/", "pyproject.toml"]
+            context = "remote-builder"
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::from_file_with_env(&path, vars(&[])).unwrap();
+        let relative = |file: &str| dir.path().join(file).display().to_string();
+        assert_eq!(
+            config.docker.auth_file,
+            Some(relative("../secrets/credentials.json"))
+        );
+        assert_eq!(
+            config.docker.secrets,
+            vec![
+                format!("id=token,src={}", relative("token.txt")),
+                "id=key,env=KEY".to_string(),
+                "id=abs,source=/run/abs".to_string(),
+            ]
+        );
+        // Build context paths and docker context names aren't host paths
+        assert_eq!(config.docker.copy_files, vec!["src/", "pyproject.toml"]);
+        assert_eq!(config.docker.context.as_deref(), Some("remote-builder"));
+    }
+
+    #[test]
+    fn test_stop_settings() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [docker]
+            environment = "prod"
+            stop_signal = "SIGINT"
+            stop_grace_period = "1m30s"
+
+            [environments.worker]
+            stop_grace_period = "5"
+            exec_entrypoint = false
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.stop_signal("worker").unwrap(), Some("SIGINT"));
+        assert_eq!(config.stop_timeout("prod").unwrap(), Some(90));
+        assert_eq!(config.stop_timeout("worker").unwrap(), Some(5));
+        assert!(config.exec_entrypoint("prod"));
+        assert!(!config.exec_entrypoint("worker"));
+
+        config.docker.stop_grace_period = Some("soon".to_string());
+        let err = config.stop_timeout("prod").unwrap_err().to_string();
+        assert!(err.contains("Invalid stop_grace_period 'soon' for environment 'prod'"));
+
+        config.docker.stop_signal = Some("SIGTERM\nRUN rm -rf /".to_string());
+        assert!(config.stop_signal("prod").is_err());
+
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1m"), Some(60));
+        assert_eq!(parse_duration("10x"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let run: RunConfig =
+            toml::from_str("memory = \"1.5GB\"\ncpus = 1.5\nshm_size = \"64m\"").unwrap();
+        assert_eq!(
+            run.resource_limits().unwrap(),
+            [
+                ("--memory", "1.5GB"),
+                ("--shm-size", "64m"),
+                ("--cpus", "1.5")
+            ]
+        );
+
+        for bad in [
+            "memory = \"2x\"",
+            "shm_size = \"\"",
+            "cpus = 0",
+            "cpus = \"many\"",
+        ] {
+            let run: RunConfig = toml::from_str(bad).unwrap();
+            assert!(run.resource_limits().is_err(), "{}", bad);
+        }
+        assert!(toml::from_str::<RunConfig>("cpus = true").is_err());
+    }
+
+    #[test]
+    fn test_byte_size() {
+        assert_eq!(byte_size("512"), Some(512));
+        assert_eq!(byte_size("64m"), Some(64 * 1024 * 1024));
+        assert_eq!(byte_size("1.5GB"), Some(3 * 512 * 1024 * 1024));
+        assert_eq!(byte_size("2k"), Some(2048));
+        for bad in ["", "0", "-1m", "2x", "m"] {
+            assert_eq!(byte_size(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_display_path() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::create_dir_all(dir.path().join("deploy")).unwrap();
+        let path = dir.path().join("deploy/pixi_docker.toml");
+        fs::write(&path, "[docker]\nenvironment = \"prod\"\n").unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(
+            config.display_path(),
+            Path::new("deploy")
+                .join("pixi_docker.toml")
+                .display()
+                .to_string()
+        );
+    }
 }
@@ -0,0 +1,118 @@
+use similar::{ChangeTag, TextDiff};
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Label whose value changes on every generation unless reproducible output is enabled
+const CREATED_LABEL: &str = "LABEL org.opencontainers.image.created=";
+
+/// Compare two Dockerfiles, ignoring differences in trailing newlines and the creation timestamp
+pub fn contents_match(existing: &str, generated: &str) -> bool {
+    comparable_lines(existing) == comparable_lines(generated)
+}
+
+/// The lines of a Dockerfile that depend on its inputs: without trailing newlines and the
+/// creation timestamp
+pub fn comparable_lines(content: &str) -> Vec<&str> {
+    content
+        .trim_end_matches(['\r', '\n'])
+        .lines()
+        .filter(|line| !line.starts_with(CREATED_LABEL))
+        .collect()
+}
+
+/// Whether diff output on stdout should be colored
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+/// Render a unified diff between `old` and `new`, optionally with ANSI colors
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str, color: bool) -> String {
+    let paint = |text: String, style: &str| {
+        if color && !style.is_empty() {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text
+        }
+    };
+
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = String::new();
+    out.push_str(&paint(format!("--- {}", old_name), BOLD));
+    out.push('\n');
+    out.push_str(&paint(format!("+++ {}", new_name), BOLD));
+    out.push('\n');
+
+    for hunk in diff.unified_diff().iter_hunks() {
+        out.push_str(&paint(hunk.header().to_string(), CYAN));
+        out.push('\n');
+        for change in hunk.iter_changes() {
+            let (sign, style) = match change.tag() {
+                ChangeTag::Delete => ("-", RED),
+                ChangeTag::Insert => ("+", GREEN),
+                ChangeTag::Equal => (" ", ""),
+            };
+            let line = change.value().trim_end_matches('\n');
+            out.push_str(&paint(format!("{}{}", sign, line), style));
+            out.push('\n');
+            if change.missing_newline() {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contents_match_ignores_trailing_newlines() {
+        assert!(contents_match("FROM a\n", "FROM a"));
+        assert!(contents_match("FROM a\n\n", "FROM a\n"));
+        assert!(contents_match("FROM a\r\n", "FROM a\n"));
+        assert!(!contents_match("FROM a\n", "FROM b\n"));
+        assert!(!contents_match("\nFROM a", "FROM a"));
+    }
+
+    #[test]
+    fn test_contents_match_ignores_created_label() {
+        let old = "FROM a\nLABEL org.opencontainers.image.created=\"2026-01-01T00:00:00Z\"\n";
+        let new = "FROM a\nLABEL org.opencontainers.image.created=\"2026-01-02T00:00:00Z\"\n";
+        assert!(contents_match(old, new));
+        assert!(!contents_match(old, "FROM b\n"));
+    }
+
+    #[test]
+    fn test_unified_diff_plain() {
+        let old = "FROM a\nEXPOSE 80\n";
+        let new = "FROM a\nEXPOSE 8080\n";
+        let diff = unified_diff(old, new, "Dockerfile.prod", "generated", false);
+
+        assert!(diff.starts_with("--- Dockerfile.prod\n+++ generated\n"));
+        assert!(diff.contains("@@ -1,2 +1,2 @@"));
+        assert!(diff.contains("-EXPOSE 80\n"));
+        assert!(diff.contains("+EXPOSE 8080\n"));
+        assert!(diff.contains(" FROM a\n"));
+        assert!(!diff.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_unified_diff_color() {
+        let diff = unified_diff("a\n", "b\n", "old", "new", true);
+        assert!(diff.contains(&format!("{}-a{}", RED, RESET)));
+        assert!(diff.contains(&format!("{}+b{}", GREEN, RESET)));
+    }
+
+    #[test]
+    fn test_unified_diff_identical() {
+        let diff = unified_diff("a\n", "a\n", "old", "new", false);
+        assert_eq!(diff, "--- old\n+++ new\n");
+    }
+}
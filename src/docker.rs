@@ -0,0 +1,1195 @@
+use crate::config::Config;
+use crate::error::{bail, Context, PixiDockerError, Result};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Create a command for the configured container runtime
+pub fn command(config: &Config) -> Command {
+    let mut cmd = Command::new(config.runtime());
+    if let Some(context) = &config.docker.context {
+        cmd.arg("--context").arg(context);
+    }
+    cmd
+}
+
+/// The daemon the runtime talks to, and what selected it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Endpoint {
+    /// Daemon address, e.g. `ssh://builder01`, unknown for the default local daemon
+    pub host: Option<String>,
+    /// The docker context naming the daemon, if one did
+    pub context: Option<String>,
+    /// What selected the daemon, e.g. `DOCKER_HOST` or `cli --context`
+    pub source: Option<String>,
+}
+
+impl Endpoint {
+    /// Whether the daemon runs on this machine, so that host paths mean the same to it
+    pub fn is_local(&self) -> bool {
+        let Some(host) = &self.host else {
+            return true;
+        };
+        if host.starts_with("unix://") || host.starts_with("npipe://") {
+            return true;
+        }
+        let address = host
+            .split_once("://")
+            .map_or(host.as_str(), |(_, rest)| rest);
+        let address = address.rsplit_once('@').map_or(address, |(_, rest)| rest);
+        let name = match address.strip_prefix('[') {
+            Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+            None => address.split([':', '/']).next().unwrap_or_default(),
+        };
+        matches!(name, "localhost" | "127.0.0.1" | "::1")
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut details = Vec::new();
+        match (&self.host, &self.context) {
+            (Some(host), context) => {
+                write!(f, "{}", host)?;
+                details.extend(
+                    context
+                        .iter()
+                        .map(|context| format!("context '{}'", context)),
+                );
+            }
+            (None, Some(context)) => write!(f, "context '{}'", context)?,
+            (None, None) => write!(f, "the local daemon")?,
+        }
+        match &self.source {
+            Some(source) => details.push(format!("from {}", source)),
+            None if self.context.is_none() => details.push("default context".to_string()),
+            None => {}
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the daemon the runtime talks to, the way the docker CLI picks it: `--context`, then
+/// `DOCKER_HOST`, then `DOCKER_CONTEXT`, then the current context of `docker context use`
+pub fn endpoint(config: &Config) -> Endpoint {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let context = |name: String, source: String| Endpoint {
+        host: context_host(config, &name),
+        context: Some(name).filter(|name| name != "default"),
+        source: Some(source),
+    };
+    if let Some(name) = &config.docker.context {
+        let source = config
+            .sources
+            .get("docker.context")
+            .map_or("config".to_string(), ToString::to_string);
+        return context(name.clone(), source);
+    }
+    if let Some(host) = var("DOCKER_HOST") {
+        return Endpoint {
+            host: Some(host),
+            context: None,
+            source: Some("DOCKER_HOST".to_string()),
+        };
+    }
+    if let Some(name) = var("DOCKER_CONTEXT") {
+        return context(name, "DOCKER_CONTEXT".to_string());
+    }
+    match current_context().filter(|name| name != "default") {
+        Some(name) => context(name, "docker context show".to_string()),
+        None => Endpoint::default(),
+    }
+}
+
+/// The docker CLI config, `config.json` in `$DOCKER_CONFIG` or `~/.docker`
+pub fn cli_config() -> Option<serde_json::Value> {
+    let dir = match std::env::var_os("DOCKER_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".docker"),
+    };
+    let content = fs::read_to_string(dir.join("config.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// The current context set by `docker context use`, which `docker context show` prints,
+/// read from the CLI config instead of starting docker
+fn current_context() -> Option<String> {
+    let config = cli_config()?;
+    let name = config.get("currentContext")?.as_str()?;
+    Some(name.to_string()).filter(|name| !name.is_empty())
+}
+
+/// The daemon address of a docker context, from `docker context inspect`
+fn context_host(config: &Config, name: &str) -> Option<String> {
+    if name == "default" || is_podman(config) {
+        return None;
+    }
+    let output = Command::new(config.runtime())
+        .args([
+            "context",
+            "inspect",
+            "--format",
+            "{{.Endpoints.docker.Host}}",
+            name,
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let host = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    host.contains("://").then_some(host)
+}
+
+/// Check that a remote daemon answers, naming the endpoint that was probed when it doesn't
+///
+/// Local daemons aren't probed: the runtime's own errors are clear enough for them.
+pub fn check_daemon(config: &Config, endpoint: &Endpoint) -> Result<(), PixiDockerError> {
+    if endpoint.is_local() {
+        return Ok(());
+    }
+    let mut cmd = command(config);
+    cmd.args(["version", "--format", "{{.Server.Version}}"]);
+    let output = cmd
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|source| PixiDockerError::spawn(&cmd, source))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .unwrap_or("no answer");
+        return Err(PixiDockerError::DaemonUnreachable {
+            endpoint: endpoint.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Whether `docker run` flags bind-mount host paths, with `-v`, `--volume` or `--mount`
+pub fn binds_host_paths(docker_args: &[String]) -> bool {
+    docker_args.iter().enumerate().any(|(i, arg)| {
+        let value = match arg.as_str() {
+            "-v" | "--volume" | "--mount" => docker_args.get(i + 1).map(String::as_str),
+            _ => arg
+                .strip_prefix("--volume=")
+                .or_else(|| arg.strip_prefix("--mount="))
+                .or_else(|| arg.strip_prefix("-v").filter(|value| !value.is_empty())),
+        };
+        value.is_some_and(|value| {
+            if arg.starts_with("--mount") {
+                value.split(',').any(|option| option == "type=bind")
+            } else {
+                // Named volumes have no `/`; host paths do
+                volume_source(value).contains(['/', '\\', '.'])
+            }
+        })
+    })
+}
+
+/// The source of a `-v` value, keeping the drive of Windows host paths like `C:\data:/data`
+fn volume_source(value: &str) -> &str {
+    let skip = match value.as_bytes() {
+        [drive, b':', b'\\' | b'/', ..] if drive.is_ascii_alphabetic() => 2,
+        _ => 0,
+    };
+    match value[skip..].find(':') {
+        Some(end) => &value[..skip + end],
+        None => value,
+    }
+}
+
+/// A host path as `-v` accepts it: without the `\\?\` prefix `fs::canonicalize` adds on Windows
+pub fn host_path(path: &Path) -> String {
+    let path = path.display().to_string();
+    if let Some(share) = path.strip_prefix(r"\\?\UNC\") {
+        return format!(r"\\{}", share);
+    }
+    match path.strip_prefix(r"\\?\") {
+        Some(path) => path.to_string(),
+        None => path,
+    }
+}
+
+/// Run a command to completion, reporting a runtime that can't be started as `RuntimeSpawn`
+pub fn status(cmd: &mut Command) -> Result<ExitStatus, PixiDockerError> {
+    cmd.status()
+        .map_err(|source| PixiDockerError::spawn(cmd, source))
+}
+
+/// Whether the configured runtime is podman rather than docker
+pub fn is_podman(config: &Config) -> bool {
+    Path::new(config.runtime())
+        .file_stem()
+        .is_some_and(|stem| stem == "podman")
+}
+
+/// The uid and gid of this process, which own the files it creates; `None` off Linux, where
+/// docker runs in a VM that maps bind-mount ownership by itself
+pub fn host_user() -> Option<(u32, u32)> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // `/proc/self` belongs to the effective user and group of the process
+        let metadata = std::fs::metadata("/proc/self").ok()?;
+        Some((metadata.uid(), metadata.gid()))
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Whether the runtime can read the Dockerfile from stdin with `build -f -`
+pub fn supports_stdin_dockerfile(config: &Config) -> bool {
+    Path::new(config.runtime())
+        .file_stem()
+        .is_some_and(|stem| stem == "docker" || stem == "podman")
+}
+
+/// How build output is shown while the build runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildOutput {
+    /// Echo stdout and stderr as they arrive
+    Echo,
+    /// Echo both to stderr, keeping stdout free for machine-readable output
+    EchoToStderr,
+    /// Echo like `Echo`, each line written whole behind this prefix so that concurrent
+    /// builds can share the terminal
+    Prefixed(Arc<str>),
+    /// Show nothing, only keep the tail for the error
+    Hidden,
+}
+
+/// A resolved image build, shared by the docker CLI and the Engine API backends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildSpec {
+    /// Image tags, the first one is the primary tag
+    pub tags: Vec<String>,
+    pub target: Option<String>,
+    pub platform: Option<String>,
+    pub build_args: Vec<(String, String)>,
+    /// Build args passed by name only, so docker takes their values from its environment
+    pub env_build_args: Vec<String>,
+    /// BuildKit secrets in `--secret` syntax, e.g. `id=pixi_auth,src=auth.json`
+    pub secrets: Vec<String>,
+    /// SSH agents forwarded with `--ssh`, e.g. `default`
+    pub ssh: Vec<String>,
+    pub labels: Vec<(String, String)>,
+    /// Caches imported with `--cache-from`, e.g. `type=registry,ref=...`
+    pub cache_from: Vec<String>,
+    /// Cache exported with `--cache-to`
+    pub cache_to: Option<String>,
+}
+
+impl BuildSpec {
+    /// The `--target`, `--platform`, `--build-arg`, `--secret`, `--ssh`, `--label` and cache flags
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(target) = &self.target {
+            args.extend(["--target".to_string(), target.clone()]);
+        }
+        if let Some(platform) = &self.platform {
+            args.extend(["--platform".to_string(), platform.clone()]);
+        }
+        for (name, value) in &self.build_args {
+            args.extend(["--build-arg".to_string(), format!("{}={}", name, value)]);
+        }
+        for name in &self.env_build_args {
+            args.extend(["--build-arg".to_string(), name.clone()]);
+        }
+        for secret in &self.secrets {
+            args.extend(["--secret".to_string(), secret.clone()]);
+        }
+        for ssh in &self.ssh {
+            args.extend(["--ssh".to_string(), ssh.clone()]);
+        }
+        for (key, value) in &self.labels {
+            args.extend(["--label".to_string(), format!("{}={}", key, value)]);
+        }
+        for cache in &self.cache_from {
+            args.extend(["--cache-from".to_string(), cache.clone()]);
+        }
+        if let Some(cache) = &self.cache_to {
+            args.extend(["--cache-to".to_string(), cache.clone()]);
+        }
+        args
+    }
+}
+
+/// A resolved container run, shared by the docker CLI and the Engine API backends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSpec {
+    pub image: String,
+    pub rm: bool,
+    pub name: Option<String>,
+    pub network: Option<String>,
+    pub restart: Option<String>,
+    /// `--memory`, `--cpus` and `--shm-size` with their values
+    pub limits: Vec<(&'static str, String)>,
+    pub stop_timeout: Option<u64>,
+    /// Run an init process as PID 1 (`--init`)
+    pub init: bool,
+    pub detach: bool,
+    /// Publish every exposed port on a random host port (`-P`)
+    pub publish_all: bool,
+    /// Published ports, e.g. `8080:8080`
+    pub ports: Vec<String>,
+    /// Keep stdin open (`-i`)
+    pub interactive: bool,
+    /// Allocate a pseudo-terminal (`-t`)
+    pub tty: bool,
+    pub workdir: Option<String>,
+    /// The `uid:gid` to run as (`--user`)
+    pub user: Option<String>,
+    /// The user namespace mode (`--userns`), e.g. podman's `keep-id`
+    pub userns: Option<String>,
+    /// `--mount` flags, e.g. the project sources of dev mode
+    pub mounts: Vec<Mount>,
+    /// Replaces the image's default command when not empty
+    pub command: Vec<String>,
+}
+
+/// A `docker run --mount`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mount {
+    /// A host path, which must exist, mounted read-write
+    Bind { source: String, target: String },
+    /// An anonymous volume, which docker fills with the image's files at `target`
+    Volume { target: String },
+}
+
+impl Mount {
+    /// The `--mount` value; fields containing a comma are quoted, as the value is CSV
+    pub fn arg(&self) -> String {
+        let field = |key: &str, value: &str| match value.contains(',') {
+            true => format!("\"{}={}\"", key, value.replace('"', "\"\"")),
+            false => format!("{}={}", key, value),
+        };
+        match self {
+            Mount::Bind { source, target } => format!(
+                "type=bind,{},{}",
+                field("source", source),
+                field("target", target)
+            ),
+            Mount::Volume { target } => format!("type=volume,{}", field("target", target)),
+        }
+    }
+}
+
+impl RunSpec {
+    /// The `docker run` flags that go before user flags and the image
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.rm {
+            args.push("--rm".to_string());
+        }
+        if let Some(name) = &self.name {
+            args.extend(["--name".to_string(), name.clone()]);
+        }
+        if let Some(network) = &self.network {
+            args.extend(["--network".to_string(), network.clone()]);
+        }
+        if let Some(restart) = &self.restart {
+            args.extend(["--restart".to_string(), restart.clone()]);
+        }
+        for (flag, value) in &self.limits {
+            args.extend([flag.to_string(), value.clone()]);
+        }
+        if let Some(timeout) = self.stop_timeout {
+            args.extend(["--stop-timeout".to_string(), timeout.to_string()]);
+        }
+        if self.init {
+            args.push("--init".to_string());
+        }
+        if self.detach {
+            args.push("-d".to_string());
+        }
+        if self.publish_all {
+            args.push("-P".to_string());
+        }
+        for port in &self.ports {
+            args.extend(["-p".to_string(), port.clone()]);
+        }
+        match (self.interactive, self.tty) {
+            (true, true) => args.push("-it".to_string()),
+            (true, false) => args.push("-i".to_string()),
+            (false, true) => args.push("-t".to_string()),
+            (false, false) => {}
+        }
+        if let Some(workdir) = &self.workdir {
+            args.extend(["-w".to_string(), workdir.clone()]);
+        }
+        if let Some(user) = &self.user {
+            args.extend(["--user".to_string(), user.clone()]);
+        }
+        if let Some(userns) = &self.userns {
+            args.push(format!("--userns={}", userns));
+        }
+        for mount in &self.mounts {
+            args.extend(["--mount".to_string(), mount.arg()]);
+        }
+        args
+    }
+}
+
+/// A buildx `--output` spec, e.g. `type=oci,dest=out.tar` or the `./dist` shorthand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSpec {
+    /// The spec as given, passed on to the runtime
+    pub spec: String,
+    /// The exporter, e.g. `local`, `tar`, `oci` or `registry`
+    pub kind: String,
+    /// Where the result is written, `-` for stdout
+    pub dest: Option<String>,
+}
+
+impl OutputSpec {
+    /// Parse `key=value` pairs with a `type`, or a bare path for a `local` export (`-` for a
+    /// tar archive on stdout), like buildx does
+    pub fn parse(spec: &str) -> Result<Self> {
+        if !spec.contains('=') {
+            if spec.is_empty() {
+                bail!("Empty build output");
+            }
+            return Ok(Self {
+                spec: spec.to_string(),
+                kind: if spec == "-" { "tar" } else { "local" }.to_string(),
+                dest: Some(spec.to_string()),
+            });
+        }
+        let mut kind = None;
+        let mut dest = None;
+        for pair in spec.split(',') {
+            match pair.split_once('=') {
+                Some(("type", value)) => kind = Some(value.to_string()),
+                Some(("dest", value)) => dest = Some(value.to_string()),
+                Some(_) => {}
+                None => bail!(
+                    "Invalid build output '{}': expected key=value pairs, e.g. type=local,dest=dist",
+                    spec
+                ),
+            }
+        }
+        let Some(kind) = kind.filter(|kind| !kind.is_empty()) else {
+            bail!(
+                "Build output '{}' has no type, e.g. type=local,dest=dist or type=oci,dest=out.tar",
+                spec
+            );
+        };
+        Ok(Self {
+            spec: spec.to_string(),
+            kind,
+            dest,
+        })
+    }
+
+    /// Whether the exporter names the image, so that `-t` tags apply to it
+    pub fn takes_tags(&self) -> bool {
+        matches!(self.kind.as_str(), "image" | "registry" | "docker" | "oci")
+    }
+}
+
+/// A `--extract <container-path>:<host-dir>` pair: a path in the built image to copy to the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extract {
+    /// Absolute path in the image; a directory's contents are copied, not the directory itself
+    pub path: String,
+    /// Host directory the files are written to, created by the runtime if needed
+    pub dest: PathBuf,
+}
+
+impl Extract {
+    /// Parse `/app/dist:./dist`, splitting at the first `:` so the host side may contain one
+    pub fn parse(value: &str) -> Result<Self> {
+        let Some((path, dest)) = value
+            .split_once(':')
+            .filter(|(path, dest)| !path.is_empty() && !dest.is_empty())
+        else {
+            bail!(
+                "Invalid --extract '{}': expected <container-path>:<host-dir>, e.g. /app/dist:./dist",
+                value
+            );
+        };
+        if !path.starts_with('/') {
+            bail!(
+                "Invalid --extract '{}': the container path '{}' must be absolute, e.g. /app/{}",
+                value,
+                path,
+                path
+            );
+        }
+        Ok(Self {
+            path: path.to_string(),
+            dest: PathBuf::from(dest),
+        })
+    }
+
+    /// Whether a line of failed build output reports this path as missing from the image
+    ///
+    /// BuildKit says `"/app/dist": not found`, podman and the classic builder `no such file or
+    /// directory`.
+    pub fn is_missing_in(&self, line: &str) -> bool {
+        let path = self.path.trim_end_matches('/');
+        line.contains(path)
+            && (line.contains("not found") || line.contains("no such file or directory"))
+    }
+}
+
+/// Run a build, writing `input` to its stdin and streaming its output
+///
+/// Output is echoed line by line as it arrives, and only the last `tail` lines are kept in
+/// memory, so arbitrarily long builds never buffer their whole log. Returns the exit status and
+/// that tail.
+pub fn run_build(
+    cmd: &mut Command,
+    input: Option<&[u8]>,
+    output: BuildOutput,
+    tail: usize,
+) -> Result<(ExitStatus, Vec<String>)> {
+    if input.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = cmd
+        .spawn()
+        .map_err(|source| PixiDockerError::spawn(cmd, source))?;
+
+    // Write from a thread so a child filling its output pipes can't deadlock us
+    let writer = input.zip(child.stdin.take()).map(|(input, mut stdin)| {
+        let input = input.to_vec();
+        std::thread::spawn(move || stdin.write_all(&input))
+    });
+
+    let lines = Arc::new(Mutex::new(VecDeque::with_capacity(tail)));
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|stdout| tee(stdout, output.clone(), false, tail, Arc::clone(&lines))),
+        child
+            .stderr
+            .take()
+            .map(|stderr| tee(stderr, output, true, tail, Arc::clone(&lines))),
+    ];
+    let status = child.wait().context("Failed to wait for the runtime")?;
+    for reader in readers.into_iter().flatten() {
+        reader
+            .join()
+            .expect("output reader panicked")
+            .context("Failed to read the runtime's output")?;
+    }
+
+    // A child that fails early closes the pipe; report its status rather than the write error
+    let written = writer.map(|writer| writer.join().expect("stdin writer panicked"));
+    if let Some(Err(err)) = written {
+        if status.success() {
+            return Err(PixiDockerError::caused_by(
+                "Failed to write to the runtime's stdin",
+                err,
+            ));
+        }
+    }
+
+    let lines = std::mem::take(&mut *lines.lock().expect("output lines poisoned"));
+    Ok((status, lines.into()))
+}
+
+/// Copy a child's output line by line, echoing it and keeping the last `tail` lines
+fn tee(
+    reader: impl Read + Send + 'static,
+    output: BuildOutput,
+    is_stderr: bool,
+    tail: usize,
+    lines: Arc<Mutex<VecDeque<String>>>,
+) -> JoinHandle<io::Result<()>> {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(());
+            }
+            echo(&output, &line, is_stderr)?;
+            let mut lines = lines.lock().expect("output lines poisoned");
+            keep_tail(&mut lines, tail, &line);
+        }
+    })
+}
+
+/// Show one line of build output the way `output` asks for
+pub fn echo(output: &BuildOutput, line: &[u8], is_stderr: bool) -> io::Result<()> {
+    match output {
+        BuildOutput::Echo if !is_stderr => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(line)?;
+            stdout.flush()
+        }
+        BuildOutput::Echo | BuildOutput::EchoToStderr => io::stderr().write_all(line),
+        BuildOutput::Prefixed(prefix) => {
+            let mut prefixed = prefix.as_bytes().to_vec();
+            prefixed.extend_from_slice(line);
+            if !prefixed.ends_with(b"\n") {
+                prefixed.push(b'\n');
+            }
+            match is_stderr {
+                true => io::stderr().lock().write_all(&prefixed),
+                false => io::stdout().lock().write_all(&prefixed),
+            }
+        }
+        BuildOutput::Hidden => Ok(()),
+    }
+}
+
+/// Keep `line` among the last `tail` lines of output
+pub fn keep_tail(lines: &mut VecDeque<String>, tail: usize, line: &[u8]) {
+    if tail == 0 {
+        return;
+    }
+    if lines.len() == tail {
+        lines.pop_front();
+    }
+    lines.push_back(String::from_utf8_lossy(line).trim_end().to_string());
+}
+
+/// Map a child process status onto our own exit code
+pub fn exit_code(status: ExitStatus) -> ExitCode {
+    status
+        .code()
+        .map(|code| ExitCode::from(code as u8))
+        .unwrap_or(ExitCode::FAILURE)
+}
+
+/// Split `run` arguments into docker flags and the container command at the first `--`
+///
+/// `command` holds what clap collected after a leading `--`. Without any `--` the command is
+/// `None`, and all arguments are passed to `docker run` as before.
+pub fn split_run_args(
+    docker_args: Vec<String>,
+    command: Vec<String>,
+) -> (Vec<String>, Option<Vec<String>>) {
+    if !command.is_empty() {
+        return (docker_args, Some(command));
+    }
+    match docker_args.iter().position(|arg| arg == "--") {
+        Some(separator) => {
+            let mut docker_args = docker_args;
+            let command = docker_args.split_off(separator + 1);
+            docker_args.pop();
+            (docker_args, Some(command))
+        }
+        None => (docker_args, None),
+    }
+}
+
+/// Whether `docker run` flags already publish ports
+pub fn publishes_ports(docker_args: &[String]) -> bool {
+    docker_args.iter().any(|arg| {
+        arg == "-P"
+            || arg == "--publish-all"
+            || arg.starts_with("-p")
+            || arg == "--publish"
+            || arg.starts_with("--publish=")
+    })
+}
+
+/// The `-p`/`--publish` values of `docker run` flags
+pub fn published_ports(docker_args: &[String]) -> Vec<String> {
+    let mut ports = Vec::new();
+    let mut args = docker_args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-p" || arg == "--publish" {
+            ports.extend(args.next().cloned());
+        } else if let Some(value) = arg.strip_prefix("--publish=") {
+            ports.push(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("-p").filter(|value| !value.is_empty()) {
+            ports.push(value.trim_start_matches('=').to_string());
+        }
+    }
+    ports
+}
+
+/// The container side of a port mapping like `9000:8080` or `127.0.0.1:9000:8080/udp`, with
+/// the protocol when given
+fn container_port(mapping: &str) -> &str {
+    mapping.rsplit(':').next().unwrap_or(mapping)
+}
+
+/// Combine the default port mappings with explicit `--publish` ones, which replace the
+/// default for the same container port
+///
+/// Fails on invalid mappings and on a container port published twice, by `publish` or
+/// together with the `-p` flags in `docker_args`.
+pub fn merge_ports(
+    defaults: &[String],
+    publish: &[String],
+    docker_args: &[String],
+) -> Result<Vec<String>> {
+    // Ports are compared with their protocol, `8080` being `8080/tcp`
+    let key = |mapping: &str| {
+        let port = container_port(mapping);
+        match port.contains('/') {
+            true => port.to_string(),
+            false => format!("{}/tcp", port),
+        }
+    };
+    let mut seen: Vec<(String, &str)> = Vec::new();
+    for mapping in publish.iter().chain(&published_ports(docker_args)) {
+        let port = container_port(mapping);
+        let number = port.split_once('/').map_or(port, |(number, _)| number);
+        if number.is_empty()
+            || !number
+                .split('-')
+                .all(|part| part.parse::<u16>().is_ok_and(|port| port > 0))
+        {
+            bail!(
+                "Invalid port mapping '{}': expected [host:]container, e.g. 9000:8080",
+                mapping
+            );
+        }
+        if let Some((_, first)) = seen.iter().find(|(seen, _)| *seen == key(mapping)) {
+            bail!(
+                "Container port {} is published twice, by {} and {}",
+                port,
+                first,
+                mapping
+            );
+        }
+        seen.push((key(mapping), mapping));
+    }
+
+    let overridden = |mapping: &String| publish.iter().any(|other| key(other) == key(mapping));
+    Ok(defaults
+        .iter()
+        .filter(|mapping| !overridden(mapping))
+        .chain(publish)
+        .cloned()
+        .collect())
+}
+
+/// Whether `docker run` flags already choose interactive, tty or detached mode
+pub fn sets_terminal_mode(docker_args: &[String]) -> bool {
+    docker_args.iter().any(|arg| match arg.strip_prefix("--") {
+        Some(long) => {
+            let name = long.split('=').next().unwrap_or_default();
+            matches!(name, "interactive" | "tty" | "detach")
+        }
+        None => arg
+            .strip_prefix('-')
+            .is_some_and(|short| !short.is_empty() && short.chars().all(|c| "itd".contains(c))),
+    })
+}
+
+/// The `-i`/`-t` flags for `docker run`, forced by `interactive` or detected from the terminal
+pub fn terminal_flags(interactive: Option<bool>) -> Option<&'static str> {
+    match terminal_mode(interactive) {
+        (true, true) => Some("-it"),
+        (true, false) => Some("-i"),
+        (false, _) => None,
+    }
+}
+
+/// Whether to keep stdin open and whether to allocate a tty, forced by `interactive` or
+/// detected from the terminal
+pub fn terminal_mode(interactive: Option<bool>) -> (bool, bool) {
+    let (stdin, stdout) = match interactive {
+        Some(forced) => (forced, forced),
+        None => (io::stdin().is_terminal(), io::stdout().is_terminal()),
+    };
+    // A tty is only possible when stdin is one
+    (stdin, stdin && stdout)
+}
+
+/// Whether `docker run` flags already set one of the given long options, e.g. `--name`
+pub fn sets_option(docker_args: &[String], names: &[&str]) -> bool {
+    docker_args.iter().any(|arg| {
+        let name = arg.split('=').next().unwrap_or_default();
+        names.contains(&name)
+    })
+}
+
+/// The value of a long option in `args`, given as `--name value` or `--name=value`
+pub fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).map(String::as_str)
+        } else {
+            arg.strip_prefix(name)?.strip_prefix('=')
+        }
+    })
+}
+
+/// Whether `docker run` flags already set the working directory
+pub fn sets_workdir(docker_args: &[String]) -> bool {
+    docker_args
+        .iter()
+        .any(|arg| arg.starts_with("-w") || arg == "--workdir" || arg.starts_with("--workdir="))
+}
+
+/// Run a command without output, returning whether it succeeded
+fn probe(mut cmd: Command) -> bool {
+    cmd.stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Whether an image with the given tag exists locally
+pub fn image_exists(config: &Config, image_tag: &str) -> bool {
+    let mut cmd = command(config);
+    cmd.arg("image").arg("inspect").arg(image_tag);
+    probe(cmd)
+}
+
+/// The `image inspect` JSON of a local image, none when it doesn't exist
+pub fn inspect_image(config: &Config, image_tag: &str) -> Option<serde_json::Value> {
+    let mut cmd = command(config);
+    cmd.arg("image")
+        .arg("inspect")
+        .arg(image_tag)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let output = cmd.output().ok().filter(|output| output.status.success())?;
+    let mut images: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    Some(images.get_mut(0)?.take())
+}
+
+/// All local tags of the given image repository, e.g. `my-app:1.0`
+pub fn image_tags(config: &Config, repository: &str) -> Result<Vec<String>, PixiDockerError> {
+    let mut cmd = command(config);
+    cmd.arg("image")
+        .arg("ls")
+        .arg("--format")
+        .arg("{{.Repository}}:{{.Tag}}")
+        .arg(repository);
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|source| PixiDockerError::spawn(&cmd, source))?;
+    if !output.status.success() {
+        return Err(PixiDockerError::exit("docker image ls", output.status));
+    }
+
+    // Never touch images outside the given repository, whatever the runtime's filter matched
+    let tags = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().rsplit_once(':'))
+        .filter(|(name, tag)| *name == repository && *tag != "<none>")
+        .map(|(name, tag)| format!("{}:{}", name, tag))
+        .collect();
+    Ok(tags)
+}
+
+/// Names and images of the running containers
+pub fn running_containers(config: &Config) -> Result<Vec<(String, String)>, PixiDockerError> {
+    let mut cmd = command(config);
+    cmd.arg("ps").arg("--format").arg("{{.Names}}\t{{.Image}}");
+    let output = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|source| PixiDockerError::spawn(&cmd, source))?;
+    if !output.status.success() {
+        return Err(PixiDockerError::exit("docker ps", output.status));
+    }
+
+    let containers = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, image)| (name.to_string(), image.to_string()))
+        .collect();
+    Ok(containers)
+}
+
+/// Whether the container with the given name is running, none when there is no such container
+pub fn container_running(config: &Config, name: &str) -> Option<bool> {
+    let mut cmd = command(config);
+    cmd.arg("container")
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{.State.Running}}")
+        .arg(name)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null());
+    let output = cmd.output().ok().filter(|output| output.status.success())?;
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Stop a container and remove it, unless stopping already removed it (`--rm`)
+pub fn remove_container(config: &Config, name: &str) -> Result<(), PixiDockerError> {
+    if container_running(config, name) == Some(true) {
+        let mut cmd = command(config);
+        cmd.arg("stop").arg(name).stdout(Stdio::null());
+        let status = status(&mut cmd)?;
+        if !status.success() {
+            return Err(PixiDockerError::exit("docker stop", status));
+        }
+    }
+    if container_running(config, name).is_some() {
+        let mut cmd = command(config);
+        cmd.arg("rm").arg(name).stdout(Stdio::null());
+        let status = status(&mut cmd)?;
+        if !status.success() {
+            return Err(PixiDockerError::exit("docker rm", status));
+        }
+    }
+    Ok(())
+}
+
+/// Whether an image or manifest with the given tag exists in its registry
+pub fn manifest_exists(config: &Config, image_tag: &str) -> bool {
+    let mut cmd = command(config);
+    cmd.arg("manifest").arg("inspect").arg(image_tag);
+    probe(cmd)
+}
+
+/// Run a command, failing with a descriptive error when it exits non-zero
+pub fn run_checked(docker_cmd: &mut Command, what: &str) -> Result<(), PixiDockerError> {
+    println!("Running: {:?}", docker_cmd);
+    let status = status(docker_cmd)?;
+    if !status.success() {
+        return Err(PixiDockerError::exit(what, status));
+    }
+    Ok(())
+}
+
+/// Fail with an explanation when the runtime cannot attach SBOM/provenance attestations
+pub fn check_attestation_support(config: &Config) -> Result<(), PixiDockerError> {
+    if is_podman(config) {
+        return Err(PixiDockerError::AttestationsUnsupported(
+            "SBOM and provenance attestations require docker buildx, which is not available with podman"
+                .to_string(),
+        ));
+    }
+
+    let mut cmd = command(config);
+    cmd.arg("buildx").arg("inspect");
+    let output = cmd.stderr(Stdio::null()).output();
+    let Some(output) = output.ok().filter(|o| o.status.success()) else {
+        return Err(PixiDockerError::AttestationsUnsupported(format!(
+            "SBOM and provenance attestations require docker buildx, but `{} buildx` is not available",
+            config.runtime()
+        )));
+    };
+
+    // The default `docker` driver silently drops attestations with the classic image store
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let driver = stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Driver:"))
+        .map(str::trim);
+    if driver == Some("docker") {
+        return Err(PixiDockerError::AttestationsUnsupported(
+            "The current buildx builder uses the `docker` driver, which does not support attestations; \
+             create one that does with `docker buildx create --use`"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let endpoint = |host: Option<&str>, context: Option<&str>, source: Option<&str>| Endpoint {
+            host: host.map(str::to_string),
+            context: context.map(str::to_string),
+            source: source.map(str::to_string),
+        };
+        for (host, local) in [
+            (None, true),
+            (Some("unix:///var/run/docker.sock"), true),
+            (Some("npipe:////./pipe/docker_engine"), true),
+            (Some("tcp://127.0.0.1:2375"), true),
+            (Some("tcp://[::1]:2375"), true),
+            (Some("ssh://ci@localhost"), true),
+            (Some("ssh://builder01"), false),
+            (Some("ssh://ci@builder01:2222"), false),
+            (Some("tcp://10.0.0.5:2376"), false),
+        ] {
+            assert_eq!(endpoint(host, None, None).is_local(), local, "{:?}", host);
+        }
+
+        assert_eq!(
+            endpoint(Some("ssh://builder01"), None, Some("DOCKER_HOST")).to_string(),
+            "ssh://builder01 (from DOCKER_HOST)"
+        );
+        assert_eq!(
+            endpoint(
+                Some("ssh://builder01"),
+                Some("builder"),
+                Some("cli --context")
+            )
+            .to_string(),
+            "ssh://builder01 (context 'builder', from cli --context)"
+        );
+        assert_eq!(
+            endpoint(None, Some("builder"), Some("DOCKER_CONTEXT")).to_string(),
+            "context 'builder' (from DOCKER_CONTEXT)"
+        );
+        assert_eq!(
+            Endpoint::default().to_string(),
+            "the local daemon (default context)"
+        );
+    }
+
+    #[test]
+    fn test_binds_host_paths() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(binds_host_paths(&args(&["-v", "/data:/data"])));
+        assert!(binds_host_paths(&args(&["-v./src:/app/src"])));
+        assert!(binds_host_paths(&args(&["--volume=/tmp:/tmp:ro"])));
+        assert!(binds_host_paths(&args(&[
+            "--mount",
+            "type=bind,source=/data,target=/data"
+        ])));
+        assert!(!binds_host_paths(&args(&["-v", "cache:/root/.cache"])));
+        assert!(!binds_host_paths(&args(&[
+            "--mount=type=volume,source=cache,target=/cache"
+        ])));
+        assert!(!binds_host_paths(&args(&["-p", "8080:8080", "--rm"])));
+
+        // Windows host paths: the drive letter isn't a volume name
+        assert!(binds_host_paths(&args(&["-v", r"C:\data:/data"])));
+        assert!(binds_host_paths(&args(&["-v", "D:/work:/work:ro"])));
+        assert_eq!(volume_source(r"C:\data:/data:ro"), r"C:\data");
+        assert_eq!(volume_source("cache:/root/.cache"), "cache");
+    }
+
+    #[test]
+    fn test_host_path() {
+        assert_eq!(host_path(Path::new("/home/me/app")), "/home/me/app");
+        assert_eq!(
+            host_path(Path::new(r"\\?\C:\Users\me\app")),
+            r"C:\Users\me\app"
+        );
+        assert_eq!(
+            host_path(Path::new(r"\\?\UNC\server\share\app")),
+            r"\\server\share\app"
+        );
+    }
+
+    #[test]
+    fn test_merge_ports() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let defaults = strings(&["8080:8080", "9090:9090"]);
+
+        assert_eq!(
+            published_ports(&strings(&["-p", "1:1", "-p2:2", "--publish=3:3", "-d"])),
+            ["1:1", "2:2", "3:3"]
+        );
+        assert_eq!(merge_ports(&defaults, &[], &[]).unwrap(), defaults);
+        // Only the default of the same container port is replaced
+        assert_eq!(
+            merge_ports(&defaults, &strings(&["127.0.0.1:9000:8080", "5432"]), &[]).unwrap(),
+            ["9090:9090", "127.0.0.1:9000:8080", "5432"]
+        );
+        assert_eq!(
+            merge_ports(&defaults, &strings(&["53:53/udp"]), &[]).unwrap(),
+            ["8080:8080", "9090:9090", "53:53/udp"]
+        );
+
+        let err = merge_ports(&defaults, &strings(&["9000:8080", "9001:8080/tcp"]), &[])
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "Container port 8080/tcp is published twice, by 9000:8080 and 9001:8080/tcp"
+        );
+        let err = merge_ports(
+            &[],
+            &strings(&["9000:8080"]),
+            &strings(&["-p", "8000:8080"]),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("by 9000:8080 and 8000:8080"), "{}", err);
+        assert!(merge_ports(&[], &strings(&["9000:http"]), &[]).is_err());
+        assert!(merge_ports(&[], &strings(&["9000:"]), &[]).is_err());
+    }
+
+    #[test]
+    fn test_output_spec() {
+        let local = OutputSpec::parse("type=local,dest=./dist").unwrap();
+        assert_eq!(
+            (local.kind.as_str(), local.dest.as_deref()),
+            ("local", Some("./dist"))
+        );
+        assert!(!local.takes_tags());
+        let oci = OutputSpec::parse("type=oci,dest=out.tar,compression=zstd").unwrap();
+        assert_eq!(oci.dest.as_deref(), Some("out.tar"));
+        assert!(oci.takes_tags());
+        assert!(OutputSpec::parse("type=registry").unwrap().dest.is_none());
+
+        // Bare paths are local exports, `-` a tar archive on stdout
+        assert_eq!(OutputSpec::parse("dist").unwrap().kind, "local");
+        assert_eq!(OutputSpec::parse("-").unwrap().kind, "tar");
+
+        let err = OutputSpec::parse("dest=out").unwrap_err().to_string();
+        assert!(err.contains("has no type"), "{}", err);
+        assert!(OutputSpec::parse("type=local,dist").is_err());
+        assert!(OutputSpec::parse("").is_err());
+    }
+
+    #[test]
+    fn test_mount_arg() {
+        let bind = Mount::Bind {
+            source: r"C:\Users\me\app".to_string(),
+            target: "/app".to_string(),
+        };
+        assert_eq!(bind.arg(), r"type=bind,source=C:\Users\me\app,target=/app");
+        let volume = Mount::Volume {
+            target: "/app/.pixi/envs/prod".to_string(),
+        };
+        assert_eq!(volume.arg(), "type=volume,target=/app/.pixi/envs/prod");
+        let odd = Mount::Bind {
+            source: "/home/me/a,b".to_string(),
+            target: "/app".to_string(),
+        };
+        assert_eq!(odd.arg(), r#"type=bind,"source=/home/me/a,b",target=/app"#);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_host_user() {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let id = |key: &str| -> u32 {
+            let line = status.lines().find(|l| l.starts_with(key)).unwrap();
+            // Real, effective, saved and filesystem ids
+            line.split_whitespace().nth(2).unwrap().parse().unwrap()
+        };
+        assert_eq!(host_user(), Some((id("Uid:"), id("Gid:"))));
+    }
+
+    #[test]
+    fn test_extract() {
+        let extract = Extract::parse("/app/dist/:C:\\out").unwrap();
+        assert_eq!(extract.path, "/app/dist/");
+        assert_eq!(extract.dest, PathBuf::from("C:\\out"));
+        assert!(extract.is_missing_in(
+            r#"ERROR: failed to solve: failed to compute cache key: "/app/dist": not found"#
+        ));
+        assert!(!extract.is_missing_in("ERROR: process \"/bin/sh -c make\" did not complete"));
+
+        assert!(Extract::parse("/app/dist").is_err());
+        assert!(Extract::parse(":dist").is_err());
+        let err = Extract::parse("dist:out").unwrap_err().to_string();
+        assert!(err.contains("must be absolute"), "{}", err);
+    }
+}
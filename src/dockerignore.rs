@@ -0,0 +1,208 @@
+use std::fs;
+use std::path::Path;
+
+/// The patterns of a `.dockerignore` file, matched the way the docker CLI does
+///
+/// Paths are relative to the build context and `/`-separated. The last pattern matching a path
+/// or one of its parent directories decides: excluded, unless it starts with `!`.
+#[derive(Debug, Default)]
+pub struct DockerIgnore {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+}
+
+impl DockerIgnore {
+    /// Read `.dockerignore` from the build context, empty when there is none
+    pub fn load(context_dir: &Path) -> std::io::Result<Self> {
+        match fs::read_to_string(context_dir.join(".dockerignore")) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern.trim()),
+                    None => (false, line),
+                };
+                let pattern = clean(pattern);
+                (!pattern.is_empty()).then(|| Pattern {
+                    glob: pattern.chars().collect(),
+                    negated,
+                })
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Whether `path` is left out of the build context
+    pub fn excludes(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            if pattern.matches(&path) {
+                excluded = !pattern.negated;
+            }
+        }
+        excluded
+    }
+
+    /// Whether an excluded directory may still contain included paths, so it has to be walked
+    pub fn has_exceptions(&self) -> bool {
+        self.patterns.iter().any(|pattern| pattern.negated)
+    }
+}
+
+impl Pattern {
+    /// Match the path itself or any of its parent directories
+    fn matches(&self, path: &[char]) -> bool {
+        glob_match(&self.glob, path)
+            || path
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| **c == '/')
+                .any(|(i, _)| glob_match(&self.glob, &path[..i]))
+    }
+}
+
+/// Normalize a pattern like docker's `filepath.Clean`, without a leading `/`
+fn clean(pattern: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in pattern.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Match a glob with `*`, `?`, `[...]`, `\` escapes and `**` spanning directories
+fn glob_match(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            glob_match(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| *c == '/' && glob_match(rest, &path[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+        ['*', rest @ ..] => {
+            let segment = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            (0..=segment).any(|i| glob_match(rest, &path[i..]))
+        }
+        ['?', rest @ ..] => matches!(path, [c, ..] if *c != '/') && glob_match(rest, &path[1..]),
+        ['[', class @ ..] => {
+            let Some((&c, path_rest)) = path.split_first() else {
+                return false;
+            };
+            match match_class(class, c) {
+                Some((true, rest)) => c != '/' && glob_match(rest, path_rest),
+                Some((false, _)) => false,
+                // An unterminated class matches a literal `[`
+                None => c == '[' && glob_match(class, path_rest),
+            }
+        }
+        ['\\', escaped, rest @ ..] => {
+            matches!(path, [c, ..] if c == escaped) && glob_match(rest, &path[1..])
+        }
+        [literal, rest @ ..] => {
+            matches!(path, [c, ..] if c == literal) && glob_match(rest, &path[1..])
+        }
+    }
+}
+
+/// Match `c` against a character class after its `[`, returning the result and the pattern
+/// after the closing `]`
+fn match_class(class: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negated, mut rest) = match class {
+        ['^' | '!', rest @ ..] => (true, rest),
+        rest => (false, rest),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [']', after @ ..] if !first => return Some((matched != negated, after)),
+            [low, '-', high, after @ ..] if *high != ']' => {
+                matched |= (*low..=*high).contains(&c);
+                rest = after;
+            }
+            ['\\', escaped, after @ ..] | [escaped, after @ ..] => {
+                matched |= *escaped == c;
+                rest = after;
+            }
+            [] => return None,
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excludes() {
+        let ignore = DockerIgnore::parse(
+            "# build outputs\n\
+             .pixi\n\
+             /target/\n\
+             *.log\n\
+             **/__pycache__\n\
+             docs/**/*.png\n\
+             data/[a-c]?.csv\n\
+             secrets\n\
+             !secrets/public.pem\n",
+        );
+        for (path, excluded) in [
+            (".pixi", true),
+            (".pixi/envs/default/bin/python", true),
+            ("src/.pixi", false),
+            ("target/debug/app", true),
+            ("build.log", true),
+            ("logs/build.log", false),
+            ("__pycache__/mod.pyc", true),
+            ("src/pkg/__pycache__/mod.pyc", true),
+            ("docs/logo.png", true),
+            ("docs/img/deep/logo.png", true),
+            ("docs/logo.svg", false),
+            ("data/a1.csv", true),
+            ("data/d1.csv", false),
+            ("data/a10.csv", false),
+            ("secrets/key.pem", true),
+            ("secrets/public.pem", false),
+            ("src/main.py", false),
+        ] {
+            assert_eq!(ignore.excludes(path), excluded, "{}", path);
+        }
+        assert!(ignore.has_exceptions());
+    }
+
+    #[test]
+    fn test_patterns_are_cleaned() {
+        let ignore = DockerIgnore::parse("./build/../dist\n\n   \n!\n[unterminated\n\\*.txt\n");
+        assert!(ignore.excludes("dist/app.whl"));
+        assert!(!ignore.excludes("build"));
+        assert!(ignore.excludes("[unterminated"));
+        assert!(ignore.excludes("*.txt"));
+        assert!(!ignore.excludes("notes.txt"));
+        assert!(!ignore.has_exceptions());
+    }
+}
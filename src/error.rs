@@ -0,0 +1,296 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Command, ExitCode, ExitStatus};
+
+/// The result of every library function; only the CLI in main.rs uses `anyhow`
+pub type Result<T, E = PixiDockerError> = std::result::Result<T, E>;
+
+/// Return early with an untyped [`PixiDockerError::Other`] error, like `anyhow::bail!`
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::error::PixiDockerError::msg(format!($($arg)*)))
+    };
+}
+pub(crate) use bail;
+
+/// The error of the library functions, e.g. `Config::from_file`, `PixiToml::from_file`,
+/// `DockerfileGenerator::generate` and the runtime helpers in `docker`
+///
+/// Failures callers don't need to tell apart are `Other`, which also adds context to a
+/// cause. Look for the typed error behind them with [`PixiDockerError::find`].
+#[derive(Debug, thiserror::Error)]
+pub enum PixiDockerError {
+    /// The config file couldn't be read
+    #[error("Failed to read config file {}", path.display())]
+    ConfigIo {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The config file isn't valid TOML or doesn't match the config schema
+    #[error("{0}")]
+    ConfigParse(ParseError),
+    /// `pixi.toml` or `pyproject.toml` isn't valid TOML or doesn't match the manifest schema
+    #[error("{0}")]
+    ManifestParse(ParseError),
+    /// The Dockerfile template failed to compile or render
+    #[error("Template error in {description}")]
+    TemplateRender {
+        /// Template file, or `<built-in NAME template>`
+        template: String,
+        line: Option<usize>,
+        /// Location, message and source excerpt
+        description: String,
+    },
+    /// The rendered Dockerfile failed the structural checks of the linter
+    #[error(
+        "The Dockerfile rendered from {template} is invalid (pass --no-lint to skip this check):{}",
+        .problems.iter().map(|problem| format!("\n  {}", problem)).collect::<String>()
+    )]
+    Lint {
+        /// Template file, or `<built-in NAME template>`
+        template: String,
+        /// One line per problem, e.g. `line 3: unknown instruction CPOY`
+        problems: Vec<String>,
+    },
+    /// The container runtime couldn't be started at all, e.g. because it isn't installed
+    #[error("Failed to run {program}")]
+    RuntimeSpawn {
+        program: String,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A path given to `build --extract` doesn't exist in the stage it's copied from
+    #[error(
+        "{path} not found in stage '{stage}' of environment '{environment}', so it can't be \
+         extracted; check the path, or pass --target to copy from another stage, e.g. `build`"
+    )]
+    ExtractNotFound {
+        path: String,
+        stage: String,
+        environment: String,
+    },
+    /// The container runtime ran but exited non-zero
+    #[error("{}", runtime_exit(.what, *.code, .note.as_deref(), .output_tail))]
+    RuntimeExit {
+        /// What failed, e.g. `Docker build`
+        what: String,
+        code: Option<i32>,
+        /// Extra context appended in parentheses, e.g. which Dockerfile was built
+        note: Option<String>,
+        /// The last lines of captured output, empty when the output went to the terminal
+        output_tail: Vec<String>,
+    },
+    /// A remote daemon didn't answer the probe before a command was sent to it
+    #[error("Cannot reach the docker daemon at {endpoint}: {reason}")]
+    DaemonUnreachable {
+        endpoint: String,
+        /// The last line the runtime printed, or `no answer`
+        reason: String,
+    },
+    /// The runtime can't attach SBOM or provenance attestations
+    #[error("{0}")]
+    AttestationsUnsupported(String),
+    /// Any other failure, or context added to the failure that caused it
+    #[error("{message}")]
+    Other {
+        message: String,
+        #[source]
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
+}
+
+/// Add context to an error, or turn a missing value into one, like `anyhow::Context`
+pub trait Context<T> {
+    fn context(self, message: impl Into<String>) -> Result<T>;
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T>;
+}
+
+impl<T, E: Error + Send + Sync + 'static> Context<T> for std::result::Result<T, E> {
+    fn context(self, message: impl Into<String>) -> Result<T> {
+        self.map_err(|err| PixiDockerError::caused_by(message, err))
+    }
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.map_err(|err| PixiDockerError::caused_by(message(), err))
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context(self, message: impl Into<String>) -> Result<T> {
+        self.ok_or_else(|| PixiDockerError::msg(message))
+    }
+
+    fn with_context<M: Into<String>>(self, message: impl FnOnce() -> M) -> Result<T> {
+        self.ok_or_else(|| PixiDockerError::msg(message()))
+    }
+}
+
+impl PixiDockerError {
+    /// An untyped error
+    pub fn msg(message: impl Into<String>) -> Self {
+        Self::Other {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// An untyped error explaining what `source` failed to do
+    pub fn caused_by(
+        message: impl Into<String>,
+        source: impl Error + Send + Sync + 'static,
+    ) -> Self {
+        Self::Other {
+            message: message.into(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// The first typed error in an error's chain, skipping the context `Other` adds
+    pub fn find<'a>(err: &'a (dyn Error + 'static)) -> Option<&'a Self> {
+        std::iter::successors(Some(err), |&err| err.source())
+            .filter_map(|cause| cause.downcast_ref::<Self>())
+            .find(|typed| !matches!(typed, Self::Other { .. }))
+    }
+
+    /// Exit code for the CLI: sysexits-style codes, or the runtime's own code when it failed
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::ConfigIo { .. } | Self::ConfigParse(_) => ExitCode::from(78),
+            Self::ManifestParse(_) | Self::TemplateRender { .. } | Self::Lint { .. } => {
+                ExitCode::from(65)
+            }
+            Self::RuntimeSpawn { .. } => ExitCode::from(127),
+            Self::ExtractNotFound { .. } => ExitCode::from(66),
+            Self::RuntimeExit { code, .. } => match code {
+                Some(code) if *code > 0 && *code < 256 => ExitCode::from(*code as u8),
+                _ => ExitCode::FAILURE,
+            },
+            Self::DaemonUnreachable { .. } | Self::AttestationsUnsupported(_) => ExitCode::FAILURE,
+            Self::Other { .. } => match Self::find(self) {
+                Some(typed) => typed.exit_code(),
+                None => ExitCode::FAILURE,
+            },
+        }
+    }
+
+    /// A runtime error for a command that couldn't be spawned
+    pub fn spawn(cmd: &Command, source: std::io::Error) -> Self {
+        Self::RuntimeSpawn {
+            program: cmd.get_program().to_string_lossy().into_owned(),
+            source,
+        }
+    }
+
+    /// A runtime error for a command that exited with `status`
+    pub fn exit(what: impl Into<String>, status: ExitStatus) -> Self {
+        Self::RuntimeExit {
+            what: what.into(),
+            code: status.code(),
+            note: None,
+            output_tail: Vec::new(),
+        }
+    }
+}
+
+/// An error and its causes on one line, like anyhow's `{:#}`
+pub fn one_line(err: &(dyn Error + 'static)) -> String {
+    std::iter::successors(Some(err), |&err| err.source())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+fn runtime_exit(what: &str, code: Option<i32>, note: Option<&str>, tail: &[String]) -> String {
+    let mut message = format!("{} failed with exit code: {:?}", what, code);
+    if let Some(note) = note {
+        message.push_str(&format!(" ({})", note));
+    }
+    if !tail.is_empty() {
+        message.push_str(&format!("\nLast {} lines of output:", tail.len()));
+        for line in tail {
+            message.push_str(&format!("\n  {}", line));
+        }
+    }
+    message
+}
+
+/// A TOML error located in its file
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: PathBuf,
+    /// 1-based line and column, when the parser reported a position
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    /// The offending source line with a caret under the column
+    pub excerpt: Option<String>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.path.display())?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "{}:{}:", line, column)?;
+        }
+        write!(f, " {}", self.message)?;
+        if let Some(excerpt) = &self.excerpt {
+            f.write_str(excerpt)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_through_context() {
+        let err = Err::<(), _>(PixiDockerError::RuntimeExit {
+            what: "Docker build".to_string(),
+            code: Some(7),
+            note: Some("Dockerfile: Dockerfile.prod".to_string()),
+            output_tail: vec!["step 3".to_string(), "step 4".to_string()],
+        })
+        .context("Failed to build prod")
+        .unwrap_err();
+
+        let typed = PixiDockerError::find(&err).unwrap();
+        assert_eq!(typed.exit_code(), ExitCode::from(7));
+        assert_eq!(err.exit_code(), ExitCode::from(7));
+        assert_eq!(
+            typed.to_string(),
+            "Docker build failed with exit code: Some(7) (Dockerfile: Dockerfile.prod)\n\
+             Last 2 lines of output:\n  step 3\n  step 4"
+        );
+        assert!(PixiDockerError::find(&PixiDockerError::msg("other")).is_none());
+        assert_eq!(PixiDockerError::msg("other").exit_code(), ExitCode::FAILURE);
+    }
+
+    #[test]
+    fn test_context() {
+        let lint = PixiDockerError::Lint {
+            template: "t.j2".to_string(),
+            problems: vec!["line 1: unknown instruction CPOY".to_string()],
+        };
+        let err = Err::<(), _>(lint)
+            .context("Failed to generate")
+            .with_context(|| format!("Failed to build {}", "prod"))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Failed to build prod");
+        assert_eq!(err.exit_code(), ExitCode::from(65));
+        assert_eq!(
+            one_line(&err),
+            "Failed to build prod: Failed to generate: The Dockerfile rendered from t.j2 is \
+             invalid (pass --no-lint to skip this check):\n  line 1: unknown instruction CPOY"
+        );
+
+        let err = None::<u8>.context("No tag").unwrap_err();
+        assert!(matches!(err, PixiDockerError::Other { source: None, .. }));
+    }
+}
@@ -0,0 +1,170 @@
+//! Remote build contexts: docker fetches a git URL as the build context by itself, while a
+//! shallow checkout of the same revision provides the pixi manifest to generate the Dockerfile from
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{bail, Context, Result};
+
+use crate::network::NetworkPolicy;
+
+/// A git build context in docker's syntax, `repository#ref:subdir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitContext {
+    /// The URL as given, passed on to the runtime
+    pub url: String,
+    /// The repository to fetch from
+    pub repository: String,
+    /// Branch, tag or commit, the remote's default branch when `None`
+    pub reference: Option<String>,
+    /// Directory within the repository that holds the project
+    pub subdir: Option<String>,
+}
+
+/// Whether docker treats a build context as a git repository rather than a path or tarball URL
+pub fn is_git_url(context: &str) -> bool {
+    if ["git://", "git@", "github.com/"]
+        .iter()
+        .any(|prefix| context.starts_with(prefix))
+    {
+        return true;
+    }
+    let repository = context.split_once('#').map_or(context, |(url, _)| url);
+    (context.starts_with("https://") || context.starts_with("http://"))
+        && repository.ends_with(".git")
+}
+
+impl GitContext {
+    /// Parse a git build context, e.g. `https://github.com/org/repo.git#main:app`
+    pub fn parse(url: &str) -> Result<Self> {
+        if !is_git_url(url) {
+            bail!(
+                "git_context '{}' is not a git URL; use e.g. https://github.com/org/repo.git#main, \
+                 git@github.com:org/repo.git or github.com/org/repo",
+                url
+            );
+        }
+        let (repository, fragment) = url.split_once('#').unwrap_or((url, ""));
+        let (reference, subdir) = fragment.split_once(':').unwrap_or((fragment, ""));
+        let subdir = subdir.trim_matches('/');
+        if Path::new(subdir)
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)))
+        {
+            bail!(
+                "git_context '{}' has an invalid subdirectory '{}'; it must be relative to the \
+                 repository root",
+                url,
+                subdir
+            );
+        }
+        // Docker fetches `github.com/...` shorthands over HTTPS
+        let repository = match repository.strip_prefix("github.com/") {
+            Some(path) => format!("https://github.com/{}", path),
+            None => repository.to_string(),
+        };
+        Ok(Self {
+            url: url.to_string(),
+            repository,
+            reference: (!reference.is_empty()).then(|| reference.to_string()),
+            subdir: (!subdir.is_empty()).then(|| subdir.to_string()),
+        })
+    }
+
+    /// Shallowly fetch the revision into a temporary directory, removed again when dropped
+    pub fn checkout(&self, network: NetworkPolicy) -> Result<Checkout> {
+        network.require(
+            &format!("fetch {}", self.url),
+            "build from a local checkout instead of `git_context`",
+        )?;
+        let root = std::env::temp_dir().join(format!("pixi-docker-git-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create {}", root.display()))?;
+        // Own the directory before anything can fail, so it's removed on every path
+        let checkout = Checkout {
+            project_dir: match &self.subdir {
+                Some(subdir) => root.join(subdir),
+                None => root.clone(),
+            },
+            root,
+        };
+
+        let reference = self.reference.as_deref().unwrap_or("HEAD");
+        git(&checkout.root, &["init", "-q"])?;
+        git(
+            &checkout.root,
+            &["fetch", "-q", "--depth", "1", &self.repository, reference],
+        )
+        .with_context(|| format!("Failed to fetch {} from {}", reference, self.repository))?;
+        git(&checkout.root, &["checkout", "-q", "FETCH_HEAD"])?;
+        if !checkout.project_dir.is_dir() {
+            bail!(
+                "{} has no directory '{}'",
+                self.url,
+                self.subdir.as_deref().unwrap_or_default()
+            );
+        }
+        Ok(checkout)
+    }
+}
+
+/// A temporary checkout of a git build context
+#[derive(Debug)]
+pub struct Checkout {
+    root: PathBuf,
+    /// The repository's project directory, i.e. the root or the URL's subdirectory
+    pub project_dir: PathBuf,
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Run git in `dir`, failing with its error output
+fn git(dir: &Path, args: &[&str]) -> Result<()> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git, which `git_context` needs to read the remote project")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let context = GitContext::parse("https://github.com/org/repo.git#v1.0:app/").unwrap();
+        assert_eq!(context.repository, "https://github.com/org/repo.git");
+        assert_eq!(context.reference.as_deref(), Some("v1.0"));
+        assert_eq!(context.subdir.as_deref(), Some("app"));
+
+        let context = GitContext::parse("github.com/org/repo").unwrap();
+        assert_eq!(context.repository, "https://github.com/org/repo");
+        assert_eq!((context.reference, context.subdir), (None, None));
+
+        let context = GitContext::parse("git@github.com:org/repo.git#:sub").unwrap();
+        assert_eq!(context.repository, "git@github.com:org/repo.git");
+        assert_eq!(context.reference, None);
+        assert_eq!(context.subdir.as_deref(), Some("sub"));
+
+        // Plain HTTP URLs are tarballs or Dockerfiles to docker
+        assert!(!is_git_url("https://example.com/context.tar.gz"));
+        let err = GitContext::parse("./repo").unwrap_err().to_string();
+        assert!(err.contains("is not a git URL"), "{}", err);
+        assert!(GitContext::parse("https://host/repo.git#main:../up").is_err());
+    }
+}
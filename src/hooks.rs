@@ -0,0 +1,53 @@
+use crate::error::{bail, Context, Result};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Run hook commands through the shell in `dir`, stopping at the first failure
+///
+/// With `to_stderr`, hooks and their output go to stderr so stdout stays machine-readable.
+pub fn run(
+    stage: &str,
+    commands: &[String],
+    dir: &Path,
+    envs: &[(&str, &str)],
+    to_stderr: bool,
+) -> Result<()> {
+    for command in commands {
+        let mut cmd = shell(command);
+        if to_stderr {
+            eprintln!("Running {} hook: {}", stage, command);
+            cmd.stdout(io::stderr());
+        } else {
+            println!("Running {} hook: {}", stage, command);
+        }
+        let status = cmd
+            .current_dir(dir)
+            .envs(envs.iter().copied())
+            .status()
+            .with_context(|| format!("Failed to start {} hook: {}", stage, command))?;
+        if !status.success() {
+            bail!(
+                "{} hook failed with exit code {:?}: {}",
+                stage,
+                status.code(),
+                command
+            );
+        }
+    }
+    Ok(())
+}
+
+fn shell(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
@@ -0,0 +1,623 @@
+use std::collections::BTreeSet;
+
+/// The pixi image, whose tag becomes `pixi_version`
+const PIXI_IMAGE: &str = "ghcr.io/prefix-dev/pixi";
+
+// Hints for instructions that have no config equivalent
+const FINAL_FROM_STAGE: &str =
+    "the final stage builds on an earlier stage; pixi-docker generates its own stages";
+const MULTI_STAGE: &str =
+    "multi-stage structure is replaced by the generated build and production stages";
+const COPY_CONTEXT: &str = "the build stage always copies the whole project; \
+    list what the final image needs in `copy_files`";
+const RUN: &str = "install packages with pixi and move build steps into pixi tasks \
+    referenced by `build_command`";
+
+/// One Dockerfile instruction with its continuation lines joined
+#[derive(Debug, PartialEq, Eq)]
+pub struct Instruction {
+    /// Upper-cased keyword, e.g. `RUN`
+    pub keyword: String,
+    pub args: String,
+    /// 1-based line the instruction starts on
+    pub line: usize,
+}
+
+/// Split a Dockerfile into instructions
+///
+/// Handles the `escape` parser directive, line continuations and comment lines, including
+/// comments between continuation lines. The bodies of heredocs (`RUN <<EOF`) are skipped.
+pub fn parse_dockerfile(content: &str) -> Vec<Instruction> {
+    let escape = escape_directive(content).unwrap_or('\\');
+    let mut instructions = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    // Terminators of the heredocs opened by the last instruction, in order
+    let mut heredocs: Vec<String> = Vec::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(terminator) = heredocs.first() {
+            if trimmed == terminator {
+                heredocs.remove(0);
+            }
+            continue;
+        }
+        if trimmed.starts_with('#') || (trimmed.is_empty() && current.is_none()) {
+            continue;
+        }
+        let (text, continues) = match trimmed.strip_suffix(escape) {
+            Some(text) => (text, true),
+            None => (trimmed, false),
+        };
+        let (_, args) = current.get_or_insert_with(|| (index + 1, String::new()));
+        if !args.is_empty() && !text.is_empty() {
+            args.push(' ');
+        }
+        args.push_str(text.trim_end());
+        if !continues {
+            if let Some(instruction) = current.take().and_then(instruction) {
+                if matches!(instruction.keyword.as_str(), "RUN" | "COPY" | "ADD") {
+                    heredocs = heredoc_terminators(&instruction.args);
+                }
+                instructions.push(instruction);
+            }
+        }
+    }
+    // A continuation on the last line ends the instruction
+    instructions.extend(current.and_then(instruction));
+    instructions
+}
+
+fn instruction((line, text): (usize, String)) -> Option<Instruction> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let (keyword, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    Some(Instruction {
+        keyword: keyword.to_ascii_uppercase(),
+        args: args.trim().to_string(),
+        line,
+    })
+}
+
+/// The words ending the heredocs of an instruction, e.g. `EOF` for `RUN <<-"EOF" bash`
+fn heredoc_terminators(args: &str) -> Vec<String> {
+    let mut terminators = Vec::new();
+    let mut rest = args;
+    while let Some(start) = rest.find("<<") {
+        rest = &rest[start + 2..];
+        if rest.starts_with('<') {
+            // A `<<<` here-string, not a heredoc
+            rest = rest.trim_start_matches('<');
+            continue;
+        }
+        let word = rest.strip_prefix('-').unwrap_or(rest);
+        let word = word.trim_start_matches(['"', '\'']);
+        let end = word
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(word.len());
+        if end > 0 {
+            terminators.push(word[..end].to_string());
+        }
+    }
+    terminators
+}
+
+/// The escape character from a leading `# escape=` parser directive
+fn escape_directive(content: &str) -> Option<char> {
+    for line in content.lines() {
+        let directive = line.trim().strip_prefix('#')?;
+        let (key, value) = directive.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("escape") {
+            return value.trim().chars().next();
+        }
+    }
+    None
+}
+
+/// Parse the JSON array of an exec-form instruction such as `CMD ["python", "app.py"]`
+pub fn exec_form(args: &str) -> Option<Vec<String>> {
+    let inner = args.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let mut values = Vec::new();
+    let mut chars = inner.trim().chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next()? != '"' {
+            return None;
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    'u' => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    }
+                    other => value.push(other),
+                },
+                c => value.push(c),
+            }
+        }
+        values.push(value);
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(',') => while chars.next_if(|c| c.is_whitespace()).is_some() {},
+            None => break,
+            Some(_) => return None,
+        }
+    }
+    Some(values)
+}
+
+/// A command given in exec or shell form
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Exec(Vec<String>),
+    Shell(String),
+}
+
+impl Command {
+    fn parse(args: &str) -> Self {
+        match exec_form(args) {
+            Some(argv) => Command::Exec(argv),
+            None => Command::Shell(args.to_string()),
+        }
+    }
+}
+
+/// What an import could read from a Dockerfile
+#[derive(Debug, Default, PartialEq)]
+pub struct Imported {
+    pub pixi_version: Option<String>,
+    pub base_image: Option<String>,
+    pub multi_stage: Option<bool>,
+    pub ports: Vec<u16>,
+    pub copy_files: Vec<String>,
+    /// `(key, value)` pairs of `ENV` instructions
+    pub env: Vec<(String, String)>,
+    pub workdir: Option<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub shell_entrypoint: Option<String>,
+    pub stop_signal: Option<String>,
+    /// Instructions without an equivalent, with a hint on what to do instead
+    pub unmapped: Vec<(usize, String, &'static str)>,
+}
+
+impl Imported {
+    fn flag(&mut self, instruction: &Instruction, hint: &'static str) {
+        let text = format!("{} {}", instruction.keyword, instruction.args);
+        self.unmapped.push((instruction.line, text, hint));
+    }
+}
+
+/// Map a Dockerfile's instructions onto config values
+///
+/// Ports, the working directory, the command and the stop signal come from the final stage;
+/// copied paths and environment variables from every stage.
+pub fn import(content: &str) -> Imported {
+    let instructions = parse_dockerfile(content);
+    let mut imported = Imported::default();
+    let stages = instructions.iter().filter(|i| i.keyword == "FROM").count();
+    let mut stage = 0;
+    let mut stage_names = BTreeSet::new();
+    let mut copy_files = BTreeSet::new();
+    let mut entrypoint = None;
+    let mut cmd = None;
+
+    for instruction in &instructions {
+        let args = instruction.args.as_str();
+        let in_final_stage = stage == stages;
+        match instruction.keyword.as_str() {
+            "FROM" => {
+                stage += 1;
+                let words: Vec<&str> = args
+                    .split_whitespace()
+                    .filter(|w| !w.starts_with("--"))
+                    .collect();
+                let Some(image) = words.first().copied() else {
+                    continue;
+                };
+                if let [_, keyword, name] = words.as_slice() {
+                    if keyword.eq_ignore_ascii_case("as") {
+                        stage_names.insert(name.to_ascii_lowercase());
+                    }
+                }
+                if let Some(tag) = image
+                    .strip_prefix(PIXI_IMAGE)
+                    .and_then(|rest| rest.strip_prefix(':'))
+                {
+                    let tag = tag.split('@').next().unwrap_or(tag);
+                    imported.pixi_version = Some(tag.to_string());
+                    if stage == stages && stages == 1 {
+                        imported.multi_stage = Some(false);
+                    }
+                } else if stage == stages {
+                    if stage_names.contains(&image.to_ascii_lowercase()) {
+                        imported.flag(instruction, FINAL_FROM_STAGE);
+                    } else {
+                        imported.base_image = Some(image.to_string());
+                    }
+                }
+                if stage > 1 && stage == stages {
+                    imported.flag(instruction, MULTI_STAGE);
+                }
+            }
+            "EXPOSE" => {
+                if !in_final_stage {
+                    continue;
+                }
+                for port in args.split_whitespace() {
+                    let (number, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+                    match number.parse::<u16>() {
+                        Ok(number) if number > 0 && protocol.eq_ignore_ascii_case("tcp") => {
+                            if !imported.ports.contains(&number) {
+                                imported.ports.push(number);
+                            }
+                        }
+                        _ => imported.flag(
+                            instruction,
+                            "only fixed TCP ports can be configured as `ports`",
+                        ),
+                    }
+                }
+            }
+            "ENV" => imported.env.extend(env_pairs(args)),
+            "WORKDIR" if in_final_stage => {
+                imported.workdir = Some(args.to_string()).filter(|w| w.starts_with('/'));
+                if imported.workdir.is_none() {
+                    imported.flag(
+                        instruction,
+                        "only an absolute path can be configured as `run.workdir`",
+                    );
+                }
+            }
+            "COPY" => {
+                let words = copy_words(args);
+                if words.iter().any(|w| w.starts_with("--from")) {
+                    imported.flag(
+                        instruction,
+                        "copies between stages are replaced by the generated stages",
+                    );
+                    continue;
+                }
+                let sources: Vec<&String> = words.iter().filter(|w| !w.starts_with("--")).collect();
+                if let Some((_, sources)) = sources.split_last() {
+                    for source in sources {
+                        if matches!(source.as_str(), "." | "./") {
+                            imported.flag(instruction, COPY_CONTEXT);
+                        } else {
+                            copy_files.insert(source.trim_start_matches("./").to_string());
+                        }
+                    }
+                }
+            }
+            "CMD" if in_final_stage => cmd = Some(Command::parse(args)),
+            "ENTRYPOINT" if in_final_stage => entrypoint = Some(Command::parse(args)),
+            "STOPSIGNAL" if in_final_stage => imported.stop_signal = Some(args.to_string()),
+            "RUN" => imported.flag(instruction, RUN),
+            "ADD" => imported.flag(
+                instruction,
+                "use `COPY`-style `copy_files`, or download in a pixi task",
+            ),
+            "WORKDIR" | "CMD" | "ENTRYPOINT" | "STOPSIGNAL" => {}
+            _ => imported.flag(instruction, "no pixi_docker.toml equivalent"),
+        }
+    }
+
+    imported.copy_files = copy_files.into_iter().collect();
+    // Docker appends CMD to an exec-form ENTRYPOINT and ignores it after a shell-form one
+    match (entrypoint, cmd) {
+        (Some(Command::Exec(mut argv)), Some(Command::Exec(args))) => {
+            argv.extend(args);
+            imported.entrypoint = Some(argv);
+        }
+        (Some(Command::Exec(argv)), _) | (None, Some(Command::Exec(argv))) => {
+            imported.entrypoint = Some(argv)
+        }
+        (Some(Command::Shell(command)), _) | (None, Some(Command::Shell(command))) => {
+            imported.shell_entrypoint = Some(command)
+        }
+        (None, None) => {}
+    }
+    imported
+}
+
+/// The words of a `COPY`, from either its shell or its JSON form
+fn copy_words(args: &str) -> Vec<String> {
+    let flags: Vec<String> = args
+        .split_whitespace()
+        .take_while(|w| w.starts_with("--"))
+        .map(str::to_string)
+        .collect();
+    let rest = args
+        .split_whitespace()
+        .skip(flags.len())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let paths =
+        exec_form(&rest).unwrap_or_else(|| rest.split_whitespace().map(str::to_string).collect());
+    flags.into_iter().chain(paths).collect()
+}
+
+/// `(key, value)` pairs of an `ENV` instruction, in `KEY=value` or legacy `KEY value` form
+fn env_pairs(args: &str) -> Vec<(String, String)> {
+    let Some(first) = args.split_whitespace().next() else {
+        return Vec::new();
+    };
+    if !first.contains('=') {
+        let value = args[first.len()..].trim();
+        return vec![(first.to_string(), value.to_string())];
+    }
+    shlex::split(args)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Render an import as a `pixi_docker.toml`, with comments for everything it couldn't map
+pub fn to_config(imported: &Imported, source: &str, environment: &str) -> String {
+    let string = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let list = |values: &[String]| {
+        let values = values
+            .iter()
+            .map(|v| toml::Value::String(v.clone()))
+            .collect();
+        toml::Value::Array(values).to_string()
+    };
+
+    let mut out = format!(
+        "# Imported from {} by `pixi-docker import`; review it before building\n\n\
+         schema_version = {}\n\n[docker]\n",
+        source,
+        crate::migrate::SCHEMA_VERSION
+    );
+    out.push_str(&format!("environment = {}\n", string(environment)));
+    if let Some(version) = &imported.pixi_version {
+        out.push_str(&format!("pixi_version = {}\n", string(version)));
+    }
+    if let Some(multi_stage) = imported.multi_stage {
+        out.push_str(&format!("multi_stage = {}\n", multi_stage));
+    }
+    if let Some(image) = &imported.base_image {
+        out.push_str(&format!("base_image = {}\n", string(image)));
+    }
+    if !imported.ports.is_empty() {
+        let ports: Vec<String> = imported.ports.iter().map(u16::to_string).collect();
+        out.push_str(&format!("ports = [{}]\n", ports.join(", ")));
+    }
+    if !imported.copy_files.is_empty() {
+        out.push_str(&format!("copy_files = {}\n", list(&imported.copy_files)));
+    }
+    if let Some(argv) = &imported.entrypoint {
+        out.push_str(&format!("entrypoint = {}\n", list(argv)));
+    } else if let Some(command) = &imported.shell_entrypoint {
+        out.push_str(&format!("entrypoint = {}\n", string(command)));
+    }
+    // Generated images always build in /app
+    if let Some(workdir) = imported.workdir.as_deref().filter(|w| *w != "/app") {
+        out.push_str(&format!("run.workdir = {}\n", string(workdir)));
+    }
+    if let Some(signal) = &imported.stop_signal {
+        out.push_str(&format!("stop_signal = {}\n", string(signal)));
+    }
+
+    if !imported.env.is_empty() {
+        out.push_str(
+            "\n# Environment variables have no config key; set them in pixi.toml so that\n",
+        );
+        out.push_str(
+            "# the activation script exports them in the container:\n#\n# [activation.env]\n",
+        );
+        for (key, value) in &imported.env {
+            out.push_str(&format!("# {} = {}\n", key, string(value)));
+        }
+    }
+    if !imported.unmapped.is_empty() {
+        out.push_str("\n# Instructions that could not be mapped:\n");
+        for (line, text, hint) in &imported.unmapped {
+            out.push_str(&format!("#\n# line {}: {}\n#   {}\n", line, text, hint));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Entrypoint};
+
+    #[test]
+    fn test_parse_continuations_and_comments() {
+        let instructions = parse_dockerfile(
+            "# syntax=docker/dockerfile:1\n\
+             FROM python:3.12 AS base\n\
+             \n\
+             RUN apt-get update && \\\n\
+             \x20   # comments inside a continuation are dropped\n\
+             \x20   apt-get install -y curl \\\n\
+             \x20     git\n\
+             copy src/ /app/src/\n\
+             CMD [\"python\", \"-m\", \"app\"]  \\\n",
+        );
+        assert_eq!(
+            instructions,
+            [
+                Instruction {
+                    keyword: "FROM".to_string(),
+                    args: "python:3.12 AS base".to_string(),
+                    line: 2,
+                },
+                Instruction {
+                    keyword: "RUN".to_string(),
+                    args: "apt-get update && apt-get install -y curl git".to_string(),
+                    line: 4,
+                },
+                Instruction {
+                    keyword: "COPY".to_string(),
+                    args: "src/ /app/src/".to_string(),
+                    line: 8,
+                },
+                Instruction {
+                    keyword: "CMD".to_string(),
+                    args: "[\"python\", \"-m\", \"app\"]".to_string(),
+                    line: 9,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_escape_directive() {
+        let instructions = parse_dockerfile(
+            "# escape=`\nFROM mcr.microsoft.com/windows/servercore\nCOPY . C:\\app\\\nRUN dir `\n  C:\\app\n",
+        );
+        assert_eq!(instructions[1].args, ". C:\\app\\");
+        assert_eq!(instructions[2].args, "dir C:\\app");
+    }
+
+    #[test]
+    fn test_parse_heredocs() {
+        let instructions = parse_dockerfile(
+            "FROM ubuntu\n\
+             RUN <<EOF\n\
+             apt-get update\n\
+             EXPOSE not-an-instruction\n\
+             EOF\n\
+             COPY <<-\"A\" /a <<B /b\n\
+             a\n\
+             A\n\
+             b\n\
+             B\n\
+             RUN cat <<< \"here-string\"\n\
+             CMD [\"bash\"]\n",
+        );
+        let keywords: Vec<&str> = instructions.iter().map(|i| i.keyword.as_str()).collect();
+        assert_eq!(keywords, ["FROM", "RUN", "COPY", "RUN", "CMD"]);
+        assert_eq!(instructions[4].line, 12);
+    }
+
+    #[test]
+    fn test_exec_form() {
+        assert_eq!(
+            exec_form(r#"["python", "-c", "print(\"hi\")\n", "\u00e9"]"#).unwrap(),
+            ["python", "-c", "print(\"hi\")\n", "é"]
+        );
+        assert_eq!(exec_form("[]").unwrap(), Vec::<String>::new());
+        assert!(exec_form("python app.py").is_none());
+        assert!(exec_form(r#"["python" "app.py"]"#).is_none());
+    }
+
+    #[test]
+    fn test_import_multi_stage_python() {
+        let imported = import(
+            r#"FROM python:3.12 AS build
+WORKDIR /build
+COPY requirements.txt .
+RUN pip install --prefix=/install -r requirements.txt
+
+FROM python:3.12-slim
+ENV PYTHONUNBUFFERED=1 APP_ENV="production mode"
+ENV LEGACY some value
+COPY --from=build /install /usr/local
+COPY ./src/ static/ /srv/app/
+WORKDIR /srv/app
+EXPOSE 8000 8000/tcp 9000/udp
+USER app
+STOPSIGNAL SIGINT
+ENTRYPOINT ["gunicorn"]
+CMD ["app:wsgi", "--bind", "0.0.0.0:8000"]
+"#,
+        );
+        assert_eq!(imported.base_image.as_deref(), Some("python:3.12-slim"));
+        assert_eq!(imported.multi_stage, None);
+        assert_eq!(imported.ports, [8000]);
+        assert_eq!(imported.copy_files, ["requirements.txt", "src/", "static/"]);
+        assert_eq!(
+            imported.env,
+            [
+                ("PYTHONUNBUFFERED".to_string(), "1".to_string()),
+                ("APP_ENV".to_string(), "production mode".to_string()),
+                ("LEGACY".to_string(), "some value".to_string()),
+            ]
+        );
+        assert_eq!(imported.workdir.as_deref(), Some("/srv/app"));
+        assert_eq!(
+            imported.entrypoint.as_deref().unwrap(),
+            ["gunicorn", "app:wsgi", "--bind", "0.0.0.0:8000"]
+        );
+        assert_eq!(imported.stop_signal.as_deref(), Some("SIGINT"));
+        let unmapped: Vec<usize> = imported.unmapped.iter().map(|(line, _, _)| *line).collect();
+        assert_eq!(unmapped, [4, 6, 9, 12, 13]);
+
+        let config: Config = toml::from_str(&to_config(&imported, "Dockerfile", "prod")).unwrap();
+        assert_eq!(config.docker.ports, [8000]);
+        assert_eq!(config.docker.run.workdir.as_deref(), Some("/srv/app"));
+        assert_eq!(config.stop_signal("prod").unwrap(), Some("SIGINT"));
+        assert!(
+            matches!(config.docker.entrypoint, Some(Entrypoint::Exec(ref argv)) if argv.len() == 4)
+        );
+    }
+
+    #[test]
+    fn test_import_pixi_single_stage() {
+        let content = "FROM ghcr.io/prefix-dev/pixi:0.41.4\n\
+                       WORKDIR /app\n\
+                       COPY . .\n\
+                       RUN pixi install --locked\n\
+                       EXPOSE $PORT\n\
+                       CMD pixi run serve --port 8080\n\
+                       WORKDIR $APP_HOME\n";
+        let imported = import(content);
+        assert_eq!(imported.pixi_version.as_deref(), Some("0.41.4"));
+        assert_eq!(imported.multi_stage, Some(false));
+        assert_eq!(imported.base_image, None);
+        assert!(imported.copy_files.is_empty());
+        assert_eq!(
+            imported.shell_entrypoint.as_deref(),
+            Some("pixi run serve --port 8080")
+        );
+
+        let config = to_config(&imported, "services/api/Dockerfile", "default");
+        assert!(config.starts_with("# Imported from services/api/Dockerfile"));
+        assert!(config.contains("# line 4: RUN pixi install --locked\n"));
+        assert!(config.contains("# line 5: EXPOSE $PORT\n"));
+        assert!(config.contains("# line 7: WORKDIR $APP_HOME\n"));
+        assert!(!config.contains("run.workdir = "));
+        let config: Config = toml::from_str(&config).unwrap();
+        assert_eq!(config.docker.environment, "default");
+        assert_eq!(config.docker.pixi_version.as_deref(), Some("0.41.4"));
+        assert!(!config.docker.multi_stage);
+    }
+
+    #[test]
+    fn test_import_node_with_shell_entrypoint() {
+        let imported = import(
+            "FROM node:20-alpine\n\
+             WORKDIR /usr/src/app\n\
+             COPY [\"package.json\", \"package-lock.json\", \"./\"]\n\
+             RUN npm ci\n\
+             COPY --chown=node:node dist dist\n\
+             ENTRYPOINT exec node dist/server.js\n\
+             CMD [\"--verbose\"]\n",
+        );
+        assert_eq!(imported.base_image.as_deref(), Some("node:20-alpine"));
+        assert_eq!(
+            imported.copy_files,
+            ["dist", "package-lock.json", "package.json"]
+        );
+        // A shell-form ENTRYPOINT ignores CMD
+        assert_eq!(
+            imported.shell_entrypoint.as_deref(),
+            Some("exec node dist/server.js")
+        );
+        assert_eq!(imported.entrypoint, None);
+    }
+}
@@ -0,0 +1,196 @@
+use dialoguer::{Confirm, Input, Select};
+
+use crate::config::DEFAULT_BASE_IMAGE;
+use crate::error::{Context, PixiDockerError, Result};
+use crate::pixi::PixiToml;
+
+/// Label of the choice that leaves the entrypoint or build command unset
+const NO_TASK: &str = "(none)";
+
+const PROMPT_FAILED: &str = "Failed to read the answer";
+
+/// The settings `init` writes into a new config
+#[derive(Debug, PartialEq, Eq)]
+pub struct Answers {
+    pub environment: String,
+    pub entrypoint: Option<String>,
+    pub build_command: Option<String>,
+    pub ports: Vec<u16>,
+    pub multi_stage: bool,
+    pub base_image: String,
+}
+
+impl Answers {
+    /// The non-interactive answers: everything else is left to the config defaults
+    pub fn defaults(environment: &str) -> Self {
+        Self {
+            environment: environment.to_string(),
+            entrypoint: None,
+            build_command: None,
+            ports: Vec::new(),
+            multi_stage: true,
+            base_image: DEFAULT_BASE_IMAGE.to_string(),
+        }
+    }
+
+    /// Ask for each setting in the terminal, offering the manifest's environments and tasks
+    pub fn ask(pixi_toml: Option<&PixiToml>, environment: &str) -> Result<Self> {
+        let environments = pixi_toml
+            .map(PixiToml::environment_names)
+            .unwrap_or_default();
+        let environment = if environments.len() > 1 {
+            let default = environments
+                .iter()
+                .position(|name| *name == environment)
+                .unwrap_or_default();
+            let choice = Select::new()
+                .with_prompt("Environment")
+                .items(&environments)
+                .default(default)
+                .interact()
+                .context(PROMPT_FAILED)?;
+            environments[choice].to_string()
+        } else {
+            Input::new()
+                .with_prompt("Environment")
+                .default(environment.to_string())
+                .interact_text()
+                .context(PROMPT_FAILED)?
+        };
+
+        let tasks = pixi_toml
+            .map(|pixi_toml| pixi_toml.task_names(Some(&environment)))
+            .unwrap_or_default();
+        let inferred = pixi_toml.and_then(|pixi_toml| pixi_toml.infer_entrypoint(&environment));
+        let entrypoint = select_task("Entrypoint task", &tasks, inferred)?;
+        let build = tasks.iter().copied().find(|task| *task == "build");
+        let build_command = select_task("Build command task", &tasks, build)?;
+
+        let ports: String = Input::new()
+            .with_prompt("Ports to expose (comma-separated)")
+            .allow_empty(true)
+            .validate_with(|input: &String| parse_ports(input).map(|_| ()))
+            .interact_text()
+            .context(PROMPT_FAILED)?;
+        let multi_stage = Confirm::new()
+            .with_prompt("Multi-stage build (smaller image without the build tools)?")
+            .default(true)
+            .interact()
+            .context(PROMPT_FAILED)?;
+        let base_image = Input::new()
+            .with_prompt("Base image of the final stage")
+            .default(DEFAULT_BASE_IMAGE.to_string())
+            .interact_text()
+            .context(PROMPT_FAILED)?;
+
+        Ok(Self {
+            environment,
+            entrypoint,
+            build_command,
+            ports: parse_ports(&ports).map_err(PixiDockerError::msg)?,
+            multi_stage,
+            base_image,
+        })
+    }
+
+    /// Render the answers as a `pixi_docker.toml`, leaving out values that match the defaults
+    pub fn to_config(&self) -> String {
+        let string = |value: &str| toml::Value::String(value.to_string()).to_string();
+        let mut out = format!(
+            "# Written by `pixi-docker init`\nschema_version = {}\n\n[docker]\n",
+            crate::migrate::SCHEMA_VERSION
+        );
+        out.push_str(&format!("environment = {}\n", string(&self.environment)));
+        if let Some(entrypoint) = &self.entrypoint {
+            out.push_str(&format!("entrypoint = {}\n", string(entrypoint)));
+        }
+        if let Some(build_command) = &self.build_command {
+            out.push_str(&format!("build_command = {}\n", string(build_command)));
+        }
+        if !self.ports.is_empty() {
+            let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+            out.push_str(&format!("ports = [{}]\n", ports.join(", ")));
+        }
+        if !self.multi_stage {
+            out.push_str("multi_stage = false\n");
+        }
+        if self.base_image != DEFAULT_BASE_IMAGE {
+            out.push_str(&format!("base_image = {}\n", string(&self.base_image)));
+        }
+        out
+    }
+}
+
+/// Pick one of the tasks or none, with `default` preselected
+fn select_task(prompt: &str, tasks: &[&str], default: Option<&str>) -> Result<Option<String>> {
+    if tasks.is_empty() {
+        return Ok(None);
+    }
+    let items: Vec<&str> = std::iter::once(NO_TASK)
+        .chain(tasks.iter().copied())
+        .collect();
+    let default = default
+        .and_then(|task| items.iter().position(|item| *item == task))
+        .unwrap_or_default();
+    let choice = Select::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(default)
+        .interact()
+        .context(PROMPT_FAILED)?;
+    Ok((choice > 0).then(|| items[choice].to_string()))
+}
+
+/// Comma- or space-separated port numbers between 1 and 65535
+fn parse_ports(input: &str) -> Result<Vec<u16>, String> {
+    input
+        .split([',', ' '])
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(format!("'{}' is not a port between 1 and 65535", item)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!(parse_ports(""), Ok(vec![]));
+        assert_eq!(parse_ports("8000, 443 80"), Ok(vec![8000, 443, 80]));
+        assert!(parse_ports("8000,0").is_err());
+        assert!(parse_ports("http").unwrap_err().contains("'http'"));
+    }
+
+    #[test]
+    fn test_to_config() {
+        let defaults = Answers::defaults("default");
+        assert_eq!(
+            defaults.to_config(),
+            "# Written by `pixi-docker init`\nschema_version = 2\n\n\
+             [docker]\nenvironment = \"default\"\n"
+        );
+
+        let answers = Answers {
+            environment: "prod".to_string(),
+            entrypoint: Some("serve".to_string()),
+            build_command: Some("build".to_string()),
+            ports: vec![8000, 9090],
+            multi_stage: false,
+            base_image: "debian:12-slim".to_string(),
+        };
+        let config: Config = toml::from_str(&answers.to_config()).unwrap();
+        assert_eq!(config.docker.environment, "prod");
+        assert_eq!(
+            config.entrypoint("prod").and_then(|e| e.as_shell()),
+            Some("serve")
+        );
+        assert_eq!(config.docker.ports, [8000, 9090]);
+        assert!(!config.docker.multi_stage);
+        assert_eq!(config.base_image("prod").unwrap(), Some("debian:12-slim"));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::error::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::dockerignore::DockerIgnore;
+
+/// Label holding the hash of everything an image was built from
+pub const LABEL: &str = "io.pixi-docker.input-hash";
+
+/// Extra build arguments after which a build always runs: they export the image elsewhere or
+/// explicitly ask for fresh layers
+const REBUILD_ARGS: &[&str] = &["--push", "--output", "-o", "--no-cache", "--pull"];
+
+/// Hash the inputs of an environment's image: the rendered Dockerfile, the build `settings`
+/// (target, platform, build args, ...), the pixi manifest and lock file and every file of the
+/// build context
+///
+/// The whole context counts, not just `copy_files`, since the build stage copies all of it
+/// before `build_command` runs. Files are streamed into the hash in sorted path order, so the
+/// hash only depends on their contents; the context is walked with its `.dockerignore`, like
+/// the runtime does. `outputs` are files the build itself writes, e.g. the `--iidfile`, which
+/// would otherwise change the hash of the next build.
+pub fn compute(
+    config: &Config,
+    dockerfile: &str,
+    settings: &[String],
+    outputs: &[&Path],
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+    // The creation timestamp changes on every render, not with the inputs
+    let dockerfile = crate::diff::comparable_lines(dockerfile).join("\n");
+    record(&mut hasher, "dockerfile", "", dockerfile.as_bytes());
+    for setting in settings {
+        record(&mut hasher, "setting", setting, &[]);
+    }
+
+    if let Some(manifest) = config.manifest_path() {
+        let lockfile = manifest.with_file_name("pixi.lock");
+        for path in [manifest.as_path(), lockfile.as_path()] {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            hash_file(&mut hasher, &name, path)?;
+        }
+    }
+
+    let context = config.project_dir();
+    let context_dir = match context.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => context.as_path(),
+    };
+    let ignore = DockerIgnore::load(context_dir).with_context(|| {
+        format!(
+            "Failed to read {}",
+            context_dir.join(".dockerignore").display()
+        )
+    })?;
+    let mut files = BTreeSet::new();
+    walk(context_dir, "", &ignore, &mut files)
+        .with_context(|| format!("Failed to list the build context {}", context_dir.display()))?;
+    if let Ok(root) = fs::canonicalize(context_dir) {
+        for output in outputs {
+            let name = fs::canonicalize(output)
+                .ok()
+                .and_then(|path| path.strip_prefix(&root).ok().map(context_name));
+            if let Some(name) = name {
+                files.remove(&name);
+            }
+        }
+    }
+    for name in files {
+        hash_file(&mut hasher, &name, &context_dir.join(&name))?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// A path relative to the context as the `/`-separated name `walk` lists it under
+fn context_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether a build with these extra arguments may be skipped when its inputs are unchanged
+pub fn may_skip(extra_args: &[String]) -> bool {
+    !extra_args.iter().any(|arg| {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        REBUILD_ARGS.contains(&name)
+    })
+}
+
+/// The input hash label of an image in `image inspect` JSON, from docker's `Config.Labels`
+/// or podman's top-level `Labels`
+pub fn image_hash(image: &serde_json::Value) -> Option<&str> {
+    image
+        .pointer("/Config/Labels")
+        .and_then(|labels| labels.get(LABEL))
+        .or_else(|| image.get("Labels").and_then(|labels| labels.get(LABEL)))
+        .and_then(serde_json::Value::as_str)
+}
+
+/// Add one length-prefixed entry, so that no two different inputs hash the same
+fn record(hasher: &mut Sha256, kind: &str, name: &str, content: &[u8]) {
+    for part in [kind.as_bytes(), name.as_bytes(), content] {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+}
+
+/// Stream a file into the hash without reading it into memory; missing files and symlinks are
+/// recorded as such
+fn hash_file(hasher: &mut Sha256, name: &str, path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            record(hasher, "missing", name, &[]);
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    if metadata.file_type().is_symlink() {
+        let target =
+            fs::read_link(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        record(hasher, "link", name, target.to_string_lossy().as_bytes());
+        return Ok(());
+    }
+
+    record(hasher, "file", name, &[]);
+    hasher.update(metadata.len().to_le_bytes());
+    let mut file =
+        fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    io::copy(&mut file, hasher).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(())
+}
+
+/// The `copy_files` entries that match nothing in the build context: paths that don't exist
+/// and glob entries without a matching file that `.dockerignore` leaves in
+pub fn missing_copy_files(context_dir: &Path, entries: &[String]) -> io::Result<Vec<String>> {
+    let ignore = DockerIgnore::load(context_dir)?;
+    let mut context_files = None;
+    let mut missing = Vec::new();
+    for original in entries {
+        let entry = context_entry(original);
+        let found = if is_glob(&entry) {
+            let pattern = DockerIgnore::parse(&entry);
+            all_files(context_dir, &ignore, &mut context_files)?
+                .iter()
+                .any(|path| pattern.excludes(path))
+        } else {
+            entry.is_empty() || fs::symlink_metadata(context_dir.join(&entry)).is_ok()
+        };
+        if !found {
+            missing.push(original.clone());
+        }
+    }
+    Ok(missing)
+}
+
+/// A `copy_files` entry as a `/`-separated path relative to the context, as `COPY` reads it
+fn context_entry(entry: &str) -> String {
+    let entry = entry.replace('\\', "/");
+    let entry = entry.trim_start_matches("./").trim_end_matches('/');
+    match entry {
+        "." => String::new(),
+        entry => entry.to_string(),
+    }
+}
+
+fn is_glob(entry: &str) -> bool {
+    entry.contains(['*', '?', '['])
+}
+
+/// Every file of the context, walked once on first use
+fn all_files<'a>(
+    context_dir: &Path,
+    ignore: &DockerIgnore,
+    context_files: &'a mut Option<BTreeSet<String>>,
+) -> io::Result<&'a BTreeSet<String>> {
+    if context_files.is_none() {
+        let mut all = BTreeSet::new();
+        walk(context_dir, "", ignore, &mut all)?;
+        *context_files = Some(all);
+    }
+    Ok(context_files.get_or_insert_default())
+}
+
+/// Collect the files under `dir` that `.dockerignore` leaves in the context, like the
+/// Engine API backend's context archive
+fn walk(
+    dir: &Path,
+    prefix: &str,
+    ignore: &DockerIgnore,
+    files: &mut BTreeSet<String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let excluded = ignore.excludes(&name);
+        if entry.file_type()?.is_dir() {
+            if !excluded || ignore.has_exceptions() {
+                walk(&entry.path(), &format!("{}/", name), ignore, files)?;
+            }
+        } else if !excluded {
+            files.insert(name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> (tempfile::TempDir, Config) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("pixi.toml"), "[workspace]\n").unwrap();
+        fs::write(root.join("pixi.lock"), "version: 6\n").unwrap();
+        fs::write(root.join(".dockerignore"), "src/__pycache__\n").unwrap();
+        fs::create_dir_all(root.join("src/__pycache__")).unwrap();
+        fs::write(root.join("src/app.py"), "print()").unwrap();
+        fs::write(root.join("src/__pycache__/app.pyc"), "bytecode").unwrap();
+        fs::write(root.join("run.py"), "import app").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+
+        let mut config: Config = toml::from_str(
+            "[docker]\nenvironment = \"default\"\ncopy_files = [\"src/\", \"*.py\"]\n",
+        )
+        .unwrap();
+        config.docker.manifest_path = Some(root.join("pixi.toml").display().to_string());
+        (dir, config)
+    }
+
+    #[test]
+    fn test_missing_copy_files() {
+        let (dir, _) = project();
+        let entries: Vec<String> = ["src/", ".", "./run.py", "scr/", "*.py", "*.rs", "src/*.pyc"]
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect();
+        // Files that .dockerignore leaves out don't match a glob either
+        assert_eq!(
+            missing_copy_files(dir.path(), &entries).unwrap(),
+            ["scr/", "*.rs", "src/*.pyc"]
+        );
+    }
+
+    #[test]
+    fn test_compute_tracks_inputs() {
+        let (dir, config) = project();
+        let root = dir.path();
+        let dockerfile = "FROM ubuntu:24.04\nLABEL org.opencontainers.image.created=\"now\"\n";
+        let hash = |dockerfile: &str, settings: &[String]| {
+            compute(&config, dockerfile, settings, &[&root.join("image.iid")]).unwrap()
+        };
+        let original = hash(dockerfile, &[]);
+        assert_eq!(original.len(), 64);
+
+        // Timestamps, ignored files and what the build writes don't matter
+        assert_eq!(hash(&dockerfile.replace("now", "later"), &[]), original);
+        fs::write(root.join("src/__pycache__/app.pyc"), "other").unwrap();
+        fs::write(root.join("image.iid"), "sha256:abc").unwrap();
+        assert_eq!(hash(dockerfile, &[]), original);
+
+        // The Dockerfile, settings, lock file and every source in the context do, also
+        // outside copy_files, as the build stage copies the whole context
+        assert_ne!(hash("FROM debian:12\n", &[]), original);
+        assert_ne!(hash(dockerfile, &["--target".to_string()]), original);
+        fs::write(root.join("src/app.py"), "print(1)").unwrap();
+        let edited = hash(dockerfile, &[]);
+        assert_ne!(edited, original);
+        fs::write(root.join("notes.txt"), "changed").unwrap();
+        let notes = hash(dockerfile, &[]);
+        assert_ne!(notes, edited);
+        fs::write(root.join("pixi.lock"), "version: 6\npackages: []\n").unwrap();
+        assert_ne!(hash(dockerfile, &[]), notes);
+    }
+
+    #[test]
+    fn test_skip_rules_and_labels() {
+        assert!(may_skip(&[]));
+        assert!(may_skip(&["--build-arg".to_string(), "A=1".to_string()]));
+        assert!(!may_skip(&["--push".to_string()]));
+        assert!(!may_skip(&["--output=type=tar,dest=out.tar".to_string()]));
+        assert!(!may_skip(&["--no-cache".to_string()]));
+
+        let docker: serde_json::Value =
+            serde_json::from_str(r#"{"Config": {"Labels": {"io.pixi-docker.input-hash": "abc"}}}"#)
+                .unwrap();
+        assert_eq!(image_hash(&docker), Some("abc"));
+        let podman: serde_json::Value = serde_json::from_str(
+            r#"{"Config": {}, "Labels": {"io.pixi-docker.input-hash": "def"}}"#,
+        )
+        .unwrap();
+        assert_eq!(image_hash(&podman), Some("def"));
+        assert_eq!(
+            image_hash(&serde_json::json!({"Config": {"Labels": null}})),
+            None
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! `${VAR}` references in config values, expanded from the process environment
+
+/// A reference in a value that can't be expanded
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// `${VAR}` without a default names a variable that isn't set
+    Undefined(String),
+    /// An unterminated `${` or a reference that isn't a variable name, e.g. `${1}`
+    Invalid(String),
+}
+
+/// Expand `${VAR}` and `${VAR:-default}` in a value, and turn `$${` into a literal `${`
+///
+/// Like the shell, a default also replaces a variable that is set but empty. A `$` that doesn't
+/// start a reference, as in `$HOME` or `$$`, is kept as is.
+pub fn expand(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start..];
+        if let Some(tail) = reference.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = tail;
+            continue;
+        }
+        let Some(tail) = reference.strip_prefix("${") else {
+            expanded.push('$');
+            rest = &reference[1..];
+            continue;
+        };
+        let Some(end) = tail.find('}') else {
+            return Err(Error::Invalid(reference.to_string()));
+        };
+        let (name, default) = match tail[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&tail[..end], None),
+        };
+        if !is_name(name) {
+            return Err(Error::Invalid(format!("${{{}}}", &tail[..end])));
+        }
+        match default {
+            Some(default) => match lookup(name).filter(|value| !value.is_empty()) {
+                Some(value) => expanded.push_str(&value),
+                None => expanded.push_str(default),
+            },
+            None => expanded.push_str(&lookup(name).ok_or_else(|| Error::Undefined(name.into()))?),
+        }
+        rest = &tail[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Whether a reference is a variable name: a letter or `_`, then letters, digits and `_`
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "REGISTRY" => Some("ghcr.io/acme".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand("${REGISTRY}/app", lookup).unwrap(),
+            "ghcr.io/acme/app"
+        );
+        assert_eq!(expand("${TAG:-latest}", lookup).unwrap(), "latest");
+        assert_eq!(expand("${EMPTY:-x}-${EMPTY}", lookup).unwrap(), "x-");
+        assert_eq!(expand("${TAG:-}", lookup).unwrap(), "");
+
+        // Escapes and shell syntax that isn't a reference stay literal
+        assert_eq!(expand("$${REGISTRY}", lookup).unwrap(), "${REGISTRY}");
+        assert_eq!(expand("echo $HOME $$", lookup).unwrap(), "echo $HOME $$");
+        assert_eq!(expand("no references", lookup).unwrap(), "no references");
+
+        assert_eq!(
+            expand("${MISSING}/app", lookup),
+            Err(Error::Undefined("MISSING".to_string()))
+        );
+        assert_eq!(
+            expand("a ${REGISTRY", lookup),
+            Err(Error::Invalid("${REGISTRY".to_string()))
+        );
+        assert_eq!(
+            expand("${1}", lookup),
+            Err(Error::Invalid("${1}".to_string()))
+        );
+    }
+}
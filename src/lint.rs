@@ -0,0 +1,369 @@
+use crate::error::PixiDockerError;
+use crate::import::{exec_form, parse_dockerfile, Instruction};
+use std::fmt;
+
+/// Dockerfile instructions with the fewest and most arguments they take, flags not counted
+const INSTRUCTIONS: &[(&str, usize, Option<usize>)] = &[
+    ("FROM", 1, Some(3)),
+    ("RUN", 1, None),
+    ("CMD", 0, None),
+    ("LABEL", 1, None),
+    ("MAINTAINER", 1, None),
+    ("EXPOSE", 1, None),
+    ("ENV", 1, None),
+    ("ADD", 2, None),
+    ("COPY", 2, None),
+    ("ENTRYPOINT", 0, None),
+    ("VOLUME", 1, None),
+    ("USER", 1, Some(1)),
+    ("WORKDIR", 1, None),
+    ("ARG", 1, None),
+    ("ONBUILD", 1, None),
+    ("STOPSIGNAL", 1, Some(1)),
+    ("HEALTHCHECK", 1, None),
+    ("SHELL", 1, None),
+];
+
+/// Instructions whose arguments may be a JSON array
+const EXEC_FORM: &[&str] = &["RUN", "CMD", "ENTRYPOINT", "SHELL", "COPY", "ADD", "VOLUME"];
+
+/// A structural problem in a Dockerfile
+#[derive(Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// 1-based line of the instruction, none for problems with the file as a whole
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Whether `keyword` (upper case) is a Dockerfile instruction
+pub fn is_instruction(keyword: &str) -> bool {
+    INSTRUCTIONS.iter().any(|(name, ..)| *name == keyword)
+}
+
+/// Fail with every problem of a Dockerfile rendered from `template`
+pub fn check(content: &str, template: &str) -> Result<(), PixiDockerError> {
+    let problems = lint(content);
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(PixiDockerError::Lint {
+        template: template.to_string(),
+        problems: problems.iter().map(Problem::to_string).collect(),
+    })
+}
+
+/// Check instruction names, their number of arguments, exec-form arrays, `EXPOSE` ports and
+/// `COPY --from` stage references
+pub fn lint(content: &str) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    // Stage names in order, lower-cased, with unnamed stages as `None`
+    let mut stages: Vec<Option<String>> = Vec::new();
+
+    for instruction in parse_dockerfile(content) {
+        let mut problem = |message: String| {
+            problems.push(Problem {
+                line: Some(instruction.line),
+                message,
+            })
+        };
+        let keyword = instruction.keyword.as_str();
+        if stages.is_empty() && keyword != "FROM" && keyword != "ARG" {
+            problem(format!("{} before the first FROM", keyword));
+        }
+        let stage_count = stages.len();
+        if let Err(message) = check_instruction(&instruction, &mut stages) {
+            problem(message);
+        }
+        // A malformed FROM still starts a stage
+        if keyword == "FROM" && stages.len() == stage_count {
+            stages.push(None);
+        }
+    }
+    if stages.is_empty() {
+        problems.push(Problem {
+            line: None,
+            message: "no FROM instruction".to_string(),
+        });
+    }
+    problems
+}
+
+fn check_instruction(
+    instruction: &Instruction,
+    stages: &mut Vec<Option<String>>,
+) -> Result<(), String> {
+    let keyword = instruction.keyword.as_str();
+    let Some((_, min, max)) = INSTRUCTIONS.iter().find(|(name, ..)| *name == keyword) else {
+        return Err(format!("unknown instruction {}", keyword));
+    };
+    if instruction.args.is_empty() {
+        return Err(format!("{} needs arguments", keyword));
+    }
+
+    let (flags, words) = if instruction.args.starts_with('[') && EXEC_FORM.contains(&keyword) {
+        let Some(argv) = exec_form(&instruction.args) else {
+            return Err(format!("{} has an invalid JSON array", keyword));
+        };
+        (Vec::new(), argv)
+    } else if keyword == "SHELL" {
+        return Err("SHELL needs a JSON array, e.g. [\"/bin/bash\", \"-c\"]".to_string());
+    } else {
+        split_flags(&instruction.args)
+    };
+    if words.len() < *min || max.is_some_and(|max| words.len() > max) {
+        return Err(match (keyword, min, max) {
+            ("COPY" | "ADD", ..) => format!("{} needs a source and a destination", keyword),
+            (_, _, Some(1)) => format!("{} takes a single argument", keyword),
+            _ => format!("{} has {} arguments", keyword, words.len()),
+        });
+    }
+
+    match keyword {
+        "FROM" => {
+            let name = match words.as_slice() {
+                [_] => None,
+                [_, as_keyword, name] if as_keyword.eq_ignore_ascii_case("as") => {
+                    Some(name.to_ascii_lowercase())
+                }
+                _ => return Err("FROM takes an image and an optional `AS <name>`".to_string()),
+            };
+            if name.is_some() && stages.contains(&name) {
+                return Err(format!("duplicate stage name {}", words[2]));
+            }
+            stages.push(name);
+        }
+        "EXPOSE" => {
+            if let Some(port) = words.iter().find(|port| !is_port(port)) {
+                return Err(format!("EXPOSE has an invalid port {}", port));
+            }
+        }
+        "COPY" => {
+            let from = flags.iter().find_map(|flag| flag.strip_prefix("--from="));
+            if let Some(from) = from {
+                check_copy_from(from, stages)?;
+            }
+        }
+        "HEALTHCHECK" => {
+            let kind = words[0].to_ascii_uppercase();
+            if kind != "NONE" && kind != "CMD" {
+                return Err("HEALTHCHECK needs NONE or CMD".to_string());
+            }
+        }
+        "ONBUILD" => {
+            let nested = words[0].to_ascii_uppercase();
+            if matches!(nested.as_str(), "ONBUILD" | "FROM" | "MAINTAINER")
+                || !INSTRUCTIONS.iter().any(|(name, ..)| *name == nested)
+            {
+                return Err(format!("ONBUILD can't trigger {}", words[0]));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Leading `--flag` / `--flag=value` words, and the remaining words
+fn split_flags(args: &str) -> (Vec<String>, Vec<String>) {
+    let mut words = args.split_whitespace().peekable();
+    let mut flags = Vec::new();
+    while let Some(flag) = words.next_if(|word| word.starts_with("--")) {
+        flags.push(flag.to_string());
+    }
+    (flags, words.map(str::to_string).collect())
+}
+
+/// A port, port range or variable, each optionally with `/tcp`, `/udp` or `/sctp`
+fn is_port(value: &str) -> bool {
+    if value.contains('$') {
+        return true;
+    }
+    let (ports, protocol) = value.split_once('/').unwrap_or((value, "tcp"));
+    let is_number = |port: &str| port.parse::<u16>().is_ok_and(|port| port > 0);
+    let valid_ports = match ports.split_once('-') {
+        Some((start, end)) => is_number(start) && is_number(end),
+        None => is_number(ports),
+    };
+    valid_ports
+        && matches!(
+            protocol.to_ascii_lowercase().as_str(),
+            "tcp" | "udp" | "sctp"
+        )
+}
+
+/// `COPY --from` must name an earlier stage, by name or index, or an image reference
+///
+/// Like docker, any other name is taken as an image, e.g. `busybox`, unless it is the
+/// current stage or up to two edits away from an earlier one, which is more likely a typo.
+fn check_copy_from(from: &str, stages: &[Option<String>]) -> Result<(), String> {
+    if from.contains('$') {
+        return Ok(());
+    }
+    // The stage the COPY is in can't be copied from
+    let (current, earlier) = match stages.split_last() {
+        Some((current, earlier)) => (current.as_deref(), earlier),
+        None => (None, stages),
+    };
+    if let Ok(index) = from.parse::<usize>() {
+        return if index < earlier.len() {
+            Ok(())
+        } else {
+            Err(format!("COPY --from={} names no earlier stage", from))
+        };
+    }
+    let name = from.to_ascii_lowercase();
+    let names: Vec<&str> = earlier.iter().flatten().map(String::as_str).collect();
+    let typo = current == Some(name.as_str())
+        || names
+            .iter()
+            .any(|stage| crate::pixi::edit_distance(&name, stage) <= 2);
+    if names.contains(&name.as_str()) || from.contains(['/', ':', '.', '@']) || !typo {
+        return Ok(());
+    }
+    Err(format!(
+        "COPY --from={} names no earlier stage (stages: {})",
+        from,
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let cases: &[(&str, &[&str])] = &[
+            // Valid files
+            ("FROM ubuntu:24.04\nCMD [\"bash\"]\n", &[]),
+            (
+                "ARG BASE=ubuntu\n\
+                 FROM --platform=$BUILDPLATFORM ${BASE} AS build\n\
+                 RUN <<EOF\nnot an instruction\nEOF\n\
+                 FROM debian AS final\n\
+                 COPY --from=build /app /app\n\
+                 COPY --from=0 --chown=1000 /x /y\n\
+                 COPY --from=ghcr.io/prefix-dev/pixi:0.40.0 /usr/local/bin/pixi /bin/\n\
+                 COPY --from=busybox /bin/sh /sh\n\
+                 EXPOSE 8000 9000-9010/udp ${PORT}\n\
+                 HEALTHCHECK --interval=5s CMD curl -f localhost\n\
+                 SHELL [\"/bin/bash\", \"-c\"]\n\
+                 ONBUILD RUN make\n",
+                &[],
+            ),
+            // Missing or misplaced FROM
+            (
+                "RUN echo hi\n",
+                &["line 1: RUN before the first FROM", "no FROM instruction"],
+            ),
+            ("", &["no FROM instruction"]),
+            (
+                "CMD [\"bash\"]\nFROM ubuntu\n",
+                &["line 1: CMD before the first FROM"],
+            ),
+            // Unknown instructions and arity
+            (
+                "FROM ubuntu\nCPOY . /app\n",
+                &["line 2: unknown instruction CPOY"],
+            ),
+            (
+                "FROM ubuntu\nCOPY /app\n",
+                &["line 2: COPY needs a source and a destination"],
+            ),
+            (
+                "FROM ubuntu\nADD --chown=1 x\n",
+                &["line 2: ADD needs a source and a destination"],
+            ),
+            (
+                "FROM ubuntu\nWORKDIR\n",
+                &["line 2: WORKDIR needs arguments"],
+            ),
+            (
+                "FROM ubuntu\nUSER app root\n",
+                &["line 2: USER takes a single argument"],
+            ),
+            (
+                "FROM ubuntu as\n",
+                &["line 1: FROM takes an image and an optional `AS <name>`"],
+            ),
+            (
+                "FROM a AS x\nFROM b AS X\n",
+                &["line 2: duplicate stage name X"],
+            ),
+            // Exec form
+            (
+                "FROM ubuntu\nCMD [\"bash\n",
+                &["line 2: CMD has an invalid JSON array"],
+            ),
+            (
+                "FROM ubuntu\nCOPY [\"a\"]\n",
+                &["line 2: COPY needs a source and a destination"],
+            ),
+            (
+                "FROM ubuntu\nSHELL /bin/sh -c\n",
+                &["line 2: SHELL needs a JSON array, e.g. [\"/bin/bash\", \"-c\"]"],
+            ),
+            // Ports
+            (
+                "FROM ubuntu\nEXPOSE http\n",
+                &["line 2: EXPOSE has an invalid port http"],
+            ),
+            (
+                "FROM ubuntu\nEXPOSE 80/quic\n",
+                &["line 2: EXPOSE has an invalid port 80/quic"],
+            ),
+            (
+                "FROM ubuntu\nEXPOSE 0\n",
+                &["line 2: EXPOSE has an invalid port 0"],
+            ),
+            // Stage references
+            (
+                "FROM ubuntu AS build\nFROM debian\nCOPY --from=biuld /a /a\n",
+                &["line 3: COPY --from=biuld names no earlier stage (stages: build)"],
+            ),
+            (
+                "FROM ubuntu AS build\nCOPY --from=build /a /a\n",
+                &["line 2: COPY --from=build names no earlier stage (stages: none)"],
+            ),
+            (
+                "FROM ubuntu\nFROM debian\nCOPY --from=1 /a /a\n",
+                &["line 3: COPY --from=1 names no earlier stage"],
+            ),
+            // Other instructions
+            (
+                "FROM ubuntu\nHEALTHCHECK curl\n",
+                &["line 2: HEALTHCHECK needs NONE or CMD"],
+            ),
+            (
+                "FROM ubuntu\nONBUILD FROM x\n",
+                &["line 2: ONBUILD can't trigger FROM"],
+            ),
+        ];
+        for (dockerfile, expected) in cases {
+            let problems: Vec<String> = lint(dockerfile).iter().map(Problem::to_string).collect();
+            assert_eq!(problems, *expected, "{dockerfile}");
+        }
+    }
+
+    #[test]
+    fn test_check_lists_every_problem() {
+        let err = check("RUN x\nFROM ubuntu\nEXPOSE web\n", "custom.j2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The Dockerfile rendered from custom.j2 is invalid (pass --no-lint to skip this check):\n  \
+             line 1: RUN before the first FROM\n  line 3: EXPOSE has an invalid port web"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use crate::config::Config;
+use crate::error::{bail, Context, PixiDockerError, Result};
+use crate::registry::RegistryClient;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Sidecar file next to the config recording resolved external inputs
+pub const LOCK_FILE: &str = "pixi_docker.lock";
+
+/// Format version of the lock file, raised only for changes older releases can't read
+pub const LOCK_VERSION: u32 = 1;
+
+const HEADER: &str = "# Generated by pixi-docker; refresh with `pixi-docker pin --update`\n\n";
+
+/// Held while the lock file is read, updated and written, as `build --all --jobs` generates
+/// environments concurrently
+static UPDATE: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LockFile {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    /// The release `pixi_version = "latest"` resolved to
+    pub pixi_version: Option<String>,
+    /// Image digests by the reference used in the Dockerfile, e.g. `ubuntu:24.04`
+    #[serde(default)]
+    pub digests: BTreeMap<String, String>,
+    /// Hashes of the template each environment was rendered from, e.g. `sha256:...`
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+}
+
+fn default_version() -> u32 {
+    LOCK_VERSION
+}
+
+impl Default for LockFile {
+    fn default() -> Self {
+        Self {
+            version: LOCK_VERSION,
+            pixi_version: None,
+            digests: BTreeMap::new(),
+            templates: BTreeMap::new(),
+        }
+    }
+}
+
+impl LockFile {
+    /// Load the lock file, or an empty one if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let lock: Self = crate::parse::toml(path, &content)
+            .map_err(|err| PixiDockerError::msg(err.to_string()))?;
+        if lock.version > LOCK_VERSION {
+            bail!(
+                "{} has format version {}, but this pixi-docker reads up to version {}; \
+                 upgrade pixi-docker",
+                path.display(),
+                lock.version,
+                LOCK_VERSION
+            );
+        }
+        Ok(lock)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = format!(
+            "{}{}",
+            HEADER,
+            toml::to_string(self).context("Failed to serialize the lock file")?
+        );
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Resolve the digests of images missing from the lock file, or of all of them with `update`;
+    /// returns true if any digest was resolved
+    pub fn resolve_digests(
+        &mut self,
+        config: &Config,
+        images: &[String],
+        update: bool,
+    ) -> Result<bool> {
+        let path = config.lock_path();
+        let missing: Vec<&String> = images
+            .iter()
+            .filter(|image| update || !self.digests.contains_key(*image))
+            .collect();
+        let Some(image) = missing.first() else {
+            return Ok(false);
+        };
+        if config.lock_read_only {
+            bail!(
+                "No digest for {} in {}; run `pixi-docker pin` to record it",
+                image,
+                path.display()
+            );
+        }
+        config.network.require(
+            &format!("resolve the digest of {}", image),
+            &format!(
+                "run `pixi-docker pin` while online to record it in {}",
+                path.display()
+            ),
+        )?;
+        let client = RegistryClient::new();
+        for image in missing {
+            let digest = client.manifest_digest(image).with_context(|| {
+                format!(
+                    "No digest for {} in {}; run `pixi-docker pin` while online",
+                    image,
+                    path.display()
+                )
+            })?;
+            eprintln!("Pinned {} to {}", image, digest);
+            self.digests.insert(image.clone(), digest);
+        }
+        Ok(true)
+    }
+
+    /// What the lock file lacks for the given inputs, e.g. `digest of ubuntu:24.04`
+    ///
+    /// `latest_pixi` says whether an environment uses `pixi_version = "latest"`, and
+    /// `templates` holds the current template hash of each environment.
+    pub fn missing(
+        &self,
+        latest_pixi: bool,
+        images: &[String],
+        templates: &BTreeMap<String, String>,
+    ) -> Vec<String> {
+        let mut missing = Vec::new();
+        if latest_pixi && self.pixi_version.is_none() {
+            missing.push("pixi_version for pixi_version = \"latest\"".to_string());
+        }
+        for image in images {
+            if !self.digests.contains_key(image) {
+                missing.push(format!("digest of {}", image));
+            }
+        }
+        for (environment, hash) in templates {
+            match self.templates.get(environment) {
+                None => missing.push(format!("template of environment '{}'", environment)),
+                Some(locked) if locked != hash => missing.push(format!(
+                    "template of environment '{}' (changed since it was locked)",
+                    environment
+                )),
+                Some(_) => {}
+            }
+        }
+        missing
+    }
+}
+
+/// Digests of the given images from the lock file
+///
+/// Images without a recorded digest, or all of them with `update`, are resolved from their
+/// registry and the lock file is updated, unless the config only reads it.
+pub fn pinned_digests(
+    config: &Config,
+    images: &[String],
+    update: bool,
+) -> Result<BTreeMap<String, String>> {
+    let _guard = UPDATE.lock().unwrap_or_else(|err| err.into_inner());
+    let path = config.lock_path();
+    let mut lock = LockFile::load(&path)?;
+    if lock.resolve_digests(config, images, update)? {
+        lock.save(&path)?;
+    }
+
+    Ok(images
+        .iter()
+        .filter_map(|image| {
+            lock.digests
+                .get(image)
+                .map(|digest| (image.clone(), digest.clone()))
+        })
+        .collect())
+}
+
+/// The release `pixi_version = "latest"` stands for: the locked one when the lock file
+/// exists, otherwise the newest release
+///
+/// A resolved version is added to an existing lock file, so later generations reuse it;
+/// a config that only reads the lock file fails instead.
+pub fn locked_pixi_version(config: &Config) -> Result<String> {
+    let _guard = UPDATE.lock().unwrap_or_else(|err| err.into_inner());
+    let path = config.lock_path();
+    if !path.exists() {
+        return crate::registry::latest_pixi_version(config.network);
+    }
+    let mut lock = LockFile::load(&path)?;
+    if let Some(version) = lock.pixi_version {
+        return Ok(version);
+    }
+    if config.lock_read_only {
+        bail!(
+            "No pixi_version for pixi_version = \"latest\" in {}; run `pixi-docker pin` to \
+             record it",
+            path.display()
+        );
+    }
+    let version = crate::registry::latest_pixi_version(config.network)?;
+    lock.pixi_version = Some(version.clone());
+    lock.save(&path)?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_file_format() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(LOCK_FILE);
+
+        // Lock files from before the version field are version 1
+        std::fs::write(&path, "[digests]\n\"ubuntu:24.04\" = \"sha256:2222\"\n").unwrap();
+        let mut lock = LockFile::load(&path).unwrap();
+        assert_eq!(lock.version, 1);
+        assert_eq!(lock.digests["ubuntu:24.04"], "sha256:2222");
+
+        lock.pixi_version = Some("0.45.0".to_string());
+        lock.templates
+            .insert("prod".to_string(), "sha256:abcd".to_string());
+        lock.save(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(HEADER), "{}", content);
+        assert!(
+            content.contains(
+                "version = 1\npixi_version = \"0.45.0\"\n\n[digests]\n\"ubuntu:24.04\" = \"sha256:2222\"\n\n\
+                 [templates]\nprod = \"sha256:abcd\"\n"
+            ),
+            "{}",
+            content
+        );
+
+        std::fs::write(&path, "version = 2\n").unwrap();
+        let err = LockFile::load(&path).unwrap_err().to_string();
+        assert!(err.contains("format version 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_missing() {
+        let lock = LockFile {
+            digests: BTreeMap::from([("ubuntu:24.04".to_string(), "sha256:2222".to_string())]),
+            templates: BTreeMap::from([("prod".to_string(), "sha256:aaaa".to_string())]),
+            ..Default::default()
+        };
+        let images = ["ubuntu:24.04".to_string(), "debian:12".to_string()];
+        let templates = BTreeMap::from([
+            ("prod".to_string(), "sha256:bbbb".to_string()),
+            ("dev".to_string(), "sha256:cccc".to_string()),
+        ]);
+        assert_eq!(
+            lock.missing(true, &images, &templates),
+            [
+                "pixi_version for pixi_version = \"latest\"",
+                "digest of debian:12",
+                "template of environment 'dev'",
+                "template of environment 'prod' (changed since it was locked)",
+            ]
+        );
+        let templates = BTreeMap::from([("prod".to_string(), "sha256:aaaa".to_string())]);
+        assert!(lock.missing(false, &images[..1], &templates).is_empty());
+    }
+}
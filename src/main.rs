@@ -1,29 +1,102 @@
+mod api;
+mod bake;
 mod config;
+mod diff;
+mod docker;
+mod dockerignore;
+mod error;
+mod git_context;
+mod hooks;
+mod import;
+mod init;
+mod input_hash;
+mod interpolate;
+mod lint;
+mod lock;
+mod migrate;
+mod network;
+mod parse;
 mod pixi;
+mod reference;
+mod registry;
+mod show;
+mod size;
 mod template;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 
-use config::Config;
-use pixi::PixiToml;
+use config::{
+    BaseImage, Config, DevMount, InitMode, InstallMode, DEFAULT_PIXI_VERSION, PROXY_VARS,
+};
+use error::PixiDockerError;
+use network::NetworkPolicy;
+use pixi::{channel_needs_auth, PixiToml, TaskValue, VersionRequirement};
 use template::DockerfileGenerator;
 
 #[derive(Parser)]
 #[command(name = "pixi-docker")]
 #[command(about = "Generate Dockerfiles for pixi projects", long_about = None)]
 struct Cli {
-    /// Configuration file
-    #[arg(short, long, default_value = "pixi_docker.toml", global = true)]
-    config: PathBuf,
+    /// Configuration file [default: pixi_docker.toml in the current or a parent directory]
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
 
     /// Target environment
     #[arg(short, long, global = true)]
     environment: Option<String>,
 
+    /// Fail when the entrypoint, build or test command is not a known pixi task or a
+    /// copy_files entry matches nothing in the build context (without a build_command)
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Don't check the structure of rendered Dockerfiles
+    #[arg(long, global = true)]
+    no_lint: bool,
+
+    /// Forward the host's SSH agent to `pixi install` in the build (see `ssh_forward`)
+    #[arg(long, global = true)]
+    ssh: bool,
+
+    /// Path to pixi.toml or pyproject.toml (default: search the current and parent directories)
+    #[arg(long, global = true)]
+    manifest_path: Option<PathBuf>,
+
+    /// Built-in template flavor (see `templates list`)
+    #[arg(long, global = true)]
+    flavor: Option<String>,
+
+    /// Registry prepended to image names, overriding every environment's `registry`
+    #[arg(long, global = true)]
+    registry: Option<String>,
+
+    /// Target platform, e.g. `linux/arm64`, overriding every environment's `platform`
+    #[arg(long, global = true)]
+    platform: Option<String>,
+
+    /// Never reach the network: use locked or configured values, or fail right away
+    #[arg(long, global = true, env = "PIXI_DOCKER_OFFLINE", value_parser = clap::builder::FalseyValueParser::new())]
+    offline: bool,
+
+    /// Docker context to use, passed to the docker CLI as `--context`
+    #[arg(long, global = true)]
+    context: Option<String>,
+
+    /// Print additional diagnostic output
+    #[arg(long, global = true)]
+    verbose: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -32,15 +105,125 @@ struct Cli {
 enum Commands {
     /// Generate Dockerfiles without building
     Generate {
-        /// Output directory
-        #[arg(short, long, default_value = ".")]
-        output: PathBuf,
+        /// Output directory [default: `output_dir`, or .]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Generate all environments
+        #[arg(short, long)]
+        all: bool,
+
+        /// Check that existing Dockerfiles are up to date without writing anything
+        #[arg(long)]
+        check: bool,
+
+        /// Overwrite existing Dockerfiles, even those that differ from or match the generated ones
+        #[arg(short, long, conflicts_with = "check")]
+        force: bool,
+
+        /// Skip checking that pixi.lock exists
+        #[arg(long)]
+        no_lock_check: bool,
+
+        /// Skip checking that pixi.toml declares the Linux platform of the image
+        #[arg(long)]
+        skip_platform_check: bool,
     },
     /// Generate and build a Docker image
     Build {
-        /// Custom image tag (default: from pixi.toml)
+        /// Custom image tag, repeatable (default: from pixi.toml)
         #[arg(short = 't', long)]
-        tag: Option<String>,
+        tag: Vec<String>,
+
+        /// Attach an SBOM attestation (requires buildx)
+        #[arg(long)]
+        sbom: bool,
+
+        /// Attach a provenance attestation, e.g. `mode=max` (requires buildx)
+        #[arg(long)]
+        provenance: Option<String>,
+
+        /// Print the docker command instead of running it
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Skip checking that pixi.lock exists
+        #[arg(long)]
+        no_lock_check: bool,
+
+        /// Skip checking that pixi.toml declares the Linux platform of the image
+        #[arg(long)]
+        skip_platform_check: bool,
+
+        /// Overwrite an existing Dockerfile, even one that differs from or matches the generated one
+        #[arg(short, long)]
+        force: bool,
+
+        /// Don't run the pre_build and post_build hooks
+        #[arg(long)]
+        skip_hooks: bool,
+
+        /// Build even if the image already carries the hash of the current inputs
+        #[arg(long)]
+        force_rebuild: bool,
+
+        /// Build only up to this stage, e.g. `build` for the full toolchain
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Build the production stage without the test stage of `test_in_build`
+        #[arg(long, conflicts_with = "target")]
+        skip_tests: bool,
+
+        /// Capture the docker output and only show it when the build fails
+        #[arg(long)]
+        quiet: bool,
+
+        /// Number of output lines shown when a quiet build fails
+        #[arg(long, default_value_t = 50, requires = "quiet")]
+        tail: usize,
+
+        /// Output format; `json` prints one JSON document on stdout and the build log on stderr
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Write the image ID to this file
+        #[arg(long)]
+        iidfile: Option<PathBuf>,
+
+        /// Write the buildx build metadata to this file (requires buildx)
+        #[arg(long)]
+        metadata_file: Option<PathBuf>,
+
+        /// Export the build result instead of loading it, e.g. `type=local,dest=dist` (buildx)
+        #[arg(long, value_name = "SPEC")]
+        output: Option<String>,
+
+        /// Build from a git repository instead of the local project, e.g.
+        /// `https://github.com/org/repo.git#main`
+        #[arg(long, value_name = "URL")]
+        git_context: Option<String>,
+
+        /// Copy a path of the built image to a host directory instead of tagging an image, e.g.
+        /// `/app/dist:./dist`; repeatable
+        #[arg(
+            long,
+            value_name = "PATH:DIR",
+            conflicts_with_all = ["tag", "format", "iidfile", "metadata_file", "output", "all"]
+        )]
+        extract: Vec<String>,
+
+        /// Build every environment, each with its own tags
+        #[arg(short, long, conflicts_with_all = ["tag", "format", "iidfile", "metadata_file", "output"])]
+        all: bool,
+
+        /// With --all, build the remaining environments after a failure instead of stopping
+        #[arg(long, requires = "all")]
+        keep_going: bool,
+
+        /// With --all, run up to this many builds at once, prefixing their output with `[env]`
+        #[arg(short, long, default_value_t = 1, requires = "all", value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
 
         /// Additional arguments passed to 'docker build'
         #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
@@ -52,179 +235,3470 @@ enum Commands {
         #[arg(short = 't', long)]
         tag: Option<String>,
 
-        /// Additional arguments passed to 'docker run'
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        /// Working directory inside the container (absolute path)
+        #[arg(long)]
+        workdir: Option<String>,
+
+        /// Always attach stdin and a terminal (default: only when running in a terminal)
+        #[arg(long, overrides_with = "no_interactive")]
+        interactive: bool,
+
+        /// Never attach stdin or a terminal
+        #[arg(long)]
+        no_interactive: bool,
+
+        /// Run this pixi task instead of the default command; arguments after `--` are appended
+        #[arg(long)]
+        task: Option<String>,
+
+        /// Stop and remove an existing container of the same name first
+        #[arg(long)]
+        replace: bool,
+
+        /// Publish a port as `[ip:]host:container`, replacing the default mapping of that
+        /// container port (repeatable)
+        #[arg(long = "publish", value_name = "HOST:CONTAINER")]
+        publish: Vec<String>,
+
+        /// Publish every exposed port on a random host port (`-P`) instead of the defaults
+        #[arg(long)]
+        publish_all: bool,
+
+        /// Mount the project sources over their copies in the image and run `dev_command`
+        #[arg(long)]
+        dev: bool,
+
+        /// Additional arguments passed to 'docker run' before the image
+        #[arg(allow_hyphen_values = true)]
         docker_args: Vec<String>,
+
+        /// Command and arguments run in the container, given after `--`
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Start an interactive shell in the image
+    Shell {
+        /// Custom image tag (default: from pixi.toml)
+        #[arg(short = 't', long)]
+        tag: Option<String>,
+
+        /// Publish the configured ports
+        #[arg(long)]
+        ports: bool,
+
+        /// Bind-mount the project directory read-write at the working directory
+        #[arg(long)]
+        mount_src: bool,
+
+        /// Build the image first if it doesn't exist locally, without asking
+        #[arg(long)]
+        build: bool,
+    },
+    /// Run a pixi task inside an already-running container
+    Exec {
+        /// Pixi task or shell command to run
+        task: String,
+
+        /// Arguments appended to the task
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+
+        /// Container to run in (default: `<image-name>-<environment>`)
+        #[arg(long)]
+        container: Option<String>,
+    },
+    /// Build the image and run the project's test task inside it
+    Test {
+        /// Custom image tag (default: from pixi.toml)
+        #[arg(short = 't', long)]
+        tag: Option<String>,
+
+        /// Skip rebuilding when the image already exists locally
+        #[arg(long)]
+        no_build: bool,
+    },
+    /// Create and push a multi-arch manifest from per-architecture images
+    Manifest {
+        /// Custom image tag (default: from pixi.toml)
+        #[arg(short = 't', long)]
+        tag: Option<String>,
+
+        /// Architectures to include (default: manifest_archs from the config)
+        #[arg(long = "arch")]
+        archs: Vec<String>,
+    },
+    /// Export a built image to a tarball for offline deployment
+    Save {
+        /// Custom image tag (default: from pixi.toml)
+        #[arg(short = 't', long)]
+        tag: Option<String>,
+
+        /// Output file, gzip-compressed when it ends in `.tar.gz` (default: derived from the tag)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Remove generated Dockerfiles and, optionally, the project's images
+    Clean {
+        /// Directory containing the Dockerfiles [default: `output_dir`, or .]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Clean all environments
+        #[arg(short, long)]
+        all: bool,
+
+        /// Also remove the images this project builds
+        #[arg(long)]
+        images: bool,
+
+        /// Remove every tag of the project's image name, not just the current version
+        #[arg(long, requires = "images")]
+        all_versions: bool,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Show the effective configuration and where each value came from
+    ShowConfig {
+        /// Output format; `json` also includes values as written before `${VAR}` expansion
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Compare existing Dockerfiles with freshly generated output
+    ///
+    /// Exits with 0 when identical, 1 when different and 2 on error.
+    Diff {
+        /// Directory containing the Dockerfiles [default: `output_dir`, or .]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Compare all environments
+        #[arg(short, long)]
+        all: bool,
+    },
+    /// Print the effective Dockerfile of an environment without writing anything
+    Show {
+        /// Directory with a previously generated Dockerfile to compare against [default: `output_dir`, or .]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Number the lines
+        #[arg(short = 'n', long)]
+        line_numbers: bool,
+    },
+    /// Write a `docker buildx bake` file with a target per environment
+    Bake {
+        /// Bake file to write [default: docker-bake.hcl, or docker-bake.json with --json]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write JSON instead of HCL
+        #[arg(long)]
+        json: bool,
+
+        /// Overwrite existing Dockerfiles, even those that differ from or match the generated ones
+        #[arg(short, long)]
+        force: bool,
+
+        /// Skip checking that pixi.lock exists
+        #[arg(long)]
+        no_lock_check: bool,
+    },
+    /// Record the base image digests, pixi version and templates of every environment in
+    /// pixi_docker.lock
+    Pin {
+        /// Re-resolve the digests and pixi version already in the lock file
+        #[arg(short, long)]
+        update: bool,
+
+        /// Fail if the lock file doesn't cover what the config needs, without resolving anything
+        #[arg(long, conflicts_with = "update")]
+        check: bool,
+    },
+    /// List the pixi tasks with the shell commands they run in the container
+    Tasks {
+        /// Output format; `json` prints the tasks as a JSON array
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Write a new pixi_docker.toml for the pixi project
+    Init {
+        /// Ask for the environment, tasks, ports, multi-stage and base image in the terminal
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Config file to write
+        #[arg(short, long, default_value = config::CONFIG_FILE)]
+        output: PathBuf,
+
+        /// Overwrite an existing config file
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Write a starting pixi_docker.toml from an existing Dockerfile
+    Import {
+        /// The Dockerfile to import
+        dockerfile: PathBuf,
+
+        /// Config file to write
+        #[arg(short, long, default_value = config::CONFIG_FILE)]
+        output: PathBuf,
+
+        /// Overwrite an existing config file
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Rewrite the config file in the newest schema, printing the changes
+    Migrate {
+        /// Save the migrated config instead of only printing the diff
+        #[arg(long)]
+        write: bool,
     },
+    /// Inspect the built-in template flavors
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommand,
+    },
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum TemplatesCommand {
+    /// List the built-in template flavors
+    List,
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    let is_diff = matches!(cli.command, Some(Commands::Diff { .. }));
 
-    if !cli.config.exists() {
-        anyhow::bail!("Config file not found: {:?}", cli.config);
+    match run(cli) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            if is_diff {
+                ExitCode::from(2)
+            } else if let Some(typed) = PixiDockerError::find(err.as_ref()) {
+                typed.exit_code()
+            } else {
+                ExitCode::FAILURE
+            }
+        }
     }
+}
 
-    let config = Config::from_file(&cli.config)?;
-    let environment = cli
-        .environment
-        .as_deref()
-        .unwrap_or(&config.docker.environment);
+fn run(cli: Cli) -> Result<ExitCode> {
+    if let Some(Commands::Templates { command }) = &cli.command {
+        match command {
+            TemplatesCommand::List => list_templates(),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Commands::Init {
+        interactive,
+        output,
+        force,
+    }) = &cli.command
+    {
+        let environment = cli.environment.as_deref().unwrap_or("default");
+        init_config(
+            cli.manifest_path.as_deref(),
+            output,
+            environment,
+            *interactive,
+            *force,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Commands::Import {
+        dockerfile,
+        output,
+        force,
+    }) = &cli.command
+    {
+        let environment = cli.environment.as_deref().unwrap_or("default");
+        import_dockerfile(dockerfile, output, environment, *force)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config_path = match cli.config {
+        Some(path) => {
+            if !path.exists() {
+                anyhow::bail!("Config file not found: {:?}", path);
+            }
+            path
+        }
+        None => {
+            let cwd = std::env::current_dir()?;
+            let path = config::discover_config(&cwd).with_context(|| {
+                format!(
+                    "Config file not found: no {} in {} or its parent directories; create one \
+                     with `pixi-docker init`",
+                    config::CONFIG_FILE,
+                    cwd.display()
+                )
+            })?;
+            if cli.verbose {
+                let dir = path
+                    .parent()
+                    .and_then(|dir| fs::canonicalize(cwd.join(dir)).ok());
+                eprintln!(
+                    "Found config: {} (in {})",
+                    path.display(),
+                    dir.unwrap_or(cwd).display()
+                );
+            }
+            path
+        }
+    };
+
+    if let Some(Commands::Migrate { write }) = &cli.command {
+        migrate_config(&config_path, *write)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut config = Config::from_file(&config_path)?;
+
+    // CLI flags take precedence over environment variables and the config file
+    if let Some(environment) = cli.environment {
+        config.docker.environment = environment;
+        config.record_cli_source("docker.environment", "--environment");
+    }
+    if cli.strict {
+        config.docker.strict = true;
+        config.record_cli_source("docker.strict", "--strict");
+    }
+    if cli.no_lint {
+        config.docker.lint = false;
+        config.record_cli_source("docker.lint", "--no-lint");
+    }
+    if cli.ssh {
+        config.docker.ssh_forward = true;
+        config.record_cli_source("docker.ssh_forward", "--ssh");
+    }
+    if let Some(context) = cli.context {
+        config.docker.context = Some(context);
+        config.record_cli_source("docker.context", "--context");
+    }
+    config.network = NetworkPolicy::from_offline(cli.offline);
+    config.verbose = cli.verbose;
+    // Only the commands writing files resolve missing lock file entries
+    config.lock_read_only = matches!(
+        cli.command,
+        Some(Commands::Diff { .. })
+            | Some(Commands::Show { .. })
+            | Some(Commands::Generate { check: true, .. })
+            | Some(Commands::Pin { check: true, .. })
+    );
+    if let Some(flavor) = cli.flavor {
+        config.docker.flavor = Some(flavor);
+        config.record_cli_source("docker.flavor", "--flavor");
+    }
+    if let Some(registry) = cli.registry {
+        for env in config.environments.values_mut() {
+            env.registry = None;
+        }
+        config.docker.registry = Some(registry);
+        config.record_cli_source("docker.registry", "--registry");
+    }
+    if let Some(platform) = cli.platform {
+        for env in config.environments.values_mut() {
+            env.platform = None;
+        }
+        config.docker.platform = Some(platform);
+        config.record_cli_source("docker.platform", "--platform");
+    }
+    if let Some(manifest_path) = cli.manifest_path {
+        config.docker.manifest_path = Some(manifest_path.display().to_string());
+        config.record_cli_source("docker.manifest_path", "--manifest-path");
+    }
+    if let Some(manifest_path) = &config.docker.manifest_path {
+        if !Path::new(manifest_path).is_file() {
+            anyhow::bail!("Manifest not found: {}", manifest_path);
+        }
+    }
+    if cli.verbose {
+        match config.manifest_path() {
+            Some(path) => eprintln!("Using manifest: {}", path.display()),
+            None => eprintln!("No pixi manifest found"),
+        }
+    }
+    let environment = config.docker.environment.clone();
+    let environment = environment.as_str();
 
     match cli.command {
-        Some(Commands::Generate { output }) => {
-            generate_dockerfiles(&config, environment, output)?;
+        Some(Commands::Generate {
+            output,
+            all,
+            check,
+            force,
+            no_lock_check,
+            skip_platform_check,
+        }) => {
+            if !no_lock_check {
+                config.check_lockfile()?;
+            }
+            let environments = target_environments(&config, environment, all);
+            if !skip_platform_check {
+                for environment in &environments {
+                    config.check_platform(environment)?;
+                }
+            }
+            if check {
+                if !check_dockerfiles(&config, &environments, output.as_deref())? {
+                    return Ok(ExitCode::FAILURE);
+                }
+            } else {
+                let mut generated = 0;
+                for environment in &environments {
+                    let path = dockerfile_path(&config, environment, output.as_deref());
+                    if generate_dockerfiles(&config, environment, &path, force)? {
+                        generated += 1;
+                    }
+                }
+                println!(
+                    "{} generated, {} unchanged",
+                    generated,
+                    environments.len() - generated
+                );
+            }
+        }
+        Some(Commands::Build {
+            tag,
+            sbom,
+            provenance,
+            dry_run,
+            no_lock_check,
+            skip_platform_check,
+            force,
+            skip_hooks,
+            force_rebuild,
+            target,
+            skip_tests,
+            quiet,
+            tail,
+            format,
+            iidfile,
+            metadata_file,
+            output,
+            git_context,
+            extract,
+            all,
+            keep_going,
+            jobs,
+            extra_args,
+        }) => {
+            if let Some(url) = git_context {
+                config.docker.git_context = Some(url);
+                config.record_cli_source("docker.git_context", "--git-context");
+            }
+            // Kept until the builds are done, the checkout is removed when it goes out of scope
+            let _checkout = checkout_git_context(&mut config)?;
+            if sbom {
+                config.docker.sbom = true;
+                config.record_cli_source("docker.sbom", "--sbom");
+            }
+            if let Some(provenance) = provenance {
+                config.docker.provenance = Some(provenance);
+                config.record_cli_source("docker.provenance", "--provenance");
+            }
+            let options = BuildOptions {
+                tags: tag,
+                extra_args,
+                dry_run,
+                no_lock_check,
+                skip_platform_check,
+                force,
+                skip_hooks,
+                force_rebuild,
+                target,
+                skip_tests,
+                quiet: quiet.then_some(tail),
+                format,
+                iidfile,
+                metadata_file,
+                output,
+                extract: extract
+                    .iter()
+                    .map(|value| docker::Extract::parse(value))
+                    .collect::<Result<_, _>>()?,
+                output_prefix: None,
+            };
+            if all {
+                let environments = config.environment_names();
+                build_all(&config, &environments, options, keep_going, jobs.into())?;
+            } else {
+                build_docker_image(&config, &config.docker.environment, options)?;
+            }
+        }
+        Some(Commands::Run {
+            tag,
+            workdir,
+            interactive,
+            no_interactive,
+            task,
+            replace,
+            publish,
+            publish_all,
+            dev,
+            docker_args,
+            command,
+        }) => {
+            let (docker_args, command) = docker::split_run_args(docker_args, command);
+            let dev = dev || config.dev_mode(environment);
+            let command = match task {
+                Some(task) => Some(task_command(
+                    &config,
+                    environment,
+                    &task,
+                    command.unwrap_or_default(),
+                )?),
+                // A command of our own replaces the dev command
+                None if command.is_none() && dev => dev_command(&config, environment)?,
+                None => command,
+            };
+            let interactive = match (interactive, no_interactive) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            run_docker_container(
+                &config,
+                environment,
+                RunOptions {
+                    tag,
+                    workdir,
+                    interactive,
+                    replace,
+                    publish_ports: publish,
+                    publish_all,
+                    dev,
+                    docker_args,
+                    command,
+                },
+            )?;
+        }
+        Some(Commands::Shell {
+            tag,
+            ports,
+            mount_src,
+            build,
+        }) => {
+            let _checkout = checkout_git_context(&mut config)?;
+            open_shell(
+                &config,
+                environment,
+                ShellOptions {
+                    tag,
+                    ports,
+                    mount_src,
+                    build,
+                },
+            )?;
+        }
+        Some(Commands::Exec {
+            task,
+            args,
+            container,
+        }) => {
+            return exec_in_container(&config, environment, &task, &args, container);
+        }
+        Some(Commands::Test { tag, no_build }) => {
+            let _checkout = checkout_git_context(&mut config)?;
+            return test_docker_image(&config, environment, tag, no_build);
         }
-        Some(Commands::Build { tag, extra_args }) => {
-            build_docker_image(&config, environment, tag, extra_args)?;
+        Some(Commands::Manifest { tag, archs }) => {
+            create_manifest(&config, environment, tag, archs)?;
         }
-        Some(Commands::Run { tag, docker_args }) => {
-            run_docker_container(&config, environment, tag, docker_args)?;
+        Some(Commands::Save { tag, output }) => {
+            save_image(&config, environment, tag, output)?;
+        }
+        Some(Commands::Clean {
+            output,
+            all,
+            images,
+            all_versions,
+            yes,
+        }) => {
+            let environments = target_environments(&config, environment, all);
+            clean(
+                &config,
+                &environments,
+                CleanOptions {
+                    output,
+                    images,
+                    all_versions,
+                    yes,
+                },
+            )?;
+        }
+        Some(Commands::ShowConfig { format }) => {
+            show_config(&config, format)?;
+        }
+        Some(Commands::Diff { output, all }) => {
+            let environments = target_environments(&config, environment, all);
+            if !diff_dockerfiles(&config, &environments, output.as_deref())? {
+                return Ok(ExitCode::from(1));
+            }
+        }
+        Some(Commands::Show {
+            output,
+            line_numbers,
+        }) => {
+            show_dockerfile(&config, environment, output.as_deref(), line_numbers)?;
+        }
+        Some(Commands::Bake {
+            output,
+            json,
+            force,
+            no_lock_check,
+        }) => {
+            if !no_lock_check {
+                config.check_lockfile()?;
+            }
+            let output = output.unwrap_or_else(|| {
+                PathBuf::from(if json {
+                    "docker-bake.json"
+                } else {
+                    "docker-bake.hcl"
+                })
+            });
+            write_bake_file(&config, &output, json, force)?;
+        }
+        Some(Commands::Pin { update, check }) => {
+            if check {
+                if !check_lock_file(&config)? {
+                    return Ok(ExitCode::FAILURE);
+                }
+            } else {
+                pin_inputs(&config, update)?;
+            }
+        }
+        Some(Commands::Tasks { format }) => {
+            list_tasks(&config, environment, format)?;
+        }
+        Some(Commands::Init { .. })
+        | Some(Commands::Import { .. })
+        | Some(Commands::Migrate { .. })
+        | Some(Commands::Templates { .. }) => {
+            unreachable!("handled before loading the config")
         }
         None => {
-            generate_dockerfiles(&config, environment, PathBuf::from("."))?;
+            config.check_lockfile()?;
+            let path = dockerfile_path(&config, environment, None);
+            generate_dockerfiles(&config, environment, &path, false)?;
         }
     }
 
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The selected environment, or every known environment with `--all`
+fn target_environments(config: &Config, environment: &str, all: bool) -> Vec<String> {
+    if all {
+        config.environment_names()
+    } else {
+        vec![environment.to_string()]
+    }
+}
+
+fn show_config(config: &Config, format: OutputFormat) -> Result<()> {
+    let values = config.effective_values()?;
+    if format == OutputFormat::Json {
+        let entries = values
+            .iter()
+            .map(|(key, value, source)| {
+                let raw = match config.raw_values.get(key) {
+                    Some(raw) => serde_json::to_string(raw)?,
+                    None => "null".to_string(),
+                };
+                Ok(format!(
+                    "\n  {{\"key\": {}, \"value\": {}, \"source\": {}, \"raw\": {}}}",
+                    template::json_string(key),
+                    serde_json::to_string(value)?,
+                    source.map_or("null".to_string(), |s| template::json_string(
+                        &s.to_string()
+                    )),
+                    raw
+                ))
+            })
+            .collect::<Result<Vec<String>>>()?;
+        println!("[{}\n]", entries.join(","));
+        return Ok(());
+    }
+    for (key, value, source) in values {
+        let source = source
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| "default".to_string());
+        println!("{} = {}  # {}", key, value, source);
+    }
     Ok(())
 }
 
-/// Resolve the image tag from CLI, config, or pixi.toml
-fn resolve_image_tag(config: &Config, environment: &str, cli_tag: Option<String>) -> String {
-    if let Some(tag) = cli_tag {
-        return tag;
+/// Fetch the project of `git_context`, if set, and read the manifest from that checkout
+fn checkout_git_context(config: &mut Config) -> Result<Option<git_context::Checkout>> {
+    let Some(url) = &config.docker.git_context else {
+        return Ok(None);
+    };
+    let context = git_context::GitContext::parse(url)?;
+    if config.docker.manifest_path.is_some() {
+        anyhow::bail!(
+            "`manifest_path` can't be combined with `git_context`, which reads the manifest from \
+             the repository; select a subdirectory with the URL instead, e.g. {}#main:app",
+            context.repository
+        );
+    }
+    let checkout = context.checkout(config.network)?;
+    // Docker's context is the project directory, so the manifest has to be in it
+    let Some(manifest) = pixi::discover_manifest(&checkout.project_dir)
+        .filter(|manifest| manifest.parent() == Some(Path::new("")))
+    else {
+        anyhow::bail!(
+            "No pixi.toml or pyproject.toml with a [tool.pixi] table found in {}",
+            context.url
+        );
+    };
+    let manifest = checkout.project_dir.join(manifest);
+    if config.verbose {
+        eprintln!("Using manifest from {}", context.url);
     }
+    config.docker.manifest_path = Some(manifest.display().to_string());
+    Ok(Some(checkout))
+}
 
-    let pixi_toml_path = PathBuf::from("pixi.toml");
-    let pixi_toml = pixi_toml_path
-        .exists()
-        .then(|| PixiToml::from_file(&pixi_toml_path).ok())
-        .flatten();
+/// Load the pixi manifest, if one was found and is valid
+fn load_pixi_toml(config: &Config) -> Option<PixiToml> {
+    config
+        .manifest_path()
+        .and_then(|path| PixiToml::load(&path))
+}
 
-    let name = config
+/// Resolve the image repository name from config or pixi.toml
+fn resolve_image_name(config: &Config, pixi_toml: Option<&PixiToml>) -> String {
+    config
         .docker
         .image_name
-        .as_ref()
-        .or_else(|| pixi_toml.as_ref().and_then(|p| p.get_name()))
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "pixi-app".to_string());
+        .as_deref()
+        .or_else(|| pixi_toml.and_then(|p| p.get_name()).map(String::as_str))
+        .unwrap_or("pixi-app")
+        .to_string()
+}
 
-    let version = config
-        .docker
-        .image_tag
-        .as_ref()
-        .or_else(|| pixi_toml.as_ref().and_then(|p| p.get_version()))
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| environment.to_string());
+/// The image repository of an environment: `registry/namespace/name`, omitting unset parts
+fn resolve_image_repository(
+    config: &Config,
+    environment: &str,
+    pixi_toml: Option<&PixiToml>,
+) -> Result<String> {
+    let name = resolve_image_name(config, pixi_toml);
+    let mut parts = Vec::new();
+    if let Some(registry) = config.registry(environment) {
+        reference::check_registry("registry", registry)?;
+        parts.push(registry);
+    }
+    if let Some(namespace) = config.namespace(environment) {
+        reference::check_repository("namespace", namespace)?;
+        parts.push(namespace);
+    }
+    reference::check_repository("image name", &name)?;
+    parts.push(&name);
+    Ok(parts.join("/"))
+}
 
-    format!("{}:{}", name, version)
+/// Resolve the image tag from CLI, config, or pixi.toml
+fn resolve_image_tag(
+    config: &Config,
+    environment: &str,
+    cli_tag: Option<String>,
+) -> Result<String> {
+    Ok(resolve_image_tags(config, environment, cli_tag.into_iter().collect())?.remove(0))
 }
 
-fn generate_dockerfiles(config: &Config, environment: &str, output_dir: PathBuf) -> Result<()> {
-    let generator = if let Some(template_path) = &config.docker.template_path {
-        DockerfileGenerator::with_template_path(Some(PathBuf::from(template_path)))
+/// Resolve every tag of an image, primary tag first
+///
+/// Tags given on the command line replace the configured `image_tag` and `extra_tags`.
+/// `{version}` and `{environment}` are expanded in all of them.
+fn resolve_image_tags(
+    config: &Config,
+    environment: &str,
+    cli_tags: Vec<String>,
+) -> Result<Vec<String>> {
+    let pixi_toml = load_pixi_toml(config);
+    let version = pixi_toml
+        .as_ref()
+        .and_then(|p| p.get_version())
+        .map(String::as_str)
+        .unwrap_or(environment);
+    let expand = |tag: &str| {
+        tag.replace("{version}", version)
+            .replace("{environment}", environment)
+    };
+
+    let mut tags: Vec<String> = Vec::new();
+    if cli_tags.is_empty() {
+        let repository = resolve_image_repository(config, environment, pixi_toml.as_ref())?;
+        let primary = config.docker.image_tag.as_deref().unwrap_or(version);
+        for tag in
+            std::iter::once(primary).chain(config.docker.extra_tags.iter().map(String::as_str))
+        {
+            let tag = expand(tag);
+            reference::check_tag("image tag", &tag)?;
+            tags.push(format!("{}:{}", repository, tag));
+        }
     } else {
-        DockerfileGenerator::new()
+        for tag in &cli_tags {
+            let tag = expand(tag);
+            reference::check_reference(&tag)?;
+            tags.push(tag);
+        }
+    }
+
+    let mut unique = Vec::with_capacity(tags.len());
+    for tag in tags {
+        if !unique.contains(&tag) {
+            unique.push(tag);
+        }
+    }
+    Ok(unique)
+}
+
+/// The `cache_from` sources and `cache_to` export of an environment's builds, with `{name}` and
+/// `{environment}` expanded
+fn resolve_build_cache(config: &Config, environment: &str) -> (Vec<String>, Option<String>) {
+    let name = resolve_image_name(config, load_pixi_toml(config).as_ref());
+    let expand = |cache: &String| {
+        cache
+            .replace("{name}", &name)
+            .replace("{environment}", environment)
     };
+    (
+        config.docker.cache_from.iter().map(expand).collect(),
+        config.docker.cache_to.as_ref().map(expand),
+    )
+}
 
-    if !output_dir.exists() {
-        fs::create_dir_all(&output_dir)?;
+/// Container name for an environment: the `run.name` setting, or e.g. `my-app-prod`
+fn container_name(config: &Config, environment: &str) -> String {
+    let name = resolve_image_name(config, load_pixi_toml(config).as_ref());
+    // Container names can't contain the registry and namespace separators of image names
+    let name = name.replace(['/', ':'], "-");
+    match config.run_defaults(environment).name {
+        Some(pattern) => pattern
+            .replace("{name}", &name)
+            .replace("{environment}", environment),
+        None => format!("{}-{}", name, environment),
     }
+}
 
-    let dockerfile_content = generator.generate(config, Some(environment))?;
-    let filename = format!("Dockerfile.{}", environment);
-    let output_path = output_dir.join(&filename);
-    fs::write(&output_path, dockerfile_content)?;
-    println!("Generated: {}", output_path.display());
+fn create_generator(config: &Config, environment: &str) -> Result<DockerfileGenerator> {
+    Ok(DockerfileGenerator::for_environment(config, environment)?)
+}
 
-    Ok(())
+fn list_templates() {
+    let width = template::FLAVORS
+        .iter()
+        .map(|f| f.name.len())
+        .max()
+        .unwrap_or(0);
+    for flavor in template::FLAVORS {
+        println!(
+            "{:width$}  {}",
+            flavor.name,
+            flavor.description,
+            width = width
+        );
+    }
 }
 
-fn build_docker_image(
-    config: &Config,
+/// Write a new config, from the terminal's answers with `interactive` or else the defaults
+fn init_config(
+    manifest_path: Option<&Path>,
+    output: &Path,
     environment: &str,
-    tag: Option<String>,
-    extra_args: Vec<String>,
+    interactive: bool,
+    force: bool,
 ) -> Result<()> {
-    // First generate the Dockerfile
-    let generator = if let Some(template_path) = &config.docker.template_path {
-        DockerfileGenerator::with_template_path(Some(PathBuf::from(template_path)))
+    if output.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, use --force to overwrite it",
+            output.display()
+        );
+    }
+
+    // Prompts without a terminal would wait forever in CI, so fall back to the defaults
+    let terminal = io::stdin().is_terminal() && io::stderr().is_terminal();
+    if interactive && !terminal {
+        eprintln!("Warning: not running in a terminal, writing the defaults instead of asking");
+    }
+    let answers = if interactive && terminal {
+        let manifest = match manifest_path {
+            Some(path) => Some(path.to_path_buf()),
+            None => pixi::discover_manifest(&std::env::current_dir()?),
+        };
+        let pixi_toml = manifest.as_deref().and_then(PixiToml::load);
+        init::Answers::ask(pixi_toml.as_ref(), environment)?
     } else {
-        DockerfileGenerator::new()
+        init::Answers::defaults(environment)
     };
-    let dockerfile_content = generator.generate(config, Some(environment))?;
-    let dockerfile_name = format!("Dockerfile.{}", environment);
-    fs::write(&dockerfile_name, &dockerfile_content)?;
-    println!("Generated: {}", dockerfile_name);
+    fs::write(output, answers.to_config())
+        .with_context(|| format!("Failed to write {}", output.display()))?;
 
-    let image_tag = resolve_image_tag(config, environment, tag);
-
-    // Build the Docker command
-    let mut docker_cmd = Command::new("docker");
-    docker_cmd
-        .arg("build")
-        .arg("-t")
-        .arg(&image_tag)
-        .arg("-f")
-        .arg(&dockerfile_name);
+    println!("Wrote {}", output.display());
+    println!("\nNext steps:");
+    println!(
+        "  pixi-docker generate   # write Dockerfile.{}",
+        answers.environment
+    );
+    println!("  pixi-docker build      # build the image");
+    println!("  pixi-docker run        # start a container");
+    Ok(())
+}
 
-    for arg in extra_args {
-        docker_cmd.arg(arg);
+fn import_dockerfile(
+    dockerfile: &Path,
+    output: &Path,
+    environment: &str,
+    force: bool,
+) -> Result<()> {
+    if output.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, use --force to overwrite it",
+            output.display()
+        );
     }
+    let content = fs::read_to_string(dockerfile)
+        .with_context(|| format!("Failed to read {}", dockerfile.display()))?;
+    let imported = import::import(&content);
+    let config = import::to_config(&imported, &dockerfile.display().to_string(), environment);
+    fs::write(output, config).with_context(|| format!("Failed to write {}", output.display()))?;
 
-    docker_cmd.arg(".");
+    println!(
+        "Imported {} into {}",
+        dockerfile.display(),
+        output.display()
+    );
+    if !imported.unmapped.is_empty() {
+        println!(
+            "{} instructions could not be mapped, see the comments in {}",
+            imported.unmapped.len(),
+            output.display()
+        );
+    }
+    Ok(())
+}
 
-    println!("Building Docker image: {}", image_tag);
-    println!("Running: {:?}", docker_cmd);
+/// Print the rewrite of an older config file in the current schema, and save it with `write`
+fn migrate_config(path: &Path, write: bool) -> Result<()> {
+    let content = fs::read_to_string(path).map_err(|source| PixiDockerError::ConfigIo {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let table: toml::Table = parse::toml(path, &content).map_err(PixiDockerError::ConfigParse)?;
+    let version = migrate::schema_version(path, &table)?;
+    if version == migrate::SCHEMA_VERSION {
+        println!("{} already uses schema_version {}", path.display(), version);
+        return Ok(());
+    }
 
-    let status = docker_cmd.status()?;
-    if !status.success() {
-        anyhow::bail!("Docker build failed with exit code: {:?}", status.code());
+    let migrated = migrate::migrate(&content, version)?;
+    println!(
+        "Migrating {} from schema_version {} to {}:",
+        path.display(),
+        version,
+        migrate::SCHEMA_VERSION
+    );
+    for change in migrate::pending(version) {
+        println!("  - {}", change);
     }
+    let name = path.display().to_string();
+    let new_name = format!("{} (migrated)", name);
+    print!(
+        "{}",
+        diff::unified_diff(&content, &migrated, &name, &new_name, diff::use_color())
+    );
 
-    println!("Successfully built Docker image: {}", image_tag);
+    if write {
+        fs::write(path, migrated).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    } else {
+        println!("Run `pixi-docker migrate --write` to save the changes");
+    }
     Ok(())
 }
 
-fn run_docker_container(
-    config: &Config,
-    environment: &str,
-    tag: Option<String>,
-    docker_args: Vec<String>,
-) -> Result<()> {
-    let image_tag = resolve_image_tag(config, environment, tag);
+/// A config field referencing a pixi task, as `(field, environment)`
+type TaskReference = (&'static str, String);
 
-    let mut docker_cmd = Command::new("docker");
-    docker_cmd.arg("run");
+/// Config fields referencing each pixi task
+fn task_references<'a>(
+    config: &'a Config,
+    pixi_toml: &'a PixiToml,
+) -> BTreeMap<String, Vec<TaskReference>> {
+    let mut references: BTreeMap<String, Vec<TaskReference>> = BTreeMap::new();
+    for environment in config.environment_names() {
+        let entrypoint = match config.entrypoint(&environment) {
+            Some(entrypoint) => entrypoint.as_shell(),
+            None if config.docker.infer_entrypoint => {
+                pixi_toml.infer_entrypoint(config.pixi_environment(&environment))
+            }
+            None => None,
+        };
+        let fields = entrypoint
+            .map(|e| ("entrypoint", e))
+            .into_iter()
+            .chain(
+                config
+                    .build_commands(&environment)
+                    .iter()
+                    .map(|b| ("build_command", b.as_str())),
+            )
+            .chain(
+                config
+                    .test_command(&environment)
+                    .map(|t| ("test_command", t)),
+            );
+        for (field, value) in fields {
+            let task = shlex::split(value)
+                .and_then(|words| words.into_iter().next())
+                .filter(|task| {
+                    pixi_toml
+                        .task(task, Some(config.pixi_environment(&environment)))
+                        .is_some()
+                });
+            if let Some(task) = task {
+                references
+                    .entry(task)
+                    .or_default()
+                    .push((field, environment.clone()));
+            }
+        }
+    }
+    references
+}
 
-    // If no args provided, add sensible defaults (port mapping + interactive)
-    if docker_args.is_empty() {
-        let env_config = config.environments.get(environment);
-        let ports = env_config
-            .filter(|e| !e.ports.is_empty())
-            .map(|e| &e.ports)
-            .unwrap_or(&config.docker.ports);
+fn list_tasks(config: &Config, environment: &str, format: OutputFormat) -> Result<()> {
+    let pixi_toml = load_pixi_toml(config).context("No pixi manifest found for the config")?;
+    let environment = config.pixi_environment(environment);
+    let references = task_references(config, &pixi_toml);
+    let tasks: Vec<(&str, Result<String, String>, &[TaskReference])> = pixi_toml
+        .task_names(Some(environment))
+        .into_iter()
+        .map(|name| {
+            let command = match pixi_toml.resolve_task(name, Some(environment)) {
+                Ok(steps) => Ok(pixi::steps_to_shell(&steps.unwrap_or_default(), false)),
+                Err(err) => Err(error::one_line(&err)),
+            };
+            let used_by = references.get(name).map(Vec::as_slice).unwrap_or_default();
+            (name, command, used_by)
+        })
+        .collect();
 
-        for port in ports {
-            docker_cmd.arg("-p").arg(format!("{}:{}", port, port));
-        }
-        docker_cmd.arg("-it");
-    } else {
-        // Pass all args through - user is responsible for correct ordering
-        for arg in &docker_args {
-            docker_cmd.arg(arg);
+    if format == OutputFormat::Json {
+        let tasks: Vec<String> = tasks
+            .iter()
+            .map(|(name, command, used_by)| {
+                task_json(&pixi_toml, environment, name, command, used_by)
+            })
+            .collect();
+        println!("[{}]", tasks.join(","));
+        return Ok(());
+    }
+    if tasks.is_empty() {
+        println!("pixi.toml defines no tasks");
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 3]> = tasks
+        .iter()
+        .map(|(name, command, used_by)| {
+            let command = match command {
+                Ok(command) => command.clone(),
+                Err(err) => format!("error: {}", err),
+            };
+            [name.to_string(), describe_references(used_by), command]
+        })
+        .collect();
+    let header = ["TASK", "USED BY", "COMMAND"].map(String::from);
+    let width = |column: usize| {
+        rows.iter()
+            .chain([&header])
+            .map(|row| row[column].len())
+            .max()
+            .unwrap_or(0)
+    };
+    let (name_width, used_width) = (width(0), width(1));
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:name_width$}  {:used_width$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            name_width = name_width,
+            used_width = used_width
+        );
+    }
+    Ok(())
+}
+
+/// `entrypoint (default, prod), build_command (prod)`, or `-` without references
+fn describe_references(used_by: &[TaskReference]) -> String {
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (field, environment) in used_by {
+        match fields.iter_mut().find(|(f, _)| f == field) {
+            Some((_, environments)) => environments.push(environment),
+            None => fields.push((field, vec![environment])),
         }
     }
+    if fields.is_empty() {
+        return "-".to_string();
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(field, environments)| format!("{} ({})", field, environments.join(", ")))
+        .collect();
+    fields.join(", ")
+}
 
-    docker_cmd.arg(&image_tag);
+/// One task of `tasks --format json`
+fn task_json(
+    pixi_toml: &PixiToml,
+    environment: &str,
+    name: &str,
+    command: &Result<String, String>,
+    used_by: &[TaskReference],
+) -> String {
+    let json = |value: &str| template::json_string(value);
+    let optional = |value: Option<&str>| value.map_or("null".to_string(), json);
+    let cmd = pixi_toml.get_task_command(name, Some(environment));
+    let config = match pixi_toml.task(name, Some(environment)) {
+        Some(TaskValue::Complex(config)) => Some(config),
+        _ => None,
+    };
+    let env: Vec<String> = config
+        .into_iter()
+        .flat_map(|config| &config.env)
+        .map(|(key, value)| format!("{}: {}", json(key), json(value)))
+        .collect();
+    let depends_on: Vec<String> = config
+        .into_iter()
+        .flat_map(|config| &config.depends_on)
+        .map(|dependency| json(dependency.task()))
+        .collect();
+    let used_by: Vec<String> = used_by
+        .iter()
+        .map(|(field, environment)| {
+            format!(
+                "{{\"field\": {}, \"environment\": {}}}",
+                json(field),
+                json(environment)
+            )
+        })
+        .collect();
+    let (command, error) = match command {
+        Ok(command) => (json(command), "null".to_string()),
+        Err(err) => ("null".to_string(), json(err)),
+    };
+    format!(
+        "\n  {{\"name\": {}, \"description\": {}, \"cmd\": {}, \"env\": {{{}}}, \
+         \"depends_on\": [{}], \"cwd\": {}, \"clean_env\": {}, \"command\": {}, \"error\": {}, \
+         \"used_by\": [{}]}}",
+        json(name),
+        optional(config.and_then(|c| c.description.as_deref())),
+        optional(cmd.as_deref()),
+        env.join(", "),
+        depends_on.join(", "),
+        optional(config.and_then(|c| c.cwd.as_deref())),
+        config.is_some_and(|c| c.clean_env),
+        command,
+        error,
+        used_by.join(", ")
+    )
+}
 
-    println!("Running Docker container: {}", image_tag);
-    println!("Command: {:?}", docker_cmd);
+fn dockerfile_name(environment: &str) -> String {
+    format!("Dockerfile.{}", environment)
+}
 
-    let status = docker_cmd.status()?;
-    if !status.success() {
-        anyhow::bail!("Docker run failed with exit code: {:?}", status.code());
+/// Where an environment's generated Dockerfile is written and read, by every command
+///
+/// That's the environment's `output_file`, or `Dockerfile.<environment>` in `output_dir`,
+/// falling back to the build context, so every command finds it wherever it runs from. With
+/// a git context it goes to the working directory instead. A directory given with `-o`
+/// replaces both directories and keeps only the file name of `output_file`.
+fn dockerfile_path(config: &Config, environment: &str, cli_dir: Option<&Path>) -> PathBuf {
+    let output_file = config
+        .environments
+        .get(environment)
+        .and_then(|e| e.output_file.as_deref())
+        .map(Path::new);
+    let file_name = output_file
+        .and_then(Path::file_name)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(dockerfile_name(environment)));
+    match (cli_dir, output_file) {
+        (Some(dir), _) => dir.join(file_name),
+        (None, Some(file)) => file.to_path_buf(),
+        (None, None) => match config.docker.output_dir.as_deref() {
+            Some(dir) => Path::new(dir).join(file_name),
+            None if config.docker.git_context.is_some() => file_name,
+            None => config.project_dir().join(file_name),
+        },
     }
+}
 
+fn generate_dockerfiles(
+    config: &Config,
+    environment: &str,
+    path: &Path,
+    force: bool,
+) -> Result<bool> {
+    let generator = create_generator(config, environment)?;
+    let dockerfile_content = generator.generate(config, Some(environment))?;
+    let written = write_dockerfile(path, &dockerfile_content, force)?;
+    report_write(path, written);
+    Ok(written)
+}
+
+/// Write a generated Dockerfile, leaving an identical file untouched and refusing to
+/// overwrite one that differs unless `force` is set; returns false if nothing was written
+///
+/// With `force` the file is always written, which bumps its mtime for tools watching it.
+fn write_dockerfile(path: &Path, content: &str, force: bool) -> Result<bool> {
+    create_parent_dir(path)?;
+    if path.exists() && !force {
+        let existing = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if diff::contents_match(&existing, content) {
+            return Ok(false);
+        }
+        anyhow::bail!(
+            "{} already exists and differs from the generated Dockerfile; \
+             pass --force to overwrite it or run `pixi-docker diff` to inspect the changes",
+            path.display()
+        );
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(true)
+}
+
+/// Create the directory a generated file goes in, e.g. a configured `output_dir`
+fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.exists() => {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn report_write(path: &Path, written: bool) {
+    if written {
+        println!("Generated: {}", path.display());
+    } else {
+        println!("Unchanged: {}", path.display());
+    }
+}
+
+/// Print a unified diff for each environment whose Dockerfile differs; returns true if all match
+fn diff_dockerfiles(
+    config: &Config,
+    environments: &[String],
+    output_dir: Option<&Path>,
+) -> Result<bool> {
+    let color = diff::use_color();
+    let mut identical = true;
+
+    for environment in environments {
+        let generated =
+            create_generator(config, environment)?.generate(config, Some(environment))?;
+        let path = dockerfile_path(config, environment, output_dir);
+        let (existing, old_name) = if path.exists() {
+            (fs::read_to_string(&path)?, path.display().to_string())
+        } else {
+            (String::new(), "/dev/null".to_string())
+        };
+
+        if !diff::contents_match(&existing, &generated) {
+            identical = false;
+            let new_name = format!("{} (generated)", path.display());
+            print!(
+                "{}",
+                diff::unified_diff(&existing, &generated, &old_name, &new_name, color)
+            );
+        }
+    }
+
+    Ok(identical)
+}
+
+/// Render an environment's Dockerfile to stdout, noting when the generated one is stale
+fn show_dockerfile(
+    config: &Config,
+    environment: &str,
+    output_dir: Option<&Path>,
+    line_numbers: bool,
+) -> Result<()> {
+    let color = diff::use_color();
+    let generator = create_generator(config, environment)?;
+    let dockerfile = generator.generate(config, Some(environment))?;
+    print!(
+        "{}{}",
+        show::header(generator.origin(), environment, color),
+        show::highlight(&dockerfile, line_numbers, color)
+    );
+
+    let path = dockerfile_path(config, environment, output_dir);
+    if path.exists() {
+        let existing = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if !diff::contents_match(&existing, &dockerfile) {
+            eprintln!(
+                "Note: {} differs from this render; run `pixi-docker diff` to compare them \
+                 or `pixi-docker generate --force` to update it",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Generate every environment's Dockerfile into the build context and a bake file building them all
+fn write_bake_file(config: &Config, output: &Path, json: bool, force: bool) -> Result<()> {
+    let context = config.project_dir();
+    let context_dir = if context.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        context.as_path()
+    };
+
+    let mut targets = Vec::new();
+    for environment in config.environment_names() {
+        let platforms: Vec<String> = match config.platform(&environment) {
+            Some(platform) if config.docker.manifest_archs.is_empty() => {
+                vec![platform.to_string()]
+            }
+            _ => config
+                .docker
+                .manifest_archs
+                .iter()
+                .map(|arch| format!("linux/{}", arch))
+                .collect(),
+        };
+        // One Dockerfile serves every platform of a target, so it can't switch base images
+        if platforms.len() > 1
+            && matches!(
+                config.configured_base_image(&environment),
+                Some(BaseImage::PerArch(_))
+            )
+        {
+            anyhow::bail!(
+                "Environment '{}' sets base_image per architecture, which a bake target building \
+                 {} at once can't use; build each platform with `pixi-docker build --platform` \
+                 and combine them with `pixi-docker manifest`",
+                environment,
+                platforms.join(", ")
+            );
+        }
+        let dockerfile = dockerfile_path(config, &environment, None);
+        generate_dockerfiles(config, &environment, &dockerfile, force)?;
+        let (cache_from, cache_to) = resolve_build_cache(config, &environment);
+        targets.push(bake::Target {
+            context: context_dir.display().to_string(),
+            // Bake resolves a relative Dockerfile against the context
+            dockerfile: dockerfile
+                .strip_prefix(context_dir)
+                .unwrap_or(&dockerfile)
+                .display()
+                .to_string(),
+            tags: resolve_image_tags(config, &environment, Vec::new())?,
+            platforms,
+            args: config.build_args(&environment),
+            cache_from,
+            cache_to: cache_to.into_iter().collect(),
+            secret: config.build_secrets(),
+            ssh: config.build_ssh(),
+            name: environment,
+        });
+    }
+
+    let content = if json {
+        bake::to_json(&targets)
+    } else {
+        bake::to_hcl(&targets)
+    };
+    fs::write(output, content).with_context(|| format!("Failed to write {}", output.display()))?;
+    println!("Generated: {}", output.display());
+    Ok(())
+}
+
+/// The base images and template hashes of every environment, in the order they appear
+fn locked_inputs(config: &Config) -> Result<(Vec<String>, BTreeMap<String, String>)> {
+    let mut images = Vec::new();
+    let mut templates = BTreeMap::new();
+    for environment in config.environment_names() {
+        let generator = create_generator(config, &environment)?;
+        let dockerfile = generator.render(config, Some(&environment))?;
+        // Per-architecture base images are pinned for every architecture, not just this one
+        let per_arch = match config.configured_base_image(&environment) {
+            Some(BaseImage::PerArch(per_arch)) => per_arch.values().cloned().collect(),
+            _ => Vec::new(),
+        };
+        for image in template::base_images(&dockerfile)
+            .into_iter()
+            .chain(per_arch)
+        {
+            if !images.contains(&image) {
+                images.push(image);
+            }
+        }
+        templates.insert(environment, generator.template_hash());
+    }
+    Ok((images, templates))
+}
+
+/// Whether any environment resolves `pixi_version = "latest"`
+fn uses_latest_pixi(config: &Config) -> bool {
+    config
+        .environment_names()
+        .iter()
+        .any(|environment| config.configured_pixi_version(environment) == Some("latest"))
+}
+
+/// Record the pixi version, the digest of every base image and the templates of every
+/// environment in the lock file
+fn pin_inputs(config: &Config, update: bool) -> Result<()> {
+    let path = config.lock_path();
+    let mut lock = lock::LockFile::load(&path)?;
+    // Resolved first, as the base images are rendered with it
+    lock.pixi_version = match uses_latest_pixi(config) {
+        true if update || lock.pixi_version.is_none() => {
+            Some(registry::latest_pixi_version(config.network)?)
+        }
+        true => lock.pixi_version,
+        false => None,
+    };
+    lock.save(&path)?;
+
+    let (images, templates) = locked_inputs(config)?;
+    lock.resolve_digests(config, &images, update)?;
+    lock.templates = templates;
+    lock.save(&path)?;
+
+    if let Some(version) = &lock.pixi_version {
+        println!("pixi {}", version);
+    }
+    for image in &images {
+        println!("{}@{}", image, lock.digests[image]);
+    }
+    println!("Locked {} image(s) in {}", images.len(), path.display());
     Ok(())
 }
+
+/// Report what the lock file lacks for the config; returns true if it covers everything
+fn check_lock_file(config: &Config) -> Result<bool> {
+    let path = config.lock_path();
+    if !path.exists() {
+        println!("MISSING {} (run pixi-docker pin)", path.display());
+        return Ok(false);
+    }
+    let lock = lock::LockFile::load(&path)?;
+    let latest_pixi = uses_latest_pixi(config);
+    // The base images can't be rendered without the pixi version, which needs the network
+    let missing = if latest_pixi && lock.pixi_version.is_none() {
+        lock.missing(true, &[], &BTreeMap::new())
+    } else {
+        let (images, templates) = locked_inputs(config)?;
+        lock.missing(latest_pixi, &images, &templates)
+    };
+    for item in &missing {
+        println!("MISSING {} (run pixi-docker pin)", item);
+    }
+    if missing.is_empty() {
+        println!("OK {}", path.display());
+    }
+    Ok(missing.is_empty())
+}
+
+/// Report whether each environment's Dockerfile is up to date; returns true if all are
+fn check_dockerfiles(
+    config: &Config,
+    environments: &[String],
+    output_dir: Option<&Path>,
+) -> Result<bool> {
+    let mut up_to_date = true;
+
+    for environment in environments {
+        let generated =
+            create_generator(config, environment)?.generate(config, Some(environment))?;
+        let path = dockerfile_path(config, environment, output_dir);
+
+        if !path.exists() {
+            up_to_date = false;
+            println!("MISSING {} (run pixi-docker generate)", path.display());
+        } else if diff::contents_match(&fs::read_to_string(&path)?, &generated) {
+            println!("OK {}", path.display());
+        } else {
+            up_to_date = false;
+            println!("STALE {} (run pixi-docker generate)", path.display());
+        }
+    }
+
+    Ok(up_to_date)
+}
+
+#[derive(Clone, Default)]
+struct BuildOptions {
+    /// Tags from the command line, replacing the configured ones
+    tags: Vec<String>,
+    extra_args: Vec<String>,
+    dry_run: bool,
+    no_lock_check: bool,
+    skip_platform_check: bool,
+    /// Overwrite a persisted Dockerfile that differs from the generated one
+    force: bool,
+    skip_hooks: bool,
+    /// Build even when the image's input hash matches
+    force_rebuild: bool,
+    /// Stage to stop the build at
+    target: Option<String>,
+    /// Stop at the production stage of environments that run their tests in the build
+    skip_tests: bool,
+    /// Capture the docker output, showing this many trailing lines on failure
+    quiet: Option<usize>,
+    format: OutputFormat,
+    /// Where the runtime writes the image ID
+    iidfile: Option<PathBuf>,
+    /// Where buildx writes its build metadata, which makes the build use buildx
+    metadata_file: Option<PathBuf>,
+    /// Output spec exporting the result instead of loading it, overriding the `output` setting
+    output: Option<String>,
+    /// Paths copied out of the image instead of tagging it
+    extract: Vec<docker::Extract>,
+    /// Prefix for every line of build output, e.g. `[prod] ` when builds run concurrently
+    output_prefix: Option<String>,
+}
+
+/// Outcome of one environment in `build --all`
+enum BuildStatus {
+    Built,
+    Failed,
+    /// Not attempted because an earlier build failed
+    Skipped,
+}
+
+/// Build every environment and print a summary table of their tags and outcomes
+///
+/// Up to `jobs` builds run at once, each on its own thread with its output lines prefixed by
+/// the environment; with one job they run in turn exactly as single builds do. After a
+/// failure no new build starts unless `keep_going` is set; either way the command fails when
+/// any build did. The builds stay in our process group, so Ctrl-C reaches every one of them.
+fn build_all(
+    config: &Config,
+    environments: &[String],
+    options: BuildOptions,
+    keep_going: bool,
+    jobs: usize,
+) -> Result<()> {
+    let build = |environment: &String, options: BuildOptions| {
+        let tag = resolve_image_tag(config, environment, None).ok();
+        let result = build_docker_image(config, environment, options)
+            .with_context(|| format!("Failed to build environment '{}'", environment));
+        if let (true, Err(err)) = (keep_going, &result) {
+            eprintln!("Error: {:#}", err);
+        }
+        (tag, result)
+    };
+
+    // One slot per environment, so the summary keeps the environment order
+    let mut outcomes: Vec<Option<(Option<String>, Result<()>)>> = Vec::new();
+    if jobs <= 1 {
+        for environment in environments {
+            let outcome = build(environment, options.clone());
+            let failed = outcome.1.is_err();
+            outcomes.push(Some(outcome));
+            if failed && !keep_going {
+                break;
+            }
+        }
+    } else {
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let slots: Vec<Mutex<Option<_>>> = environments.iter().map(|_| Mutex::new(None)).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.min(environments.len()) {
+                scope.spawn(|| {
+                    while !stop.load(Ordering::SeqCst) {
+                        let index = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(environment) = environments.get(index) else {
+                            return;
+                        };
+                        let options = BuildOptions {
+                            output_prefix: Some(format!("[{}] ", environment)),
+                            ..options.clone()
+                        };
+                        let outcome = build(environment, options);
+                        if outcome.1.is_err() && !keep_going {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        *slots[index].lock().expect("build slot poisoned") = Some(outcome);
+                    }
+                });
+            }
+        });
+        outcomes = slots
+            .into_iter()
+            .map(|slot| slot.into_inner().expect("build slot poisoned"))
+            .collect();
+    }
+    outcomes.resize_with(environments.len(), || None);
+
+    let mut results = Vec::new();
+    let mut failures = Vec::new();
+    let mut first_error = None;
+    for (environment, outcome) in environments.iter().zip(outcomes) {
+        match outcome {
+            None => results.push((environment, None, BuildStatus::Skipped)),
+            Some((tag, Ok(()))) => results.push((environment, tag, BuildStatus::Built)),
+            Some((tag, Err(err))) => {
+                results.push((environment, tag, BuildStatus::Failed));
+                failures.push(environment.as_str());
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    // Quiet builds keep stdout for the tags
+    let summary = render_build_summary(&results, options.dry_run);
+    if options.quiet.is_some() {
+        eprint!("{}", summary);
+    } else {
+        print!("{}", summary);
+    }
+
+    match first_error {
+        Some(err) if !keep_going => Err(err),
+        Some(_) => anyhow::bail!(
+            "{} of {} environments failed to build: {}",
+            failures.len(),
+            environments.len(),
+            failures.join(", ")
+        ),
+        None => Ok(()),
+    }
+}
+
+fn render_build_summary(
+    results: &[(&String, Option<String>, BuildStatus)],
+    dry_run: bool,
+) -> String {
+    let rows: Vec<[&str; 3]> = results
+        .iter()
+        .map(|(environment, tag, status)| {
+            let status = match status {
+                BuildStatus::Built if dry_run => "dry run",
+                BuildStatus::Built => "built",
+                BuildStatus::Failed => "failed",
+                BuildStatus::Skipped => "skipped",
+            };
+            [environment.as_str(), tag.as_deref().unwrap_or("-"), status]
+        })
+        .collect();
+    let header = ["ENVIRONMENT", "TAG", "STATUS"];
+    let width = |column: usize| {
+        rows.iter()
+            .map(|row| row[column].len())
+            .chain([header[column].len()])
+            .max()
+            .unwrap_or(0)
+    };
+    let (env_width, tag_width) = (width(0), width(1));
+    let mut summary = String::from("\n");
+    for row in std::iter::once(header).chain(rows) {
+        summary.push_str(&format!(
+            "{:env_width$}  {:tag_width$}  {}\n",
+            row[0],
+            row[1],
+            row[2],
+            env_width = env_width,
+            tag_width = tag_width
+        ));
+    }
+    summary
+}
+
+/// Trailing build output lines included in the error when a build fails
+const BUILD_ERROR_TAIL: usize = 30;
+
+/// Where `docker build` reads the generated Dockerfile from
+enum DockerfileSource {
+    /// `Dockerfile.<env>` in the build context, kept after the build
+    File(PathBuf),
+    /// Piped to `docker build -f -`
+    Stdin,
+    /// A file in the build context that is removed after the build
+    TempFile(PathBuf),
+}
+
+fn build_docker_image(config: &Config, environment: &str, options: BuildOptions) -> Result<()> {
+    if !options.no_lock_check {
+        config.check_lockfile()?;
+    }
+    if !options.skip_platform_check {
+        config.check_platform(environment)?;
+    }
+    if !options.dry_run {
+        config.check_ssh_agent()?;
+        warn_base_image_platform(config, environment);
+    }
+
+    let context = config.project_dir();
+    let context_dir = if context.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        context.as_path()
+    };
+    warn_private_channels(config, environment);
+    warn_lock_schema(config, environment);
+    let run_hooks = !options.skip_hooks && !options.dry_run;
+    let json = options.format == OutputFormat::Json;
+    if run_hooks {
+        hooks::run(
+            "pre_build",
+            config.pre_build(environment),
+            context_dir,
+            &[],
+            json,
+        )?;
+    }
+
+    // First generate the Dockerfile
+    let generator = create_generator(config, environment)?;
+    let dockerfile_content = generator.generate(config, Some(environment))?;
+    let target = match options.skip_tests && config.test_in_build(environment) {
+        true => Some(template::PRODUCTION_STAGE.to_string()),
+        false => options.target.clone(),
+    };
+    if let Some(target) = &target {
+        let stages = template::stage_names(&dockerfile_content);
+        if !stages.contains(target) {
+            anyhow::bail!(
+                "Stage '{}' not found in the Dockerfile for environment '{}' (available stages: {})",
+                target,
+                environment,
+                stages.join(", ")
+            );
+        }
+    }
+    // A git context is fetched by the runtime, the Dockerfile is generated into the working
+    // directory and passed next to it
+    let git_context = config.docker.git_context.as_deref();
+    let generated_path = dockerfile_path(config, environment, None);
+    let dockerfile = if !config.docker.ephemeral_dockerfile {
+        DockerfileSource::File(generated_path.clone())
+    } else if docker::supports_stdin_dockerfile(config) && git_context.is_none() {
+        DockerfileSource::Stdin
+    } else {
+        // Unique per process so that concurrent builds don't clash
+        let file_name = generated_path.file_name().unwrap_or_default();
+        DockerfileSource::TempFile(generated_path.with_file_name(format!(
+            ".{}.{}",
+            file_name.to_string_lossy(),
+            std::process::id()
+        )))
+    };
+    if let (DockerfileSource::File(path), false) = (&dockerfile, options.dry_run) {
+        let written = write_dockerfile(path, &dockerfile_content, options.force)?;
+        if options.quiet.is_none() && !json {
+            report_write(path, written);
+        }
+    }
+
+    let image_tags = resolve_image_tags(config, environment, options.tags.clone())?;
+    let image_tag = &image_tags[0];
+
+    // Attestations and metadata files are only supported by buildx
+    let attestations = config.docker.sbom || config.docker.provenance.is_some();
+    if attestations && !options.dry_run && !config.engine_api() {
+        docker::check_attestation_support(config)?;
+    }
+    if options.metadata_file.is_some() && docker::is_podman(config) && !config.engine_api() {
+        anyhow::bail!("--metadata-file requires docker buildx, which is not available with podman");
+    }
+    let export = match options.output.as_deref().or(config.output(environment)) {
+        Some(spec) => Some(docker::OutputSpec::parse(spec)?),
+        None => None,
+    };
+    if let Some(export) = &export {
+        check_export(export, &options.extra_args)?;
+    }
+    // podman builds export and use caches by themselves, docker needs buildx
+    let (cache_from, cache_to) = resolve_build_cache(config, environment);
+    let caches = !cache_from.is_empty() || cache_to.is_some();
+    let buildx = attestations
+        || options.metadata_file.is_some()
+        || ((export.is_some() || caches) && !docker::is_podman(config));
+    for (option, path) in [
+        ("--iidfile", &options.iidfile),
+        ("--metadata-file", &options.metadata_file),
+    ] {
+        if let Some(path) = path {
+            check_output_file(option, path)?;
+        }
+    }
+
+    let proxy = config.build_proxy();
+    if config.docker.forward_proxy == Some(true) && proxy.is_empty() {
+        eprintln!(
+            "Warning: `forward_proxy` is set, but none of {} is, so no proxy is passed to the build",
+            PROXY_VARS.join(", ")
+        );
+    }
+    let mut spec = docker::BuildSpec {
+        tags: image_tags.clone(),
+        target,
+        platform: config.platform(environment).map(str::to_string),
+        build_args: config.build_args(environment).into_iter().collect(),
+        env_build_args: proxy,
+        secrets: config.build_secrets(),
+        ssh: config.build_ssh(),
+        labels: match config.docker.metadata_labels {
+            true => metadata_labels(config, context_dir)?
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            false => Vec::new(),
+        },
+        cache_from,
+        cache_to,
+    };
+    // Everything but the labels themselves and the caches goes into the input hash
+    let mut settings = docker::BuildSpec {
+        labels: Vec::new(),
+        cache_from: Vec::new(),
+        cache_to: None,
+        ..spec.clone()
+    }
+    .args();
+    settings.extend(options.extra_args.iter().cloned());
+    // Files the build writes into the context aren't inputs of the next one; the Dockerfile
+    // is hashed as rendered
+    let outputs: Vec<&Path> = [
+        Some(generated_path.as_path()),
+        options.iidfile.as_deref(),
+        docker::option_value(&options.extra_args, "--iidfile").map(Path::new),
+        options.metadata_file.as_deref(),
+        docker::option_value(&options.extra_args, "--metadata-file").map(Path::new),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let input_hash = input_hash::compute(config, &dockerfile_content, &settings, &outputs)?;
+    spec.labels
+        .push((input_hash::LABEL.to_string(), input_hash.clone()));
+    if !options.extract.is_empty() {
+        if config.engine_api() {
+            check_engine_api_build(config, &spec, false, true, &options.extra_args)?;
+        }
+        return extract_artifacts(
+            config,
+            environment,
+            &options,
+            &spec,
+            &dockerfile_content,
+            &generated_path,
+        );
+    }
+
+    let capture = options.quiet.is_some();
+    let prefix = options.output_prefix.clone().unwrap_or_default();
+
+    let may_skip = !options.dry_run
+        && !options.force_rebuild
+        && options.metadata_file.is_none()
+        && export.is_none()
+        && input_hash::may_skip(&options.extra_args);
+    if let Some(image_id) = may_skip
+        .then(|| up_to_date_image(config, &image_tags, &input_hash))
+        .flatten()
+    {
+        if let Some(path) = &options.iidfile {
+            fs::write(path, &image_id)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        if json {
+            println!(
+                "{}",
+                build_json(environment, &spec, Some(&image_id), None, None, true)
+            );
+        } else if capture {
+            for tag in &image_tags {
+                println!("{}", tag);
+            }
+        } else {
+            println!(
+                "{}Image {} is up to date, skipping the build (pass --force-rebuild to build anyway)",
+                prefix,
+                image_tags.join(", ")
+            );
+            println!("{}Image ID: {}", prefix, image_id);
+        }
+        return Ok(());
+    }
+    let output = if capture {
+        docker::BuildOutput::Hidden
+    } else if json {
+        docker::BuildOutput::EchoToStderr
+    } else if let Some(prefix) = &options.output_prefix {
+        docker::BuildOutput::Prefixed(prefix.as_str().into())
+    } else {
+        docker::BuildOutput::Echo
+    };
+    let tail = options.quiet.unwrap_or(BUILD_ERROR_TAIL);
+
+    let endpoint = docker::endpoint(config);
+    let announce_daemon = || -> Result<()> {
+        docker::check_daemon(config, &endpoint)?;
+        if !capture && !json && (config.verbose || !endpoint.is_local()) {
+            println!("{}Docker daemon: {}", prefix, endpoint);
+        }
+        Ok(())
+    };
+
+    // The size report compares with the image the repository had before this build
+    let report_size =
+        options.quiet.is_none() && !options.dry_run && !config.engine_api() && export.is_none();
+    let previous_image = match report_size {
+        true => size::previous_image(config, image_tag),
+        false => None,
+    };
+
+    let image_id = if config.engine_api() {
+        let push = check_engine_api_build(
+            config,
+            &spec,
+            options.metadata_file.is_some(),
+            export.is_some(),
+            &options.extra_args,
+        )?;
+        if options.dry_run {
+            println!(
+                "Would build {} with the Docker Engine API: {} {}",
+                spec.tags.join(", "),
+                spec.args().join(" "),
+                context_dir.display()
+            );
+            return Ok(());
+        }
+
+        warn_unignored_pixi_dir(context_dir);
+        announce_daemon()?;
+        if !capture && !json {
+            println!("{}Building Docker image: {}", prefix, image_tag);
+            println!("{}Using the Docker Engine API", prefix);
+        }
+        let image_id = api::build(
+            &endpoint,
+            &spec,
+            context_dir,
+            &dockerfile_name(environment),
+            &dockerfile_content,
+            output.clone(),
+            tail,
+        )?;
+        if let Some(path) = &options.iidfile {
+            fs::write(path, &image_id)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        if push {
+            api::push(&endpoint, &spec.tags, output)?;
+        }
+        Some(image_id)
+    } else {
+        // Build the Docker command
+        let mut docker_cmd = docker::command(config);
+        if buildx {
+            docker_cmd.arg("buildx");
+        }
+        docker_cmd.arg("build");
+        // Exports like `type=local` write files, which have no name to tag
+        if export.as_ref().is_none_or(docker::OutputSpec::takes_tags) {
+            for tag in &spec.tags {
+                docker_cmd.arg("-t").arg(tag);
+            }
+        }
+        docker_cmd.arg("-f");
+        match &dockerfile {
+            DockerfileSource::File(path) | DockerfileSource::TempFile(path) => docker_cmd.arg(path),
+            DockerfileSource::Stdin => docker_cmd.arg("-"),
+        };
+
+        if config.docker.sbom {
+            docker_cmd.arg("--sbom=true");
+        }
+        if let Some(provenance) = &config.docker.provenance {
+            docker_cmd.arg(format!("--provenance={}", provenance));
+        }
+        if buildx {
+            // Keep the image available locally like a plain `docker build`, unless exporting elsewhere
+            let exports = export.is_some()
+                || options.extra_args.iter().any(|arg| {
+                    arg == "--push" || arg == "--output" || arg.starts_with("--output=")
+                });
+            if !exports {
+                docker_cmd.arg("--load");
+            }
+        }
+
+        docker_cmd.args(spec.args());
+        if let Some(export) = &export {
+            docker_cmd.arg("--output").arg(&export.spec);
+        }
+
+        // Have the runtime write the image ID to a file, unless the caller already asked for one
+        let iidfile = match (
+            options.iidfile,
+            docker::option_value(&options.extra_args, "--iidfile"),
+        ) {
+            (Some(path), _) => {
+                docker_cmd.arg("--iidfile").arg(&path);
+                IidFile::Given(path)
+            }
+            (None, Some(path)) => IidFile::Given(PathBuf::from(path)),
+            (None, None) if options.dry_run => IidFile::None,
+            (None, None) => {
+                let path = std::env::temp_dir().join(format!(
+                    "pixi-docker-{}-{}.iid",
+                    std::process::id(),
+                    environment
+                ));
+                docker_cmd.arg("--iidfile").arg(&path);
+                IidFile::Temporary(path)
+            }
+        };
+        if let Some(path) = &options.metadata_file {
+            docker_cmd.arg("--metadata-file").arg(path);
+        }
+
+        for arg in options.extra_args {
+            docker_cmd.arg(arg);
+        }
+
+        match git_context {
+            Some(url) => docker_cmd.arg(url),
+            None => docker_cmd.arg(context_dir),
+        };
+
+        if options.dry_run {
+            println!("Would run: {:?}", docker_cmd);
+            return Ok(());
+        }
+
+        if git_context.is_none() {
+            warn_unignored_pixi_dir(context_dir);
+        }
+        announce_daemon()?;
+        if !capture && !json {
+            println!("{}Building Docker image: {}", prefix, image_tag);
+            println!("{}Running: {:?}", prefix, docker_cmd);
+        }
+
+        let result = match &dockerfile {
+            DockerfileSource::File(_) => docker::run_build(&mut docker_cmd, None, output, tail),
+            DockerfileSource::Stdin => docker::run_build(
+                &mut docker_cmd,
+                Some(dockerfile_content.as_bytes()),
+                output,
+                tail,
+            ),
+            DockerfileSource::TempFile(path) => {
+                create_parent_dir(path)?;
+                fs::write(path, &dockerfile_content)?;
+                let result = docker::run_build(&mut docker_cmd, None, output, tail);
+                fs::remove_file(path)?;
+                result
+            }
+        };
+        let image_id = iidfile.read();
+        let (status, output_tail) = result?;
+        if !status.success() {
+            if capture || json {
+                eprintln!("Running: {:?}", docker_cmd);
+            }
+            let dockerfile = match &dockerfile {
+                DockerfileSource::File(path) => path.display().to_string(),
+                // Temporary and piped Dockerfiles are gone, point at how to reproduce them
+                DockerfileSource::Stdin | DockerfileSource::TempFile(_) => format!(
+                    "not kept, run `pixi-docker generate -e {}` to inspect it",
+                    environment
+                ),
+            };
+            return Err(PixiDockerError::RuntimeExit {
+                what: "Docker build".to_string(),
+                code: status.code(),
+                note: Some(format!("Dockerfile: {}", dockerfile)),
+                output_tail,
+            }
+            .into());
+        }
+        image_id
+    };
+
+    let size_report = match report_size {
+        true => size::report(config, image_tag, previous_image)
+            .inspect_err(|err| {
+                if config.verbose {
+                    eprintln!(
+                        "Warning: couldn't read the size of {}: {}",
+                        image_tag,
+                        error::one_line(err)
+                    );
+                }
+            })
+            .ok(),
+        false => None,
+    };
+
+    if json {
+        // Fold the buildx metadata in, so callers only parse one document
+        let metadata = match &options.metadata_file {
+            Some(path) => Some(
+                fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            ),
+            None => None,
+        };
+        println!(
+            "{}",
+            build_json(
+                environment,
+                &spec,
+                image_id.as_deref(),
+                metadata.as_deref(),
+                size_report.as_ref(),
+                false
+            )
+        );
+    } else if capture {
+        for tag in &image_tags {
+            println!("{}", tag);
+        }
+    } else if let Some(export) = &export {
+        println!("{}Exported the build result to {}", prefix, export.spec);
+        if let Some(image_id) = &image_id {
+            println!("{}Image ID: {}", prefix, image_id);
+        }
+    } else {
+        println!(
+            "{}Successfully built Docker image: {}",
+            prefix,
+            image_tags.join(", ")
+        );
+        if let Some(image_id) = &image_id {
+            println!("{}Image ID: {}", prefix, image_id);
+        }
+        for line in size_report.iter().flat_map(size::SizeReport::lines) {
+            println!("{}{}", prefix, line);
+        }
+    }
+
+    if run_hooks {
+        hooks::run(
+            "post_build",
+            config.post_build(environment),
+            context_dir,
+            &[
+                ("IMAGE_TAG", image_tag),
+                ("IMAGE_TAGS", &image_tags.join(" ")),
+            ],
+            json,
+        )?;
+    }
+    Ok(())
+}
+
+/// Copy paths out of the built image to the host, through a `FROM scratch` stage per directory
+///
+/// The stages are only added to the Dockerfile handed to the runtime, piped or in a temporary
+/// file, so `Dockerfile.<env>` keeps building the tagged image as before. Each stage copies from
+/// `--target`, or else the final stage, and is exported with a `type=local` build output.
+fn extract_artifacts(
+    config: &Config,
+    environment: &str,
+    options: &BuildOptions,
+    spec: &docker::BuildSpec,
+    dockerfile_content: &str,
+    generated_path: &Path,
+) -> Result<()> {
+    let source = match &spec.target {
+        Some(target) => target.clone(),
+        None => template::final_stage(dockerfile_content).with_context(|| {
+            format!(
+                "The last stage of the Dockerfile for environment '{}' has no name to copy from; \
+                 name it with `FROM <image> AS <name>` or pass --target",
+                environment
+            )
+        })?,
+    };
+    // Paths for the same directory share a stage, and each directory is its own build
+    let mut groups: Vec<(&Path, Vec<&docker::Extract>)> = Vec::new();
+    for extract in &options.extract {
+        match groups.iter_mut().find(|(dest, _)| *dest == extract.dest) {
+            Some((_, extracts)) => extracts.push(extract),
+            None => groups.push((&extract.dest, vec![extract])),
+        }
+    }
+    let stages: Vec<String> = (1..=groups.len())
+        .map(|n| match n {
+            1 => template::EXPORT_STAGE.to_string(),
+            n => format!("{}-{}", template::EXPORT_STAGE, n),
+        })
+        .collect();
+    let mut variant = dockerfile_content.to_string();
+    for ((_, extracts), stage) in groups.iter().zip(&stages) {
+        let paths: Vec<&str> = extracts.iter().map(|e| e.path.as_str()).collect();
+        variant.push_str(&template::export_stage(stage, &source, &paths));
+    }
+
+    let project_dir = config.project_dir();
+    let context = match &config.docker.git_context {
+        Some(url) => OsStr::new(url),
+        None if project_dir.as_os_str().is_empty() => OsStr::new("."),
+        None => project_dir.as_os_str(),
+    };
+    let temp_file =
+        match docker::supports_stdin_dockerfile(config) && config.docker.git_context.is_none() {
+            true => None,
+            false => {
+                let file_name = generated_path.file_name().unwrap_or_default();
+                Some(generated_path.with_file_name(format!(
+                    ".{}.{}",
+                    file_name.to_string_lossy(),
+                    std::process::id()
+                )))
+            }
+        };
+    let prefix = options.output_prefix.clone().unwrap_or_default();
+    let capture = options.quiet.is_some();
+    let tail = options.quiet.unwrap_or(BUILD_ERROR_TAIL);
+
+    let mut commands = Vec::new();
+    for ((dest, _), stage) in groups.iter().zip(&stages) {
+        let mut docker_cmd = docker::command(config);
+        // podman exports by itself, docker needs buildx
+        if !docker::is_podman(config) {
+            docker_cmd.arg("buildx");
+        }
+        docker_cmd.arg("build").arg("-f");
+        match &temp_file {
+            Some(path) => docker_cmd.arg(path),
+            None => docker_cmd.arg("-"),
+        };
+        // The labels describe a tagged image, which an export doesn't produce
+        let stage_spec = docker::BuildSpec {
+            target: Some(stage.clone()),
+            labels: Vec::new(),
+            ..spec.clone()
+        };
+        docker_cmd.args(stage_spec.args());
+        docker_cmd
+            .arg("--output")
+            .arg(format!("type=local,dest={}", dest.display()));
+        docker_cmd.args(&options.extra_args);
+        docker_cmd.arg(context);
+        commands.push(docker_cmd);
+    }
+    if options.dry_run {
+        for docker_cmd in &commands {
+            println!("Would run: {:?}", docker_cmd);
+        }
+        return Ok(());
+    }
+
+    if config.docker.git_context.is_none() {
+        warn_unignored_pixi_dir(Path::new(context));
+    }
+    let endpoint = docker::endpoint(config);
+    docker::check_daemon(config, &endpoint)?;
+    if let Some(path) = &temp_file {
+        create_parent_dir(path)?;
+        fs::write(path, &variant)?;
+    }
+    let result = (|| -> Result<()> {
+        for (docker_cmd, (dest, extracts)) in commands.iter_mut().zip(&groups) {
+            if !capture {
+                println!("{}Running: {:?}", prefix, docker_cmd);
+            }
+            let input = temp_file.is_none().then_some(variant.as_bytes());
+            let output = match (capture, &options.output_prefix) {
+                (true, _) => docker::BuildOutput::Hidden,
+                (false, Some(prefix)) => docker::BuildOutput::Prefixed(prefix.as_str().into()),
+                (false, None) => docker::BuildOutput::Echo,
+            };
+            let (status, output_tail) = docker::run_build(docker_cmd, input, output, tail)?;
+            if !status.success() {
+                if let Some(missing) = extracts
+                    .iter()
+                    .find(|extract| output_tail.iter().any(|line| extract.is_missing_in(line)))
+                {
+                    return Err(PixiDockerError::ExtractNotFound {
+                        path: missing.path.clone(),
+                        stage: source.clone(),
+                        environment: environment.to_string(),
+                    }
+                    .into());
+                }
+                return Err(PixiDockerError::RuntimeExit {
+                    what: "Docker build".to_string(),
+                    code: status.code(),
+                    note: Some(format!("extracting to {}", dest.display())),
+                    output_tail,
+                }
+                .into());
+            }
+            for extract in extracts {
+                println!("{}Extracted {} to {}", prefix, extract.path, dest.display());
+            }
+        }
+        Ok(())
+    })();
+    if let Some(path) = &temp_file {
+        fs::remove_file(path)?;
+    }
+    result
+}
+
+/// Fail on build settings the Engine API backend can't honor, returning whether to push
+///
+/// The classic build endpoint has no attestations, build metadata, exports, caches, git contexts
+/// or BuildKit secrets, and of the extra build arguments only `--push` is understood.
+fn check_engine_api_build(
+    config: &Config,
+    spec: &docker::BuildSpec,
+    metadata_file: bool,
+    export: bool,
+    extra_args: &[String],
+) -> Result<bool> {
+    let unsupported = [
+        (config.docker.sbom, "`sbom`"),
+        (config.docker.provenance.is_some(), "`provenance`"),
+        (metadata_file, "--metadata-file"),
+        (export, "Build outputs (`output`, --output, --extract)"),
+        (
+            !spec.secrets.is_empty(),
+            "build secrets (`auth_file`, `secrets`)",
+        ),
+        (!spec.ssh.is_empty(), "`ssh_forward`"),
+        (config.docker.git_context.is_some(), "`git_context`"),
+        (
+            !spec.cache_from.is_empty() || spec.cache_to.is_some(),
+            "Build caches (`cache_from`, `cache_to`)",
+        ),
+    ];
+    if let Some((_, what)) = unsupported.iter().find(|(used, _)| *used) {
+        anyhow::bail!(
+            "{} can't be used with runtime = \"api\", which builds without BuildKit; \
+             use the docker CLI instead",
+            what
+        );
+    }
+    match extra_args {
+        [] => Ok(false),
+        [arg] if arg == "--push" => Ok(true),
+        args => anyhow::bail!(
+            "Extra build arguments ({}) can't be passed with runtime = \"api\", which only \
+             understands --push",
+            args.join(" ")
+        ),
+    }
+}
+
+/// Warn when `pixi.lock` uses a schema newer than the pinned `pixi_version` can read, which
+/// would otherwise only show up as a parse error from `pixi install` inside the build
+fn warn_lock_schema(config: &Config, environment: &str) {
+    let Some(pixi_version) = config
+        .configured_pixi_version(environment)
+        .filter(|version| *version != DEFAULT_PIXI_VERSION)
+    else {
+        return;
+    };
+    if config.docker.install_mode == InstallMode::None || config.manifest_path().is_none() {
+        return;
+    }
+    let lockfile = config.project_dir().join("pixi.lock");
+    let Some(schema) = fs::read_to_string(&lockfile)
+        .ok()
+        .and_then(|content| pixi::lock_schema_version(&content))
+    else {
+        return;
+    };
+    let Some(minimum) = pixi::min_pixi_for_lock_schema(schema) else {
+        return;
+    };
+    let supported = VersionRequirement::parse(&format!(">={}", minimum))
+        .and_then(|requirement| requirement.matches(pixi_version));
+    if let Ok(false) = supported {
+        eprintln!(
+            "Warning: {} has schema version {}, which needs pixi {} or newer, but environment \
+             '{}' pins pixi_version {}; raise `pixi_version` or re-lock with pixi {}",
+            lockfile.display(),
+            schema,
+            minimum,
+            environment,
+            pixi_version,
+            pixi_version
+        );
+    }
+}
+
+/// Warn about channels that need credentials when none are passed to the build, which would
+/// otherwise only fail with an opaque 401 once `pixi install` runs
+fn warn_private_channels(config: &Config, environment: &str) {
+    if config.docker.auth_file.is_some() || !config.docker.secrets.is_empty() {
+        return;
+    }
+    let Some(pixi_toml) = load_pixi_toml(config) else {
+        return;
+    };
+    let pixi_environment = config.pixi_environment(environment);
+    for channel in pixi_toml.environment_channels(Some(pixi_environment)) {
+        if channel_needs_auth(channel) {
+            eprintln!(
+                "Warning: channel {} looks private, but no credentials are passed to the build; \
+                 set `auth_file` to a pixi credentials file or add `secrets` in [docker]",
+                channel
+            );
+        }
+    }
+}
+
+/// Warn when the single `base_image` of an environment built for a requested platform doesn't
+/// provide that architecture, which docker would otherwise build emulated
+fn warn_base_image_platform(config: &Config, environment: &str) {
+    let (Some(BaseImage::Single(image)), Some(platform)) = (
+        config.configured_base_image(environment),
+        config.platform(environment),
+    ) else {
+        return;
+    };
+    if config.network == NetworkPolicy::Offline || image.contains(['$', '@']) {
+        return;
+    }
+    let arch = config.target_arch(environment);
+    match registry::RegistryClient::new().image_architectures(image) {
+        Ok(architectures) if !architectures.contains(&arch) => eprintln!(
+            "Warning: base image {} has no {} image (it provides {}), so environment '{}' is \
+             built for {} under emulation; set base_image = {{ {} = \"...\" }} to pick an \
+             image per architecture",
+            image,
+            arch,
+            architectures.join(", "),
+            environment,
+            platform,
+            arch
+        ),
+        Ok(_) => {}
+        Err(err) if config.verbose => {
+            eprintln!(
+                "Could not check the architectures of {}: {}",
+                image,
+                error::one_line(&err)
+            )
+        }
+        Err(_) => {}
+    }
+}
+
+/// Warn when a local `.pixi` directory would be copied over the environment installed in the image
+fn warn_unignored_pixi_dir(context_dir: &Path) {
+    if !context_dir.join(".pixi").is_dir() {
+        return;
+    }
+    let ignored = fs::read_to_string(context_dir.join(".dockerignore")).is_ok_and(|content| {
+        content.lines().any(|line| {
+            let pattern = line.trim().trim_start_matches('/').trim_end_matches('/');
+            pattern == ".pixi" || pattern == "**/.pixi" || pattern.starts_with(".pixi/")
+        })
+    });
+    if !ignored {
+        eprintln!(
+            "Warning: {} is not in .dockerignore, so it is copied over the environment installed \
+             in the image; add `.pixi` to {}",
+            context_dir.join(".pixi").display(),
+            context_dir.join(".dockerignore").display()
+        );
+    }
+}
+
+/// Where the runtime writes the ID of the built image
+enum IidFile {
+    /// Not requested, e.g. for a dry run
+    None,
+    /// Passed by the caller with `--iidfile`, left in place
+    Given(PathBuf),
+    /// Our own temporary file, removed once read
+    Temporary(PathBuf),
+}
+
+impl IidFile {
+    /// The image ID, e.g. `sha256:...`, if the runtime wrote one
+    fn read(&self) -> Option<String> {
+        let path = match self {
+            IidFile::None => return None,
+            IidFile::Given(path) | IidFile::Temporary(path) => path,
+        };
+        let image_id = fs::read_to_string(path).ok();
+        if let IidFile::Temporary(path) = self {
+            let _ = fs::remove_file(path);
+        }
+        image_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+}
+
+/// The result of `build --format json`, with the buildx metadata file's JSON embedded as is
+fn build_json(
+    environment: &str,
+    spec: &docker::BuildSpec,
+    image_id: Option<&str>,
+    metadata: Option<&str>,
+    size: Option<&size::SizeReport>,
+    up_to_date: bool,
+) -> String {
+    let json_list = |items: &[String]| {
+        let items: Vec<String> = items
+            .iter()
+            .map(|item| template::json_string(item))
+            .collect();
+        format!("[{}]", items.join(", "))
+    };
+    let metadata = metadata
+        .map(str::trim)
+        .filter(|metadata| !metadata.is_empty())
+        .unwrap_or("null");
+    format!(
+        "{{\n  \"environment\": {},\n  \"tags\": {},\n  \"image_id\": {},\n  \"cache\": {{\"from\": {}, \"to\": {}}},\n  \"metadata\": {},\n  \"size\": {},\n  \"up_to_date\": {}\n}}",
+        template::json_string(environment),
+        json_list(&spec.tags),
+        image_id.map_or("null".to_string(), template::json_string),
+        json_list(&spec.cache_from),
+        spec.cache_to
+            .as_deref()
+            .map_or("null".to_string(), template::json_string),
+        metadata,
+        size.map_or("null".to_string(), size::SizeReport::json),
+        up_to_date
+    )
+}
+
+/// The image ID of the tags when every one of them is a local image built from `input_hash`
+fn up_to_date_image(config: &Config, tags: &[String], input_hash: &str) -> Option<String> {
+    let mut image_ids = tags.iter().map(|tag| {
+        let image = docker::inspect_image(config, tag)?;
+        (input_hash::image_hash(&image)? == input_hash)
+            .then(|| image.get("Id")?.as_str().map(str::to_string))
+            .flatten()
+    });
+    let first = image_ids.next()??;
+    image_ids
+        .all(|image_id| image_id.as_ref() == Some(&first))
+        .then_some(first)
+}
+
+/// Fail on extra build arguments that compete with an export, and on a destination without
+/// a directory to go in
+fn check_export(export: &docker::OutputSpec, extra_args: &[String]) -> Result<()> {
+    let competing = extra_args.iter().find(|arg| {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        matches!(name, "--push" | "--load" | "--output" | "-o")
+    });
+    if let Some(arg) = competing {
+        anyhow::bail!(
+            "--output {} exports the build result instead of loading or pushing the image, so it \
+             can't be combined with {}; push with `type=registry` or load with `type=docker` \
+             instead",
+            export.spec,
+            arg
+        );
+    }
+    match export.dest.as_deref() {
+        Some(dest) if dest != "-" => check_output_file("--output", Path::new(dest)),
+        _ => Ok(()),
+    }
+}
+
+/// Fail early when a file the runtime should write has no directory to go in
+fn check_output_file(option: &str, path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.is_dir() {
+        anyhow::bail!(
+            "Cannot write {} {}: directory {} does not exist",
+            option,
+            path.display(),
+            parent.display()
+        );
+    }
+    Ok(())
+}
+
+/// Labels describing how an image was built, computed per build so generated Dockerfiles stay reproducible
+fn metadata_labels(config: &Config, context_dir: &Path) -> Result<Vec<(&'static str, String)>> {
+    let mut labels = vec![
+        (
+            "io.pixi-docker.version",
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("io.pixi-docker.config-sha256", config.digest()?),
+    ];
+    if let Some(revision) = git_revision(context_dir) {
+        labels.push(("org.opencontainers.image.revision", revision));
+    }
+    labels.push((
+        "org.opencontainers.image.created",
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    ));
+    Ok(labels)
+}
+
+/// The commit checked out in `dir`, if it is inside a git repository
+fn git_revision(dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!revision.is_empty()).then_some(revision)
+}
+
+struct RunOptions {
+    tag: Option<String>,
+    workdir: Option<String>,
+    /// Force (`Some(true)`) or suppress (`Some(false)`) `-i`/`-t`, detected when `None`
+    interactive: Option<bool>,
+    /// Stop and remove an existing container with the default name
+    replace: bool,
+    /// `--publish` mappings, replacing the defaults for their container ports
+    publish_ports: Vec<String>,
+    /// Publish all exposed ports with `-P` instead of the default mappings
+    publish_all: bool,
+    /// Mount the project sources over the image's copies
+    dev: bool,
+    docker_args: Vec<String>,
+    command: Option<Vec<String>>,
+}
+
+fn run_docker_container(config: &Config, environment: &str, options: RunOptions) -> Result<()> {
+    let RunOptions {
+        tag,
+        workdir,
+        interactive,
+        replace,
+        publish_ports,
+        publish_all,
+        dev,
+        docker_args,
+        command,
+    } = options;
+    let image_tag = resolve_image_tag(config, environment, tag)?;
+
+    // Docker only accepts absolute container paths
+    let workdir = workdir.as_deref().or(config.run_workdir(environment));
+    if let Some(workdir) = workdir.filter(|w| !w.starts_with('/')) {
+        anyhow::bail!(
+            "The run working directory must be an absolute container path, got '{}'",
+            workdir
+        );
+    }
+
+    let run_defaults = config.run_defaults(environment);
+    let detach = run_defaults.detach.unwrap_or(false);
+    let restart = run_defaults
+        .restart
+        .as_deref()
+        .filter(|_| detach && !docker::sets_option(&docker_args, &["--restart", "--rm"]));
+    // Exited containers are removed so they don't pile up, unless they should be restarted
+    let rm = run_defaults.rm.unwrap_or(restart.is_none())
+        && !docker::sets_option(&docker_args, &["--rm", "--restart"]);
+    if rm && restart.is_some() {
+        anyhow::bail!(
+            "The run settings for environment '{}' combine `rm` with `restart`, which docker rejects; \
+             remove one of them",
+            environment
+        );
+    }
+
+    // Configured defaults, unless the docker flags set the same option
+    let mut spec = docker::RunSpec {
+        image: image_tag,
+        rm,
+        // A predictable name lets `exec` find the container; an empty `name` leaves it anonymous
+        name: Some(container_name(config, environment))
+            .filter(|name| !name.is_empty() && !docker::sets_option(&docker_args, &["--name"])),
+        network: run_defaults
+            .network
+            .clone()
+            .filter(|_| !docker::sets_option(&docker_args, &["--network", "--net"])),
+        restart: restart.map(str::to_string),
+        limits: resource_limits(&run_defaults, environment, &docker_args)?,
+        stop_timeout: config
+            .stop_timeout(environment)?
+            .filter(|_| !docker::sets_option(&docker_args, &["--stop-timeout"])),
+        init: config.init(environment) == InitMode::Docker
+            && !docker::sets_option(&docker_args, &["--init"]),
+        detach: detach && !docker::sets_terminal_mode(&docker_args),
+        workdir: workdir
+            .filter(|_| !docker::sets_workdir(&docker_args))
+            .map(str::to_string),
+        command: command.clone().unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let env_config = config.environments.get(environment);
+    let ports = env_config
+        .filter(|e| !e.ports.is_empty())
+        .map(|e| &e.ports)
+        .unwrap_or(&config.docker.ports);
+    let (publish, terminal) = match &command {
+        // With `--`, defaults are kept unless the docker flags cover them
+        Some(_) => (
+            !docker::publishes_ports(&docker_args),
+            !spec.detach && !docker::sets_terminal_mode(&docker_args),
+        ),
+        // If no args provided, add sensible defaults (port mapping + terminal)
+        None if docker_args.is_empty() => (true, !spec.detach),
+        // Pass all args through - user is responsible for correct ordering
+        None => (false, false),
+    };
+    // `--publish-all` swaps the default mappings for `-P`; explicit ones still apply
+    let defaults: Vec<String> = match publish && !publish_all {
+        true => ports
+            .iter()
+            .map(|port| format!("{}:{}", port, port))
+            .collect(),
+        false => Vec::new(),
+    };
+    spec.ports = docker::merge_ports(&defaults, &publish_ports, &docker_args)?;
+    spec.publish_all = publish_all;
+    if dev {
+        spec.mounts = dev_mounts(config, environment)?;
+    }
+    // Files written to bind mounts belong to the host user instead of root
+    if config.match_host_user(environment).unwrap_or(dev)
+        && !docker::sets_option(&docker_args, &["--user", "-u"])
+    {
+        match docker::host_user() {
+            // Rootless podman maps the host user to the same ids in the container
+            Some(_) if docker::is_podman(config) => {
+                spec.userns = Some("keep-id".to_string())
+                    .filter(|_| !docker::sets_option(&docker_args, &["--userns"]));
+            }
+            Some((uid, gid)) => spec.user = Some(format!("{}:{}", uid, gid)),
+            None => eprintln!(
+                "Note: not matching the host user, as docker on this OS maps the ownership of \
+                 bind-mounted files by itself"
+            ),
+        }
+    }
+    if terminal {
+        (spec.interactive, spec.tty) = docker::terminal_mode(interactive);
+    }
+
+    let endpoint = docker::endpoint(config);
+    if !endpoint.is_local() && (dev || docker::binds_host_paths(&docker_args)) {
+        eprintln!(
+            "Warning: the docker daemon runs on another machine ({}), so bind-mounted host paths \
+             refer to that machine, not this one",
+            endpoint
+        );
+    }
+    docker::check_daemon(config, &endpoint)?;
+    if config.verbose || !endpoint.is_local() {
+        println!("Docker daemon: {}", endpoint);
+    }
+
+    if let Some(name) = &spec.name {
+        match docker::container_running(config, name) {
+            Some(_) if replace => {
+                println!("Replacing container {}", name);
+                docker::remove_container(config, name)?;
+            }
+            Some(true) => anyhow::bail!(
+                "Container {} is already running; pass --replace to stop and remove it first, \
+                 or run tasks in it with `pixi-docker exec`",
+                name
+            ),
+            Some(false) => anyhow::bail!(
+                "A stopped container named {} exists; pass --replace to remove it first",
+                name
+            ),
+            None => {}
+        }
+    }
+
+    println!("Running Docker container: {}", spec.image);
+    if config.engine_api() {
+        if !docker_args.is_empty() {
+            anyhow::bail!(
+                "docker run flags ({}) can't be passed with runtime = \"api\"; configure them under \
+                 [docker.run] or use the docker CLI",
+                docker_args.join(" ")
+            );
+        }
+        return Ok(api::run(&endpoint, &spec)?);
+    }
+
+    let mut docker_cmd = docker::command(config);
+    docker_cmd.arg("run");
+    docker_cmd.args(spec.args());
+    docker_cmd.args(&docker_args);
+    docker_cmd.arg(&spec.image);
+    docker_cmd.args(&spec.command);
+
+    println!("Command: {:?}", docker_cmd);
+
+    let status = docker::status(&mut docker_cmd)?;
+    if !status.success() {
+        return Err(PixiDockerError::exit("Docker run", status).into());
+    }
+
+    Ok(())
+}
+
+/// The container command of dev mode: `dev_command`, with a pixi task translated
+fn dev_command(config: &Config, environment: &str) -> Result<Option<Vec<String>>> {
+    let Some(command) = config.dev_command(environment) else {
+        return Ok(None);
+    };
+    let command = match load_pixi_toml(config) {
+        Some(pixi_toml) => pixi_toml
+            .translate_task_to_shell(command, Some(config.pixi_environment(environment)))?,
+        None => None,
+    }
+    .unwrap_or_else(|| command.to_string());
+    Ok(Some(vec![
+        "/bin/bash".to_string(),
+        "-c".to_string(),
+        command,
+    ]))
+}
+
+/// The mounts of dev mode, putting the host's sources where the image has its copies
+///
+/// Bind mounts need absolute host paths. Canonical ones also resolve symlinks, e.g. macOS's
+/// `/var` to `/private/var`, which Docker Desktop shares, and [`docker::host_path`] drops the
+/// `\\?\` prefix canonical paths get on Windows.
+fn dev_mounts(config: &Config, environment: &str) -> Result<Vec<docker::Mount>> {
+    let project_dir = config.project_dir();
+    let project_dir = if project_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        project_dir.as_path()
+    };
+    let project_dir = fs::canonicalize(project_dir)
+        .with_context(|| format!("Failed to resolve {}", project_dir.display()))?;
+    let copy_files = config.copy_files(environment);
+    if config.multi_stage(environment) && copy_files.is_empty() {
+        eprintln!(
+            "Warning: environment '{}' is built multi-stage without copy_files, so its image has \
+             no project sources; the mounted ones may lack what build_command produced in the \
+             build stage",
+            environment
+        );
+    }
+
+    match config.dev_mount(environment) {
+        DevMount::Project => {
+            let mut mounts = vec![docker::Mount::Bind {
+                source: docker::host_path(&project_dir),
+                target: "/app".to_string(),
+            }];
+            // Volumes start out with the image's files, so the environment survives the bind
+            // mount instead of the host's `.pixi` showing through
+            let mut kept = vec![template::env_prefix(config.pixi_environment(environment))];
+            for path in template::runtime_paths(config, environment) {
+                if path.starts_with("/app/") && !kept.contains(&path) {
+                    kept.push(path);
+                }
+            }
+            mounts.extend(
+                kept.into_iter()
+                    .map(|target| docker::Mount::Volume { target }),
+            );
+            Ok(mounts)
+        }
+        DevMount::CopyFiles => {
+            if copy_files.is_empty() {
+                anyhow::bail!(
+                    "dev_mount = \"copy-files\" mounts the copy_files paths, but environment '{}' \
+                     has none; set copy_files or use dev_mount = \"project\"",
+                    environment
+                );
+            }
+            copy_files
+                .iter()
+                .map(|file| {
+                    if file.contains(['*', '?', '[']) {
+                        anyhow::bail!(
+                            "copy_files entry '{}' is a pattern, which can't be mounted; use \
+                             dev_mount = \"project\"",
+                            file
+                        );
+                    }
+                    let source = fs::canonicalize(project_dir.join(file)).with_context(|| {
+                        format!(
+                            "copy_files entry '{}' doesn't exist in {}",
+                            file,
+                            project_dir.display()
+                        )
+                    })?;
+                    let target = file.replace('\\', "/");
+                    let target = target.trim_start_matches("./").trim_end_matches('/');
+                    Ok(docker::Mount::Bind {
+                        source: docker::host_path(&source),
+                        target: format!("/app/{}", target),
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// The container command running pixi task `task` with `args` appended
+fn task_command(
+    config: &Config,
+    environment: &str,
+    task: &str,
+    args: Vec<String>,
+) -> Result<Vec<String>> {
+    let pixi_toml =
+        load_pixi_toml(config).context("--task needs a pixi.toml next to the config file")?;
+    let pixi_environment = Some(config.pixi_environment(environment));
+    if pixi_toml.task(task, pixi_environment).is_none() {
+        let tasks = pixi_toml.task_names(pixi_environment);
+        let available = if tasks.is_empty() {
+            "pixi.toml defines no tasks".to_string()
+        } else {
+            format!("available tasks: {}", tasks.join(", "))
+        };
+        anyhow::bail!(
+            "Task '{}' is not defined in pixi.toml ({})",
+            task,
+            available
+        );
+    }
+
+    let entry = shlex::try_join(std::iter::once(task).chain(args.iter().map(String::as_str)))
+        .context("Cannot quote the task arguments")?;
+    let command = pixi_toml
+        .translate_task_to_shell(&entry, pixi_environment)?
+        .unwrap_or(entry);
+    Ok(vec!["/bin/bash".to_string(), "-c".to_string(), command])
+}
+
+/// The configured `--memory`, `--cpus` and `--shm-size` limits unless `docker_args` set them
+fn resource_limits(
+    run_defaults: &config::RunConfig,
+    environment: &str,
+    docker_args: &[String],
+) -> Result<Vec<(&'static str, String)>> {
+    let limits = run_defaults
+        .resource_limits()
+        .with_context(|| format!("Invalid run settings for environment '{}'", environment))?;
+    Ok(limits
+        .into_iter()
+        .filter(|(flag, _)| {
+            let short = if *flag == "--memory" { "-m" } else { flag };
+            !docker::sets_option(docker_args, &[flag, short])
+        })
+        .map(|(flag, value)| (flag, value.to_string()))
+        .collect())
+}
+
+/// Add the configured `--memory`, `--cpus` and `--shm-size` limits unless `docker_args` set them
+fn add_resource_limits(
+    docker_cmd: &mut Command,
+    run_defaults: &config::RunConfig,
+    environment: &str,
+    docker_args: &[String],
+) -> Result<()> {
+    for (flag, value) in resource_limits(run_defaults, environment, docker_args)? {
+        docker_cmd.arg(flag).arg(value);
+    }
+    Ok(())
+}
+
+struct ShellOptions {
+    tag: Option<String>,
+    ports: bool,
+    mount_src: bool,
+    /// Build a missing image without asking
+    build: bool,
+}
+
+fn open_shell(config: &Config, environment: &str, options: ShellOptions) -> Result<()> {
+    let image_tag = resolve_image_tag(config, environment, options.tag.clone())?;
+    if !docker::image_exists(config, &image_tag) {
+        let build = options.build
+            || (io::stdin().is_terminal()
+                && confirm(&format!(
+                    "Image {} not found locally. Build it now?",
+                    image_tag
+                ))?);
+        if !build {
+            anyhow::bail!(
+                "Image not found locally: {} (pass --build to build it first)",
+                image_tag
+            );
+        }
+        build_docker_image(
+            config,
+            environment,
+            BuildOptions {
+                tags: options.tag.into_iter().collect(),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let mut docker_cmd = docker::command(config);
+    docker_cmd.arg("run").arg("--rm").arg("-it");
+    add_resource_limits(
+        &mut docker_cmd,
+        &config.run_defaults(environment),
+        environment,
+        &[],
+    )?;
+    if config.init(environment) == InitMode::Docker {
+        docker_cmd.arg("--init");
+    }
+
+    if options.ports {
+        let ports = config
+            .environments
+            .get(environment)
+            .filter(|e| !e.ports.is_empty())
+            .map(|e| &e.ports)
+            .unwrap_or(&config.docker.ports);
+        for port in ports {
+            docker_cmd.arg("-p").arg(format!("{}:{}", port, port));
+        }
+    }
+
+    let endpoint = docker::endpoint(config);
+    let workdir = config.run_workdir(environment).unwrap_or("/app");
+    if options.mount_src {
+        let project_dir = config.project_dir();
+        let project_dir = if project_dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            project_dir.as_path()
+        };
+        let source = fs::canonicalize(project_dir)
+            .with_context(|| format!("Failed to resolve {}", project_dir.display()))?;
+        if !endpoint.is_local() {
+            eprintln!(
+                "Warning: the docker daemon runs on another machine ({}), so --mount-src mounts \
+                 {} from that machine, not this one",
+                endpoint,
+                source.display()
+            );
+        }
+        docker_cmd
+            .arg("-v")
+            .arg(format!("{}:{}", docker::host_path(&source), workdir));
+    }
+    docker_cmd.arg("-w").arg(workdir);
+
+    let shell = config.docker.shell.as_deref().unwrap_or("/bin/bash");
+    docker_cmd.arg("--entrypoint").arg(shell).arg(&image_tag);
+    // Bypassing the entrypoint skips activation, so have bash source the shell-hook instead
+    if Path::new(shell)
+        .file_name()
+        .is_some_and(|name| name == "bash")
+    {
+        docker_cmd.arg("--rcfile").arg("/shell-hook.sh");
+    }
+
+    docker::check_daemon(config, &endpoint)?;
+    if config.verbose || !endpoint.is_local() {
+        println!("Docker daemon: {}", endpoint);
+    }
+    println!("Starting shell in Docker container: {}", image_tag);
+    println!("Command: {:?}", docker_cmd);
+
+    let status = docker::status(&mut docker_cmd)?;
+    if !status.success() {
+        return Err(PixiDockerError::exit("Docker run", status).into());
+    }
+
+    Ok(())
+}
+
+fn exec_in_container(
+    config: &Config,
+    environment: &str,
+    task: &str,
+    args: &[String],
+    container: Option<String>,
+) -> Result<ExitCode> {
+    let container = container.unwrap_or_else(|| container_name(config, environment));
+    let containers = docker::running_containers(config)?;
+    if !containers.iter().any(|(name, _)| *name == container) {
+        let candidates = if containers.is_empty() {
+            "no containers are running".to_string()
+        } else {
+            let names: Vec<String> = containers
+                .iter()
+                .map(|(name, image)| format!("{} ({})", name, image))
+                .collect();
+            format!("running: {}", names.join(", "))
+        };
+        anyhow::bail!(
+            "Container '{}' is not running ({}); pass --container to pick one",
+            container,
+            candidates
+        );
+    }
+
+    let entry = shlex::try_join(std::iter::once(task).chain(args.iter().map(String::as_str)))
+        .context("Cannot quote the task arguments")?;
+    let pixi_environment = Some(config.pixi_environment(environment));
+    let command = match load_pixi_toml(config) {
+        Some(pixi_toml) => pixi_toml.translate_task_to_shell(&entry, pixi_environment)?,
+        None => None,
+    }
+    .unwrap_or(entry);
+
+    let mut docker_cmd = docker::command(config);
+    docker_cmd.arg("exec");
+    docker_cmd.args(docker::terminal_flags(None));
+    // `docker exec` bypasses the entrypoint, so activate the environment the same way
+    docker_cmd
+        .arg(&container)
+        .arg("/bin/bash")
+        .arg("/shell-hook.sh")
+        .arg("/bin/bash")
+        .arg("-c")
+        .arg(&command);
+
+    println!("Command: {:?}", docker_cmd);
+
+    let status = docker::status(&mut docker_cmd)?;
+    Ok(docker::exit_code(status))
+}
+
+fn test_docker_image(
+    config: &Config,
+    environment: &str,
+    tag: Option<String>,
+    no_build: bool,
+) -> Result<ExitCode> {
+    let pixi_toml = load_pixi_toml(config);
+    let pixi_environment = Some(config.pixi_environment(environment));
+    let test_task = config.test_task(environment, pixi_toml.as_ref()).context(
+        "No test command configured: set `test_command` or add a `test` task to pixi.toml",
+    )?;
+    let test_command = match &pixi_toml {
+        Some(pixi_toml) => pixi_toml.translate_task_to_shell(&test_task, pixi_environment)?,
+        None => None,
+    }
+    .unwrap_or(test_task);
+
+    let image_tag = resolve_image_tag(config, environment, tag.clone())?;
+    if no_build && docker::image_exists(config, &image_tag) {
+        println!("Using existing Docker image: {}", image_tag);
+    } else {
+        build_docker_image(
+            config,
+            environment,
+            BuildOptions {
+                tags: tag.into_iter().collect(),
+                ..Default::default()
+            },
+        )?;
+    }
+
+    let mut docker_cmd = docker::command(config);
+    docker_cmd.arg("run").arg("--rm");
+    add_resource_limits(
+        &mut docker_cmd,
+        &config.run_defaults(environment),
+        environment,
+        &[],
+    )?;
+    if config.init(environment) == InitMode::Docker {
+        docker_cmd.arg("--init");
+    }
+    docker_cmd
+        .arg(&image_tag)
+        .arg("/bin/bash")
+        .arg("-c")
+        .arg(&test_command);
+
+    println!("Running tests in Docker container: {}", image_tag);
+    println!("Command: {:?}", docker_cmd);
+
+    let status = docker::status(&mut docker_cmd)?;
+    if !status.success() {
+        eprintln!("Tests failed with exit code: {:?}", status.code());
+    }
+
+    Ok(docker::exit_code(status))
+}
+
+fn create_manifest(
+    config: &Config,
+    environment: &str,
+    tag: Option<String>,
+    archs: Vec<String>,
+) -> Result<()> {
+    let archs = if archs.is_empty() {
+        config.docker.manifest_archs.clone()
+    } else {
+        archs
+    };
+    if archs.is_empty() {
+        anyhow::bail!("No architectures given: pass --arch or set `manifest_archs` in the config");
+    }
+
+    let image_tag = resolve_image_tag(config, environment, tag)?;
+    let pattern = config
+        .docker
+        .manifest_tag_pattern
+        .as_deref()
+        .unwrap_or("{tag}-{arch}");
+    let arch_tags: Vec<(String, String)> = archs
+        .iter()
+        .map(|arch| {
+            // Variants like `arm64/v8` are not valid in tags
+            let tag = pattern
+                .replace("{tag}", &image_tag)
+                .replace("{arch}", &arch.replace('/', "-"));
+            (arch.clone(), tag)
+        })
+        .collect();
+
+    // Every per-arch image has to be pushed before it can be referenced
+    let missing: Vec<&str> = arch_tags
+        .iter()
+        .filter(|(_, arch_tag)| !docker::manifest_exists(config, arch_tag))
+        .map(|(_, arch_tag)| arch_tag.as_str())
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "Images not found in the registry: {} (build and push them first)",
+            missing.join(", ")
+        );
+    }
+
+    println!("Creating manifest: {}", image_tag);
+
+    // --amend replaces an existing local manifest list instead of failing
+    let mut create_cmd = docker::command(config);
+    create_cmd
+        .arg("manifest")
+        .arg("create")
+        .arg("--amend")
+        .arg(&image_tag);
+    for (_, arch_tag) in &arch_tags {
+        create_cmd.arg(arch_tag);
+    }
+    docker::run_checked(&mut create_cmd, "docker manifest create")?;
+
+    for (arch, arch_tag) in &arch_tags {
+        let mut annotate_cmd = docker::command(config);
+        annotate_cmd
+            .arg("manifest")
+            .arg("annotate")
+            .arg(&image_tag)
+            .arg(arch_tag)
+            .arg("--os")
+            .arg("linux");
+        match arch.split_once('/') {
+            Some((arch, variant)) => annotate_cmd
+                .arg("--arch")
+                .arg(arch)
+                .arg("--variant")
+                .arg(variant),
+            None => annotate_cmd.arg("--arch").arg(arch),
+        };
+        docker::run_checked(&mut annotate_cmd, "docker manifest annotate")?;
+    }
+
+    let mut push_cmd = docker::command(config);
+    push_cmd
+        .arg("manifest")
+        .arg("push")
+        .arg("--purge")
+        .arg(&image_tag);
+    docker::run_checked(&mut push_cmd, "docker manifest push")?;
+
+    println!("Successfully pushed manifest: {}", image_tag);
+    Ok(())
+}
+
+fn save_image(
+    config: &Config,
+    environment: &str,
+    tag: Option<String>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let image_tag = resolve_image_tag(config, environment, tag)?;
+    if !docker::image_exists(config, &image_tag) {
+        anyhow::bail!(
+            "Image not found locally: {} (run `pixi-docker build` first)",
+            image_tag
+        );
+    }
+
+    // `ghcr.io/org/app:1.0` becomes `ghcr.io-org-app-1.0.tar`
+    let output = output
+        .unwrap_or_else(|| PathBuf::from(format!("{}.tar", image_tag.replace(['/', ':'], "-"))));
+    let compress = output.to_string_lossy().ends_with(".tar.gz");
+
+    let mut docker_cmd = docker::command(config);
+    docker_cmd.arg("save");
+    if compress {
+        docker_cmd.arg(&image_tag);
+        println!("Running: {:?}", docker_cmd);
+        if let Err(err) = save_compressed(&mut docker_cmd, &output) {
+            let _ = fs::remove_file(&output);
+            return Err(err);
+        }
+    } else {
+        docker_cmd.arg("-o").arg(&output).arg(&image_tag);
+        docker::run_checked(&mut docker_cmd, "docker save")?;
+    }
+
+    let size = fs::metadata(&output)
+        .with_context(|| format!("Failed to read {}", output.display()))?
+        .len();
+    println!(
+        "Saved {} to {} ({})",
+        image_tag,
+        output.display(),
+        size::human_size(size)
+    );
+    Ok(())
+}
+
+/// Stream the output of `docker save` through gzip into `path`
+fn save_compressed(docker_cmd: &mut Command, path: &Path) -> Result<()> {
+    let file =
+        fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut encoder = GzEncoder::new(io::BufWriter::new(file), Compression::default());
+
+    let mut child = docker_cmd
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|source| PixiDockerError::spawn(docker_cmd, source))?;
+    let mut stdout = child
+        .stdout
+        .take()
+        .context("Failed to capture docker save output")?;
+    let copied = io::copy(&mut stdout, &mut encoder);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(PixiDockerError::exit("docker save", status).into());
+    }
+    copied?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+struct CleanOptions {
+    output: Option<PathBuf>,
+    images: bool,
+    /// Match every tag of the image name instead of the resolved tags
+    all_versions: bool,
+    yes: bool,
+}
+
+fn clean(config: &Config, environments: &[String], options: CleanOptions) -> Result<()> {
+    // Only the environments' generated Dockerfiles
+    let files: Vec<PathBuf> = environments
+        .iter()
+        .map(|environment| dockerfile_path(config, environment, options.output.as_deref()))
+        .filter(|path| path.is_file())
+        .collect();
+
+    let mut images = Vec::new();
+    if options.images {
+        if options.all_versions {
+            let pixi_toml = load_pixi_toml(config);
+            let mut repositories = Vec::new();
+            for environment in environments {
+                let repository = resolve_image_repository(config, environment, pixi_toml.as_ref())?;
+                if !repositories.contains(&repository) {
+                    repositories.push(repository);
+                }
+            }
+            for repository in repositories {
+                images.extend(docker::image_tags(config, &repository)?);
+            }
+        } else {
+            for environment in environments {
+                for image_tag in resolve_image_tags(config, environment, Vec::new())? {
+                    if !images.contains(&image_tag) && docker::image_exists(config, &image_tag) {
+                        images.push(image_tag);
+                    }
+                }
+            }
+        }
+    }
+
+    if files.is_empty() && images.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    println!("This will remove:");
+    for file in &files {
+        println!("  {}", file.display());
+    }
+    for image in &images {
+        println!("  image {}", image);
+    }
+    if !options.yes && !confirm("Continue?")? {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    for file in &files {
+        fs::remove_file(file).with_context(|| format!("Failed to remove {}", file.display()))?;
+        println!("Removed: {}", file.display());
+    }
+    if !images.is_empty() {
+        let mut rmi_cmd = docker::command(config);
+        rmi_cmd.arg("rmi").args(&images);
+        docker::run_checked(&mut rmi_cmd, "docker rmi")?;
+    }
+    Ok(())
+}
+
+/// Ask a yes/no question on the terminal, refusing when stdin isn't one
+fn confirm(prompt: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("Not running in a terminal: pass --yes to confirm");
+    }
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
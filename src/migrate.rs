@@ -0,0 +1,255 @@
+use crate::error::{bail, Context, Result};
+use std::path::Path;
+
+use toml_edit::{DocumentMut, InlineTable, Item, Key, Table, Value};
+
+/// The config schema this version of pixi-docker reads and writes
+pub const SCHEMA_VERSION: i64 = 2;
+
+/// A rewrite of a config document from the schema version before `to`
+struct Migration {
+    to: i64,
+    description: &'static str,
+    apply: fn(&mut DocumentMut),
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    to: 2,
+    description: "`run_workdir` moved into the `run` table as `workdir`",
+    apply: run_workdir_to_run_table,
+}];
+
+/// The `schema_version` of a parsed config file, 1 when absent
+///
+/// Fails for versions newer than [`SCHEMA_VERSION`], since this binary can't know what changed.
+pub fn schema_version(path: &Path, table: &toml::Table) -> Result<i64> {
+    let version = match table.get("schema_version") {
+        None => return Ok(1),
+        Some(toml::Value::Integer(version)) if *version >= 1 => *version,
+        Some(other) => bail!(
+            "Invalid schema_version in {}: expected a positive integer, got {}",
+            path.display(),
+            other
+        ),
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "{} uses config schema_version {}, but this pixi-docker only reads up to {}; \
+             upgrade pixi-docker to load it",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(version)
+}
+
+/// The changes between `version` and the current schema, in order
+pub fn pending(version: i64) -> Vec<&'static str> {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| migration.to > version)
+        .map(|migration| migration.description)
+        .collect()
+}
+
+/// Rewrite a config file of schema `version` in the current schema, keeping comments and
+/// formatting of the untouched parts
+pub fn migrate(content: &str, version: i64) -> Result<String> {
+    let mut document: DocumentMut = content.parse().context("Failed to parse the config")?;
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > version) {
+        (migration.apply)(&mut document);
+    }
+    match document.get_mut("schema_version") {
+        Some(Item::Value(value)) => {
+            let decor = value.decor().clone();
+            *value = Value::from(SCHEMA_VERSION);
+            *value.decor_mut() = decor;
+        }
+        _ => {
+            document.insert("schema_version", toml_edit::value(SCHEMA_VERSION));
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// The `[docker]` and `[environments.<name>]` sections of a document
+fn sections(document: &mut DocumentMut) -> Vec<&mut Item> {
+    let mut sections = Vec::new();
+    for (key, item) in document.as_table_mut().iter_mut() {
+        match key.get() {
+            "docker" => sections.push(item),
+            "environments" => {
+                if let Some(environments) = item.as_table_like_mut() {
+                    sections.extend(environments.iter_mut().map(|(_, item)| item));
+                }
+            }
+            _ => {}
+        }
+    }
+    sections
+}
+
+/// Schema 2: `run_workdir = "..."` becomes `workdir` in the section's `run` table
+fn run_workdir_to_run_table(document: &mut DocumentMut) {
+    for section in sections(document) {
+        match section {
+            Item::Table(section) => move_run_workdir(section),
+            Item::Value(Value::InlineTable(section)) => {
+                let Some(workdir) = section.remove("run_workdir") else {
+                    continue;
+                };
+                match section.get_mut("run").and_then(Value::as_inline_table_mut) {
+                    Some(run) => {
+                        run.insert("workdir", workdir);
+                    }
+                    None => {
+                        let mut run = InlineTable::new();
+                        run.insert("workdir", workdir);
+                        section.insert("run", Value::InlineTable(run));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn move_run_workdir(section: &mut Table) {
+    if !section.get("run_workdir").is_some_and(Item::is_value) {
+        return;
+    }
+    if section.get("run").is_some_and(Item::is_table_like) {
+        let (key, workdir) = section.remove_entry("run_workdir").expect("checked above");
+        match &mut section["run"] {
+            Item::Value(Value::InlineTable(run)) => {
+                run.insert(
+                    "workdir",
+                    workdir.into_value().expect("run_workdir is a value"),
+                );
+                run.fmt();
+            }
+            run => {
+                let run = run.as_table_like_mut().expect("checked above");
+                run.insert("workdir", workdir);
+                if let Some(mut workdir_key) = run.key_mut("workdir") {
+                    *workdir_key.leaf_decor_mut() = key.leaf_decor().clone();
+                }
+            }
+        }
+        return;
+    }
+    // Without a `run` table, a dotted `run.workdir` takes the old key's place and comments
+    let keys: Vec<String> = section.iter().map(|(key, _)| key.to_string()).collect();
+    for key in keys {
+        let (key, item) = section.remove_entry(&key).expect("key was just listed");
+        if key.get() == "run_workdir" {
+            let mut run = Table::new();
+            run.set_dotted(true);
+            let workdir_key = Key::new("workdir").with_leaf_decor(key.leaf_decor().clone());
+            run.insert_formatted(&workdir_key, item);
+            section.insert("run", Item::Table(run));
+        } else {
+            section.insert_formatted(&key, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_run_workdir() {
+        let old = r#"include = ["base.toml"]
+
+# Deployment settings
+[docker]
+environment = "prod"
+# Where `pixi-docker run` starts
+run_workdir = "/srv"  # absolute
+ports = [8000]
+
+[docker.run]
+rm = true
+
+[environments.dev]
+# Next to the sources
+run_workdir = "/app/dev"
+shell = "zsh"
+
+[environments.test]
+run = { rm = false }
+run_workdir = "/app/test"
+
+[environments.ci]
+pixi_environment = "test"
+"#;
+        let migrated = migrate(old, 1).unwrap();
+        assert_eq!(
+            migrated,
+            r#"include = ["base.toml"]
+schema_version = 2
+
+# Deployment settings
+[docker]
+environment = "prod"
+ports = [8000]
+
+[docker.run]
+rm = true
+# Where `pixi-docker run` starts
+workdir = "/srv"  # absolute
+
+[environments.dev]
+# Next to the sources
+run.workdir = "/app/dev"
+shell = "zsh"
+
+[environments.test]
+run = { rm = false, workdir = "/app/test" }
+
+[environments.ci]
+pixi_environment = "test"
+"#
+        );
+
+        // The result loads like the old shape and migrating again changes nothing
+        let old: toml::Table = toml::from_str(old).unwrap();
+        let new: toml::Table = toml::from_str(&migrated).unwrap();
+        assert_eq!(new["docker"]["run"]["workdir"].as_str(), Some("/srv"));
+        assert_eq!(
+            new["environments"]["dev"]["run"]["workdir"],
+            old["environments"]["dev"]["run_workdir"]
+        );
+        assert_eq!(migrate(&migrated, 2).unwrap(), migrated);
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let path = Path::new("pixi_docker.toml");
+        let version = |content: &str| schema_version(path, &toml::from_str(content).unwrap());
+        assert_eq!(version("[docker]").unwrap(), 1);
+        assert_eq!(version("schema_version = 2").unwrap(), 2);
+        assert!(version("schema_version = \"2\"").is_err());
+        assert!(version("schema_version = 0").is_err());
+
+        let err = version("schema_version = 99").unwrap_err().to_string();
+        assert!(err.contains("schema_version 99"), "{}", err);
+        assert!(err.contains("upgrade pixi-docker"), "{}", err);
+
+        assert_eq!(pending(1).len(), 1);
+        assert!(pending(SCHEMA_VERSION).is_empty());
+    }
+
+    #[test]
+    fn test_migrate_inline_sections_and_existing_version() {
+        let old = "schema_version = 1 # old\n\n[docker]\nenvironment = \"dev\"\n\n\
+                   [environments]\ndev = { run_workdir = \"/src\" }\n";
+        assert_eq!(
+            migrate(old, 1).unwrap(),
+            "schema_version = 2 # old\n\n[docker]\nenvironment = \"dev\"\n\n\
+             [environments]\ndev = { run = { workdir = \"/src\" } }\n"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use crate::error::{bail, Result};
+
+/// Whether features may reach the network, shared by everything that does
+///
+/// Offline, network features use cached or configured values, and fail right away with a
+/// targeted message when they have none, rather than waiting for a connection to time out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkPolicy {
+    #[default]
+    Online,
+    /// Set by `--offline` or `PIXI_DOCKER_OFFLINE=1`
+    Offline,
+}
+
+impl NetworkPolicy {
+    pub fn from_offline(offline: bool) -> Self {
+        if offline {
+            Self::Offline
+        } else {
+            Self::Online
+        }
+    }
+
+    /// Fail with "Cannot <action> in offline mode; <hint>" unless the network may be used
+    pub fn require(self, action: &str, hint: &str) -> Result<()> {
+        match self {
+            Self::Online => Ok(()),
+            Self::Offline => bail!("Cannot {} in offline mode; {}", action, hint),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_require() {
+        NetworkPolicy::Online.require("fetch", "retry").unwrap();
+        let err = NetworkPolicy::from_offline(true)
+            .require(
+                "resolve the digest of ubuntu:24.04",
+                "run `pixi-docker pin` while online",
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Cannot resolve the digest of ubuntu:24.04 in offline mode; \
+             run `pixi-docker pin` while online"
+        );
+    }
+}
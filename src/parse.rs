@@ -0,0 +1,100 @@
+use crate::error::ParseError;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// Parse TOML, reporting syntax errors as `file:line:column: message` with a source excerpt
+pub fn toml<T: DeserializeOwned>(path: &Path, content: &str) -> Result<T, ParseError> {
+    toml::from_str(content).map_err(|err| {
+        // Multi-line messages read better folded onto the location line
+        let message = err
+            .message()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let location = err.span().map(|span| locate(content, span.start));
+        ParseError {
+            path: path.to_path_buf(),
+            line: location.as_ref().map(|location| location.line),
+            column: location.as_ref().map(|location| location.column),
+            message,
+            excerpt: location.map(|location| location.excerpt),
+        }
+    })
+}
+
+/// A schema error found after parsing, e.g. of merged tables, which has no position
+pub fn schema_error(path: &Path, err: toml::de::Error) -> ParseError {
+    ParseError {
+        path: path.to_path_buf(),
+        line: None,
+        column: None,
+        message: err.message().trim().to_string(),
+        excerpt: None,
+    }
+}
+
+/// Format `origin:line:column: message` followed by the source line and a caret at `offset`
+pub fn describe_at(origin: &str, content: &str, offset: usize, message: &str) -> String {
+    let location = locate(content, offset);
+    format!(
+        "{}:{}:{}: {}{}",
+        origin, location.line, location.column, message, location.excerpt
+    )
+}
+
+struct Location {
+    line: usize,
+    column: usize,
+    /// The source line and a caret under the column, each on a new line
+    excerpt: String,
+}
+
+fn locate(content: &str, offset: usize) -> Location {
+    let start = floor_char_boundary(content, offset.min(content.len()));
+    let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[start..]
+        .find('\n')
+        .map_or(content.len(), |i| start + i);
+    let line = content[..start].matches('\n').count() + 1;
+    let column = content[line_start..start].chars().count() + 1;
+
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    let excerpt = format!(
+        "\n{} | {}\n{} | {}^",
+        number,
+        content[line_start..line_end].trim_end_matches('\r'),
+        gutter,
+        " ".repeat(column - 1)
+    );
+    Location {
+        line,
+        column,
+        excerpt,
+    }
+}
+
+fn floor_char_boundary(content: &str, mut index: usize) -> usize {
+    while !content.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_location() {
+        let content = "[docker]\nenvironment = \"prod\"\nports = [8080,]]\n";
+        let err = toml::<toml::Table>(Path::new("pixi_docker.toml"), content).unwrap_err();
+        assert_eq!((err.line, err.column), (Some(3), Some(16)));
+        assert_eq!(
+            err.to_string(),
+            "pixi_docker.toml:3:16: expected newline, `#`\n3 | ports = [8080,]]\n  |                ^"
+        );
+    }
+}
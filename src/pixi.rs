@@ -1,7 +1,45 @@
-use anyhow::Result;
+use crate::error::{bail, Context, PixiDockerError, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const PIXI_TOML: &str = "pixi.toml";
+const PYPROJECT_TOML: &str = "pyproject.toml";
+
+/// Find the pixi manifest by walking up from `cwd`, returning its path relative to `cwd`
+///
+/// Looks for `pixi.toml`, or a `pyproject.toml` with a `[tool.pixi]` table, and stops at the
+/// filesystem root or the root of the enclosing git repository.
+pub fn discover_manifest(cwd: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for dir in cwd.ancestors() {
+        if dir.join(PIXI_TOML).is_file() {
+            return Some(relative.join(PIXI_TOML));
+        }
+        if is_pixi_pyproject(&dir.join(PYPROJECT_TOML)) {
+            return Some(relative.join(PYPROJECT_TOML));
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+        relative.push("..");
+    }
+    None
+}
+
+fn is_pixi_pyproject(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| toml::from_str::<toml::Table>(&content).ok())
+        .is_some_and(|table| table.get("tool").and_then(|t| t.get("pixi")).is_some())
+}
+
+/// Operators that end a shell command, so no arguments can follow them
+const SHELL_OPERATORS: &[&str] = &["&&", "||", "|", ";", "&"];
+
+/// Task names tried, in priority order, when no entrypoint is configured
+pub const CONVENTIONAL_ENTRYPOINTS: &[&str] = &["start", "serve", "run", "main"];
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PixiToml {
@@ -10,7 +48,58 @@ pub struct PixiToml {
     #[serde(rename = "project")]
     pub project: Option<ProjectConfig>,
     #[serde(default)]
-    pub tasks: HashMap<String, TaskValue>,
+    pub tasks: BTreeMap<String, TaskValue>,
+    #[serde(default)]
+    pub feature: BTreeMap<String, FeatureConfig>,
+    #[serde(default)]
+    pub environments: BTreeMap<String, EnvironmentValue>,
+    /// The remaining tables, e.g. dependencies and targets
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+/// A `[feature.<name>]` table; only its tasks are parsed
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FeatureConfig {
+    #[serde(default)]
+    pub tasks: BTreeMap<String, TaskValue>,
+    /// The remaining tables, e.g. dependencies and targets
+    #[serde(flatten)]
+    pub other: toml::Table,
+}
+
+/// An `[environments]` entry: a list of features, or a table listing them
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EnvironmentValue {
+    Features(Vec<String>),
+    Table {
+        #[serde(default)]
+        features: Vec<String>,
+        /// Other settings such as `solve-group`
+        #[serde(flatten)]
+        other: toml::Table,
+    },
+}
+
+/// A channel name or URL, optionally with a priority
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Channel {
+    Name(String),
+    Prioritized {
+        channel: String,
+        priority: Option<i64>,
+    },
+}
+
+impl Channel {
+    pub fn name(&self) -> &str {
+        match self {
+            Channel::Name(name) => name,
+            Channel::Prioritized { channel, .. } => channel,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,30 +109,215 @@ pub enum TaskValue {
     Complex(TaskConfig),
 }
 
+/// A task table; pixi accepts both the kebab-case and snake_case spelling of its keys
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TaskConfig {
-    pub cmd: String,
+    /// The command, absent for an alias that only runs its dependencies
+    #[serde(default, deserialize_with = "string_or_list")]
+    pub cmd: Option<String>,
     #[serde(default)]
-    pub env: HashMap<String, String>,
-    pub depends_on: Option<Vec<String>>,
+    pub env: BTreeMap<String, String>,
+    #[serde(default, alias = "depends-on", deserialize_with = "one_or_many")]
+    pub depends_on: Vec<TaskDependency>,
+    /// Directory the command runs in, relative to the project root
+    pub cwd: Option<String>,
+    /// Arguments the command takes as `{{ name }}` placeholders
+    #[serde(default)]
+    pub args: Vec<TaskArg>,
+    pub description: Option<String>,
+    #[serde(default, alias = "clean-env")]
+    pub clean_env: bool,
+}
+
+/// A `depends-on` entry: a task name, or a table with the arguments to run the task with
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TaskDependency {
+    Name(String),
+    Task {
+        task: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl TaskDependency {
+    pub fn task(&self) -> &str {
+        match self {
+            TaskDependency::Name(task) | TaskDependency::Task { task, .. } => task,
+        }
+    }
+
+    fn args(&self) -> &[String] {
+        match self {
+            TaskDependency::Name(_) => &[],
+            TaskDependency::Task { args, .. } => args,
+        }
+    }
+}
+
+/// A task argument, optionally with a default value
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TaskArg {
+    Name(String),
+    WithDefault {
+        arg: String,
+        default: Option<String>,
+    },
+}
+
+impl TaskArg {
+    fn name(&self) -> &str {
+        match self {
+            TaskArg::Name(name) | TaskArg::WithDefault { arg: name, .. } => name,
+        }
+    }
+
+    fn default(&self) -> Option<&str> {
+        match self {
+            TaskArg::Name(_) => None,
+            TaskArg::WithDefault { default, .. } => default.as_deref(),
+        }
+    }
+}
+
+/// A `cmd` given as a string, or as a list of words that are quoted and joined
+fn string_or_list<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Cmd {
+        String(String),
+        List(Vec<String>),
+    }
+    Ok(match Option::<Cmd>::deserialize(deserializer)? {
+        None => None,
+        Some(Cmd::String(cmd)) => Some(cmd),
+        Some(Cmd::List(words)) => Some(
+            shlex::try_join(words.iter().map(String::as_str)).map_err(serde::de::Error::custom)?,
+        ),
+    })
+}
+
+/// A single `depends-on` entry or a list of them
+fn one_or_many<'de, D>(deserializer: D) -> std::result::Result<Vec<TaskDependency>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(TaskDependency),
+        Many(Vec<TaskDependency>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(dependency) => vec![dependency],
+        OneOrMany::Many(dependencies) => dependencies,
+    })
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct WorkspaceConfig {
     pub name: Option<String>,
     pub version: Option<String>,
+    /// Version requirement on pixi itself, e.g. `>=0.41`
+    #[serde(alias = "requires-pixi")]
+    pub requires_pixi: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ProjectConfig {
     pub name: Option<String>,
     pub version: Option<String>,
+    /// Version requirement on pixi itself, e.g. `>=0.41`
+    #[serde(alias = "requires-pixi")]
+    pub requires_pixi: Option<String>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
 }
 
 impl PixiToml {
-    pub fn from_file(path: &Path) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let pixi_toml: PixiToml = toml::from_str(&content)?;
+    pub fn from_file(path: &Path) -> Result<Self, PixiDockerError> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if path.file_name() == Some(PYPROJECT_TOML.as_ref()) {
+            return Self::from_pyproject(path, &content);
+        }
+        crate::parse::toml(path, &content).map_err(PixiDockerError::ManifestParse)
+    }
+
+    /// Load the manifest, warning instead of failing when it can't be read or parsed
+    pub fn load(path: &Path) -> Option<Self> {
+        match Self::from_file(path) {
+            Ok(pixi_toml) => Some(pixi_toml),
+            Err(err) => {
+                // The manifest is loaded by several steps of one command, so only warn once
+                static WARNED: AtomicBool = AtomicBool::new(false);
+                if WARNED.swap(true, Ordering::Relaxed) {
+                    return None;
+                }
+                eprintln!(
+                    "Warning: ignoring the pixi manifest, task names won't be translated: {}",
+                    crate::error::one_line(&err)
+                );
+                None
+            }
+        }
+    }
+
+    /// Read the `[tool.pixi]` table of a pyproject.toml, taking metadata from `[project]`
+    fn from_pyproject(path: &Path, content: &str) -> Result<Self, PixiDockerError> {
+        let pyproject: toml::Table =
+            crate::parse::toml(path, content).map_err(PixiDockerError::ManifestParse)?;
+        let pixi = pyproject
+            .get("tool")
+            .and_then(|tool| tool.get("pixi"))
+            .context("pyproject.toml has no [tool.pixi] table")?;
+        let mut pixi_toml: PixiToml = pixi
+            .clone()
+            .try_into()
+            .map_err(|err| PixiDockerError::ManifestParse(crate::parse::schema_error(path, err)))?;
+
+        // PEP 621 metadata fills the fields [tool.pixi.project] leaves unset, one at a time
+        let project = pyproject.get("project");
+        let field = |key: &str| {
+            project
+                .and_then(|p| p.get(key))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        let pixi_project = pixi_toml.project.get_or_insert_with(ProjectConfig::default);
+        for (key, value) in [
+            ("name", &mut pixi_project.name),
+            ("version", &mut pixi_project.version),
+            ("description", &mut pixi_project.description),
+        ] {
+            if value.is_none() {
+                *value = field(key);
+            }
+        }
+
         Ok(pixi_toml)
     }
 
@@ -54,6 +328,18 @@ impl PixiToml {
             .or_else(|| self.project.as_ref().and_then(|p| p.name.as_ref()))
     }
 
+    /// The `requires-pixi` requirement of the workspace
+    pub fn get_requires_pixi(&self) -> Option<&str> {
+        self.workspace
+            .as_ref()
+            .and_then(|w| w.requires_pixi.as_deref())
+            .or_else(|| {
+                self.project
+                    .as_ref()
+                    .and_then(|p| p.requires_pixi.as_deref())
+            })
+    }
+
     pub fn get_version(&self) -> Option<&String> {
         self.workspace
             .as_ref()
@@ -61,18 +347,714 @@ impl PixiToml {
             .or_else(|| self.project.as_ref().and_then(|p| p.version.as_ref()))
     }
 
-    pub fn get_task_command(&self, task_name: &str) -> Option<String> {
-        self.tasks.get(task_name).map(|task| match task {
-            TaskValue::Simple(cmd) => cmd.clone(),
-            TaskValue::Complex(config) => config.cmd.clone(),
+    pub fn get_description(&self) -> Option<&String> {
+        self.workspace
+            .as_ref()
+            .and_then(|w| w.description.as_ref())
+            .or_else(|| self.project.as_ref().and_then(|p| p.description.as_ref()))
+    }
+
+    /// The source repository, falling back to the homepage
+    pub fn get_source(&self) -> Option<&String> {
+        self.workspace
+            .as_ref()
+            .and_then(|w| w.repository.as_ref().or(w.homepage.as_ref()))
+            .or_else(|| {
+                self.project
+                    .as_ref()
+                    .and_then(|p| p.repository.as_ref().or(p.homepage.as_ref()))
+            })
+    }
+
+    pub fn get_license(&self) -> Option<&String> {
+        self.workspace
+            .as_ref()
+            .and_then(|w| w.license.as_ref())
+            .or_else(|| self.project.as_ref().and_then(|p| p.license.as_ref()))
+    }
+
+    pub fn get_authors(&self) -> &[String] {
+        self.workspace
+            .as_ref()
+            .map(|w| w.authors.as_slice())
+            .filter(|authors| !authors.is_empty())
+            .or_else(|| self.project.as_ref().map(|p| p.authors.as_slice()))
+            .unwrap_or_default()
+    }
+
+    pub fn get_channels(&self) -> Vec<&str> {
+        self.workspace
+            .as_ref()
+            .map(|w| w.channels.as_slice())
+            .filter(|channels| !channels.is_empty())
+            .or_else(|| self.project.as_ref().map(|p| p.channels.as_slice()))
+            .unwrap_or_default()
+            .iter()
+            .map(Channel::name)
+            .collect()
+    }
+
+    pub fn get_platforms(&self) -> &[String] {
+        self.workspace
+            .as_ref()
+            .map(|w| w.platforms.as_slice())
+            .filter(|platforms| !platforms.is_empty())
+            .or_else(|| self.project.as_ref().map(|p| p.platforms.as_slice()))
+            .unwrap_or_default()
+    }
+
+    /// All environment names, including the implicit `default` one, sorted
+    pub fn environment_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.environments.keys().map(String::as_str).collect();
+        names.push("default");
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// The features a pixi environment includes, in the order they are listed
+    pub fn environment_features(&self, environment: &str) -> &[String] {
+        match self.environments.get(environment) {
+            Some(EnvironmentValue::Features(features))
+            | Some(EnvironmentValue::Table { features, .. }) => features,
+            None => &[],
+        }
+    }
+
+    /// The platforms a pixi environment is solved for: those of the workspace, narrowed to the
+    /// ones supported by every feature of the environment that lists its own `platforms`
+    pub fn environment_platforms(&self, environment: Option<&str>) -> Vec<&str> {
+        let mut platforms: Vec<&str> = self.get_platforms().iter().map(String::as_str).collect();
+        let features = environment
+            .map(|environment| self.environment_features(environment))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|feature| self.feature.get(feature));
+        for feature in features {
+            let Some(toml::Value::Array(entries)) = feature.other.get("platforms") else {
+                continue;
+            };
+            let supported: Vec<&str> = entries.iter().filter_map(toml::Value::as_str).collect();
+            if platforms.is_empty() {
+                platforms = supported;
+            } else {
+                platforms.retain(|platform| supported.contains(platform));
+            }
+        }
+        platforms
+    }
+
+    /// The channels of a pixi environment: the workspace channels followed by those its
+    /// features add, without duplicates
+    pub fn environment_channels(&self, environment: Option<&str>) -> Vec<&str> {
+        let mut channels = self.get_channels();
+        let features = environment
+            .map(|environment| self.environment_features(environment))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|feature| self.feature.get(feature));
+        for feature in features {
+            let Some(toml::Value::Array(entries)) = feature.other.get("channels") else {
+                continue;
+            };
+            for entry in entries {
+                let channel = match entry {
+                    toml::Value::String(channel) => Some(channel.as_str()),
+                    toml::Value::Table(table) => table.get("channel").and_then(|c| c.as_str()),
+                    _ => None,
+                };
+                if let Some(channel) = channel.filter(|c| !channels.contains(c)) {
+                    channels.push(channel);
+                }
+            }
+        }
+        channels
+    }
+
+    /// Look up a task, preferring the features of `environment` over the top-level `[tasks]`
+    pub fn task(&self, name: &str, environment: Option<&str>) -> Option<&TaskValue> {
+        environment
+            .map(|environment| self.environment_features(environment))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|feature| self.feature.get(feature))
+            .find_map(|feature| feature.tasks.get(name))
+            .or_else(|| self.tasks.get(name))
+    }
+
+    /// The names of the tasks available in `environment`, sorted
+    pub fn task_names(&self, environment: Option<&str>) -> Vec<&str> {
+        let features = environment
+            .map(|environment| self.environment_features(environment))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|feature| self.feature.get(feature));
+        let mut names: Vec<&str> = self
+            .tasks
+            .keys()
+            .chain(features.flat_map(|feature| feature.tasks.keys()))
+            .map(String::as_str)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn get_task_command(&self, task_name: &str, environment: Option<&str>) -> Option<String> {
+        self.task(task_name, environment)
+            .and_then(|task| match task {
+                TaskValue::Simple(cmd) => Some(cmd.clone()),
+                TaskValue::Complex(config) => config.cmd.clone(),
+            })
+    }
+
+    /// Resolve a task into the steps that run it: its dependencies first, each once
+    ///
+    /// Returns `None` when `name` is not a task, and an error for a dependency that is missing
+    /// or part of a cycle.
+    pub fn resolve_task(
+        &self,
+        name: &str,
+        environment: Option<&str>,
+    ) -> Result<Option<Vec<TaskStep>>> {
+        self.resolve_task_with_args(name, &[], environment)
+    }
+
+    fn resolve_task_with_args(
+        &self,
+        name: &str,
+        args: &[String],
+        environment: Option<&str>,
+    ) -> Result<Option<Vec<TaskStep>>> {
+        if self.task(name, environment).is_none() {
+            return Ok(None);
+        }
+        let mut steps = Vec::new();
+        self.push_steps(name, args, environment, &mut Vec::new(), &mut steps)?;
+        if steps.is_empty() {
+            bail!("Task '{}' runs no command", name);
+        }
+        Ok(Some(steps))
+    }
+
+    fn push_steps<'a>(
+        &'a self,
+        name: &'a str,
+        args: &[String],
+        environment: Option<&str>,
+        stack: &mut Vec<&'a str>,
+        steps: &mut Vec<TaskStep>,
+    ) -> Result<()> {
+        if stack.contains(&name) {
+            bail!("Task dependency cycle: {} -> {}", stack.join(" -> "), name);
+        }
+        let Some(task) = self.task(name, environment) else {
+            bail!(
+                "Task '{}' depends on '{}', which is not a task in pixi.toml",
+                stack.last().copied().unwrap_or_default(),
+                name
+            );
+        };
+
+        stack.push(name);
+        let step = match task {
+            TaskValue::Simple(cmd) => Some(TaskStep {
+                task: name.to_string(),
+                cmd: with_args(name, cmd, &[], args)?,
+                env: BTreeMap::new(),
+                cwd: None,
+            }),
+            TaskValue::Complex(config) => {
+                for dependency in &config.depends_on {
+                    self.push_steps(
+                        dependency.task(),
+                        dependency.args(),
+                        environment,
+                        stack,
+                        steps,
+                    )?;
+                }
+                match &config.cmd {
+                    Some(cmd) => Some(TaskStep {
+                        task: name.to_string(),
+                        cmd: with_args(name, cmd, &config.args, args)?,
+                        env: config.env.clone(),
+                        cwd: config.cwd.clone(),
+                    }),
+                    None if args.is_empty() => None,
+                    None => bail!(
+                        "Cannot pass arguments to task '{}': it has no command",
+                        name
+                    ),
+                }
+            }
+        };
+        stack.pop();
+
+        // A task reached twice with the same arguments runs once
+        if let Some(step) = step.filter(|step| !steps.contains(step)) {
+            steps.push(step);
+        }
+        Ok(())
+    }
+
+    /// Resolve `task [args...]` into its steps, with the arguments passed to the task
+    ///
+    /// Returns `None` when the first word is not a task, so the string can be used as-is.
+    pub fn translate_task(
+        &self,
+        entry: &str,
+        environment: Option<&str>,
+    ) -> Result<Option<Vec<TaskStep>>> {
+        let words = shlex::split(entry)
+            .with_context(|| format!("Invalid quoting in command: {}", entry))?;
+        let Some((task_name, args)) = words.split_first() else {
+            return Ok(None);
+        };
+        self.resolve_task_with_args(task_name, args, environment)
+    }
+
+    /// Translate `task [args...]` into the task's shell command with the arguments appended
+    ///
+    /// Returns `None` when the first word is not a task, so the string can be used as-is.
+    pub fn translate_task_to_shell(
+        &self,
+        entry: &str,
+        environment: Option<&str>,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .translate_task(entry, environment)?
+            .map(|steps| steps_to_shell(&steps, false)))
+    }
+
+    /// Up to three task names closest to `name` by edit distance
+    pub fn closest_tasks(&self, name: &str, environment: Option<&str>) -> Vec<&str> {
+        let mut tasks: Vec<(usize, &str)> = self
+            .task_names(environment)
+            .into_iter()
+            .map(|task| (edit_distance(name, task), task))
+            .collect();
+        tasks.sort();
+        tasks.into_iter().take(3).map(|(_, task)| task).collect()
+    }
+
+    /// Whether any dependency, in any feature or target, is installed from a local `path`
+    ///
+    /// Such projects, e.g. a pyproject.toml installing itself editable, need their source in
+    /// the image before `pixi install` can run.
+    pub fn has_path_dependencies(&self) -> bool {
+        has_path_dependencies(&self.other)
+            || self
+                .feature
+                .values()
+                .any(|feature| has_path_dependencies(&feature.other))
+    }
+
+    /// Guess an entrypoint task: one named after the environment, then a conventional name
+    pub fn infer_entrypoint<'a>(&'a self, environment: &'a str) -> Option<&'a str> {
+        std::iter::once(environment)
+            .chain(CONVENTIONAL_ENTRYPOINTS.iter().copied())
+            .find(|name| self.task(name, Some(environment)).is_some())
+    }
+}
+
+/// A task's command with `args` filled into its declared arguments, or appended to it
+fn with_args(name: &str, cmd: &str, declared: &[TaskArg], args: &[String]) -> Result<String> {
+    if declared.is_empty() {
+        if args.is_empty() {
+            return Ok(cmd.to_string());
+        }
+        // Arguments after a trailing operator would end up in a different command
+        let trimmed = cmd.trim_end();
+        if SHELL_OPERATORS.iter().any(|op| trimmed.ends_with(op)) {
+            bail!(
+                "Cannot append arguments to task '{}': its command ends in a shell operator: {}",
+                name,
+                cmd
+            );
+        }
+        let args = shlex::try_join(args.iter().map(String::as_str))
+            .with_context(|| format!("Cannot quote arguments for task '{}'", name))?;
+        return Ok(format!("{} {}", trimmed, args));
+    }
+
+    if args.len() > declared.len() {
+        bail!(
+            "Task '{}' takes {} arguments, got {}",
+            name,
+            declared.len(),
+            args.len()
+        );
+    }
+    let mut values = BTreeMap::new();
+    for (i, arg) in declared.iter().enumerate() {
+        let value = match args.get(i).map(String::as_str).or(arg.default()) {
+            Some(value) => value,
+            None => bail!(
+                "Task '{}' needs a value for argument '{}'",
+                name,
+                arg.name()
+            ),
+        };
+        values.insert(arg.name(), value);
+    }
+    minijinja::Environment::new()
+        .render_str(cmd, values)
+        .with_context(|| format!("Cannot fill in the arguments of task '{}'", name))
+}
+
+/// One command of a resolved task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskStep {
+    pub task: String,
+    pub cmd: String,
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<String>,
+}
+
+impl TaskStep {
+    fn to_shell(&self, last: bool, exec: bool) -> String {
+        let cmd = match exec && !self.cmd.starts_with("exec ") {
+            true => format!("exec {}", self.cmd),
+            false => self.cmd.clone(),
+        };
+        let mut parts = Vec::new();
+        if !self.env.is_empty() {
+            // Double quotes keep references like `$PIXI_PROJECT_ROOT` expanding, as in pixi
+            let assignments: Vec<String> = self
+                .env
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, double_quote(value)))
+                .collect();
+            parts.push(format!("export {}", assignments.join(" ")));
+        }
+        if let Some(cwd) = &self.cwd {
+            let quoted = shlex::try_quote(cwd).map_or_else(|_| double_quote(cwd), String::from);
+            parts.push(format!("cd {}", quoted));
+        }
+        if parts.is_empty() {
+            return cmd;
+        }
+        parts.push(cmd);
+
+        let command = parts.join(" && ");
+        // A subshell keeps a dependency's variables and directory from leaking into the tasks
+        // after it
+        match last {
+            true => command,
+            false => format!("({})", command),
+        }
+    }
+}
+
+/// Join resolved task steps into one shell command, `exec`-ing the last one when asked
+pub fn steps_to_shell(steps: &[TaskStep], exec: bool) -> String {
+    let commands: Vec<String> = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let last = i + 1 == steps.len();
+            step.to_shell(last, exec && last)
         })
+        .collect();
+    commands.join(" && ")
+}
+
+fn double_quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
     }
+    quoted.push('"');
+    quoted
+}
+
+fn has_path_dependencies(table: &toml::Table) -> bool {
+    table.iter().any(|(key, value)| match value {
+        toml::Value::Table(dependencies) if key.ends_with("dependencies") => dependencies
+            .values()
+            .any(|dependency| dependency.get("path").is_some()),
+        // Features and targets nest their own dependency tables
+        toml::Value::Table(nested) => has_path_dependencies(nested),
+        _ => false,
+    })
+}
 
-    pub fn translate_task_to_shell(&self, task_name: &str) -> Option<String> {
-        self.get_task_command(task_name)
+/// The conda platform matching a docker architecture, e.g. `linux-aarch64` for `arm64`
+pub fn conda_platform(arch: &str) -> String {
+    match arch {
+        "amd64" => "linux-64".to_string(),
+        "arm64" => "linux-aarch64".to_string(),
+        "386" => "linux-32".to_string(),
+        "arm" => "linux-armv7l".to_string(),
+        arch => format!("linux-{}", arch),
     }
 }
 
+/// The first pixi release able to read each `pixi.lock` schema version
+const LOCK_SCHEMA_SUPPORT: &[(u64, &str)] = &[(4, "0.13.0"), (5, "0.20.0"), (6, "0.40.0")];
+
+/// The schema `version:` recorded at the top of a `pixi.lock`
+pub fn lock_schema_version(content: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("version:"))
+        .and_then(|version| version.trim().parse().ok())
+}
+
+/// The oldest pixi that reads a lock file schema version, if it is a known one
+pub fn min_pixi_for_lock_schema(schema: u64) -> Option<&'static str> {
+    LOCK_SCHEMA_SUPPORT
+        .iter()
+        .find(|(version, _)| *version == schema)
+        .map(|(_, pixi)| *pixi)
+}
+
+/// A version requirement in pixi's syntax, e.g. `requires-pixi = ">=0.41,<1"`
+///
+/// Constraints are joined with `,` (all must hold) and `|` (either side may hold), like in conda
+/// match specs. Each is a version with one of `>=`, `>`, `<=`, `<`, `==`, `!=`, `~=` or `=`, a
+/// bare version (exact), or a `.*` glob such as `0.42.*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRequirement {
+    spec: String,
+    alternatives: Vec<Vec<Constraint>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Constraint {
+    op: VersionOp,
+    version: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VersionOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    /// The leading components equal the version, from `0.42.*` or `=0.42`
+    StartsWith,
+    NotStartsWith,
+}
+
+impl VersionRequirement {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let alternatives = spec
+            .split('|')
+            .map(|alternative| {
+                let mut constraints = Vec::new();
+                for constraint in alternative.split(',') {
+                    constraints.extend(parse_constraint(constraint.trim()).with_context(|| {
+                        format!("Invalid version requirement '{}'", spec.trim())
+                    })?);
+                }
+                Ok(constraints)
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            spec: spec.trim().to_string(),
+            alternatives,
+        })
+    }
+
+    /// Whether a version, e.g. `0.41.2` or the image tag `0.41.2-noble`, meets the requirement
+    pub fn matches(&self, version: &str) -> Result<bool> {
+        let release = version.split('-').next().unwrap_or(version);
+        let version = parse_version(release)
+            .with_context(|| format!("Invalid pixi version '{}'", version))?;
+        Ok(self
+            .alternatives
+            .iter()
+            .any(|constraints| all_match(constraints, &version)))
+    }
+
+    /// The lowest `major.minor.patch` release allowed, or none without a lower bound
+    pub fn minimum_version(&self) -> Option<String> {
+        self.alternatives
+            .iter()
+            .filter_map(|constraints| {
+                let lowest = constraints
+                    .iter()
+                    .filter_map(|c| match c.op {
+                        VersionOp::Ge | VersionOp::Eq | VersionOp::StartsWith => {
+                            Some(padded(&c.version))
+                        }
+                        VersionOp::Gt => {
+                            let mut version = padded(&c.version);
+                            *version.last_mut()? += 1;
+                            Some(version)
+                        }
+                        _ => None,
+                    })
+                    .max_by(|a, b| compare_versions(a, b))?;
+                all_match(constraints, &lowest).then_some(lowest)
+            })
+            .min_by(|a, b| compare_versions(a, b))
+            .map(|version| {
+                version
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            })
+    }
+}
+
+impl std::fmt::Display for VersionRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+fn parse_constraint(constraint: &str) -> Result<Vec<Constraint>> {
+    if constraint == "*" {
+        return Ok(Vec::new());
+    }
+    let operators = [">=", "<=", "==", "!=", "~=", ">", "<", "="];
+    let (op, rest) = operators
+        .iter()
+        .find_map(|op| constraint.strip_prefix(op).map(|rest| (*op, rest.trim())))
+        .unwrap_or(("", constraint));
+    let (rest, glob) = match rest.strip_suffix(".*") {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+    let version = parse_version(rest)?;
+    let with_op = |op| Constraint {
+        op,
+        version: version.clone(),
+    };
+    Ok(match op {
+        ">=" => vec![with_op(VersionOp::Ge)],
+        "<=" => vec![with_op(VersionOp::Le)],
+        ">" => vec![with_op(VersionOp::Gt)],
+        "<" => vec![with_op(VersionOp::Lt)],
+        "!=" if glob => vec![with_op(VersionOp::NotStartsWith)],
+        "!=" => vec![with_op(VersionOp::Ne)],
+        "=" => vec![with_op(VersionOp::StartsWith)],
+        "~=" if version.len() < 2 => {
+            bail!("'{}' needs at least two version components", constraint)
+        }
+        "~=" => vec![
+            with_op(VersionOp::Ge),
+            Constraint {
+                op: VersionOp::StartsWith,
+                version: version[..version.len() - 1].to_vec(),
+            },
+        ],
+        _ if glob => vec![with_op(VersionOp::StartsWith)],
+        _ => vec![with_op(VersionOp::Eq)],
+    })
+}
+
+/// The numeric components of a version like `0.41.2` or `v0.41`
+fn parse_version(version: &str) -> Result<Vec<u64>> {
+    let version = version.trim();
+    version
+        .strip_prefix('v')
+        .unwrap_or(version)
+        .split('.')
+        .map(|component| {
+            component
+                .parse()
+                .with_context(|| format!("'{}' is not a version", version))
+        })
+        .collect()
+}
+
+fn all_match(constraints: &[Constraint], version: &[u64]) -> bool {
+    constraints.iter().all(|constraint| {
+        let ordering = compare_versions(version, &constraint.version);
+        let starts_with = version.len() >= constraint.version.len()
+            && version[..constraint.version.len()] == constraint.version[..];
+        match constraint.op {
+            VersionOp::Lt => ordering.is_lt(),
+            VersionOp::Le => ordering.is_le(),
+            VersionOp::Gt => ordering.is_gt(),
+            VersionOp::Ge => ordering.is_ge(),
+            VersionOp::Eq => ordering.is_eq(),
+            VersionOp::Ne => ordering.is_ne(),
+            VersionOp::StartsWith => starts_with,
+            VersionOp::NotStartsWith => !starts_with,
+        }
+    })
+}
+
+/// Compare versions component by component, missing components counting as zero
+fn compare_versions(a: &[u64], b: &[u64]) -> std::cmp::Ordering {
+    let len = a.len().max(b.len());
+    let component = |version: &[u64], i: usize| version.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| component(a, i).cmp(&component(b, i)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(std::cmp::Ordering::Equal)
+}
+
+/// A version padded with zeros to `major.minor.patch`
+fn padded(version: &[u64]) -> Vec<u64> {
+    let mut version = version.to_vec();
+    if version.len() < 3 {
+        version.resize(3, 0);
+    }
+    version
+}
+
+/// Hosts serving public channels that need no credentials
+const PUBLIC_CHANNEL_HOSTS: &[&str] = &[
+    "conda.anaconda.org",
+    "anaconda.org",
+    "repo.anaconda.com",
+    "prefix.dev",
+    "repo.prefix.dev",
+    "fast.prefix.dev",
+];
+
+/// Whether downloading from a channel likely needs credentials inside the build
+///
+/// Plain names resolve to anaconda.org, and OCI mirrors and local paths need no login.
+/// URLs on other hosts (or S3 buckets) do, unless they already carry a token or user.
+pub fn channel_needs_auth(channel: &str) -> bool {
+    let Some((scheme, rest)) = channel.split_once("://") else {
+        return false;
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    if authority.contains('@') || rest.contains("/t/") {
+        return false;
+    }
+    match scheme {
+        "s3" => true,
+        "http" | "https" => {
+            let host = authority.split(':').next().unwrap_or_default();
+            !PUBLIC_CHANNEL_HOSTS.contains(&host)
+        }
+        _ => false,
+    }
+}
+
+/// Levenshtein distance between two strings
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +1069,33 @@ mod tests {
         assert_eq!(pixi.get_version(), Some(&"2.3.4".to_string()));
     }
 
+    #[test]
+    fn test_has_path_dependencies() {
+        let pixi: PixiToml = toml::from_str(
+            r#"
+            [dependencies]
+            python = ">=3.12"
+
+            [feature.test.pypi-dependencies]
+            requests = { version = ">=2" }
+        "#,
+        )
+        .unwrap();
+        assert!(!pixi.has_path_dependencies());
+
+        let pixi: PixiToml = toml::from_str(
+            r#"
+            [dependencies]
+            python = ">=3.12"
+
+            [target.linux-64.pypi-dependencies]
+            my-app = { path = ".", editable = true }
+        "#,
+        )
+        .unwrap();
+        assert!(pixi.has_path_dependencies());
+    }
+
     #[test]
     fn test_project_config() {
         let toml_str = r#"
@@ -118,6 +1127,294 @@ mod tests {
         assert_eq!(pixi.get_version(), Some(&"2.0.0".to_string()));
     }
 
+    #[test]
+    fn test_environment_channels() {
+        let toml_str = r#"
+            [workspace]
+            channels = ["conda-forge", { channel = "bioconda", priority = 1 }]
+
+            [feature.gpu]
+            channels = ["nvidia", "conda-forge"]
+
+            [feature.internal]
+            channels = [{ channel = "https://conda.example.com/internal", priority = 2 }]
+
+            [environments]
+            prod = ["gpu", "internal"]
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        assert_eq!(pixi.environment_channels(None), ["conda-forge", "bioconda"]);
+        assert_eq!(
+            pixi.environment_channels(Some("prod")),
+            [
+                "conda-forge",
+                "bioconda",
+                "nvidia",
+                "https://conda.example.com/internal"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment_platforms() {
+        let toml_str = r#"
+            [workspace]
+            platforms = ["linux-64", "linux-aarch64", "osx-arm64"]
+
+            [feature.mac]
+            platforms = ["osx-arm64"]
+
+            [feature.cuda]
+            platforms = ["linux-64"]
+
+            [feature.arm]
+            platforms = ["linux-aarch64", "osx-arm64"]
+
+            [environments]
+            mac = ["mac"]
+            gpu = ["cuda"]
+            arm = ["arm"]
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        assert_eq!(
+            pixi.environment_platforms(None),
+            ["linux-64", "linux-aarch64", "osx-arm64"]
+        );
+        assert_eq!(pixi.environment_platforms(Some("mac")), ["osx-arm64"]);
+        assert_eq!(pixi.environment_platforms(Some("gpu")), ["linux-64"]);
+        assert_eq!(
+            pixi.environment_platforms(Some("arm")),
+            ["linux-aarch64", "osx-arm64"]
+        );
+
+        assert_eq!(conda_platform("amd64"), "linux-64");
+        assert_eq!(conda_platform("arm64"), "linux-aarch64");
+        assert_eq!(conda_platform("ppc64le"), "linux-ppc64le");
+    }
+
+    #[test]
+    fn test_version_requirement() {
+        let cases: &[(&str, &[&str], &[&str])] = &[
+            (
+                ">=0.41",
+                &["0.41.0", "0.41.3", "1.0.0", "v0.42"],
+                &["0.40.9", "0.4.1"],
+            ),
+            (">0.41.0", &["0.41.1", "0.42.0"], &["0.41.0", "0.41"]),
+            (">=0.40,<0.45", &["0.40.0", "0.44.9"], &["0.45.0", "0.39.0"]),
+            ("<=0.45.2", &["0.45.2", "0.1.0"], &["0.45.3"]),
+            (
+                "==0.42.1",
+                &["0.42.1", "0.42.1-noble"],
+                &["0.42.0", "0.42.10"],
+            ),
+            ("0.42.1", &["0.42.1"], &["0.42.2"]),
+            ("0.42.*", &["0.42.0", "0.42.7"], &["0.43.0", "0.4.2"]),
+            ("=0.42", &["0.42.0", "0.42.7"], &["0.43.0"]),
+            ("!=0.42.0", &["0.42.1"], &["0.42.0"]),
+            ("!=0.42.*", &["0.41.0", "0.43.0"], &["0.42.5"]),
+            ("~=0.41.2", &["0.41.2", "0.41.9"], &["0.41.1", "0.42.0"]),
+            (">=0.45 | 0.41.*", &["0.41.3", "0.46.0"], &["0.42.0"]),
+            (">= 0.41, < 1", &["0.50.0"], &["1.0.0"]),
+            ("*", &["0.1.0"], &[]),
+        ];
+        for (spec, matching, failing) in cases {
+            let requirement = VersionRequirement::parse(spec).unwrap();
+            for version in *matching {
+                assert!(requirement.matches(version).unwrap(), "{spec} {version}");
+            }
+            for version in *failing {
+                assert!(!requirement.matches(version).unwrap(), "{spec} {version}");
+            }
+        }
+
+        let minimum = |spec| VersionRequirement::parse(spec).unwrap().minimum_version();
+        assert_eq!(minimum(">=0.41").as_deref(), Some("0.41.0"));
+        assert_eq!(minimum(">0.41.0,<1").as_deref(), Some("0.41.1"));
+        assert_eq!(minimum("~=0.41.2").as_deref(), Some("0.41.2"));
+        assert_eq!(minimum("0.42.*").as_deref(), Some("0.42.0"));
+        assert_eq!(minimum(">=0.45 | >=0.41,<0.42").as_deref(), Some("0.41.0"));
+        assert_eq!(minimum(">=0.41,!=0.41.0").as_deref(), None);
+        assert_eq!(minimum("<1").as_deref(), None);
+
+        assert!(VersionRequirement::parse(">=abc").is_err());
+        assert!(VersionRequirement::parse("~=1").is_err());
+        assert!(VersionRequirement::parse(">=0.41")
+            .unwrap()
+            .matches("latest")
+            .is_err());
+    }
+
+    #[test]
+    fn test_lock_schema_version() {
+        let lock = "version: 6\nenvironments:\n  default:\n    version: 1\n";
+        assert_eq!(lock_schema_version(lock), Some(6));
+        assert_eq!(lock_schema_version("environments: {}\n"), None);
+        assert_eq!(min_pixi_for_lock_schema(6), Some("0.40.0"));
+        assert_eq!(min_pixi_for_lock_schema(5), Some("0.20.0"));
+        assert_eq!(min_pixi_for_lock_schema(99), None);
+    }
+
+    #[test]
+    fn test_channel_needs_auth() {
+        for public in [
+            "conda-forge",
+            "bioconda",
+            "https://conda.anaconda.org/conda-forge",
+            "https://prefix.dev/conda-forge",
+            "https://repo.prefix.dev/my-channel/",
+            "oci://ghcr.io/channel-mirrors/conda-forge",
+            "file:///opt/channel",
+            "./local-channel",
+            "https://user@conda.example.com/internal",
+            "https://conda.example.com/t/abc123/internal",
+        ] {
+            assert!(!channel_needs_auth(public), "{public}");
+        }
+        for private in [
+            "https://conda.example.com/internal",
+            "http://artifactory.local:8081/conda/main",
+            "s3://my-bucket/channel",
+        ] {
+            assert!(channel_needs_auth(private), "{private}");
+        }
+    }
+
+    #[test]
+    fn test_metadata_fields() {
+        let toml_str = r#"
+            [workspace]
+            name = "my-app"
+            description = "A demo app"
+            homepage = "https://example.com"
+            repository = "https://github.com/acme/my-app"
+            license = "BSD-3-Clause"
+            authors = ["Jane Doe <jane@example.com>"]
+        "#;
+
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        assert_eq!(pixi.get_description(), Some(&"A demo app".to_string()));
+        assert_eq!(
+            pixi.get_source(),
+            Some(&"https://github.com/acme/my-app".to_string())
+        );
+        assert_eq!(pixi.get_license(), Some(&"BSD-3-Clause".to_string()));
+        assert_eq!(pixi.get_authors(), ["Jane Doe <jane@example.com>"]);
+    }
+
+    #[test]
+    fn test_source_falls_back_to_homepage() {
+        let toml_str = r#"
+            [project]
+            name = "my-project"
+            homepage = "https://example.com"
+        "#;
+
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        assert_eq!(pixi.get_source(), Some(&"https://example.com".to_string()));
+        assert!(pixi.get_authors().is_empty());
+    }
+
+    #[test]
+    fn test_infer_entrypoint() {
+        let toml_str = r#"
+            [tasks]
+            main = "python main.py"
+            serve = "python -m http.server"
+            dev = "python -m http.server --reload"
+        "#;
+
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        assert_eq!(pixi.infer_entrypoint("prod"), Some("serve"));
+        // A task named after the environment wins over the conventional names
+        assert_eq!(pixi.infer_entrypoint("dev"), Some("dev"));
+
+        let pixi: PixiToml = toml::from_str("[tasks]\nlint = \"ruff check\"").unwrap();
+        assert_eq!(pixi.infer_entrypoint("prod"), None);
+    }
+
+    #[test]
+    fn test_closest_tasks() {
+        let toml_str = r#"
+            [tasks]
+            serve = "python -m http.server"
+            start = "python app.py"
+            lint = "ruff check"
+            test = "pytest"
+        "#;
+
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            pixi.closest_tasks("sevre", None),
+            ["serve", "start", "test"]
+        );
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_discover_manifest() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let nested = root.join("src").join("app");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir(root.join(".git")).unwrap();
+
+        assert_eq!(discover_manifest(&nested), None);
+
+        std::fs::write(root.join("pyproject.toml"), "[project]\nname = \"plain\"\n").unwrap();
+        assert_eq!(discover_manifest(&nested), None);
+
+        std::fs::write(
+            root.join("pyproject.toml"),
+            "[project]\nname = \"py-app\"\nversion = \"0.3.0\"\n\n[tool.pixi.tasks]\nstart = \"python -m app\"\n",
+        )
+        .unwrap();
+        let manifest = discover_manifest(&nested).unwrap();
+        assert_eq!(manifest, PathBuf::from("../../pyproject.toml"));
+
+        let pixi = PixiToml::from_file(&nested.join(&manifest)).unwrap();
+        assert_eq!(pixi.get_name(), Some(&"py-app".to_string()));
+        assert_eq!(pixi.get_version(), Some(&"0.3.0".to_string()));
+        assert!(pixi.tasks.contains_key("start"));
+
+        // pixi.toml wins in the same directory and is found from the project root itself
+        std::fs::write(root.join("pixi.toml"), "[workspace]\nname = \"app\"\n").unwrap();
+        assert_eq!(discover_manifest(root), Some(PathBuf::from("pixi.toml")));
+    }
+
+    #[test]
+    fn test_pyproject_tool_pixi_project() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("pyproject.toml");
+        std::fs::write(
+            &path,
+            "[project]\nname = \"py-app\"\nversion = \"0.3.0\"\ndescription = \"From PEP 621\"\n\n\
+             [tool.pixi.project]\nchannels = [\"conda-forge\"]\nplatforms = [\"linux-64\"]\n\
+             description = \"From pixi\"\n",
+        )
+        .unwrap();
+
+        // [tool.pixi.project] keeps what it sets, [project] fills the rest
+        let pixi = PixiToml::from_file(&path).unwrap();
+        assert_eq!(pixi.get_name(), Some(&"py-app".to_string()));
+        assert_eq!(pixi.get_version(), Some(&"0.3.0".to_string()));
+        assert_eq!(pixi.get_description(), Some(&"From pixi".to_string()));
+        assert_eq!(pixi.project.unwrap().platforms, ["linux-64"]);
+    }
+
+    #[test]
+    fn test_discover_manifest_stops_at_git_root() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::write(temp_dir.path().join("pixi.toml"), "").unwrap();
+
+        assert_eq!(discover_manifest(&repo), None);
+    }
+
     #[test]
     fn test_default_values() {
         let toml_str = r#"
@@ -167,25 +1464,318 @@ mod tests {
 
         // Test task command extraction
         assert_eq!(
-            pixi.get_task_command("simple-task"),
+            pixi.get_task_command("simple-task", None),
             Some("echo hello".to_string())
         );
         assert_eq!(
-            pixi.get_task_command("server"),
+            pixi.get_task_command("server", None),
             Some("python src/main.py".to_string())
         );
         assert_eq!(
-            pixi.get_task_command("build"),
+            pixi.get_task_command("build", None),
             Some("cargo build".to_string())
         );
-        assert_eq!(pixi.get_task_command("nonexistent"), None);
+        assert_eq!(pixi.get_task_command("nonexistent", None), None);
 
         // Test task translation
         assert_eq!(
-            pixi.translate_task_to_shell("server"),
+            pixi.translate_task_to_shell("server", None).unwrap(),
             Some("python src/main.py".to_string())
         );
-        assert_eq!(pixi.translate_task_to_shell("nonexistent"), None);
+        assert_eq!(
+            pixi.translate_task_to_shell("nonexistent", None).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_task_translation_with_arguments() {
+        let toml_str = r#"
+            [tasks]
+            serve = "python -m http.server"
+            chained = "make && ./run.sh &&"
+        "#;
+
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            pixi.translate_task_to_shell("serve --port 8080 --workers 4", None)
+                .unwrap(),
+            Some("python -m http.server --port 8080 --workers 4".to_string())
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell(r#"serve --name "my app""#, None)
+                .unwrap(),
+            Some("python -m http.server --name 'my app'".to_string())
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("python app.py", None).unwrap(),
+            None
+        );
+        assert!(pixi
+            .translate_task_to_shell("chained --fast", None)
+            .is_err());
+        assert!(pixi
+            .translate_task_to_shell("serve \"unclosed", None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_resolve_task_dependencies() {
+        let toml_str = r#"
+            [tasks]
+            fetch = "curl -O data.csv"
+            prepare = { cmd = "python prepare.py", depends-on = ["fetch"] }
+            lint = { cmd = "ruff check", env = { RUFF_CACHE_DIR = "$HOME/.ruff \"cache\"" } }
+            serve = { cmd = "python serve.py", depends-on = ["prepare", "lint", "fetch"], env = { PORT = "8000" } }
+            loop-a = { cmd = "a", depends-on = ["loop-b"] }
+            loop-b = { cmd = "b", depends-on = ["loop-a"] }
+            broken = { cmd = "c", depends-on = ["missing"] }
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        // Dependencies run first and once, even when reached twice
+        let steps = pixi.resolve_task("serve", None).unwrap().unwrap();
+        let order: Vec<&str> = steps.iter().map(|s| s.task.as_str()).collect();
+        assert_eq!(order, ["fetch", "prepare", "lint", "serve"]);
+        assert_eq!(
+            steps_to_shell(&steps, true),
+            "curl -O data.csv && python prepare.py && \
+             (export RUFF_CACHE_DIR=\"$HOME/.ruff \\\"cache\\\"\" && ruff check) && \
+             export PORT=\"8000\" && exec python serve.py"
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("serve --reload", None)
+                .unwrap()
+                .unwrap(),
+            "curl -O data.csv && python prepare.py && \
+             (export RUFF_CACHE_DIR=\"$HOME/.ruff \\\"cache\\\"\" && ruff check) && \
+             export PORT=\"8000\" && python serve.py --reload"
+        );
+
+        assert_eq!(pixi.resolve_task("nonexistent", None).unwrap(), None);
+        let err = pixi.resolve_task("loop-a", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Task dependency cycle: loop-a -> loop-b -> loop-a"
+        );
+        let err = pixi.resolve_task("broken", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Task 'broken' depends on 'missing', which is not a task in pixi.toml"
+        );
+    }
+
+    #[test]
+    fn test_task_cwd() {
+        let toml_str = r#"
+            [tasks]
+            codegen = { cmd = "make gen", cwd = "proto files" }
+            build = { cmd = "npm run build", cwd = "frontend", depends-on = ["codegen"] }
+            migrate = { cmd = "alembic upgrade head", depends-on = ["build"] }
+            serve = { cmd = "python -m app", cwd = "backend", env = { PORT = "8000" }, depends-on = ["migrate"] }
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        // Dependencies change directory in a subshell, so the next task starts from the root
+        let steps = pixi.resolve_task("serve", None).unwrap().unwrap();
+        assert_eq!(
+            steps_to_shell(&steps, true),
+            "(cd 'proto files' && make gen) && (cd frontend && npm run build) && \
+             alembic upgrade head && \
+             export PORT=\"8000\" && cd backend && exec python -m app"
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("build --watch", None)
+                .unwrap()
+                .unwrap(),
+            "(cd 'proto files' && make gen) && cd frontend && npm run build --watch"
+        );
+    }
+
+    #[test]
+    fn test_task_table_spellings() {
+        // Task tables as written by current and older pixi versions
+        let toml_str = r#"
+            [workspace]
+            name = "real-world"
+            channels = ["conda-forge"]
+            platforms = ["linux-64"]
+
+            [tasks]
+            configure = { cmd = ["cmake", "-S", ".", "-B", "build dir"], inputs = ["CMakeLists.txt"] }
+            compile = { cmd = "cmake --build 'build dir'", depends_on = ["configure"], outputs = ["build/"] }
+            greet = { cmd = "echo Hello {{ name }}{{ punctuation }}", args = ["name", { arg = "punctuation", default = "!" }] }
+            setup = { depends-on = ["compile", { task = "greet", args = ["setup"] }], description = "Build everything" }
+            check = { cmd = "ctest", depends-on = "setup", clean-env = true }
+            deploy = { depends-on = [{ task = "greet", args = ["deploy", "?"] }, "check"] }
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+        let TaskValue::Complex(setup) = &pixi.tasks["setup"] else {
+            panic!("setup is a task table");
+        };
+        assert_eq!(setup.cmd, None);
+        assert_eq!(setup.description.as_deref(), Some("Build everything"));
+        let TaskValue::Complex(check) = &pixi.tasks["check"] else {
+            panic!("check is a task table");
+        };
+        assert!(check.clean_env);
+
+        let check = "cmake -S . -B 'build dir' && cmake --build 'build dir' && \
+                     echo Hello setup! && ctest";
+        assert_eq!(
+            pixi.translate_task_to_shell("check", None)
+                .unwrap()
+                .unwrap(),
+            check
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("deploy", None)
+                .unwrap()
+                .unwrap(),
+            "echo Hello deploy? && cmake -S . -B 'build dir' && cmake --build 'build dir' && \
+             echo Hello setup! && ctest"
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("greet world", None)
+                .unwrap()
+                .unwrap(),
+            "echo Hello world!"
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("greet", None)
+                .unwrap_err()
+                .to_string(),
+            "Task 'greet' needs a value for argument 'name'"
+        );
+        assert!(pixi.translate_task_to_shell("greet a b c", None).is_err());
+        assert!(pixi.translate_task_to_shell("setup --fast", None).is_err());
+
+        // Writing the manifest back keeps every task resolving the same way
+        let written = toml::to_string(&pixi).unwrap();
+        let reparsed: PixiToml = toml::from_str(&written).unwrap();
+        for task in pixi.tasks.keys() {
+            assert_eq!(
+                reparsed.translate_task_to_shell(task, None).ok(),
+                pixi.translate_task_to_shell(task, None).ok(),
+                "task {} after a round trip",
+                task
+            );
+        }
+        assert_eq!(
+            reparsed
+                .translate_task_to_shell("check", None)
+                .unwrap()
+                .unwrap(),
+            check
+        );
+    }
+
+    #[test]
+    fn test_feature_tasks() {
+        let toml_str = r#"
+            [workspace]
+            name = "features"
+
+            [tasks]
+            migrate = "alembic upgrade head"
+
+            [feature.server.tasks]
+            serve = { cmd = "gunicorn app:wsgi", depends-on = ["migrate"] }
+
+            [feature.server.dependencies]
+            gunicorn = "*"
+
+            [feature.docs.tasks]
+            docs = "mkdocs build"
+            docs-serve = { cmd = "mkdocs serve", depends-on = ["docs"] }
+
+            [environments]
+            prod = ["server"]
+            docs = { features = ["docs"], solve-group = "default" }
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        assert_eq!(pixi.environment_features("prod"), ["server"]);
+        assert_eq!(pixi.environment_features("docs"), ["docs"]);
+        assert!(pixi.environment_features("default").is_empty());
+        assert_eq!(pixi.task_names(Some("prod")), ["migrate", "serve"]);
+        assert_eq!(pixi.task_names(None), ["migrate"]);
+
+        // Feature tasks are found in environments that include the feature
+        assert_eq!(
+            pixi.translate_task_to_shell("serve", Some("prod"))
+                .unwrap()
+                .unwrap(),
+            "alembic upgrade head && gunicorn app:wsgi"
+        );
+        assert_eq!(
+            pixi.get_task_command("docs-serve", Some("docs")).as_deref(),
+            Some("mkdocs serve")
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("docs-serve", Some("docs"))
+                .unwrap()
+                .unwrap(),
+            "mkdocs build && mkdocs serve"
+        );
+        assert_eq!(
+            pixi.translate_task_to_shell("serve", Some("docs")).unwrap(),
+            None
+        );
+        assert_eq!(pixi.translate_task_to_shell("serve", None).unwrap(), None);
+
+        // Top-level tasks are available everywhere
+        assert_eq!(
+            pixi.get_task_command("migrate", Some("docs")).as_deref(),
+            Some("alembic upgrade head")
+        );
+        assert_eq!(pixi.closest_tasks("serv", Some("prod"))[0], "serve");
+        assert_eq!(pixi.infer_entrypoint("prod"), Some("serve"));
+        assert_eq!(pixi.infer_entrypoint("docs"), Some("docs"));
+        assert_eq!(pixi.infer_entrypoint("default"), None);
+    }
+
+    #[test]
+    fn test_feature_task_precedence() {
+        let toml_str = r#"
+            [tasks]
+            serve = "python -m http.server"
+            lint = "ruff check"
+
+            [feature.prod.tasks]
+            serve = { cmd = "gunicorn app:wsgi", depends-on = ["lint"] }
+            lint = "ruff check --no-fix"
+
+            [feature.extra.tasks]
+            serve = "uvicorn app:asgi"
+
+            [environments]
+            prod = ["prod", "extra"]
+            extra = ["extra", "prod"]
+        "#;
+        let pixi: PixiToml = toml::from_str(toml_str).unwrap();
+
+        // Feature definitions win over [tasks], also for dependencies
+        assert_eq!(
+            pixi.translate_task_to_shell("serve", Some("prod"))
+                .unwrap()
+                .unwrap(),
+            "ruff check --no-fix && gunicorn app:wsgi"
+        );
+        // Between features, the first one listed for the environment wins
+        assert_eq!(
+            pixi.get_task_command("serve", Some("extra")).as_deref(),
+            Some("uvicorn app:asgi")
+        );
+        // Environments without the feature, and no environment, use [tasks]
+        for environment in [Some("default"), Some("unknown"), None] {
+            assert_eq!(
+                pixi.translate_task_to_shell("serve", environment)
+                    .unwrap()
+                    .unwrap(),
+                "python -m http.server"
+            );
+        }
     }
 
     #[test]
@@ -198,6 +1788,9 @@ mod tests {
         let pixi: PixiToml = toml::from_str(toml_str).unwrap();
 
         // Should return None for non-existent tasks
-        assert_eq!(pixi.translate_task_to_shell("some-command"), None);
+        assert_eq!(
+            pixi.translate_task_to_shell("some-command", None).unwrap(),
+            None
+        );
     }
 }
@@ -0,0 +1,185 @@
+use crate::error::{bail, Result};
+
+/// Check a registry host, e.g. `ghcr.io` or `localhost:5000`
+pub fn check_registry(what: &str, value: &str) -> Result<()> {
+    check(what, value, registry_problem(value))
+}
+
+/// Check a `/`-separated repository path, e.g. `acme/my-app`
+pub fn check_repository(what: &str, value: &str) -> Result<()> {
+    check(what, value, repository_problem(value))
+}
+
+/// Check a tag: up to 128 letters, digits, `_`, `.` and `-`, not starting with `.` or `-`
+pub fn check_tag(what: &str, value: &str) -> Result<()> {
+    check(what, value, tag_problem(value))
+}
+
+/// Check a full image reference such as `ghcr.io/acme/app:1.0`
+pub fn check_reference(reference: &str) -> Result<()> {
+    let (registry, repository, tag) = split_reference(reference);
+    let problem = registry
+        .and_then(|registry| {
+            registry_problem(registry).map(|p| format!("registry '{}' {}", registry, p))
+        })
+        .or_else(|| repository_problem(repository))
+        .or_else(|| tag.and_then(|tag| tag_problem(tag).map(|p| format!("tag '{}' {}", tag, p))));
+    check("image reference", reference, problem)
+}
+
+/// Split an image reference into its registry, repository path and tag, e.g.
+/// `ghcr.io/acme/app:1.0` into `ghcr.io`, `acme/app` and `1.0`
+pub fn split_reference(reference: &str) -> (Option<&str>, &str, Option<&str>) {
+    let (path, tag) = match reference.rsplit_once(':') {
+        Some((path, tag)) if !tag.contains('/') => (path, Some(tag)),
+        _ => (reference, None),
+    };
+    // Like docker, a first component with a `.` or `:`, or `localhost`, is a registry
+    match path.split_once('/') {
+        Some((first, rest)) if first.contains(['.', ':']) || first == "localhost" => {
+            (Some(first), rest, tag)
+        }
+        _ => (None, path, tag),
+    }
+}
+
+fn check(what: &str, value: &str, problem: Option<String>) -> Result<()> {
+    match problem {
+        Some(problem) => bail!("Invalid {} '{}': {}", what, value, problem),
+        None => Ok(()),
+    }
+}
+
+fn registry_problem(value: &str) -> Option<String> {
+    let (host, port) = match value.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (value, None),
+    };
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return Some(format!("has a port '{}' that is not a number", port));
+        }
+    }
+    host.split('.').find_map(|label| {
+        if let Some(c) = label
+            .chars()
+            .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+        {
+            Some(format!("contains invalid character {:?}", c))
+        } else if label.is_empty() || label.starts_with('-') || label.ends_with('-') {
+            Some(format!(
+                "has a label '{}' that doesn't start and end with a letter or digit",
+                label
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+fn repository_problem(value: &str) -> Option<String> {
+    value.split('/').find_map(|component| {
+        path_component_problem(component).map(|problem| format!("'{}' {}", component, problem))
+    })
+}
+
+/// Lowercase letters and digits, joined by `.`, `_`, `__` or any number of `-`
+fn path_component_problem(component: &str) -> Option<String> {
+    if component.is_empty() {
+        return Some("is empty".to_string());
+    }
+    if let Some(c) = component.chars().find(|c| c.is_ascii_uppercase()) {
+        return Some(format!(
+            "contains uppercase {:?}, repository names must be lowercase",
+            c
+        ));
+    }
+    if let Some(c) = component
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !matches!(c, '.' | '_' | '-'))
+    {
+        return Some(format!("contains invalid character {:?}", c));
+    }
+
+    let is_separator = |c: char| matches!(c, '.' | '_' | '-');
+    if component.starts_with(is_separator) || component.ends_with(is_separator) {
+        return Some("must start and end with a letter or digit".to_string());
+    }
+    component
+        .split(|c: char| c.is_ascii_alphanumeric())
+        .filter(|separator| !separator.is_empty())
+        .find(|separator| {
+            !matches!(*separator, "." | "_" | "__") && !separator.chars().all(|c| c == '-')
+        })
+        .map(|separator| format!("contains invalid separator '{}'", separator))
+}
+
+fn tag_problem(value: &str) -> Option<String> {
+    if value.is_empty() || value.len() > 128 {
+        return Some("must be 1 to 128 characters long".to_string());
+    }
+    if let Some(c) = value
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '.' | '-'))
+    {
+        return Some(format!("contains invalid character {:?}", c));
+    }
+    if value.starts_with(['.', '-']) {
+        return Some("must not start with '.' or '-'".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_reference() {
+        assert_eq!(split_reference("app"), (None, "app", None));
+        assert_eq!(
+            split_reference("ghcr.io/acme/app:1.0"),
+            (Some("ghcr.io"), "acme/app", Some("1.0"))
+        );
+        assert_eq!(
+            split_reference("localhost:5000/app"),
+            (Some("localhost:5000"), "app", None)
+        );
+        assert_eq!(
+            split_reference("acme/app:dev"),
+            (None, "acme/app", Some("dev"))
+        );
+    }
+
+    #[test]
+    fn test_check_reference() {
+        check_reference("app").unwrap();
+        check_reference("ghcr.io/acme/my-app:1.2.3").unwrap();
+        check_reference("localhost:5000/acme/my_app__x:latest").unwrap();
+
+        let err = check_reference("ghcr.io/acme/MyApp:1.0").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid image reference 'ghcr.io/acme/MyApp:1.0': \
+             'MyApp' contains uppercase 'M', repository names must be lowercase"
+        );
+        let err = check_reference("app:1.0 beta").unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("tag '1.0 beta' contains invalid character ' '"));
+
+        let err = check_repository("namespace", "acme/my app").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'my app' contains invalid character ' '"));
+
+        let err = check_repository("image_name", "my..app").unwrap_err();
+        assert!(err.to_string().contains("invalid separator '..'"));
+
+        let err = check_tag("image tag", "-dev").unwrap_err();
+        assert!(err.to_string().contains("must not start with '.' or '-'"));
+
+        let err = check_registry("registry", "ghcr.io:abc").unwrap_err();
+        assert!(err.to_string().contains("port 'abc' that is not a number"));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::error::{bail, Context, Result};
+use crate::network::NetworkPolicy;
+use serde::Deserialize;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Manifest types accepted when resolving a digest; multi-arch indexes come first
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+/// GitHub API endpoint for the newest pixi release, whose tags match the pixi image tags
+const PIXI_RELEASE_URL: &str = "https://api.github.com/repos/prefix-dev/pixi/releases/latest";
+
+/// The resolved `latest` pixi version, looked up at most once per invocation
+static LATEST_PIXI_VERSION: OnceLock<String> = OnceLock::new();
+
+/// An image reference split into the parts the registry API needs
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImageRef {
+    /// Registry host, e.g. `ghcr.io` or `registry-1.docker.io` for Docker Hub
+    pub registry: String,
+    /// Repository path, e.g. `library/ubuntu`
+    pub repository: String,
+    /// Tag, `latest` when none is given
+    pub tag: String,
+}
+
+impl ImageRef {
+    /// Parse a reference like `ubuntu:24.04` or `ghcr.io/prefix-dev/pixi:0.45.0`
+    pub fn parse(image: &str) -> Result<Self> {
+        if image.contains('@') {
+            bail!("'{}' is already pinned to a digest", image);
+        }
+        let (registry, path, tag) = crate::reference::split_reference(image);
+        let (registry, repository) = match registry {
+            Some(registry) => (registry.to_string(), path.to_string()),
+            None if path.contains('/') => ("docker.io".to_string(), path.to_string()),
+            None => ("docker.io".to_string(), format!("library/{}", path)),
+        };
+        let registry = match registry.as_str() {
+            "docker.io" | "index.docker.io" => "registry-1.docker.io".to_string(),
+            _ => registry,
+        };
+        Ok(Self {
+            registry,
+            repository,
+            tag: tag.unwrap_or("latest").to_string(),
+        })
+    }
+
+    fn manifest_url(&self) -> String {
+        format!(
+            "https://{}/v2/{}/manifests/{}",
+            self.registry, self.repository, self.tag
+        )
+    }
+
+    fn blob_url(&self, digest: &str) -> String {
+        format!(
+            "https://{}/v2/{}/blobs/{}",
+            self.registry, self.repository, digest
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Anonymous client for the registry HTTP API
+pub struct RegistryClient {
+    agent: ureq::Agent,
+}
+
+impl RegistryClient {
+    pub fn new() -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(20)))
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Self { agent }
+    }
+
+    /// The current digest of an image, e.g. `sha256:...`
+    ///
+    /// Registries that require a token (Docker Hub, ghcr.io) answer the first request with a
+    /// `WWW-Authenticate` challenge, which is used to fetch an anonymous pull token.
+    pub fn manifest_digest(&self, image: &str) -> Result<String> {
+        let reference = ImageRef::parse(image)?;
+        let url = reference.manifest_url();
+        let failed = || format!("Failed to resolve the digest of {}", image);
+
+        let response = self
+            .send(true, &url, MANIFEST_TYPES, &mut None)
+            .with_context(failed)?;
+        if !response.status().is_success() {
+            bail!("{}: {} returned {}", failed(), url, response.status());
+        }
+        response
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .with_context(|| format!("{}: no Docker-Content-Digest header", failed()))
+    }
+
+    /// The architectures an image provides, e.g. `["amd64", "arm64"]`
+    ///
+    /// Multi-arch images list them in their index; a single-arch image only names its
+    /// architecture in its config blob, which takes a second request.
+    pub fn image_architectures(&self, image: &str) -> Result<Vec<String>> {
+        let reference = ImageRef::parse(image)?;
+        let failed = || format!("Failed to look up the architectures of {}", image);
+        let mut token = None;
+
+        let manifest = self
+            .get_json(&reference.manifest_url(), MANIFEST_TYPES, &mut token)
+            .with_context(failed)?;
+        if let Some(architectures) = index_architectures(&manifest) {
+            return Ok(architectures);
+        }
+        let digest = manifest
+            .pointer("/config/digest")
+            .and_then(serde_json::Value::as_str)
+            .with_context(|| format!("{}: the manifest has no config", failed()))?;
+        let config = self
+            .get_json(&reference.blob_url(digest), "*/*", &mut token)
+            .with_context(failed)?;
+        config
+            .get("architecture")
+            .and_then(serde_json::Value::as_str)
+            .map(|architecture| vec![architecture.to_string()])
+            .with_context(|| format!("{}: the image config has no architecture", failed()))
+    }
+
+    /// GET a JSON document from the registry
+    fn get_json(
+        &self,
+        url: &str,
+        accept: &str,
+        token: &mut Option<String>,
+    ) -> Result<serde_json::Value> {
+        let mut response = self.send(false, url, accept, token)?;
+        if !response.status().is_success() {
+            bail!("{} returned {}", url, response.status());
+        }
+        response
+            .body_mut()
+            .read_json()
+            .with_context(|| format!("{} returned invalid JSON", url))
+    }
+
+    /// Send a HEAD or GET request, answering a `WWW-Authenticate` challenge with an anonymous
+    /// pull token, which is kept in `token` for the following requests
+    fn send(
+        &self,
+        head: bool,
+        url: &str,
+        accept: &str,
+        token: &mut Option<String>,
+    ) -> Result<ureq::http::Response<ureq::Body>> {
+        let call = |token: Option<&str>| {
+            let mut request = match head {
+                true => self.agent.head(url),
+                false => self.agent.get(url),
+            }
+            .header("Accept", accept);
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            request.call()
+        };
+
+        let response =
+            call(token.as_deref()).with_context(|| format!("Request to {} failed", url))?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+        let challenge = response
+            .headers()
+            .get("www-authenticate")
+            .and_then(|value| value.to_str().ok())
+            .context("unauthorized without a challenge")?;
+        let fresh = self.token(challenge)?;
+        let response = call(Some(&fresh)).with_context(|| format!("Request to {} failed", url))?;
+        *token = Some(fresh);
+        Ok(response)
+    }
+
+    /// The version of the newest pixi release, e.g. `0.45.0`
+    pub fn latest_pixi_version(&self) -> Result<String> {
+        let mut response = self
+            .agent
+            .get(PIXI_RELEASE_URL)
+            .header("Accept", "application/vnd.github+json")
+            .header(
+                "User-Agent",
+                concat!("pixi-docker/", env!("CARGO_PKG_VERSION")),
+            )
+            .call()
+            .with_context(|| format!("Request to {} failed", PIXI_RELEASE_URL))?;
+        if !response.status().is_success() {
+            bail!("{} returned {}", PIXI_RELEASE_URL, response.status());
+        }
+        let release: Release = response
+            .body_mut()
+            .read_json()
+            .with_context(|| format!("{} returned invalid JSON", PIXI_RELEASE_URL))?;
+        Ok(release_version(&release.tag_name).to_string())
+    }
+
+    /// Fetch an anonymous token for a `Bearer realm="...",service="...",scope="..."` challenge
+    fn token(&self, challenge: &str) -> Result<String> {
+        let params = parse_challenge(challenge)
+            .with_context(|| format!("Unsupported authentication challenge: {}", challenge))?;
+        let realm = params
+            .iter()
+            .find(|(key, _)| key == "realm")
+            .map(|(_, value)| value.as_str())
+            .with_context(|| format!("No realm in authentication challenge: {}", challenge))?;
+
+        let mut request = self.agent.get(realm);
+        for (key, value) in params.iter().filter(|(key, _)| key != "realm") {
+            request = request.query(key, value);
+        }
+        let mut response = request
+            .call()
+            .with_context(|| format!("Request to {} failed", realm))?;
+        if !response.status().is_success() {
+            bail!("{} returned {}", realm, response.status());
+        }
+        let body: TokenResponse = response
+            .body_mut()
+            .read_json()
+            .with_context(|| format!("{} returned invalid JSON", realm))?;
+        body.token
+            .or(body.access_token)
+            .with_context(|| format!("{} returned no token", realm))
+    }
+}
+
+/// Resolve `pixi_version = "latest"` to the newest release, printing the selected version once
+pub fn latest_pixi_version(network: NetworkPolicy) -> Result<String> {
+    if let Some(version) = LATEST_PIXI_VERSION.get() {
+        return Ok(version.clone());
+    }
+    let hint = "pin a version instead, e.g. pixi_version = \"0.45.0\"";
+    network.require("resolve pixi_version = \"latest\"", hint)?;
+    let version = RegistryClient::new()
+        .latest_pixi_version()
+        .with_context(|| {
+            format!(
+                "Failed to resolve pixi_version = \"latest\" (are you offline?); {}",
+                hint
+            )
+        })?;
+    eprintln!("Resolved pixi_version \"latest\" to {}", version);
+    Ok(LATEST_PIXI_VERSION.get_or_init(|| version).clone())
+}
+
+/// The image tag of a release: `v0.45.0` is published as `ghcr.io/prefix-dev/pixi:0.45.0`
+fn release_version(tag_name: &str) -> &str {
+    tag_name.strip_prefix('v').unwrap_or(tag_name)
+}
+
+/// The architectures listed in a multi-arch index, `None` for a single-arch manifest
+///
+/// Only Linux images count; attestation manifests, listed as `unknown`, are left out.
+fn index_architectures(manifest: &serde_json::Value) -> Option<Vec<String>> {
+    let mut architectures: Vec<String> = Vec::new();
+    for entry in manifest.get("manifests")?.as_array()? {
+        let platform = |key: &str| {
+            entry
+                .get("platform")
+                .and_then(|platform| platform.get(key))
+                .and_then(serde_json::Value::as_str)
+        };
+        if platform("os") != Some("linux") {
+            continue;
+        }
+        if let Some(architecture) = platform("architecture") {
+            if !architectures.iter().any(|known| known == architecture) {
+                architectures.push(architecture.to_string());
+            }
+        }
+    }
+    Some(architectures)
+}
+
+/// Parse the parameters of a `Bearer` challenge
+fn parse_challenge(challenge: &str) -> Option<Vec<(String, String)>> {
+    let params = challenge.strip_prefix("Bearer ")?;
+    let mut parsed = Vec::new();
+    let mut rest = params.trim();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let after = after.strip_prefix('"')?;
+        let (value, after) = after.split_once('"')?;
+        parsed.push((key.trim().to_string(), value.to_string()));
+        rest = after.trim_start_matches([',', ' ']);
+    }
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_image() {
+        assert_eq!(
+            ImageRef::parse("ubuntu:24.04").unwrap(),
+            ImageRef {
+                registry: "registry-1.docker.io".to_string(),
+                repository: "library/ubuntu".to_string(),
+                tag: "24.04".to_string(),
+            }
+        );
+        let pixi = ImageRef::parse("ghcr.io/prefix-dev/pixi:0.45.0").unwrap();
+        assert_eq!(pixi.registry, "ghcr.io");
+        assert_eq!(pixi.repository, "prefix-dev/pixi");
+        assert_eq!(
+            pixi.manifest_url(),
+            "https://ghcr.io/v2/prefix-dev/pixi/manifests/0.45.0"
+        );
+        let local = ImageRef::parse("localhost:5000/team/app").unwrap();
+        assert_eq!(local.registry, "localhost:5000");
+        assert_eq!(local.tag, "latest");
+        assert_eq!(
+            ImageRef::parse("nvidia/cuda:12.4.1-base-ubuntu22.04")
+                .unwrap()
+                .repository,
+            "nvidia/cuda"
+        );
+        assert!(ImageRef::parse("ubuntu@sha256:abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/ubuntu:pull""#,
+        )
+        .unwrap();
+        assert_eq!(
+            params,
+            vec![
+                (
+                    "realm".to_string(),
+                    "https://auth.docker.io/token".to_string()
+                ),
+                ("service".to_string(), "registry.docker.io".to_string()),
+                (
+                    "scope".to_string(),
+                    "repository:library/ubuntu:pull".to_string()
+                ),
+            ]
+        );
+        assert!(parse_challenge("Basic realm=\"x\"").is_none());
+    }
+
+    #[test]
+    fn test_index_architectures() {
+        let index = serde_json::json!({
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [
+                {"digest": "sha256:a", "platform": {"architecture": "amd64", "os": "linux"}},
+                {"digest": "sha256:b", "platform": {"architecture": "arm64", "os": "linux", "variant": "v8"}},
+                {"digest": "sha256:c", "platform": {"architecture": "unknown", "os": "unknown"}},
+                {"digest": "sha256:d", "platform": {"architecture": "arm", "os": "windows"}}
+            ]
+        });
+        assert_eq!(
+            index_architectures(&index),
+            Some(vec!["amd64".to_string(), "arm64".to_string()])
+        );
+        let manifest = serde_json::json!({
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": {"digest": "sha256:e"}
+        });
+        assert_eq!(index_architectures(&manifest), None);
+    }
+
+    #[test]
+    fn test_release_version() {
+        assert_eq!(release_version("v0.45.0"), "0.45.0");
+        assert_eq!(release_version("0.45.0"), "0.45.0");
+    }
+}
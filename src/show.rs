@@ -0,0 +1,106 @@
+use crate::lint::is_instruction;
+
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The lines printed above the Dockerfile, naming the template and environment it came from
+pub fn header(template: &str, environment: &str, color: bool) -> String {
+    let lines = [
+        format!("Environment: {}", environment),
+        format!("Template: {}", template),
+    ];
+    let mut out = String::new();
+    for line in lines {
+        if color {
+            out.push_str(&format!("{}{}{}\n", BOLD, line, RESET));
+        } else {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// A Dockerfile for the terminal, with instruction keywords and comments highlighted when
+/// `color` is set and each line numbered with `line_numbers`
+pub fn highlight(dockerfile: &str, line_numbers: bool, color: bool) -> String {
+    let width = dockerfile.lines().count().to_string().len();
+    let mut out = String::new();
+    // Lines after one ending in a backslash continue the instruction, so they hold no keyword
+    let mut continued = false;
+
+    for (index, line) in dockerfile.lines().enumerate() {
+        if line_numbers {
+            let number = format!("{:>width$} ", index + 1, width = width);
+            out.push_str(&paint(&number, DIM, color));
+        }
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        if trimmed.starts_with('#') {
+            out.push_str(&paint(line, DIM, color));
+        } else {
+            let keyword_end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+            let (keyword, rest) = trimmed.split_at(keyword_end);
+            if !continued && is_instruction(&keyword.to_ascii_uppercase()) {
+                out.push_str(indent);
+                out.push_str(&paint(keyword, CYAN, color));
+                out.push_str(rest);
+            } else {
+                out.push_str(line);
+            }
+            continued = line.trim_end().ends_with('\\');
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCKERFILE: &str = "# syntax=docker/dockerfile:1\n\
+                              FROM ubuntu:24.04 AS build\n\
+                              RUN apt-get update && \\\n    \
+                              apt-get install -y curl \\\n    \
+                              copy\n\
+                              \n\
+                              copy . /app\n";
+
+    #[test]
+    fn test_highlight_plain() {
+        assert_eq!(highlight(DOCKERFILE, false, false), DOCKERFILE);
+        let numbered = highlight(DOCKERFILE, true, false);
+        let lines: Vec<&str> = numbered.lines().collect();
+        assert_eq!(lines[0], "1 # syntax=docker/dockerfile:1");
+        assert_eq!(lines[5], "6 ");
+        assert_eq!(lines[6], "7 copy . /app");
+    }
+
+    #[test]
+    fn test_highlight_color() {
+        let colored = highlight(DOCKERFILE, false, true);
+        let lines: Vec<&str> = colored.lines().collect();
+        assert_eq!(lines[0], "\x1b[2m# syntax=docker/dockerfile:1\x1b[0m");
+        assert_eq!(lines[1], "\x1b[36mFROM\x1b[0m ubuntu:24.04 AS build");
+        // Continuation lines are arguments, even when they look like a keyword
+        assert_eq!(lines[4], "    copy");
+        assert_eq!(lines[6], "\x1b[36mcopy\x1b[0m . /app");
+
+        assert_eq!(
+            header("<built-in default template>", "prod", false),
+            "Environment: prod\nTemplate: <built-in default template>\n\n"
+        );
+    }
+}
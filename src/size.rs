@@ -0,0 +1,335 @@
+use crate::error::{bail, Context, Result};
+use std::process::Stdio;
+
+use crate::config::Config;
+use crate::docker;
+use crate::template::json_string;
+
+/// Number of layers listed in the report
+const LARGEST_LAYERS: usize = 5;
+
+/// Longest creating instruction shown for a layer in the text report
+const INSTRUCTION_WIDTH: usize = 72;
+
+/// One image layer and the instruction that created it
+#[derive(Debug, PartialEq, Eq)]
+pub struct Layer {
+    pub size: u64,
+    pub created_by: String,
+}
+
+/// The size of a freshly built image, its largest layers and the size before the build
+#[derive(Debug, PartialEq, Eq)]
+pub struct SizeReport {
+    pub size: u64,
+    /// The largest layers, biggest first
+    pub layers: Vec<Layer>,
+    /// The image of the same repository that existed before the build, with its size
+    pub previous: Option<(String, u64)>,
+}
+
+/// The image of `tag`'s repository present before a build: the tag itself, or else the most
+/// recently created tag of the repository
+pub fn previous_image(config: &Config, tag: &str) -> Option<(String, u64)> {
+    if let Ok(size) = image_size(config, tag) {
+        return Some((tag.to_string(), size));
+    }
+    let repository = match tag.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => tag,
+    };
+    // `image ls` lists the newest images first
+    let previous = docker::image_tags(config, repository)
+        .ok()?
+        .into_iter()
+        .next()?;
+    let size = image_size(config, &previous).ok()?;
+    Some((previous, size))
+}
+
+/// Inspect a built image, comparing it with the image that existed before the build
+pub fn report(config: &Config, tag: &str, previous: Option<(String, u64)>) -> Result<SizeReport> {
+    let size = image_size(config, tag)?;
+    let mut layers = image_history(config, tag)?;
+    layers.sort_by_key(|layer| std::cmp::Reverse(layer.size));
+    layers.truncate(LARGEST_LAYERS);
+    Ok(SizeReport {
+        size,
+        layers,
+        previous,
+    })
+}
+
+/// The size of a local image in bytes, from `image inspect`
+fn image_size(config: &Config, tag: &str) -> Result<u64> {
+    let output = capture(config, &["image", "inspect", tag])?;
+    parse_inspect_size(&output)
+        .with_context(|| format!("No image size in the inspect output of {}", tag))
+}
+
+/// The layers of a local image, from `history`
+fn image_history(config: &Config, tag: &str) -> Result<Vec<Layer>> {
+    let output = capture(
+        config,
+        &[
+            "history",
+            "--no-trunc",
+            "--human=false",
+            "--format",
+            "{{.Size}}\t{{.CreatedBy}}",
+            tag,
+        ],
+    )?;
+    Ok(parse_history(&output))
+}
+
+/// Run a runtime command quietly, returning its stdout
+fn capture(config: &Config, args: &[&str]) -> Result<String> {
+    let mut cmd = docker::command(config);
+    cmd.args(args).stdin(Stdio::null()).stderr(Stdio::null());
+    let output = cmd
+        .output()
+        .with_context(|| format!("Failed to run {:?}", cmd))?;
+    if !output.status.success() {
+        bail!("{:?} failed with {}", cmd, output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The `Size` of the first image in `image inspect` JSON, as written by docker and podman
+pub fn parse_inspect_size(output: &str) -> Option<u64> {
+    let images: serde_json::Value = serde_json::from_str(output).ok()?;
+    let image = images.get(0)?;
+    ["Size", "size", "VirtualSize"]
+        .iter()
+        .find_map(|key| image.get(key).and_then(serde_json::Value::as_u64))
+}
+
+/// Parse `history` lines of `<size>\t<created by>`
+///
+/// Sizes are bytes with `--human=false`, but human-readable sizes like `12.3MB` or `1.5 kB`
+/// (podman before 4.0 ignores the flag in templates) are read too.
+pub fn parse_history(output: &str) -> Vec<Layer> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (size, created_by) = line.split_once('\t')?;
+            Some(Layer {
+                size: parse_size(size.trim())?,
+                created_by: instruction(created_by.trim()),
+            })
+        })
+        .collect()
+}
+
+/// Bytes from a plain number or a size with a decimal unit, e.g. `512`, `12.3MB` or `1.5 kB`
+fn parse_size(value: &str) -> Option<u64> {
+    if let Ok(bytes) = value.parse() {
+        return Some(bytes);
+    }
+    let split = value.find(|c: char| c.is_ascii_alphabetic())?;
+    let number: f64 = value[..split].trim().parse().ok()?;
+    let factor = match value[split..].to_ascii_lowercase().as_str() {
+        "b" => 1e0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "tb" => 1e12,
+        _ => return None,
+    };
+    Some((number * factor).round() as u64)
+}
+
+/// The Dockerfile instruction of a history entry, without the shell wrapper and comments the
+/// runtimes add, e.g. `/bin/sh -c #(nop)` or `# buildkit`
+fn instruction(created_by: &str) -> String {
+    let created_by = created_by.trim_end_matches("# buildkit").trim();
+    let created_by = match created_by.strip_prefix("/bin/sh -c #(nop)") {
+        Some(instruction) => instruction.to_string(),
+        None => match created_by
+            .strip_prefix("/bin/sh -c ")
+            .or_else(|| created_by.strip_prefix("RUN /bin/sh -c "))
+        {
+            Some(command) => format!("RUN {}", command),
+            None => created_by.to_string(),
+        },
+    };
+    created_by.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// A size in decimal units like docker prints them, e.g. `512.3 MB`
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < units.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, units[unit]),
+    }
+}
+
+/// A signed size difference, e.g. `+12.1 MB`
+fn human_delta(current: u64, previous: u64) -> String {
+    match current.cmp(&previous) {
+        std::cmp::Ordering::Less => format!("-{}", human_size(previous - current)),
+        _ => format!("+{}", human_size(current - previous)),
+    }
+}
+
+impl SizeReport {
+    /// Lines of the text report, without trailing newlines
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![match &self.previous {
+            Some((tag, previous)) => format!(
+                "Image size: {} ({} from {} before the build)",
+                human_size(self.size),
+                human_delta(self.size, *previous),
+                tag
+            ),
+            None => format!("Image size: {}", human_size(self.size)),
+        }];
+        if !self.layers.is_empty() {
+            lines.push("Largest layers:".to_string());
+        }
+        for layer in &self.layers {
+            let mut created_by = layer.created_by.clone();
+            if created_by.chars().count() > INSTRUCTION_WIDTH {
+                created_by = created_by.chars().take(INSTRUCTION_WIDTH - 3).collect();
+                created_by.push_str("...");
+            }
+            lines.push(format!("  {:>10}  {}", human_size(layer.size), created_by));
+        }
+        lines
+    }
+
+    /// The report as a JSON object, with sizes in bytes
+    pub fn json(&self) -> String {
+        let layers: Vec<String> = self
+            .layers
+            .iter()
+            .map(|layer| {
+                format!(
+                    "{{\"size\": {}, \"created_by\": {}}}",
+                    layer.size,
+                    json_string(&layer.created_by)
+                )
+            })
+            .collect();
+        let (previous_tag, previous_size) = match &self.previous {
+            Some((tag, size)) => (json_string(tag), size.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"size\": {}, \"previous_image\": {}, \"previous_size\": {}, \"largest_layers\": [{}]}}",
+            self.size,
+            previous_tag,
+            previous_size,
+            layers.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inspect_size() {
+        let docker = r#"[{"Id": "sha256:abc", "Size": 523456789, "Os": "linux"}]"#;
+        assert_eq!(parse_inspect_size(docker), Some(523456789));
+        let podman = r#"[{"Id": "abc", "size": 1200, "VirtualSize": 1300}]"#;
+        assert_eq!(parse_inspect_size(podman), Some(1200));
+        assert_eq!(parse_inspect_size("[]"), None);
+        assert_eq!(parse_inspect_size(""), None);
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let output = "0\tCMD [\"/bin/bash\"] # buildkit\n\
+                      312000000\tCOPY /app/.pixi/envs/prod /app/.pixi/envs/prod # buildkit\n\
+                      1.5 kB\t/bin/sh -c #(nop) COPY file:abc in /app/src\n\
+                      77.9MB\t/bin/sh -c apt-get update &&     apt-get install -y curl\n\
+                      garbage\n\
+                      12 parsecs\tRUN nothing\n\
+                      4096\tRUN /bin/sh -c pixi install --locked # buildkit\n";
+        assert_eq!(
+            parse_history(output),
+            [
+                Layer {
+                    size: 0,
+                    created_by: "CMD [\"/bin/bash\"]".to_string()
+                },
+                Layer {
+                    size: 312000000,
+                    created_by: "COPY /app/.pixi/envs/prod /app/.pixi/envs/prod".to_string()
+                },
+                Layer {
+                    size: 1500,
+                    created_by: "COPY file:abc in /app/src".to_string()
+                },
+                Layer {
+                    size: 77900000,
+                    created_by: "RUN apt-get update && apt-get install -y curl".to_string()
+                },
+                Layer {
+                    size: 4096,
+                    created_by: "RUN pixi install --locked".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_output() {
+        assert_eq!(human_size(999), "999 B");
+        assert_eq!(human_size(1_500), "1.5 kB");
+        assert_eq!(human_size(523_456_789), "523.5 MB");
+
+        let report = SizeReport {
+            size: 523_456_789,
+            layers: vec![
+                Layer {
+                    size: 312_000_000,
+                    created_by: "COPY /app/.pixi/envs/prod /app/.pixi/envs/prod".to_string(),
+                },
+                Layer {
+                    size: 77_900_000,
+                    created_by: format!("RUN {}", "x".repeat(100)),
+                },
+            ],
+            previous: Some(("app:1.0".to_string(), 500_000_000)),
+        };
+        let lines = report.lines();
+        assert_eq!(
+            lines[0],
+            "Image size: 523.5 MB (+23.5 MB from app:1.0 before the build)"
+        );
+        assert_eq!(lines[1], "Largest layers:");
+        assert_eq!(
+            lines[2],
+            "    312.0 MB  COPY /app/.pixi/envs/prod /app/.pixi/envs/prod"
+        );
+        assert!(lines[3].ends_with("xxx..."));
+        assert_eq!(lines[3].chars().count(), 14 + INSTRUCTION_WIDTH);
+
+        let json: serde_json::Value = serde_json::from_str(&report.json()).unwrap();
+        assert_eq!(json["size"], 523_456_789);
+        assert_eq!(json["previous_image"], "app:1.0");
+        assert_eq!(json["previous_size"], 500_000_000);
+        assert_eq!(json["largest_layers"][0]["size"], 312_000_000);
+
+        let smaller = SizeReport {
+            previous: Some(("app:1.0".to_string(), 600_000_000)),
+            layers: Vec::new(),
+            ..report
+        };
+        assert_eq!(
+            smaller.lines(),
+            ["Image size: 523.5 MB (-76.5 MB from app:1.0 before the build)"]
+        );
+    }
+}
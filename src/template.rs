@@ -1,44 +1,224 @@
-use crate::config::Config;
-use crate::pixi::PixiToml;
-use anyhow::Result;
+use crate::config::{
+    Config, Entrypoint, InitMode, InstallMode, Packaging, AUTH_SECRET_ID, DEFAULT_PIXI_VERSION,
+};
+use crate::error::{bail, Context, PixiDockerError, Result};
+use crate::pixi::{self, PixiToml, TaskValue, VersionRequirement};
 use minijinja::{context, Environment};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct DockerfileGenerator {
     template_content: String,
+    /// Where the template came from, for error messages
+    origin: String,
+}
+
+/// A template bundled into the binary, selectable with the `flavor` config key
+pub struct Flavor {
+    pub name: &'static str,
+    pub description: &'static str,
+    template: &'static str,
+}
+
+/// Name of the flavor used when none is configured
+pub const DEFAULT_FLAVOR: &str = "default";
+
+/// All built-in template flavors, in the order `templates list` prints them
+pub const FLAVORS: &[Flavor] = &[
+    Flavor {
+        name: DEFAULT_FLAVOR,
+        description: "Multi-stage build with the environment copied onto ubuntu:24.04",
+        template: include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/templates/Dockerfile.j2"
+        )),
+    },
+    Flavor {
+        name: "slim",
+        description: "Always multi-stage onto debian:bookworm-slim, with the package cache dropped",
+        template: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/slim.j2")),
+    },
+    Flavor {
+        name: "cuda",
+        description: "Runs on an nvidia/cuda base image with GPU-enabled packages resolved",
+        template: include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/cuda.j2")),
+    },
+    Flavor {
+        name: "single-stage-dev",
+        description: "Single stage that keeps pixi and the full source tree for development",
+        template: include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/templates/single-stage-dev.j2"
+        )),
+    },
+];
+
+/// The template in `dir` for an environment: `Dockerfile.<environment>.j2`, else `Dockerfile.j2`
+fn find_in_template_dir(dir: &Path, environment: &str) -> Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        bail!("template_dir {} is not a directory", dir.display());
+    }
+    Ok([
+        format!("Dockerfile.{}.j2", environment),
+        "Dockerfile.j2".to_string(),
+    ]
+    .into_iter()
+    .map(|name| dir.join(name))
+    .find(|path| path.is_file()))
+}
+
+/// Look up a built-in flavor by name
+pub fn find_flavor(name: &str) -> Result<&'static Flavor> {
+    FLAVORS.iter().find(|f| f.name == name).ok_or_else(|| {
+        let names: Vec<&str> = FLAVORS.iter().map(|f| f.name).collect();
+        PixiDockerError::msg(format!(
+            "unknown template flavor '{}' (available: {})",
+            name,
+            names.join(", ")
+        ))
+    })
 }
 
 impl DockerfileGenerator {
-    pub fn new() -> Self {
-        Self::with_template_path(None)
+    pub fn new() -> Result<Self> {
+        Self::with_template_path(None, None)
     }
 
-    pub fn with_template_path(template_path: Option<PathBuf>) -> Self {
-        let template_content = if let Some(path) = template_path {
-            fs::read_to_string(path).unwrap_or_else(|_| Self::default_template().to_string())
-        } else {
-            let default_path = PathBuf::from("templates/Dockerfile.j2");
-            if default_path.exists() {
-                fs::read_to_string(&default_path)
-                    .unwrap_or_else(|_| Self::default_template().to_string())
-            } else {
-                Self::default_template().to_string()
+    /// An explicit `template_path` wins over `flavor`; without either, a local
+    /// `templates/Dockerfile.j2` overrides the default flavor
+    pub fn with_template_path(
+        template_path: Option<PathBuf>,
+        flavor: Option<&str>,
+    ) -> Result<Self> {
+        if let Some(path) = template_path {
+            return Self::from_file(&path);
+        }
+        match flavor {
+            Some(name) if name != DEFAULT_FLAVOR => Ok(Self::from_flavor(find_flavor(name)?)),
+            _ => {
+                let local_path = PathBuf::from("templates/Dockerfile.j2");
+                if local_path.exists() {
+                    Self::from_file(&local_path)
+                } else {
+                    Ok(Self::from_flavor(&FLAVORS[0]))
+                }
             }
+        }
+    }
+
+    /// The generator for one environment, from the first of: its `template_path` (or the
+    /// `[docker]` one), `Dockerfile.<environment>.j2` and then `Dockerfile.j2` in
+    /// `template_dir`, and the configured flavor
+    pub fn for_environment(config: &Config, environment: &str) -> Result<Self> {
+        let built_in = || match config.docker.flavor.as_deref() {
+            Some(flavor) => Self::with_template_path(None, Some(flavor)),
+            None => Self::new(),
+        };
+        let generator = match config.template_path(environment) {
+            Some(path) => Self::from_file(Path::new(path))?,
+            None => match &config.docker.template_dir {
+                Some(dir) => match find_in_template_dir(Path::new(dir), environment)? {
+                    Some(path) => Self::from_file(&path)?,
+                    None => built_in()?,
+                },
+                None => built_in()?,
+            },
         };
+        if config.verbose {
+            eprintln!(
+                "Using template {} for environment '{}'",
+                generator.origin, environment
+            );
+        }
+        Ok(generator)
+    }
 
-        Self { template_content }
+    /// Where the template came from: its path or `<built-in NAME template>`
+    pub fn origin(&self) -> &str {
+        &self.origin
     }
 
-    fn default_template() -> &'static str {
-        include_str!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/templates/Dockerfile.j2"
-        ))
+    /// The SHA-256 of the template, as recorded in `pixi_docker.lock`
+    pub fn template_hash(&self) -> String {
+        format!("sha256:{:x}", Sha256::digest(&self.template_content))
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let template_content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        Ok(Self {
+            template_content,
+            origin: path.display().to_string(),
+        })
+    }
+
+    fn from_flavor(flavor: &Flavor) -> Self {
+        Self {
+            template_content: flavor.template.to_string(),
+            origin: format!("<built-in {} template>", flavor.name),
+        }
+    }
+
+    /// Describe a template error with its origin, location and a source excerpt
+    fn template_error(&self, err: minijinja::Error) -> PixiDockerError {
+        let message = match err.detail() {
+            Some(detail) => format!("{}: {}", err.kind(), detail),
+            None => err.kind().to_string(),
+        };
+        let offset = err.range().map(|range| range.start).or_else(|| {
+            // Only the line is known: point at its first character
+            let line = err.line()?;
+            let offset = self
+                .template_content
+                .split_inclusive('\n')
+                .take(line - 1)
+                .map(str::len)
+                .sum();
+            Some(offset)
+        });
+        let description = match offset {
+            Some(offset) => {
+                crate::parse::describe_at(&self.origin, &self.template_content, offset, &message)
+            }
+            None => format!("{}: {}", self.origin, message),
+        };
+        PixiDockerError::TemplateRender {
+            template: self.origin.clone(),
+            line: err.line(),
+            description,
+        }
+    }
+
+    /// Render the Dockerfile, with base images pinned to digests when `pin_digests` is set
+    /// and the generated-file header unless `header = false`, and lint it unless `lint = false`
+    pub fn generate(
+        &self,
+        config: &Config,
+        environment: Option<&str>,
+    ) -> Result<String, PixiDockerError> {
+        let mut dockerfile = self.render(config, environment)?;
+        if config.docker.pin_digests {
+            let digests = crate::lock::pinned_digests(config, &base_images(&dockerfile), false)?;
+            dockerfile = pin_base_images(&dockerfile, &digests);
+        }
+        if config.docker.header {
+            let environment = environment.unwrap_or(&config.docker.environment);
+            dockerfile = with_header(&dockerfile, &header(config, environment));
+        }
+        if config.docker.lint {
+            crate::lint::check(&dockerfile, &self.origin)?;
+        }
+        Ok(dockerfile)
     }
 
-    pub fn generate(&self, config: &Config, environment: Option<&str>) -> Result<String> {
+    /// Render the Dockerfile with base images as written in the template
+    pub fn render(&self, config: &Config, environment: Option<&str>) -> Result<String> {
         let environment = environment.unwrap_or(&config.docker.environment);
+        // Tasks are looked up in, and the image installs, the pixi environment
+        let pixi_environment = config.pixi_environment(environment);
 
         let env_config = config.environments.get(environment);
 
@@ -52,133 +232,755 @@ impl DockerfileGenerator {
             config.docker.ports.clone()
         };
 
-        let entrypoint = if let Some(env_cfg) = env_config {
-            env_cfg
-                .entrypoint
+        let entrypoint = config.entrypoint(environment);
+
+        let copy_files: Vec<String> = config
+            .copy_files(environment)
+            .iter()
+            .map(|path| posix_path(path))
+            .collect();
+
+        let multi_stage = config.multi_stage(environment);
+
+        if config.docker.packaging == Packaging::PixiPack && !multi_stage {
+            bail!(
+                "packaging = \"pixi-pack\" requires a multi-stage build, but environment '{}' sets multi_stage = false",
+                environment
+            );
+        }
+
+        let test_in_build = config.test_in_build(environment);
+        if test_in_build && !multi_stage {
+            bail!(
+                "test_in_build requires a multi-stage build, but environment '{}' sets multi_stage = false",
+                environment
+            );
+        }
+
+        let base_image = config.base_image(environment)?;
+
+        // Try to load the pixi manifest to translate task names to shell commands
+        let pixi_toml = config
+            .manifest_path()
+            .and_then(|path| PixiToml::load(&path));
+
+        let inferred_entrypoint = if entrypoint.is_none() && config.docker.infer_entrypoint {
+            pixi_toml
                 .as_ref()
-                .or(config.docker.entrypoint.as_ref())
+                .and_then(|p| p.infer_entrypoint(pixi_environment))
+                .map(|task| {
+                    eprintln!(
+                        "Note: no entrypoint configured for environment '{}', using pixi task '{}' \
+                         (set `entrypoint` to override or `infer_entrypoint = false` to disable)",
+                        environment, task
+                    );
+                    Entrypoint::Shell(task.to_string())
+                })
         } else {
-            config.docker.entrypoint.as_ref()
+            None
         };
+        let entrypoint = entrypoint.or(inferred_entrypoint.as_ref());
 
-        let copy_files = if let Some(env_cfg) = env_config {
-            if !env_cfg.copy_files.is_empty() {
-                env_cfg.copy_files.clone()
-            } else {
-                config.docker.copy_files.clone()
+        let build_commands = config.build_commands(environment);
+
+        if let Some(pixi_toml) = &pixi_toml {
+            let references = entrypoint
+                .and_then(Entrypoint::as_shell)
+                .map(|e| ("entrypoint", e))
+                .into_iter()
+                .chain(build_commands.iter().map(|b| ("build_command", b.as_str())))
+                .chain(
+                    config
+                        .test_command(environment)
+                        .map(|t| ("test_command", t)),
+                );
+            for (field, value) in references {
+                let Some(problem) = unknown_task(pixi_toml, pixi_environment, field, value) else {
+                    continue;
+                };
+                if config.docker.strict {
+                    bail!("{}", problem);
+                }
+                eprintln!("Warning: {}", problem);
             }
-        } else {
-            config.docker.copy_files.clone()
-        };
+        }
 
-        let build_command = if let Some(env_cfg) = env_config {
-            env_cfg
-                .build_command
-                .as_ref()
-                .or(config.docker.build_command.as_ref())
-        } else {
-            config.docker.build_command.as_ref()
+        // A resolved environment without any of these builds an image that only starts bash
+        if entrypoint.is_none() && build_commands.is_empty() && copy_files.is_empty() {
+            let problem = format!(
+                "environment '{}' has no entrypoint, build_command or copy_files, so its image \
+                 only starts an interactive bash in the pixi environment{}; set `entrypoint` \
+                 (e.g. a pixi task), `build_command` or `copy_files` in [docker] or \
+                 [environments.{}]",
+                environment,
+                if multi_stage {
+                    " and the final stage receives none of the project files"
+                } else {
+                    ""
+                },
+                environment
+            );
+            if config.docker.strict {
+                bail!("{}", problem);
+            }
+            eprintln!("Warning: {}", problem);
+        }
+
+        // A missing source would only fail the COPY, deep into the docker build
+        let context = config.project_dir();
+        let context_dir = match context.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => context.as_path(),
         };
+        // A build command may create the entries it copies, so they only get a warning
+        let build_creates = !config.build_commands(environment).is_empty();
+        let missing =
+            crate::input_hash::missing_copy_files(context_dir, config.copy_files(environment))
+                .with_context(|| {
+                    format!("Failed to list the build context {}", context_dir.display())
+                })?;
+        for entry in missing {
+            let problem = format!(
+                "copy_files entry '{}' of environment '{}' (set in [{}]) {} in the build context {}",
+                entry,
+                environment,
+                copy_files_section(config, environment, &entry),
+                match entry.contains(['*', '?', '[']) {
+                    true => "matches no file",
+                    false => "doesn't exist",
+                },
+                context_dir.display()
+            );
+            if build_creates {
+                eprintln!("Warning: {}; fine if build_command creates it", problem);
+                continue;
+            }
+            if config.docker.strict {
+                bail!("{}", problem);
+            }
+            eprintln!("Warning: {}", problem);
+        }
 
-        let multi_stage = if let Some(env_cfg) = env_config {
-            env_cfg.multi_stage.unwrap_or(config.docker.multi_stage)
-        } else {
-            config.docker.multi_stage
+        let translate = |command: &str| -> Result<String> {
+            Ok(match &pixi_toml {
+                Some(pixi_toml) => {
+                    pixi_toml.translate_task_to_shell(command, Some(pixi_environment))?
+                }
+                None => None,
+            }
+            .unwrap_or_else(|| command.to_string()))
         };
 
-        let base_image = if let Some(env_cfg) = env_config {
-            env_cfg
-                .base_image
-                .as_ref()
-                .or(config.docker.base_image.as_ref())
-        } else {
-            config.docker.base_image.as_ref()
+        // The exec form bypasses task translation and the bash wrapper
+        let (translated_entrypoint, entrypoint_argv) = match entrypoint {
+            Some(Entrypoint::Shell(entrypoint_task)) => {
+                let steps = match &pixi_toml {
+                    Some(pixi_toml) => {
+                        pixi_toml.translate_task(entrypoint_task, Some(pixi_environment))?
+                    }
+                    None => None,
+                };
+                // Let the command replace bash so it receives the stop signal
+                let exec = config.exec_entrypoint(environment);
+                let command = match steps {
+                    Some(steps) => pixi::steps_to_shell(&steps, exec),
+                    None if exec && !entrypoint_task.starts_with("exec ") => {
+                        format!("exec {}", entrypoint_task)
+                    }
+                    None => entrypoint_task.clone(),
+                };
+                (command, None)
+            }
+            Some(Entrypoint::Exec(argv)) if argv.is_empty() => {
+                bail!(
+                    "The entrypoint of environment '{}' is an empty array",
+                    environment
+                );
+            }
+            Some(Entrypoint::Exec(argv)) => ("".to_string(), Some(argv)),
+            None => ("".to_string(), None),
         };
 
-        // Try to load pixi.toml to translate task names to shell commands
-        let pixi_toml_path = PathBuf::from("pixi.toml");
-        let translated_entrypoint = if let Some(entrypoint_task) = entrypoint {
-            if pixi_toml_path.exists() {
-                if let Ok(pixi_toml) = PixiToml::from_file(&pixi_toml_path) {
-                    pixi_toml
-                        .translate_task_to_shell(entrypoint_task)
-                        .unwrap_or_else(|| entrypoint_task.to_string())
-                } else {
-                    entrypoint_task.to_string()
-                }
-            } else {
-                entrypoint_task.to_string()
+        let build_commands = build_commands
+            .iter()
+            .map(|command| translate(command))
+            .collect::<Result<Vec<_>>>()?;
+        let test_command = match test_in_build {
+            true => {
+                let task = config
+                    .test_task(environment, pixi_toml.as_ref())
+                    .with_context(|| {
+                        format!(
+                            "Environment '{}' sets test_in_build but has no tests to run: set \
+                             `test_command` or add a `test` task to pixi.toml",
+                            environment
+                        )
+                    })?;
+                Some(translate(&task)?)
             }
+            false => None,
+        };
+
+        let labels = if config.docker.oci_labels {
+            oci_labels(pixi_toml.as_ref(), config.docker.reproducible)
         } else {
-            "".to_string()
+            Vec::new()
         };
 
+        // Embed the concrete release so the Dockerfile builds the same image later
+        let requirement = pixi_toml
+            .as_ref()
+            .and_then(PixiToml::get_requires_pixi)
+            .map(VersionRequirement::parse)
+            .transpose()?;
+        let pixi_version = match (config.configured_pixi_version(environment), &requirement) {
+            (Some("latest"), _) => crate::lock::locked_pixi_version(config)?,
+            // Without a configured version, use the oldest release the manifest accepts
+            (None, Some(requirement)) => requirement
+                .minimum_version()
+                .unwrap_or_else(|| config.pixi_version(environment).to_string()),
+            _ => config.pixi_version(environment).to_string(),
+        };
+        if let Some(requirement) = &requirement {
+            check_requires_pixi(config, environment, requirement, &pixi_version)?;
+        }
+
         let mut env = Environment::new();
-        env.add_template("dockerfile", &self.template_content)?;
-        let tmpl = env.get_template("dockerfile")?;
+        env.add_filter("quote", quote);
+        env.add_filter("exec_form", exec_form);
+        add_functions(&mut env, config);
+        env.add_template("dockerfile", &self.template_content)
+            .map_err(|err| self.template_error(err))?;
+        let tmpl = env
+            .get_template("dockerfile")
+            .map_err(|err| self.template_error(err))?;
         let output = tmpl.render(context! {
             environment => environment,
+            pixi_environment => pixi_environment,
             ports => ports,
             entrypoint => if translated_entrypoint.is_empty() { None } else { Some(translated_entrypoint) },
+            entrypoint_argv => entrypoint_argv,
             copy_files => copy_files,
-            pixi_version => config.docker.pixi_version.as_ref(),
-            build_command => build_command,
+            pixi_version => pixi_version,
+            build_commands => build_commands,
+            single_layer => config.docker.single_layer,
             multi_stage => multi_stage,
+            clean_cache => config.docker.clean_cache.unwrap_or(!multi_stage),
+            packaging => config.docker.packaging.as_str(),
             base_image => base_image,
-        })?;
+            target_arch => config.target_arch(environment),
+            labels => labels,
+            oci_labels => config.docker.oci_labels,
+            pixi => PixiContext::new(pixi_toml.as_ref()),
+            tasks => task_context(pixi_toml.as_ref(), pixi_environment),
+            install_flag => config.docker.install_mode.flag(),
+            auth_secret => config.docker.auth_file.as_ref().map(|_| AUTH_SECRET_ID),
+            ssh_forward => config.docker.ssh_forward,
+            proxy_args => config.proxy_args(),
+            channels => pixi_toml
+                .as_ref()
+                .map(|p| p.environment_channels(Some(pixi_environment)))
+                .unwrap_or_default(),
+            manifest_files => manifest_files(config),
+            env_prefix => env_prefix(pixi_environment),
+            stop_signal => config.stop_signal(environment)?,
+            init => config.init(environment) == InitMode::Tini,
+            tini_version => TINI_VERSION,
+            runtime_paths => runtime_paths(config, environment),
+            source_first => pixi_toml.as_ref().is_some_and(PixiToml::has_path_dependencies),
+            build_stage => BUILD_STAGE,
+            production_stage => PRODUCTION_STAGE,
+            test_command => test_command,
+            test_stage => TEST_STAGE,
+            tested_stage => TESTED_STAGE,
+        })
+        .map_err(|err| self.template_error(err))?;
+        if test_in_build && !stage_names(&output).iter().any(|stage| stage == TEST_STAGE) {
+            eprintln!(
+                "Warning: environment '{}' sets test_in_build, but {} has no `{}` stage, so the \
+                 build doesn't run the tests",
+                environment, self.origin, TEST_STAGE
+            );
+        }
+
+        // Templates checked out or written on Windows may use CRLF; Dockerfiles always get LF
+        Ok(output.replace("\r\n", "\n"))
+    }
+}
+
+/// The tini release installed as the init process when `init = "tini"`
+pub const TINI_VERSION: &str = "0.19.0";
+
+/// Name of the stage that installs the environment and runs the build tasks
+pub const BUILD_STAGE: &str = "build";
+/// Name of the stage that holds the runtime image of a multi-stage build
+pub const PRODUCTION_STAGE: &str = "production";
+/// Name of the stage that runs the tests on top of the build stage, with `test_in_build`
+pub const TEST_STAGE: &str = "test";
+/// Name of the stage that gates the production stage on the test stage, with `test_in_build`
+pub const TESTED_STAGE: &str = "tested";
+
+/// Name of the `FROM scratch` stage `build --extract` copies files into
+pub const EXPORT_STAGE: &str = "export";
+
+/// The name of the last stage, which docker builds by default, unless it has none
+pub fn final_stage(dockerfile: &str) -> Option<String> {
+    let last = dockerfile.lines().rfind(|line| {
+        line.split_whitespace()
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("FROM"))
+    })?;
+    stage_names(last).pop()
+}
+
+/// A `FROM scratch` stage holding `paths` of stage `source`, for a local build output
+pub fn export_stage(name: &str, source: &str, paths: &[&str]) -> String {
+    let mut stage = format!("\nFROM scratch AS {}\n", name);
+    for path in paths {
+        stage.push_str(&format!("COPY --from={} {} /\n", source, path));
+    }
+    stage
+}
+
+/// The stage names declared by `FROM <image> AS <name>` in a Dockerfile
+pub fn stage_names(dockerfile: &str) -> Vec<String> {
+    dockerfile
+        .lines()
+        .filter_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [from, .., as_, name]
+                    if from.eq_ignore_ascii_case("FROM") && as_.eq_ignore_ascii_case("AS") =>
+                {
+                    Some(name.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The image of a `FROM` line, unless it refers to an earlier stage, `scratch`, a build arg
+/// or is already pinned
+fn from_image<'a>(line: &'a str, stages: &[String]) -> Option<&'a str> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("FROM") {
+        return None;
+    }
+    let image = words.find(|word| !word.starts_with("--"))?;
+    let internal = image == "scratch" || stages.iter().any(|stage| stage == image);
+    (!internal && !image.contains(['@', '$'])).then_some(image)
+}
+
+/// The external images a Dockerfile builds from, in order and without duplicates
+pub fn base_images(dockerfile: &str) -> Vec<String> {
+    let mut stages = Vec::new();
+    let mut images: Vec<String> = Vec::new();
+    for line in dockerfile.lines() {
+        if let Some(image) = from_image(line, &stages) {
+            if !images.iter().any(|known| known == image) {
+                images.push(image.to_string());
+            }
+        }
+        stages.extend(stage_names(line));
+    }
+    images
+}
+
+/// Append `@<digest>` to every base image with a known digest
+pub fn pin_base_images(dockerfile: &str, digests: &BTreeMap<String, String>) -> String {
+    let mut stages = Vec::new();
+    let mut pinned = String::with_capacity(dockerfile.len());
+    for line in dockerfile.split_inclusive('\n') {
+        match from_image(line, &stages).and_then(|image| Some((image, digests.get(image)?))) {
+            Some((image, digest)) => {
+                let start = line.find(image).unwrap_or_default();
+                pinned.push_str(&line[..start + image.len()]);
+                pinned.push('@');
+                pinned.push_str(digest);
+                pinned.push_str(&line[start + image.len()..]);
+            }
+            None => pinned.push_str(line),
+        }
+        stages.extend(stage_names(line));
+    }
+    pinned
+}
+
+/// The comment marking a Dockerfile as generated; free of timestamps so `--check` stays stable
+fn header(config: &Config, environment: &str) -> String {
+    format!(
+        "# Generated by pixi-docker {} from {} (environment: {}) \u{2014} do not edit, run `pixi-docker generate`\n",
+        env!("CARGO_PKG_VERSION"),
+        config.display_path(),
+        environment
+    )
+}
+
+/// Insert `header` at the top of `dockerfile`, after any parser directives such as
+/// `# syntax=...`, which docker only honors before the first comment
+fn with_header(dockerfile: &str, header: &str) -> String {
+    let is_directive = |line: &str| {
+        line.strip_prefix('#')
+            .and_then(|rest| rest.split_once('='))
+            .is_some_and(|(key, _)| {
+                matches!(
+                    key.trim().to_ascii_lowercase().as_str(),
+                    "syntax" | "escape" | "check"
+                )
+            })
+    };
+    let directives: usize = dockerfile
+        .split_inclusive('\n')
+        .take_while(|line| is_directive(line))
+        .map(str::len)
+        .sum();
+    let (directives, rest) = dockerfile.split_at(directives);
+    format!("{}{}{}", directives, header, rest)
+}
+
+/// Fail when the pixi version of the image doesn't meet the manifest's `requires-pixi`
+fn check_requires_pixi(
+    config: &Config,
+    environment: &str,
+    requirement: &VersionRequirement,
+    pixi_version: &str,
+) -> Result<()> {
+    // The unresolved `latest` tag can't be compared
+    if pixi_version == DEFAULT_PIXI_VERSION || requirement.matches(pixi_version)? {
+        return Ok(());
+    }
+    let manifest = config
+        .manifest_path()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "pixi.toml".to_string());
+    let suggestion = match requirement.minimum_version() {
+        Some(version) => format!("set `pixi_version = \"{}\"`", version),
+        None => "set a `pixi_version` that matches".to_string(),
+    };
+    bail!(
+        "{} requires pixi {} (`requires-pixi`), but environment '{}' uses pixi {}; {}",
+        manifest,
+        requirement,
+        environment,
+        pixi_version,
+        suggestion
+    );
+}
+
+/// Where pixi installs an environment in the build stage, kept in the final image because
+/// environments are not relocatable
+pub fn env_prefix(environment: &str) -> String {
+    format!("/app/.pixi/envs/{}", environment)
+}
+
+/// What the final stage copies from the build stage besides the activation script and
+/// `copy_files`: `runtime_copy` if set, otherwise just the environment
+pub fn runtime_paths(config: &Config, environment: &str) -> Vec<String> {
+    let runtime_copy = config.runtime_copy(environment);
+    if !runtime_copy.is_empty() {
+        // Relative paths are relative to the project in the build stage
+        return runtime_copy
+            .iter()
+            .map(|path| posix_path(path))
+            .map(|path| match path.starts_with('/') {
+                true => path,
+                false => format!("/app/{}", path.trim_start_matches("./")),
+            })
+            .collect();
+    }
+    match config.docker.packaging {
+        Packaging::PixiPack => vec![PIXI_PACK_PREFIX.to_string()],
+        Packaging::Copy => vec![env_prefix(config.pixi_environment(environment))],
+    }
+}
+
+/// A path written with Windows separators, e.g. `src\app`, as the `/`-separated path
+/// Dockerfile instructions expect
+fn posix_path(path: &str) -> String {
+    path.replace('\\', "/")
+}
 
-        Ok(output)
+/// Where the pixi-pack templates unpack the environment
+const PIXI_PACK_PREFIX: &str = "/opt/pixi-pack/env";
+
+/// The manifest and lock file copied before `pixi install`, so that layer survives source changes
+fn manifest_files(config: &Config) -> Vec<String> {
+    let manifest = config
+        .manifest_path()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "pixi.toml".to_string());
+    let mut files = vec![manifest];
+    if config.docker.install_mode != InstallMode::None {
+        files.push("pixi.lock".to_string());
+    }
+    files
+}
+
+/// Describe a task reference that matches no pixi task and isn't a shell command, if any
+fn unknown_task(
+    pixi_toml: &PixiToml,
+    environment: &str,
+    field: &str,
+    value: &str,
+) -> Option<String> {
+    let looks_like_command = value.contains(char::is_whitespace) || value.contains('/');
+    if looks_like_command || pixi_toml.task(value, Some(environment)).is_some() {
+        return None;
+    }
+
+    let closest = pixi_toml.closest_tasks(value, Some(environment));
+    let hint = if closest.is_empty() {
+        "pixi.toml defines no tasks".to_string()
+    } else {
+        format!("closest tasks: {}", closest.join(", "))
+    };
+    Some(format!(
+        "{} '{}' is not a task in pixi.toml ({})",
+        field, value, hint
+    ))
+}
+
+/// The config section an entry of an environment's `copy_files` came from: `[docker]` for
+/// entries inherited from it, also through `append`
+fn copy_files_section(config: &Config, environment: &str, entry: &str) -> String {
+    let base = &config.docker.copy_files;
+    let inherited = config.environments.get(environment).is_none_or(|env| {
+        env.copy_files.is_empty()
+            || (env.copy_files.starts_with(base) && base.iter().any(|e| e == entry))
+    });
+    match inherited {
+        true => "docker".to_string(),
+        false => format!("environments.{}", environment),
+    }
+}
+
+/// Double-quote a value for use in a Dockerfile instruction
+///
+/// Line breaks are escaped too, since a raw one would end the instruction.
+fn quote(value: String) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", escaped)
+}
+
+/// Register `env()`, `now()` and `file_exists()` for templates
+fn add_functions(env: &mut Environment, config: &Config) {
+    let strict = config.docker.strict;
+    env.add_function(
+        "env",
+        move |name: String, default: Option<String>| -> Result<String, minijinja::Error> {
+            match (std::env::var(&name), default) {
+                (Ok(value), _) => Ok(value),
+                (Err(_), Some(default)) => Ok(default),
+                (Err(_), None) if strict => Err(minijinja::Error::new(
+                    minijinja::ErrorKind::InvalidOperation,
+                    format!("environment variable {} is not set", name),
+                )),
+                (Err(_), None) => {
+                    eprintln!(
+                        "Warning: environment variable {} used by the template is not set",
+                        name
+                    );
+                    Ok(String::new())
+                }
+            }
+        },
+    );
+
+    // `now()` is none in reproducible mode, so templates using it still pass `generate --check`
+    let reproducible = config.docker.reproducible;
+    env.add_function("now", move || -> Option<String> {
+        (!reproducible)
+            .then(|| chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    });
+
+    let context_dir = config.project_dir();
+    env.add_function("file_exists", move |path: String| {
+        context_dir.join(path).exists()
+    });
+}
+
+/// Manifest data exposed to templates as `pixi`, empty without a manifest
+#[derive(Default, Serialize)]
+struct PixiContext<'a> {
+    name: Option<&'a str>,
+    version: Option<&'a str>,
+    channels: Vec<&'a str>,
+    platforms: &'a [String],
+    environments: Vec<&'a str>,
+}
+
+impl<'a> PixiContext<'a> {
+    fn new(pixi_toml: Option<&'a PixiToml>) -> Self {
+        let Some(pixi_toml) = pixi_toml else {
+            return Self::default();
+        };
+        Self {
+            name: pixi_toml.get_name().map(String::as_str),
+            version: pixi_toml.get_version().map(String::as_str),
+            channels: pixi_toml.get_channels(),
+            platforms: pixi_toml.get_platforms(),
+            environments: pixi_toml.environment_names(),
+        }
+    }
+}
+
+/// A pixi task as exposed to templates, with the same shape for simple and complex tasks
+#[derive(Serialize)]
+struct TaskContext<'a> {
+    cmd: &'a str,
+    env: BTreeMap<&'a str, &'a str>,
+    depends_on: Vec<&'a str>,
+    cwd: Option<&'a str>,
+}
+
+/// The tasks available in an environment by name, sorted for stable output
+fn task_context<'a>(
+    pixi_toml: Option<&'a PixiToml>,
+    environment: &str,
+) -> BTreeMap<&'a str, TaskContext<'a>> {
+    let Some(pixi_toml) = pixi_toml else {
+        return BTreeMap::new();
+    };
+    pixi_toml
+        .task_names(Some(environment))
+        .into_iter()
+        .filter_map(|name| Some((name, pixi_toml.task(name, Some(environment))?)))
+        .map(|(name, task)| {
+            let task = match task {
+                TaskValue::Simple(cmd) => TaskContext {
+                    cmd,
+                    env: BTreeMap::new(),
+                    depends_on: Vec::new(),
+                    cwd: None,
+                },
+                TaskValue::Complex(config) => TaskContext {
+                    // Empty for an alias that only runs its dependencies
+                    cmd: config.cmd.as_deref().unwrap_or_default(),
+                    env: config
+                        .env
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str()))
+                        .collect(),
+                    depends_on: config.depends_on.iter().map(|d| d.task()).collect(),
+                    cwd: config.cwd.as_deref(),
+                },
+            };
+            (name, task)
+        })
+        .collect()
+}
+
+/// Render arguments as the JSON array of an exec-form Dockerfile instruction
+fn exec_form(args: Vec<String>) -> String {
+    let args: Vec<String> = args.iter().map(|arg| json_string(arg)).collect();
+    format!("[{}]", args.join(", "))
+}
+
+/// Quote a string as a JSON string literal
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Standard `org.opencontainers.image.*` labels derived from pixi.toml metadata
+///
+/// The title comes from `pixi.name` in the template itself.
+fn oci_labels(pixi_toml: Option<&PixiToml>, reproducible: bool) -> Vec<(&'static str, String)> {
+    let mut labels = Vec::new();
+
+    if let Some(pixi_toml) = pixi_toml {
+        let fields = [
+            ("org.opencontainers.image.version", pixi_toml.get_version()),
+            (
+                "org.opencontainers.image.description",
+                pixi_toml.get_description(),
+            ),
+            ("org.opencontainers.image.source", pixi_toml.get_source()),
+            ("org.opencontainers.image.licenses", pixi_toml.get_license()),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                labels.push((key, value.clone()));
+            }
+        }
+
+        let authors = pixi_toml.get_authors();
+        if !authors.is_empty() {
+            labels.push(("org.opencontainers.image.authors", authors.join(", ")));
+        }
+    }
+
+    // Timestamps would make every generation differ
+    if !reproducible {
+        labels.push((
+            "org.opencontainers.image.created",
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        ));
     }
+
+    labels
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Config, DockerConfig, EnvironmentConfig};
-    use std::collections::HashMap;
+    use crate::config::{BaseImage, BuildCommand, Config, InstallMode};
 
     fn create_test_config() -> Config {
-        let mut environments = HashMap::new();
-        environments.insert(
-            "dev".to_string(),
-            EnvironmentConfig {
-                ports: vec![3000],
-                entrypoint: Some("dev".to_string()),
-                copy_files: vec!["src/".to_string(), "tests/".to_string()],
-                build_command: None,
-                multi_stage: Some(false),
-                base_image: None,
-            },
-        );
+        toml::from_str(
+            r#"
+            [docker]
+            environment = "prod"
+            ports = [8080]
+            entrypoint = "serve"
+            copy_files = ["app/"]
+            pixi_version = "0.40.0"
+            build_command = "build"
+            multi_stage = true
+            base_image = "ubuntu:24.04"
+            # Tests match the rendered template exactly; see test_header
+            header = false
 
-        Config {
-            docker: DockerConfig {
-                environment: "prod".to_string(),
-                ports: vec![8080],
-                entrypoint: Some("serve".to_string()),
-                copy_files: vec!["app/".to_string()],
-                image_name: None,
-                image_tag: None,
-                pixi_version: Some("0.40.0".to_string()),
-                build_command: Some("build".to_string()),
-                multi_stage: true,
-                base_image: Some("ubuntu:24.04".to_string()),
-                template_path: None,
-            },
-            environments,
-        }
+            [environments.dev]
+            ports = [3000]
+            entrypoint = "dev"
+            copy_files = ["src/", "tests/"]
+            multi_stage = false
+            "#,
+        )
+        .unwrap()
     }
 
     #[test]
     fn test_generator_creation() {
-        let generator = DockerfileGenerator::new();
+        let generator = DockerfileGenerator::new().unwrap();
         assert!(!generator.template_content.is_empty());
     }
 
     #[test]
     fn test_generate_default_environment() {
         let config = create_test_config();
-        let generator = DockerfileGenerator::new();
+        let generator = DockerfileGenerator::new().unwrap();
 
         let result = generator.generate(&config, None).unwrap();
 
@@ -186,7 +988,7 @@ mod tests {
         assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.40.0"));
         assert!(result.contains("prod"));
         assert!(result.contains("EXPOSE 8080"));
-        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"serve\"]"));
+        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"exec serve\"]"));
         assert!(result.contains("ubuntu:24.04"));
         assert!(result.contains("pixi run --locked build"));
     }
@@ -194,14 +996,14 @@ mod tests {
     #[test]
     fn test_generate_specific_environment() {
         let config = create_test_config();
-        let generator = DockerfileGenerator::new();
+        let generator = DockerfileGenerator::new().unwrap();
 
         let result = generator.generate(&config, Some("dev")).unwrap();
 
         // Check dev-specific configuration
         assert!(result.contains("dev"));
         assert!(result.contains("EXPOSE 3000"));
-        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"dev\"]"));
+        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"exec dev\"]"));
 
         // Dev environment has multi_stage = false, so it won't have multi-stage build structure
         // Instead it should have single stage structure
@@ -211,7 +1013,7 @@ mod tests {
     #[test]
     fn test_environment_config_overrides() {
         let config = create_test_config();
-        let generator = DockerfileGenerator::new();
+        let generator = DockerfileGenerator::new().unwrap();
 
         // Test that dev environment uses its own ports instead of default
         let result = generator.generate(&config, Some("dev")).unwrap();
@@ -224,7 +1026,7 @@ mod tests {
         let mut config = create_test_config();
         config.docker.entrypoint = None;
 
-        let generator = DockerfileGenerator::new();
+        let generator = DockerfileGenerator::new().unwrap();
         let result = generator.generate(&config, None).unwrap();
 
         // Should fallback to bash when no entrypoint is specified
@@ -232,20 +1034,1200 @@ mod tests {
     }
 
     #[test]
-    fn test_custom_template_path() {
-        // Test using basic template content as we don't have a custom file
-        let test_template = "FROM test:latest\nWORKDIR /test\n";
+    fn test_pixi_version_per_environment() {
+        let mut config = create_test_config();
+        config.environments.get_mut("dev").unwrap().pixi_version = Some("0.45.0".to_string());
+        let generator = DockerfileGenerator::new().unwrap();
 
-        // For this test, we'll create a simple generator with known template content
-        let generator = DockerfileGenerator {
-            template_content: test_template.to_string(),
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.45.0 AS build"));
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.40.0 AS build"));
+
+        // Without any version the tag is explicitly `latest`, never empty
+        config.docker.pixi_version = None;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:latest AS build"));
+
+        // An explicit `latest` is resolved, which offline mode refuses to try
+        config.docker.pixi_version = Some("latest".to_string());
+        config.network = crate::network::NetworkPolicy::Offline;
+        let err = generator.generate(&config, None).unwrap_err();
+        assert!(err.to_string().contains("offline mode"));
+        assert!(err.to_string().contains("pin a version"));
+    }
+
+    #[test]
+    fn test_test_in_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\n[tasks]\nserve = \"python -m app\"\n\
+             build = \"make\"\ntest = \"pytest -x tests\"\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.docker.test_in_build = true;
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // Without `test_command`, the manifest's `test` task is translated like the entrypoint
+        let result = generator.generate(&config, None).unwrap();
+        assert_eq!(
+            stage_names(&result),
+            [BUILD_STAGE, TEST_STAGE, PRODUCTION_STAGE, TESTED_STAGE]
+        );
+        assert!(result.contains(
+            "FROM build AS test\nRUN pixi run --locked pytest -x tests\nRUN mkdir /tests-passed\n"
+        ));
+        assert!(result.contains("COPY --from=build /app/app/ /app/app/\n"));
+        assert!(result.contains("FROM production AS tested\nCOPY --from=test /tests-passed/ /\n"));
+
+        config.docker.test_command = Some("pytest -m smoke".to_string());
+        for flavor in ["slim", "cuda"] {
+            let result = generate_flavor(&config, flavor, None);
+            assert!(
+                result.contains("RUN pixi run --locked pytest -m smoke\n"),
+                "{}",
+                flavor
+            );
+            assert_eq!(stage_names(&result).last().unwrap(), TESTED_STAGE);
+        }
+
+        let err = generator.generate(&config, Some("dev")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("test_in_build requires a multi-stage build"));
+
+        config.docker.test_command = None;
+        std::fs::write(&manifest, "[workspace]\nname = \"app\"\nchannels = []\n").unwrap();
+        let err = generator.generate(&config, None).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("set `test_command` or add a `test` task"));
+
+        config.docker.test_in_build = false;
+        let result = generator.generate(&config, None).unwrap();
+        assert_eq!(stage_names(&result), [BUILD_STAGE, PRODUCTION_STAGE]);
+    }
+
+    #[test]
+    fn test_requires_pixi() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\nrequires-pixi = \">=0.41\"\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // The configured 0.40.0 is too old for the manifest
+        let err = generator.generate(&config, Some("prod")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pixi.toml requires pixi >=0.41 (`requires-pixi`), but environment 'prod' uses \
+             pixi 0.40.0; set `pixi_version = \"0.41.0\"`"
+        );
+
+        config.docker.pixi_version = Some("0.45.0".to_string());
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.45.0 AS build"));
+
+        // Without a configured version the oldest matching release is used
+        config.docker.pixi_version = None;
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.41.0 AS build"));
+    }
+
+    #[test]
+    fn test_build_command_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\n[tasks]\nfmt = \"cargo fmt -- --check\"\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.docker.build_command = Some(BuildCommand::List(vec![
+            "codegen".to_string(),
+            "fmt".to_string(),
+        ]));
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // `fmt` is translated through the manifest's tasks
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("RUN pixi run --locked codegen\n"));
+        assert!(result.contains("RUN pixi run --locked cargo fmt -- --check\n"));
+
+        config.docker.single_layer = true;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result
+            .contains("RUN pixi run --locked codegen && pixi run --locked cargo fmt -- --check\n"));
+    }
+
+    #[test]
+    fn test_install_mode_flag() {
+        let mut config = create_test_config();
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("RUN pixi install --locked -e prod"));
+
+        config.docker.install_mode = InstallMode::Frozen;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("RUN pixi install --frozen -e prod"));
+        assert!(result.contains("RUN pixi run --frozen build"));
+    }
+
+    #[test]
+    fn test_clean_cache() {
+        let mut config = create_test_config();
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // Multi-stage builds leave the cache behind in the build stage
+        let result = generator.generate(&config, None).unwrap();
+        assert!(!result.contains("pixi clean cache"));
+
+        // Single-stage builds drop it in the install layer by default
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(result.contains("RUN pixi install --locked -e dev && pixi clean cache --yes\n"));
+        assert!(!result.contains("RUN pixi clean cache"));
+
+        config.docker.clean_cache = Some(false);
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(!result.contains("pixi clean cache"));
+
+        config.docker.clean_cache = Some(true);
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("RUN pixi install --locked -e prod && pixi clean cache --yes\n"));
+    }
+
+    #[test]
+    fn test_pixi_pack_packaging() {
+        let mut config = create_test_config();
+        config.docker.packaging = Packaging::PixiPack;
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("pixi exec pixi-pack pack -e prod"));
+        assert!(result.contains("RUN cp /opt/pixi-pack/activate.sh /shell-hook.sh"));
+        assert!(result.contains("COPY --from=build /opt/pixi-pack/env /opt/pixi-pack/env"));
+        assert!(result.contains("COPY --from=build /app/app/ /app/app/"));
+        assert!(!result.contains("pixi shell-hook"));
+        assert!(!result.contains("/app/.pixi/envs"));
+
+        let err = generator.generate(&config, Some("dev")).unwrap_err();
+        assert!(err.to_string().contains("requires a multi-stage build"));
+
+        for flavor in ["slim", "cuda"] {
+            let result = generate_flavor(&config, flavor, None);
+            assert!(result.contains("COPY --from=build /opt/pixi-pack/env /opt/pixi-pack/env"));
+            assert!(!result.contains("/app/.pixi/envs"));
+        }
+    }
+
+    #[test]
+    fn test_runtime_copy() {
+        let mut config = create_test_config();
+        let generator = DockerfileGenerator::new().unwrap();
+        let production_copies = |result: &str| -> Vec<String> {
+            let (_, production) = result.split_once(" AS production").unwrap();
+            production
+                .lines()
+                .filter(|line| line.starts_with("COPY "))
+                .map(str::to_string)
+                .collect()
         };
 
-        let config = create_test_config();
-        let _result = generator.generate(&config, None).unwrap();
+        // Only the environment, the activation script and copy_files reach the final stage
+        let result = generator.generate(&config, None).unwrap();
+        assert_eq!(
+            production_copies(&result),
+            [
+                "COPY --from=build /app/.pixi/envs/prod /app/.pixi/envs/prod",
+                "COPY --from=build /shell-hook.sh /shell-hook.sh",
+                "COPY --from=build /app/app/ /app/app/",
+            ]
+        );
 
-        // The result should contain our test template parts (though it will error due to invalid template)
-        // This mainly tests that custom template content is used
+        config.docker.runtime_copy = vec![".venv".to_string(), "/opt/tools".to_string()];
+        for flavor in ["default", "slim", "cuda"] {
+            let result = generate_flavor(&config, flavor, None);
+            let copies = production_copies(&result);
+            assert_eq!(copies[0], "COPY --from=build /app/.venv /app/.venv");
+            assert_eq!(copies[1], "COPY --from=build /opt/tools /opt/tools");
+            assert!(!result.contains("/app/.pixi/envs/prod /app/.pixi/envs/prod"));
+        }
+    }
+
+    #[test]
+    fn test_windows_paths_and_line_endings() {
+        let mut config = create_test_config();
+        config.docker.copy_files = vec![r"app\static\".to_string()];
+        config.docker.runtime_copy = vec![r".\.venv".to_string()];
+        // Both renderings below must match, so no creation time label
+        config.docker.reproducible = true;
+        let result = DockerfileGenerator::new()
+            .unwrap()
+            .generate(&config, None)
+            .unwrap();
+        assert!(result.contains("COPY --from=build /app/app/static/ /app/app/static/\n"));
+        assert!(result.contains("COPY --from=build /app/.venv /app/.venv\n"));
+        assert!(!result.contains('\\'));
+
+        // A template saved with CRLF still renders a Dockerfile with LF line endings
+        let generator = DockerfileGenerator {
+            template_content: DockerfileGenerator::new()
+                .unwrap()
+                .template_content
+                .replace('\n', "\r\n"),
+            origin: "crlf.j2".to_string(),
+        };
+        let crlf = generator.generate(&config, None).unwrap();
+        assert!(!crlf.contains('\r'));
+        assert_eq!(crlf, result);
+    }
+
+    #[test]
+    fn test_stop_signal_and_exec_prefix() {
+        let mut config = create_test_config();
+        config.docker.stop_signal = Some("SIGINT".to_string());
+        let generator = DockerfileGenerator::new().unwrap();
+
+        for environment in ["prod", "dev"] {
+            let result = generator.generate(&config, Some(environment)).unwrap();
+            assert!(result.contains("STOPSIGNAL SIGINT\n"));
+        }
+        let result = generate_flavor(&config, "slim", None);
+        assert!(result.contains("STOPSIGNAL SIGINT\n"));
+
+        // Commands chaining several processes opt out of the exec prefix
+        config.docker.entrypoint = Some(Entrypoint::Shell("migrate && serve".to_string()));
+        config.docker.exec_entrypoint = false;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "migrate && serve"]"#));
+
+        config.docker.exec_entrypoint = true;
+        config.docker.entrypoint = Some(Entrypoint::Shell("exec serve".to_string()));
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec serve"]"#));
+
+        config.docker.stop_signal = None;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(!result.contains("STOPSIGNAL"));
+    }
+
+    #[test]
+    fn test_init() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [docker]
+            environment = "prod"
+            init = true
+            entrypoint = ["python", "-m", "myapp.server"]
+
+            [environments.dev]
+            entrypoint = "serve --reload"
+            multi_stage = false
+
+            [environments.ci]
+            init = "docker"
+            "#,
+        )
+        .unwrap();
+        let generator = DockerfileGenerator::new().unwrap();
+        let tini = format!(
+            "ADD --chmod=755 https://github.com/krallin/tini/releases/download/v{}/\
+             tini-static-${{TARGETARCH}} /usr/bin/tini\n",
+            TINI_VERSION
+        );
+        let wrapped = r#"ENTRYPOINT ["/usr/bin/tini", "--", "/bin/bash", "/shell-hook.sh"]"#;
+
+        // tini wraps the shell hook, so the command is still run with the environment activated
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains(&tini));
+        assert!(result.contains(wrapped));
+        assert!(result.contains(r#"CMD ["python", "-m", "myapp.server"]"#));
+
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert_eq!(stage_names(&result), [BUILD_STAGE]);
+        assert!(result.contains(wrapped));
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec serve --reload"]"#));
+
+        config.docker.entrypoint = None;
+        for flavor in ["slim", "cuda", "single-stage-dev"] {
+            let result = generate_flavor(&config, flavor, None);
+            assert!(result.contains("ARG TARGETARCH\n"), "{}", flavor);
+            assert!(result.contains(&tini), "{}", flavor);
+            assert!(result.contains(wrapped), "{}", flavor);
+            assert!(result.contains("CMD [\"/bin/bash\"]"), "{}", flavor);
+        }
+
+        // `docker run --init` needs nothing in the image
+        let plain = r#"ENTRYPOINT ["/bin/bash", "/shell-hook.sh"]"#;
+        let result = generator.generate(&config, Some("ci")).unwrap();
+        assert!(!result.contains("tini"));
+        assert!(result.contains(plain));
+
+        config.docker.init = InitMode::None;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(!result.contains("tini"));
+        assert!(result.contains(plain));
+
+        let err = toml::from_str::<Config>("[docker]\ninit = \"dumb-init\"\n").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid init 'dumb-init'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_exec_form_entrypoint() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [docker]
+            environment = "prod"
+            entrypoint = ["python", "-m", "myapp.server", "--greeting", "say \"hi\"\n"]
+
+            [environments.dev]
+            entrypoint = "serve --reload"
+            multi_stage = false
+            "#,
+        )
+        .unwrap();
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result
+            .contains(r#"CMD ["python", "-m", "myapp.server", "--greeting", "say \"hi\"\n"]"#));
+        assert!(!result.contains("/bin/bash\", \"-c"));
+
+        // A string in an environment replaces the array at the docker level
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec serve --reload"]"#));
+
+        config.docker.entrypoint = Some(Entrypoint::Exec(Vec::new()));
+        let err = generator.generate(&config, None).unwrap_err();
+        assert!(err.to_string().contains("empty array"));
+    }
+
+    #[test]
+    fn test_entrypoint_is_quoted() {
+        let mut config = create_test_config();
+        config.docker.entrypoint = Some(Entrypoint::Shell(r#"echo "hello world""#.to_string()));
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec echo \"hello world\""]"#));
+    }
+
+    #[test]
+    fn test_stage_names() {
+        let config = create_test_config();
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert_eq!(stage_names(&result), [BUILD_STAGE, PRODUCTION_STAGE]);
+
+        // The dev environment is a single stage build
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert_eq!(stage_names(&result), [BUILD_STAGE]);
+
+        assert_eq!(
+            stage_names("from python:3.12 as base\nFROM base\nFROM --platform=$BUILDPLATFORM alpine AS tools"),
+            ["base", "tools"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_task() {
+        let pixi_toml: PixiToml = toml::from_str(
+            r#"
+            [tasks]
+            serve = "python -m http.server"
+            build = "make"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            unknown_task(&pixi_toml, "default", "entrypoint", "sevre").unwrap(),
+            "entrypoint 'sevre' is not a task in pixi.toml (closest tasks: serve, build)"
+        );
+        assert!(unknown_task(&pixi_toml, "default", "entrypoint", "serve").is_none());
+        // Shell commands are passed through as-is
+        assert!(unknown_task(&pixi_toml, "default", "entrypoint", "python app.py").is_none());
+        assert!(unknown_task(&pixi_toml, "default", "build_command", "./build.sh").is_none());
+    }
+
+    #[test]
+    fn test_oci_labels_from_pixi_toml() {
+        let pixi_toml: PixiToml = toml::from_str(
+            r#"
+            [workspace]
+            name = "my-app"
+            version = "1.2.3"
+            description = "Says \"hello\""
+            repository = "https://github.com/acme/my-app"
+            license = "MIT"
+            "#,
+        )
+        .unwrap();
+
+        // The title is rendered from `pixi.name` by the template
+        let labels = oci_labels(Some(&pixi_toml), true);
+        assert_eq!(
+            labels,
+            vec![
+                ("org.opencontainers.image.version", "1.2.3".to_string()),
+                (
+                    "org.opencontainers.image.description",
+                    "Says \"hello\"".to_string()
+                ),
+                (
+                    "org.opencontainers.image.source",
+                    "https://github.com/acme/my-app".to_string()
+                ),
+                ("org.opencontainers.image.licenses", "MIT".to_string()),
+            ]
+        );
+
+        // Outside reproducible mode the creation time is included
+        let labels = oci_labels(Some(&pixi_toml), false);
+        assert_eq!(labels.last().unwrap().0, "org.opencontainers.image.created");
+        assert!(oci_labels(None, true).is_empty());
+    }
+
+    #[test]
+    fn test_quote_escapes_value() {
+        assert_eq!(
+            quote(r#"Says "hi" \ bye"#.to_string()),
+            r#""Says \"hi\" \\ bye""#
+        );
+        assert_eq!(
+            quote("First line\r\nwith more".to_string()),
+            r#""First line\r\nwith more""#
+        );
+    }
+
+    #[test]
+    fn test_multi_line_description_label() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\
+             description = \"\"\"\nServes the app.\nWith multiple lines.\n\"\"\"\n\n\
+             [tasks]\nserve = \"python -m app\"\nbuild = \"make\"\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.docker.lint = true;
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains(
+            "LABEL org.opencontainers.image.description=\"Serves the app.\\nWith multiple lines.\\n\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_oci_labels_rendering() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\n[tasks]\nserve = \"python -m app\"\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.docker.reproducible = true;
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // The manifest provides the metadata
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("LABEL org.opencontainers.image.title=\"app\""));
+        assert!(!result.contains("org.opencontainers.image.created"));
+
+        config.docker.oci_labels = false;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(!result.contains("LABEL"));
+    }
+
+    #[test]
+    fn test_pixi_context() {
+        let pixi_toml: PixiToml = toml::from_str(
+            r#"
+            [workspace]
+            name = "my-app"
+            version = "1.2.3"
+            channels = ["conda-forge", { channel = "pytorch", priority = 1 }]
+            platforms = ["linux-64", "linux-aarch64"]
+
+            [environments]
+            prod = ["prod"]
+            test = { features = ["test"] }
+            "#,
+        )
+        .unwrap();
+        let pixi = PixiContext::new(Some(&pixi_toml));
+        assert_eq!(pixi.name, Some("my-app"));
+        assert_eq!(pixi.channels, vec!["conda-forge", "pytorch"]);
+        assert_eq!(pixi.environments, vec!["default", "prod", "test"]);
+
+        let mut env = Environment::new();
+        env.add_template(
+            "t",
+            "{{ pixi.version }}/{% if \"linux-aarch64\" in pixi.platforms %}arm{% endif %}",
+        )
+        .unwrap();
+        let render = |pixi: PixiContext| {
+            env.get_template("t")
+                .unwrap()
+                .render(context! { pixi => pixi })
+                .unwrap()
+        };
+        assert_eq!(render(pixi), "1.2.3/arm");
+
+        // Without a manifest every field is empty instead of failing the render
+        assert_eq!(render(PixiContext::new(None)), "none/");
+    }
+
+    #[test]
+    fn test_tasks_context() {
+        let pixi_toml: PixiToml = toml::from_str(
+            r#"
+            [tasks]
+            "build:web" = "npm run build"
+            lint = "ruff check"
+            "build:api" = { cmd = "cargo build", env = { PROFILE = "release" }, depends-on = ["lint"] }
+            "#,
+        )
+        .unwrap();
+
+        let mut env = Environment::new();
+        env.add_template(
+            "t",
+            "{% for name, task in tasks | items %}{% if name is startingwith(\"build:\") %}\
+             RUN pixi run {{ name }} # {{ task.cmd }} {{ task.env }} {{ task.depends_on }}\n\
+             {% endif %}{% endfor %}",
+        )
+        .unwrap();
+        let result = env
+            .get_template("t")
+            .unwrap()
+            .render(context! { tasks => task_context(Some(&pixi_toml), "default") })
+            .unwrap();
+        assert_eq!(
+            result,
+            "RUN pixi run build:api # cargo build {\"PROFILE\": \"release\"} [\"lint\"]\n\
+             RUN pixi run build:web # npm run build {} []\n"
+        );
+    }
+
+    #[test]
+    fn test_template_functions() {
+        let mut config = create_test_config();
+        config.docker.reproducible = true;
+        // The fragment isn't a Dockerfile
+        config.docker.lint = false;
+        let generator = DockerfileGenerator {
+            template_content: "{{ env(\"PIXI_DOCKER_TEST_TEMPLATE_VAR\") }}|\
+                {{ env(\"PIXI_DOCKER_TEST_UNSET_VAR\", \"fallback\") }}|\
+                {{ now() is none }}|{{ file_exists(\"pixi.toml\") }}|{{ file_exists(\"missing.txt\") }}"
+                .to_string(),
+            origin: "test.j2".to_string(),
+        };
+
+        std::env::set_var("PIXI_DOCKER_TEST_TEMPLATE_VAR", "abc123");
+        let result = generator.generate(&config, None).unwrap();
+        assert_eq!(result, "abc123|fallback|true|true|false");
+
+        config.docker.reproducible = false;
+        let generator = DockerfileGenerator {
+            template_content: "{{ now() }}".to_string(),
+            origin: "test.j2".to_string(),
+        };
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.ends_with('Z'));
+
+        // A missing variable without a default is only an error in strict mode
+        let generator = DockerfileGenerator {
+            template_content: "[{{ env(\"PIXI_DOCKER_TEST_UNSET_VAR\") }}]".to_string(),
+            origin: "test.j2".to_string(),
+        };
+        assert_eq!(generator.generate(&config, None).unwrap(), "[]");
+        config.docker.strict = true;
+        config.docker.entrypoint = None;
+        config.docker.build_command = None;
+        config.docker.infer_entrypoint = false;
+        config.docker.copy_files = vec!["src/".to_string()];
+        let err = generator.generate(&config, None).unwrap_err();
+        assert!(crate::error::one_line(&err).contains("PIXI_DOCKER_TEST_UNSET_VAR is not set"));
+    }
+
+    #[test]
+    fn test_entrypoint_from_feature_task() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\n\
+             [tasks]\nbuild = \"python -m build\"\n\n\
+             [feature.server.tasks]\nserve = \"gunicorn app:wsgi\"\n\n\
+             [environments]\nprod = [\"server\"]\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec gunicorn app:wsgi"]"#));
+        assert!(result.contains("RUN pixi run --locked python -m build"));
+
+        // The dev environment doesn't include the feature, so `dev` stays a plain command
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(result.contains(r#"CMD ["/bin/bash", "-c", "exec dev"]"#));
+    }
+
+    #[test]
+    fn test_pixi_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nchannels = []\n\n\
+             [tasks]\nserve = \"python -m http.server\"\n\n\
+             [feature.prod.tasks]\nserve = \"gunicorn app:wsgi\"\n\n\
+             [environments]\nprod = [\"prod\"]\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.environments.insert(
+            "api".to_string(),
+            toml::from_str("pixi_environment = \"prod\"").unwrap(),
+        );
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // The feature's `serve` replaces the top-level one in environments with the feature
+        let prod = generator.generate(&config, Some("prod")).unwrap();
+        assert!(prod.contains(r#"CMD ["/bin/bash", "-c", "exec gunicorn app:wsgi"]"#));
+        config.environments.get_mut("dev").unwrap().entrypoint =
+            Some(Entrypoint::Shell("serve".to_string()));
+        let dev = generator.generate(&config, Some("dev")).unwrap();
+        assert!(dev.contains(r#"CMD ["/bin/bash", "-c", "exec python -m http.server"]"#));
+        assert!(dev.contains("pixi install --locked -e dev"));
+
+        // A docker environment can build a differently named pixi environment
+        assert_eq!(config.pixi_environment("api"), "prod");
+        assert_eq!(config.pixi_environment("dev"), "dev");
+        let api = generator.generate(&config, Some("api")).unwrap();
+        assert!(api.contains("pixi install --locked -e prod"));
+        assert!(api.contains("COPY --from=build /app/.pixi/envs/prod /app/.pixi/envs/prod"));
+        assert!(api.contains(r#"CMD ["/bin/bash", "-c", "exec gunicorn app:wsgi"]"#));
+    }
+
+    #[test]
+    fn test_auth_file_mounted_for_install() {
+        let mut config = create_test_config();
+        let generator = DockerfileGenerator::new().unwrap();
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(!result.contains("--mount=type=secret"));
+
+        config.docker.auth_file = Some("credentials.json".to_string());
+        config.docker.packaging = Packaging::PixiPack;
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(result.contains(
+            "RUN --mount=type=secret,id=pixi_auth RATTLER_AUTH_FILE=/run/secrets/pixi_auth \
+             pixi install --locked -e prod"
+        ));
+        assert!(result.contains(
+            "RUN --mount=type=secret,id=pixi_auth RATTLER_AUTH_FILE=/run/secrets/pixi_auth \
+             pixi exec pixi-pack pack -e prod"
+        ));
+    }
+
+    #[test]
+    fn test_ssh_agent_mounted_for_install() {
+        let mut config = create_test_config();
+        for flavor in FLAVORS {
+            let generator = DockerfileGenerator::from_flavor(flavor);
+            config.docker.ssh_forward = false;
+            let result = generator.generate(&config, Some("prod")).unwrap();
+            assert!(!result.contains("type=ssh"), "{}", flavor.name);
+
+            config.docker.ssh_forward = true;
+            let result = generator.generate(&config, Some("prod")).unwrap();
+            assert!(
+                result.contains("RUN --mount=type=ssh pixi install"),
+                "{}",
+                flavor.name
+            );
+        }
+
+        config.docker.auth_file = Some("credentials.json".to_string());
+        let generator = DockerfileGenerator::new().unwrap();
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(result.contains(
+            "RUN --mount=type=ssh --mount=type=secret,id=pixi_auth \
+             RATTLER_AUTH_FILE=/run/secrets/pixi_auth pixi install"
+        ));
+    }
+
+    #[test]
+    fn test_proxy_args_scoped_to_build_stage() {
+        let mut config = create_test_config();
+        for flavor in FLAVORS {
+            let generator = DockerfileGenerator::from_flavor(flavor);
+            let result = generator.generate(&config, Some("prod")).unwrap();
+            let (build, production) = result.split_once(" AS production").unwrap_or((&result, ""));
+            if flavor.name == "single-stage-dev" {
+                assert!(!result.contains("ARG HTTP_PROXY"));
+                continue;
+            }
+            for name in ["HTTP_PROXY", "HTTPS_PROXY", "NO_PROXY"] {
+                let arg = format!("ARG {}\n", name);
+                assert!(build.contains(&arg), "{} {}", flavor.name, name);
+                assert!(!production.contains(&arg), "{} {}", flavor.name, name);
+            }
+        }
+
+        // Declared in a single-stage image, the values would be recorded in its history
+        let generator = DockerfileGenerator::new().unwrap();
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(!result.contains("_PROXY"));
+
+        config.docker.forward_proxy = Some(false);
+        let result = generator.generate(&config, Some("prod")).unwrap();
+        assert!(!result.contains("_PROXY"));
+    }
+
+    #[test]
+    fn test_manifest_copied_before_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pyproject.toml");
+        std::fs::write(
+            &manifest,
+            "[project]\nname = \"app\"\n\n[tool.pixi.workspace]\nchannels = []\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        let generator = DockerfileGenerator::new().unwrap();
+
+        // The install layer only depends on the manifest and lock file
+        let result = generator.generate(&config, None).unwrap();
+        let position = |needle: &str| {
+            result
+                .find(needle)
+                .unwrap_or_else(|| panic!("{} not in\n{}", needle, result))
+        };
+        let copy_manifest = position("COPY pyproject.toml pixi.lock /app/\n");
+        let install = position("RUN pixi install --locked");
+        let copy_source = position("COPY . /app\n");
+        let build = position("RUN pixi run --locked build");
+        assert!(copy_manifest < install && install < copy_source && copy_source < build);
+
+        // Without a lock file only the manifest is copied
+        config.docker.install_mode = InstallMode::None;
+        let result = generator.generate(&config, None).unwrap();
+        assert!(result.contains("COPY pyproject.toml /app/\n"));
+
+        // A project installing itself from a path needs its source before the install
+        std::fs::write(
+            &manifest,
+            "[project]\nname = \"app\"\n\n[tool.pixi.workspace]\nchannels = []\n\n\
+             [tool.pixi.pypi-dependencies]\napp = { path = \".\", editable = true }\n",
+        )
+        .unwrap();
+        let result = generator.generate(&config, None).unwrap();
+        assert!(!result.contains("COPY pyproject.toml"));
+        assert!(result.find("COPY . /app\n").unwrap() < result.find("RUN pixi install").unwrap());
+    }
+
+    #[test]
+    fn test_generate_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("pixi.toml");
+        std::fs::write(
+            &manifest,
+            "[workspace]\nname = \"app\"\nversion = \"1.0.0\"\nchannels = []\n\n\
+             [tasks]\nserve = { cmd = \"serve\", env = { A = \"1\", B = \"2\", C = \"3\", D = \"4\" } }\n\
+             test = \"pytest\"\nlint = \"ruff\"\nfmt = \"ruff format\"\n\n\
+             [environments]\nprod = []\ndev = []\ndocs = []\nlint = []\n",
+        )
+        .unwrap();
+        let mut config = create_test_config();
+        config.docker.manifest_path = Some(manifest.display().to_string());
+        config.docker.reproducible = true;
+        config.docker.lint = false;
+        let generator = DockerfileGenerator {
+            template_content: "{{ pixi.environments }}\n\
+                {% for name, task in tasks | items %}{{ name }}={{ task.env }}\n{% endfor %}"
+                .to_string(),
+            origin: "test.j2".to_string(),
+        };
+
+        // Each map is seeded differently, so repeated renders would expose hash order
+        let first = generator.generate(&config, None).unwrap();
+        for _ in 0..10 {
+            assert_eq!(generator.generate(&config, None).unwrap(), first);
+        }
+        assert!(first.starts_with("[\"default\", \"dev\", \"docs\", \"lint\", \"prod\"]"));
+        assert!(first.contains("serve={\"A\": \"1\", \"B\": \"2\", \"C\": \"3\", \"D\": \"4\"}"));
+
+        let generator = DockerfileGenerator::new().unwrap();
+        let first = generator.generate(&config, None).unwrap();
+        assert_eq!(generator.generate(&config, None).unwrap(), first);
+    }
+
+    #[test]
+    fn test_header() {
+        let mut config = create_test_config();
+        config.docker.header = true;
+        let generator = DockerfileGenerator::new().unwrap();
+
+        let result = generator.generate(&config, Some("dev")).unwrap();
+        assert!(result.starts_with(&format!(
+            "# Generated by pixi-docker {} from pixi_docker.toml (environment: dev) \u{2014} \
+             do not edit, run `pixi-docker generate`\nFROM ghcr.io/prefix-dev/pixi:",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert_eq!(generator.generate(&config, Some("dev")).unwrap(), result);
+
+        // Parser directives must stay first
+        let generator = DockerfileGenerator {
+            template_content: "# syntax=docker/dockerfile:1\n#escape=`\nFROM scratch\n".to_string(),
+            origin: "test.j2".to_string(),
+        };
+        let result = generator.generate(&config, None).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!(lines[0], "# syntax=docker/dockerfile:1");
+        assert_eq!(lines[1], "#escape=`");
+        assert!(lines[2].starts_with("# Generated by pixi-docker"));
+        assert_eq!(lines[3], "FROM scratch");
+    }
+
+    #[test]
+    fn test_target_arch() {
+        let mut config = create_test_config();
+        config.docker.lint = false;
+        let generator = DockerfileGenerator {
+            template_content:
+                "{% if target_arch == \"arm64\" %}aarch64{% else %}{{ target_arch }}{% endif %}"
+                    .to_string(),
+            origin: "test.j2".to_string(),
+        };
+
+        config.docker.platform = Some("linux/arm64/v8".to_string());
+        assert_eq!(generator.generate(&config, None).unwrap(), "aarch64");
+        config.docker.platform = Some("linux/amd64".to_string());
+        assert_eq!(generator.generate(&config, None).unwrap(), "amd64");
+
+        // The bundled template declares the BuildKit platform args in both stages
+        let result = DockerfileGenerator::new()
+            .unwrap()
+            .generate(&config, None)
+            .unwrap();
+        assert!(result.starts_with("FROM ghcr.io/prefix-dev/pixi:"));
+        assert_eq!(
+            result
+                .matches("ARG TARGETARCH\nARG TARGETPLATFORM\n")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_base_image_per_arch() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [docker]
+            environment = "prod"
+            pixi_version = "0.40.0"
+            header = false
+            base_image = { amd64 = "mirror.corp/ubuntu:24.04", arm64 = "arm64v8/ubuntu:24.04" }
+
+            [environments.gpu]
+            base_image = "nvidia/cuda:12.4.1-base-ubuntu22.04"
+            "#,
+        )
+        .unwrap();
+        let generator = DockerfileGenerator::new().unwrap();
+        let production_from = |config: &Config, environment: &str| {
+            let result = generator.generate(config, Some(environment)).unwrap();
+            let line = result.lines().find(|line| line.ends_with(" AS production"));
+            line.unwrap().to_string()
+        };
+
+        config.docker.platform = Some("linux/arm64/v8".to_string());
+        assert_eq!(
+            production_from(&config, "prod"),
+            "FROM arm64v8/ubuntu:24.04 AS production"
+        );
+        config.docker.platform = Some("linux/amd64".to_string());
+        assert_eq!(
+            production_from(&config, "prod"),
+            "FROM mirror.corp/ubuntu:24.04 AS production"
+        );
+        // A single image applies to every architecture
+        assert_eq!(
+            production_from(&config, "gpu"),
+            "FROM nvidia/cuda:12.4.1-base-ubuntu22.04 AS production"
+        );
+
+        config.docker.platform = Some("linux/ppc64le".to_string());
+        let err = generator.generate(&config, Some("prod")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "base_image of environment 'prod' has no image for ppc64le (configured: amd64, \
+             arm64); add `ppc64le = \"...\"` to it or pick another architecture with --platform"
+        );
+        assert_eq!(
+            config.configured_base_image("gpu"),
+            Some(&BaseImage::Single(
+                "nvidia/cuda:12.4.1-base-ubuntu22.04".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_template_error_location() {
+        let config = create_test_config();
+        let generator = DockerfileGenerator {
+            template_content: "FROM base\nRUN {{ ports | nope }}\n".to_string(),
+            origin: "custom.j2".to_string(),
+        };
+        let err = generator.generate(&config, None).unwrap_err().to_string();
+        assert!(err.starts_with("Template error in custom.j2:2:"), "{}", err);
+        assert!(err.contains("unknown filter"), "{}", err);
+        assert!(err.contains("2 | RUN {{ ports | nope }}"), "{}", err);
+
+        // An explicit template path that can't be read is an error, not a fallback
+        let err = DockerfileGenerator::with_template_path(Some("missing/custom.j2".into()), None)
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .contains("Failed to read template missing/custom.j2"));
+    }
+
+    #[test]
+    fn test_custom_template_path() {
+        // Test using basic template content as we don't have a custom file
+        let test_template = "FROM test:latest\nWORKDIR /test\n";
+
+        // For this test, we'll create a simple generator with known template content
+        let generator = DockerfileGenerator {
+            template_content: test_template.to_string(),
+            origin: "test.j2".to_string(),
+        };
+
+        let config = create_test_config();
+        let _result = generator.generate(&config, None).unwrap();
+
+        // The result should contain our test template parts (though it will error due to invalid template)
+        // This mainly tests that custom template content is used
         assert!(generator.template_content.contains("FROM test:latest"));
     }
+
+    fn generate_flavor(config: &Config, flavor: &str, environment: Option<&str>) -> String {
+        DockerfileGenerator::with_template_path(None, Some(flavor))
+            .unwrap()
+            .generate(config, environment)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_unknown_flavor() {
+        let err = DockerfileGenerator::with_template_path(None, Some("tiny"))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("unknown template flavor 'tiny'"));
+        assert!(err.contains("default, slim, cuda, single-stage-dev"));
+    }
+
+    #[test]
+    fn test_template_dir_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let template_dir = dir.path().join("docker");
+        fs::create_dir(&template_dir).unwrap();
+        let mut config = create_test_config();
+        config.docker.template_dir = Some(template_dir.display().to_string());
+        let origin = |config: &Config, environment: &str| {
+            DockerfileGenerator::for_environment(config, environment)
+                .unwrap()
+                .origin
+        };
+
+        // A directory with only an environment-specific file serves just that environment
+        let dev_template = template_dir.join("Dockerfile.dev.j2");
+        fs::write(&dev_template, "FROM dev:{{ environment }}\n").unwrap();
+        assert_eq!(origin(&config, "dev"), dev_template.display().to_string());
+        config.docker.flavor = Some("slim".to_string());
+        assert_eq!(origin(&config, "prod"), "<built-in slim template>");
+
+        // Dockerfile.j2 covers the environments without their own file
+        let shared_template = template_dir.join("Dockerfile.j2");
+        fs::write(&shared_template, "FROM shared\n").unwrap();
+        assert_eq!(
+            origin(&config, "prod"),
+            shared_template.display().to_string()
+        );
+        assert_eq!(origin(&config, "dev"), dev_template.display().to_string());
+        let dockerfile = DockerfileGenerator::for_environment(&config, "dev")
+            .unwrap()
+            .render(&config, Some("dev"))
+            .unwrap();
+        assert_eq!(dockerfile, "FROM dev:dev");
+
+        // An explicit template_path of the environment wins over the directory
+        let explicit = dir.path().join("explicit.j2");
+        fs::write(&explicit, "FROM explicit\n").unwrap();
+        config.environments.get_mut("dev").unwrap().template_path =
+            Some(explicit.display().to_string());
+        assert_eq!(origin(&config, "dev"), explicit.display().to_string());
+        assert_eq!(
+            origin(&config, "prod"),
+            shared_template.display().to_string()
+        );
+
+        config.docker.template_dir = Some(dir.path().join("missing").display().to_string());
+        let err = DockerfileGenerator::for_environment(&config, "prod")
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is not a directory"));
+    }
+
+    #[test]
+    fn test_template_path_wins_over_flavor() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("custom.j2");
+        fs::write(&path, "FROM custom:{{ environment }}\n").unwrap();
+
+        let generator = DockerfileGenerator::with_template_path(Some(path), Some("cuda")).unwrap();
+        let result = generator.generate(&create_test_config(), None).unwrap();
+        assert_eq!(result.trim(), "FROM custom:prod");
+    }
+
+    #[test]
+    fn test_default_flavor() {
+        let config = create_test_config();
+        let result = generate_flavor(&config, DEFAULT_FLAVOR, None);
+        assert!(result.contains("FROM ubuntu:24.04 AS production"));
+        assert!(result.contains("pixi run --locked build"));
+    }
+
+    #[test]
+    fn test_slim_flavor() {
+        let mut config = create_test_config();
+        config.docker.base_image = None;
+
+        let result = generate_flavor(&config, "slim", None);
+        assert!(result.contains("FROM ghcr.io/prefix-dev/pixi:0.40.0 AS build"));
+        assert!(result.contains("pixi install --locked -e prod && rm -rf /root/.cache/rattler"));
+        assert!(result.contains("pixi run --locked build"));
+        assert!(result.contains("FROM debian:bookworm-slim AS production"));
+        assert!(result.contains("COPY --from=build /app/app/ /app/app/"));
+        assert!(result.contains("EXPOSE 8080"));
+        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"exec serve\"]"));
+
+        // Slim images stay multi-stage even when the environment disables it
+        let result = generate_flavor(&config, "slim", Some("dev"));
+        assert_eq!(stage_names(&result), vec!["build", "production"]);
+        assert!(result.contains("EXPOSE 3000"));
+    }
+
+    #[test]
+    fn test_cuda_flavor() {
+        let mut config = create_test_config();
+        config.docker.base_image = None;
+
+        let result = generate_flavor(&config, "cuda", None);
+        assert!(result.contains("ENV CONDA_OVERRIDE_CUDA=12"));
+        assert!(result.contains("FROM nvidia/cuda:12.4.1-base-ubuntu22.04 AS production"));
+        assert!(result.contains("ENV NVIDIA_VISIBLE_DEVICES=all"));
+        assert!(result.contains("pixi run --locked build"));
+        assert!(result.contains("EXPOSE 8080"));
+        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"exec serve\"]"));
+
+        let result = generate_flavor(&config, "cuda", Some("dev"));
+        assert_eq!(stage_names(&result), vec!["build"]);
+        assert!(result.contains("ENV NVIDIA_VISIBLE_DEVICES=all"));
+        assert!(result.contains("EXPOSE 3000"));
+    }
+
+    #[test]
+    fn test_single_stage_dev_flavor() {
+        let config = create_test_config();
+
+        // Always a single stage, even when the environment asks for multi_stage
+        let result = generate_flavor(&config, "single-stage-dev", None);
+        assert_eq!(stage_names(&result), vec!["build"]);
+        assert!(result.contains("COPY . /app"));
+        assert!(result.contains("pixi run --locked build"));
+        assert!(result.contains("EXPOSE 8080"));
+        assert!(result.contains("CMD [\"/bin/bash\", \"-c\", \"exec serve\"]"));
+        assert!(!result.contains("ubuntu:24.04"));
+    }
+
+    #[test]
+    fn test_export_stage() {
+        let config = create_test_config();
+        let result = DockerfileGenerator::new()
+            .unwrap()
+            .generate(&config, None)
+            .unwrap();
+        assert_eq!(final_stage(&result).as_deref(), Some(PRODUCTION_STAGE));
+        assert_eq!(
+            final_stage("FROM a AS build\nRUN make\nFROM scratch\n"),
+            None
+        );
+
+        let exported = format!(
+            "{}{}",
+            result,
+            export_stage(EXPORT_STAGE, BUILD_STAGE, &["/app/dist", "/app/README.md"])
+        );
+        assert!(exported.ends_with(
+            "\nFROM scratch AS export\nCOPY --from=build /app/dist /\n\
+             COPY --from=build /app/README.md /\n"
+        ));
+        assert_eq!(final_stage(&exported).as_deref(), Some(EXPORT_STAGE));
+        crate::lint::check(&exported, "test").unwrap();
+    }
+
+    #[test]
+    fn test_pin_base_images() {
+        let dockerfile = "FROM ghcr.io/prefix-dev/pixi:0.45.0 AS build\n\
+                          RUN pixi install\n\
+                          FROM build AS test\n\
+                          FROM --platform=$BUILDPLATFORM ubuntu:24.04 AS production\n\
+                          FROM scratch\n\
+                          FROM alpine@sha256:abc\n";
+        assert_eq!(
+            base_images(dockerfile),
+            vec!["ghcr.io/prefix-dev/pixi:0.45.0", "ubuntu:24.04"]
+        );
+
+        let digests = BTreeMap::from([("ubuntu:24.04".to_string(), "sha256:def".to_string())]);
+        let pinned = pin_base_images(dockerfile, &digests);
+        assert!(pinned.contains("FROM ghcr.io/prefix-dev/pixi:0.45.0 AS build\n"));
+        assert!(pinned
+            .contains("FROM --platform=$BUILDPLATFORM ubuntu:24.04@sha256:def AS production\n"));
+        assert!(pinned.contains("FROM build AS test\n"));
+        assert!(pinned.ends_with("FROM alpine@sha256:abc\n"));
+    }
 }
@@ -0,0 +1,141 @@
+//! A stand-in for `docker` (or any other runtime) in the integration tests
+//!
+//! The tests compile this file with `rustc` and install it under the runtime's name, so the
+//! build and run commands can be tested without a daemon on every platform. Its behavior is
+//! read from `<name>.rules` next to the executable, one directive per line:
+//!
+//! - `log <path>`: append the arguments, joined by spaces, to `path`
+//! - `rule`: start a rule; the first rule whose conditions all hold applies, one without
+//!   conditions to every call
+//! - `args <word>...`: the arguments start with these words, `*` matches any one
+//! - `contains <text>`: the arguments, joined by spaces, contain `text`
+//! - `env <name>=<value>`: the variable is set to `value`
+//! - `exists <path>`: the file exists, e.g. after an earlier call `touch`ed it
+//! - `stdout <text>`, `stderr <text>`: print `text`, with `\n` for newlines
+//! - `write <flag> <text>`: write `text` to the path following `flag`, if given
+//! - `copy <flag> <path>`: copy the file following `flag`, or stdin for `-`, to `path`, if given
+//! - `touch <path>`: create an empty file at `path`
+//! - `barrier <count> <dir>`: wait until `count` calls reached this rule, else exit 9
+//! - `exit <code>`: exit with `code` (default: 0)
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::exit;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Rule {
+    conditions: Vec<(String, String)>,
+    actions: Vec<(String, String)>,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let joined = args.join(" ");
+    let exe = std::env::current_exe().expect("no executable path");
+    let rules = fs::read_to_string(exe.with_extension("rules")).unwrap_or_default();
+
+    let mut log = None;
+    let mut parsed: Vec<Rule> = Vec::new();
+    for line in rules.lines().filter(|line| !line.is_empty()) {
+        let (directive, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.replace("\\n", "\n");
+        match directive {
+            "log" => log = Some(value),
+            "rule" => parsed.push(Rule::default()),
+            "args" | "contains" | "env" | "exists" => {
+                let rule = parsed.last_mut().expect("condition outside of a rule");
+                rule.conditions.push((directive.to_string(), value));
+            }
+            _ => {
+                let rule = parsed.last_mut().expect("action outside of a rule");
+                rule.actions.push((directive.to_string(), value));
+            }
+        }
+    }
+
+    if let Some(log) = log {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .expect("can't open the log");
+        writeln!(file, "{}", joined).expect("can't write the log");
+    }
+
+    let matched = parsed.iter().find(|rule| {
+        rule.conditions
+            .iter()
+            .all(|(kind, value)| match kind.as_str() {
+                "args" => {
+                    let words: Vec<&str> = value.split(' ').collect();
+                    words.len() <= args.len()
+                        && words
+                            .iter()
+                            .zip(&args)
+                            .all(|(word, arg)| *word == "*" || word == arg)
+                }
+                "contains" => joined.contains(value.as_str()),
+                "exists" => Path::new(value).exists(),
+                _ => {
+                    let (name, expected) = value.split_once('=').unwrap_or((value, ""));
+                    std::env::var(name).is_ok_and(|actual| actual == expected)
+                }
+            })
+    });
+    let Some(rule) = matched else {
+        return;
+    };
+
+    let after = |flag: &str| {
+        let position = args.iter().position(|arg| arg == flag)?;
+        args.get(position + 1).cloned()
+    };
+    for (action, value) in &rule.actions {
+        let (first, rest) = value.split_once(' ').unwrap_or((value, ""));
+        match action.as_str() {
+            "stdout" => print!("{}", value),
+            "stderr" => eprint!("{}", value),
+            "write" => {
+                if let Some(path) = after(first) {
+                    fs::write(path, rest).expect("can't write the file");
+                }
+            }
+            "copy" => match after(first).as_deref() {
+                Some("-") => {
+                    let mut file = fs::File::create(rest).expect("can't create the copy");
+                    io::copy(&mut io::stdin(), &mut file).expect("can't copy stdin");
+                }
+                Some(source) => {
+                    fs::copy(source, rest).expect("can't copy the file");
+                }
+                None => {}
+            },
+            "touch" => {
+                fs::write(value, "").expect("can't touch the file");
+            }
+            "barrier" => {
+                let count: usize = first.parse().expect("invalid barrier count");
+                fs::write(Path::new(rest).join(std::process::id().to_string()), "")
+                    .expect("can't write the barrier marker");
+                let reached = (0..30).any(|_| {
+                    let arrived = fs::read_dir(rest).map_or(0, |dir| dir.count());
+                    arrived >= count || {
+                        sleep(Duration::from_millis(100));
+                        false
+                    }
+                });
+                if !reached {
+                    exit(9);
+                }
+            }
+            "exit" => {
+                io::stdout().flush().ok();
+                exit(value.parse().expect("invalid exit code"));
+            }
+            _ => panic!("unknown directive {}", action),
+        }
+    }
+}